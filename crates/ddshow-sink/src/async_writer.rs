@@ -0,0 +1,461 @@
+use crate::writer::{archive_dropped, archive_event, EventWriter};
+use rkyv::{ser::serializers::AlignedSerializer, AlignedVec, Serialize};
+use std::{
+    collections::VecDeque,
+    io::Write,
+    marker::PhantomData,
+    num::NonZeroUsize,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+};
+use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+/// What an [`AsyncEventWriter`] does with new events when its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackpressurePolicy {
+    /// Blocks the logging worker until the writer thread makes room
+    Block,
+    /// Drops the batch of events that's being pushed
+    DropNewest,
+    /// Drops the oldest queued batch of events to make room for the new one,
+    /// the new batch is dropped if every queued batch is a progress update
+    DropOldest,
+}
+
+/// An event pusher that archives events on the logging worker and hands
+/// them off to a dedicated thread that writes them, so that a slow writer
+/// doesn't stall the worker
+///
+/// Up to `capacity` batches are queued for the writer thread, after which the
+/// writer's [`BackpressurePolicy`] kicks in. Progress updates are never dropped
+/// so that the written stream's frontiers stay consistent. The number of
+/// dropped events is written to the stream as a [`ddshow_types::Event::Dropped`]
+/// event, dropped events aren't counted by the stream's [`ddshow_types::Event::Sequence`]
+/// numbers so ddshow reports them as dropped rather than missing
+///
+/// Dropping the writer waits for every queued batch to be written
+///
+/// ## Examples
+///
+/// ```rust
+/// use ddshow_sink::{AsyncEventWriter, BackpressurePolicy, BatchLogger};
+/// use ddshow_types::{timely_logging::TimelyEvent, WorkerId};
+/// use std::{io, num::NonZeroUsize};
+/// use timely::logging::TimelyEvent as RawTimelyEvent;
+///
+/// timely::execute_directly(|worker| {
+///     let writer = AsyncEventWriter::new(
+///         io::sink(),
+///         NonZeroUsize::new(1024).unwrap(),
+///         BackpressurePolicy::DropOldest,
+///     );
+///     let mut logger: BatchLogger<TimelyEvent, WorkerId, _> = BatchLogger::new(writer);
+///
+///     worker
+///         .log_register()
+///         .insert::<RawTimelyEvent, _>("timely", move |time, data| {
+///             logger.publish_batch(time, data)
+///         });
+/// });
+/// ```
+///
+#[derive(Debug)]
+pub struct AsyncEventWriter<T, D> {
+    queue: Arc<Queue<T>>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    thread: Option<JoinHandle<()>>,
+    __type: PhantomData<D>,
+}
+
+impl<T, D> AsyncEventWriter<T, D>
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>> + Send + 'static,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>> + 'static,
+{
+    /// Spawns a thread that writes events to the given writer,
+    /// queueing up to `capacity` batches of events for it
+    pub fn new<W>(writer: W, capacity: NonZeroUsize, policy: BackpressurePolicy) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self::sampling_schedules(writer, capacity, policy, NonZeroUsize::new(1).unwrap())
+    }
+
+    /// Spawns a writer for a stream that only holds one
+    /// in every `one_in` of each operator's activations
+    pub(crate) fn sampling_schedules<W>(
+        writer: W,
+        capacity: NonZeroUsize,
+        policy: BackpressurePolicy,
+        one_in: NonZeroUsize,
+    ) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState {
+                batches: VecDeque::with_capacity(capacity.get()),
+                dropped: None,
+                closed: false,
+            }),
+            pushed: Condvar::new(),
+            popped: Condvar::new(),
+        });
+
+        let thread_queue = queue.clone();
+        let thread = thread::Builder::new()
+            .name("ddshow-sink-writer".to_owned())
+            .spawn(move || write_batches::<T, D, W>(&thread_queue, writer, one_in))
+            .expect("failed to spawn the ddshow-sink writer thread");
+
+        Self {
+            queue,
+            capacity: capacity.get(),
+            policy,
+            thread: Some(thread),
+            __type: PhantomData,
+        }
+    }
+}
+
+impl<T, D> EventPusher<T, D> for AsyncEventWriter<T, D>
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>> + Clone,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    fn push(&mut self, event: RawEvent<T, D>) {
+        let messages = match &event {
+            RawEvent::Messages(time, data) => Some((time.clone(), data.len() as u64)),
+            RawEvent::Progress(_) => None,
+        };
+
+        let mut archive = AlignedVec::new();
+        archive_event(event, &mut archive);
+
+        let batch = Batch { archive, messages };
+        self.queue.push(batch, self.capacity, self.policy);
+    }
+}
+
+impl<T, D> Drop for AsyncEventWriter<T, D> {
+    fn drop(&mut self) {
+        self.queue.state().closed = true;
+        self.queue.pushed.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            if let Err(_err) = thread.join() {
+                #[cfg(feature = "tracing")]
+                tracing_dep::error!("the ddshow-sink writer thread panicked: {:?}", _err);
+            }
+        }
+    }
+}
+
+/// The batches shared between an [`AsyncEventWriter`] and its writer thread
+#[derive(Debug)]
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    /// Notified when a batch is pushed or the queue is closed
+    pushed: Condvar,
+    /// Notified when batches are popped
+    popped: Condvar,
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    batches: VecDeque<Batch<T>>,
+    /// The time of the last dropped batch and the number of events dropped
+    /// since the writer thread last wrote them out
+    dropped: Option<(T, u64)>,
+    closed: bool,
+}
+
+/// An archived batch of events
+#[derive(Debug)]
+struct Batch<T> {
+    archive: AlignedVec,
+    /// The time and number of events of message batches,
+    /// progress updates can't be dropped
+    messages: Option<(T, u64)>,
+}
+
+impl<T> Queue<T> {
+    /// Locks the queue's state, the state is never left half-updated
+    /// so any poisoning is ignored
+    fn state(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, batch: Batch<T>, capacity: usize, policy: BackpressurePolicy) {
+        let mut state = self.state();
+
+        if state.batches.len() >= capacity {
+            match policy {
+                BackpressurePolicy::Block => {
+                    while state.batches.len() >= capacity && !state.closed {
+                        state = self
+                            .popped
+                            .wait(state)
+                            .unwrap_or_else(PoisonError::into_inner);
+                    }
+                }
+
+                BackpressurePolicy::DropNewest => {
+                    if let Some((time, events)) = batch.messages {
+                        state.drop_events(time, events);
+                        return;
+                    }
+                }
+
+                BackpressurePolicy::DropOldest => {
+                    let oldest = state
+                        .batches
+                        .iter()
+                        .position(|batch| batch.messages.is_some());
+
+                    match oldest.and_then(|oldest| state.batches.remove(oldest)) {
+                        Some(Batch {
+                            messages: Some((time, events)),
+                            ..
+                        }) => state.drop_events(time, events),
+
+                        _ => {
+                            if let Some((time, events)) = batch.messages {
+                                state.drop_events(time, events);
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        state.batches.push_back(batch);
+        drop(state);
+
+        self.pushed.notify_one();
+    }
+}
+
+impl<T> QueueState<T> {
+    fn drop_events(&mut self, time: T, events: u64) {
+        let dropped = self.dropped.take().map_or(0, |(_, dropped)| dropped);
+        self.dropped = Some((time, dropped + events));
+    }
+}
+
+/// Writes queued batches until the queue is closed and empty, writing the
+/// number of dropped events before each batch that follows a drop
+fn write_batches<T, D, W>(queue: &Queue<T>, writer: W, sampled_schedules: NonZeroUsize)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    W: Write,
+{
+    let mut writer = EventWriter::<T, D, W>::new(writer).sampled_schedules(sampled_schedules);
+    let mut dropped_archive = AlignedVec::new();
+
+    loop {
+        let (dropped, batch) = {
+            let mut state = queue.state();
+
+            if state.batches.is_empty() && !state.closed {
+                // Flush while there's nothing to write so that
+                // streamed events don't sit around in a buffer
+                drop(state);
+                flush(&mut writer);
+
+                state = queue.state();
+                while state.batches.is_empty() && !state.closed {
+                    state = queue
+                        .pushed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+
+            (state.dropped.take(), state.batches.pop_front())
+        };
+        queue.popped.notify_one();
+
+        if let Some((time, events)) = dropped {
+            #[cfg(feature = "tracing")]
+            tracing_dep::warn!(
+                dropped_events = events,
+                "the ddshow-sink writer's queue was full, dropped {} events",
+                events,
+            );
+
+            archive_dropped::<T, D>(time, events, &mut dropped_archive);
+            if let Err(_err) = writer.write_archive(&dropped_archive) {
+                #[cfg(feature = "tracing")]
+                tracing_dep::error!("failed to write events to the stream: {:?}", _err);
+            }
+        }
+
+        match batch {
+            Some(batch) => {
+                // Dropped batches never reach the writer, so they aren't
+                // counted by its sequence numbers
+                let messages = batch.messages.map_or(0, |(_, messages)| messages);
+                if let Err(_err) = writer.write_event(&batch.archive, messages) {
+                    #[cfg(feature = "tracing")]
+                    tracing_dep::error!("failed to write events to the stream: {:?}", _err);
+                }
+            }
+            // The queue is closed and everything has been written
+            None => break,
+        }
+    }
+
+    flush(&mut writer);
+}
+
+fn flush<T, D, W>(writer: &mut EventWriter<T, D, W>)
+where
+    W: Write,
+{
+    if let Err(_err) = writer.flush() {
+        #[cfg(feature = "tracing")]
+        tracing_dep::error!("failed to flush the ddshow-sink writer: {:?}", _err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        async_writer::{AsyncEventWriter, BackpressurePolicy},
+        writer::tests::{read_events, SharedBuffer},
+    };
+    use ddshow_types::Event;
+    use std::{
+        io::{self, Write},
+        num::NonZeroUsize,
+        sync::{Arc, Condvar, Mutex},
+        time::Duration,
+    };
+    use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+    /// A writer that blocks until it's opened, standing in for a writer
+    /// that can't keep up with the logging worker
+    #[derive(Debug, Clone, Default)]
+    struct GatedWriter {
+        buffer: SharedBuffer,
+        gate: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl GatedWriter {
+        fn open(&self) {
+            let (open, opened) = &*self.gate;
+            *open.lock().unwrap() = true;
+            opened.notify_all();
+        }
+    }
+
+    impl Write for GatedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let (open, opened) = &*self.gate;
+            let mut is_open = open.lock().unwrap();
+            while !*is_open {
+                is_open = opened.wait(is_open).unwrap();
+            }
+            drop(is_open);
+
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const BATCHES: u64 = 100;
+
+    /// Pushes a batch of one message for every time followed by a progress update
+    /// with a queue of a single batch, only opening the writer once everything
+    /// has been pushed unless the writer is allowed to block
+    fn write_through_queue(policy: BackpressurePolicy) -> Vec<Event<Duration, u64>> {
+        let writer = GatedWriter::default();
+        if policy == BackpressurePolicy::Block {
+            writer.open();
+        }
+
+        let mut pusher = AsyncEventWriter::<Duration, u64>::new(
+            writer.clone(),
+            NonZeroUsize::new(1).unwrap(),
+            policy,
+        );
+        for time in 0..BATCHES {
+            pusher.push(RawEvent::Messages(Duration::from_millis(time), vec![time]));
+        }
+        pusher.push(RawEvent::Progress(vec![(
+            Duration::from_millis(BATCHES),
+            1,
+        )]));
+
+        // Dropping the pusher waits for everything to be written
+        writer.open();
+        drop(pusher);
+
+        read_events(&writer.buffer.contents())
+    }
+
+    /// The data of every written message batch along with the number of dropped messages
+    fn written_and_dropped(events: &[Event<Duration, u64>]) -> (Vec<u64>, u64) {
+        let (mut written, mut dropped) = (Vec::new(), 0);
+        for event in events {
+            match event {
+                Event::Messages(_, data) => written.extend(data.iter().copied()),
+                Event::Dropped(_, events) => dropped += events,
+                _ => {}
+            }
+        }
+
+        (written, dropped)
+    }
+
+    #[test]
+    fn blocking_loses_nothing() {
+        let events = write_through_queue(BackpressurePolicy::Block);
+
+        let (written, dropped) = written_and_dropped(&events);
+        assert_eq!(written, (0..BATCHES).collect::<Vec<_>>());
+        assert_eq!(dropped, 0);
+        assert!(events.last().unwrap().is_progress());
+    }
+
+    #[test]
+    fn dropped_events_are_counted() {
+        for &policy in &[
+            BackpressurePolicy::DropNewest,
+            BackpressurePolicy::DropOldest,
+        ] {
+            let events = write_through_queue(policy);
+
+            // Every message is either written or counted as dropped
+            let (written, dropped) = written_and_dropped(&events);
+            assert!(dropped > 0, "{:?}", policy);
+            assert_eq!(written.len() as u64 + dropped, BATCHES, "{:?}", policy);
+            assert!(
+                written.windows(2).all(|pair| pair[0] < pair[1]),
+                "{:?}",
+                policy
+            );
+
+            // Dropped messages aren't counted by the sequence numbers
+            let sequences: Vec<_> = events
+                .iter()
+                .filter(|event| matches!(event, Event::Sequence(_)))
+                .collect();
+            assert_eq!(sequences, vec![&Event::Sequence(0)], "{:?}", policy);
+
+            // Progress updates are never dropped
+            assert!(events.last().unwrap().is_progress(), "{:?}", policy);
+        }
+
+        // The first batch always fits into the queue when dropping new batches
+        let events = write_through_queue(BackpressurePolicy::DropNewest);
+        assert_eq!(written_and_dropped(&events).0[0], 0);
+    }
+}
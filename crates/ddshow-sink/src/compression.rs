@@ -0,0 +1,266 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, BufRead, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// The magic bytes at the start of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// The magic bytes at the start of every gzip member, including
+/// the compression method byte (which is always deflate)
+const GZIP_MAGIC: [u8; 3] = [0x1F, 0x8B, 0x08];
+
+/// The compression codec used for saved log files
+///
+/// Zstd and gzip compression require the `zstd` and `gzip` features
+/// respectively, trying to use them without their feature enabled
+/// will return an [`io::ErrorKind::Unsupported`] error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Compression {
+    /// Uncompressed log files
+    None,
+    /// Zstd compressed log files, using the `.ddshow.zst` extension
+    Zstd,
+    /// Gzip compressed log files, using the `.ddshow.gz` extension
+    Gzip,
+}
+
+impl Compression {
+    /// The file extension used for log files with the current compression
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::None => "ddshow",
+            Self::Zstd => "ddshow.zst",
+            Self::Gzip => "ddshow.gz",
+        }
+    }
+
+    /// Guesses the compression of a log file from its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+
+        [Self::Zstd, Self::Gzip, Self::None]
+            .iter()
+            .copied()
+            .find(|compression| {
+                matches!(
+                    name.strip_suffix(compression.extension()),
+                    Some(stem) if stem.ends_with('.'),
+                )
+            })
+    }
+
+    /// Detects the compression of a log file from its leading bytes, returns
+    /// `None` if it doesn't start with the magic bytes of a known codec
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
+
+    /// Detects the compression of a log file, first checking the file's magic
+    /// bytes and then falling back to its extension
+    ///
+    /// This doesn't consume any bytes from the given reader
+    pub fn detect<R>(reader: &mut R, path: &Path) -> io::Result<Self>
+    where
+        R: BufRead,
+    {
+        let header = reader.fill_buf()?;
+
+        Ok(Self::from_magic(header)
+            .or_else(|| Self::from_path(path))
+            .unwrap_or(Self::None))
+    }
+
+    /// Wraps a writer so that everything written to it is compressed
+    ///
+    /// The compressed stream is finished when the returned writer is dropped
+    pub fn compress<W>(self, writer: W) -> io::Result<Box<dyn Write + Send + 'static>>
+    where
+        W: Write + Send + 'static,
+    {
+        match self {
+            Self::None => Ok(Box::new(writer)),
+
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd_dep::Encoder::new(writer, 0)?.auto_finish())),
+
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Ok(Box::new(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            ))),
+
+            #[allow(unreachable_patterns)]
+            unsupported => Err(unsupported.disabled_error()),
+        }
+    }
+
+    /// Wraps a reader so that everything read from it is decompressed
+    pub fn decompress<R>(self, reader: R) -> io::Result<Box<dyn Read + Send + 'static>>
+    where
+        R: BufRead + Send + 'static,
+    {
+        match self {
+            Self::None => Ok(Box::new(reader)),
+
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd_dep::Decoder::with_buffer(reader)?)),
+
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader))),
+
+            #[allow(unreachable_patterns)]
+            unsupported => Err(unsupported.disabled_error()),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn disabled_error(self) -> io::Error {
+        let feature = match self {
+            Self::None => "",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        };
+
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} compression requires the `{}` feature of ddshow-sink",
+                self, feature,
+            ),
+        )
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let lowercase = string.to_lowercase();
+        match lowercase.as_str() {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            "gzip" => Ok(Self::Gzip),
+            _ => Err(format!(
+                "invalid compression {:?}, only `none`, `zstd` and `gzip` are supported",
+                string,
+            )),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compression::Compression, writer::tests::SharedBuffer};
+    use std::{
+        io::{self, Read, Write},
+        path::Path,
+    };
+
+    const LOGS: &[u8] = b"some logs that are written to a file";
+
+    /// Compresses the logs and checks that they can be detected and decompressed
+    fn roundtrip(compression: Compression) {
+        let buffer = SharedBuffer::default();
+        let mut writer = compression.compress(buffer.clone()).unwrap();
+        writer.write_all(LOGS).unwrap();
+        drop(writer);
+
+        let compressed = buffer.contents();
+        if compression == Compression::None {
+            assert_eq!(compressed, LOGS);
+            assert_eq!(Compression::from_magic(&compressed), None);
+        } else {
+            assert_eq!(Compression::from_magic(&compressed), Some(compression));
+        }
+
+        // The file's magic bytes win over its extension
+        let mut reader = io::Cursor::new(compressed);
+        let detected = Compression::detect(&mut reader, Path::new("timely.worker-0.ddshow"));
+        assert_eq!(detected.unwrap(), compression);
+
+        let mut decompressed = Vec::new();
+        compression
+            .decompress(reader)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, LOGS);
+    }
+
+    #[test]
+    fn uncompressed_roundtrip() {
+        roundtrip(Compression::None);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_roundtrip() {
+        roundtrip(Compression::Zstd);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_roundtrip() {
+        roundtrip(Compression::Gzip);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_requires_its_feature() {
+        let err = Compression::Zstd.compress(io::sink()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn gzip_requires_its_feature() {
+        let err = Compression::Gzip.compress(io::sink()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn compression_from_path() {
+        let cases = [
+            ("timely.worker-0.ddshow", Some(Compression::None)),
+            ("timely.worker-0.ddshow.zst", Some(Compression::Zstd)),
+            ("timely.worker-0.ddshow.gz", Some(Compression::Gzip)),
+            ("timely.worker-0.zst", None),
+            ("ddshow", None),
+        ];
+
+        for &(path, compression) in cases.iter() {
+            assert_eq!(
+                Compression::from_path(Path::new(path)),
+                compression,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn compression_from_str() {
+        assert_eq!("ZSTD".parse(), Ok(Compression::Zstd));
+        assert_eq!(Compression::Gzip.to_string().parse(), Ok(Compression::Gzip));
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::{BackpressurePolicy, Compression, FlightRecorder, Multiplexer};
+use ddshow_types::OperatorAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::PathBuf,
+    time::Duration,
+};
+use timely::logging::{
+    StartStop, TimelyEvent as RawTimelyEvent, TimelyProgressEvent as RawTimelyProgressEvent,
+};
+
+/// Configures which events a logger saves and where it sends them
+///
+/// Logging every event can noticeably slow down the target program, so events
+/// can be dropped by their [`TimelyEventKind`], limited to the operators under
+/// a set of [`OperatorAddr`] prefixes and `Schedule` events can be sampled.
+/// Differential events are never filtered. Slow writers can also be moved off
+/// of the logging workers with [`SinkConfig::write_in_background()`]
+///
+/// The `install_*_logging()` functions send events over the config's transport,
+/// which is set with [`SinkConfig::save_to_disk()`], [`SinkConfig::stream_to()`],
+/// [`SinkConfig::multiplex_over()`] or [`SinkConfig::record_into()`]. The
+/// `enable_*_with_config()` functions write to a given writer instead
+///
+/// ## Examples
+///
+/// ```rust
+/// use ddshow_sink::{BackpressurePolicy, Compression, SinkConfig, TimelyEventKind};
+/// use std::{env, num::NonZeroUsize};
+///
+/// // Only capture the operators within the first dataflow, skipping
+/// // message and park events and keeping one in ten activations
+/// let config = SinkConfig::new()
+///     .drop_events(vec![TimelyEventKind::Messages, TimelyEventKind::Park])
+///     .capture_operators_under(vec![0])
+///     .sample_schedules(NonZeroUsize::new(10).unwrap());
+///
+/// // Save the events to log files, writing them from a background thread
+/// // and dropping the oldest queued events when the thread falls behind
+/// let config = config
+///     .save_to_disk(env::temp_dir().join("ddshow-logs"))
+///     .compression(Compression::None)
+///     .write_in_background(NonZeroUsize::new(1024).unwrap(), BackpressurePolicy::DropOldest);
+///
+/// timely::execute_directly(move |worker| {
+///     ddshow_sink::install_timely_logging(worker, &config).unwrap();
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    dropped_events: Vec<TimelyEventKind>,
+    addr_prefixes: Vec<Vec<usize>>,
+    schedule_sample_rate: NonZeroUsize,
+    compression: Compression,
+    background_writer: Option<(NonZeroUsize, BackpressurePolicy)>,
+    send_handshake: bool,
+    transport: Option<Transport>,
+}
+
+/// Where the `install_*_logging()` functions send events
+#[derive(Debug, Clone)]
+pub(crate) enum Transport {
+    /// Log files within the given directory
+    Disk(PathBuf),
+    /// A [`ReconnectingWriter`](crate::ReconnectingWriter) connected to the given address
+    Reconnecting(SocketAddr),
+    Multiplexed(Multiplexer),
+    Recorded(FlightRecorder),
+}
+
+impl SinkConfig {
+    /// Creates a config that saves every event without compression
+    pub fn new() -> Self {
+        Self {
+            dropped_events: Vec::new(),
+            addr_prefixes: Vec::new(),
+            schedule_sample_rate: NonZeroUsize::new(1).unwrap(),
+            compression: Compression::None,
+            background_writer: None,
+            send_handshake: true,
+            transport: None,
+        }
+    }
+
+    /// Drops all timely events of the given kind
+    pub fn drop_event(mut self, kind: TimelyEventKind) -> Self {
+        if !self.dropped_events.contains(&kind) {
+            self.dropped_events.push(kind);
+        }
+
+        self
+    }
+
+    /// Drops all timely events of the given kinds
+    pub fn drop_events<I>(self, kinds: I) -> Self
+    where
+        I: IntoIterator<Item = TimelyEventKind>,
+    {
+        kinds.into_iter().fold(self, Self::drop_event)
+    }
+
+    /// Only captures the operators whose address starts with the given prefix,
+    /// along with the channels and messages that touch them. Can be called
+    /// multiple times to capture the operators under multiple prefixes
+    ///
+    /// The scopes containing the prefix keep their `Operates` events so that
+    /// the captured operators still have somewhere to live in the graph
+    pub fn capture_operators_under<A>(mut self, prefix: A) -> Self
+    where
+        A: Into<OperatorAddr>,
+    {
+        let prefix: Vec<_> = prefix.into().iter().map(|id| id.into_inner()).collect();
+        if !self.addr_prefixes.contains(&prefix) {
+            self.addr_prefixes.push(prefix);
+        }
+
+        self
+    }
+
+    /// Only saves one in every `one_in` activations of each operator,
+    /// the start and stop of a saved activation are always saved together
+    ///
+    /// The sample rate is written at the start of the stream, and ddshow
+    /// flags the stream's activation counts and durations as undercounted
+    pub const fn sample_schedules(mut self, one_in: NonZeroUsize) -> Self {
+        self.schedule_sample_rate = one_in;
+        self
+    }
+
+    /// Saves events to a log file per worker and log stream within the given directory,
+    /// the files start with the worker's [`Handshake`](crate::Handshake) so that they
+    /// describe which worker and process they came from
+    pub fn save_to_disk<P>(mut self, directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.transport = Some(Transport::Disk(directory.into()));
+        self
+    }
+
+    /// Streams events to ddshow at the given address, reconnecting whenever the
+    /// connection drops. ddshow must be run with `--reconnect`, see
+    /// [`ReconnectingWriter`](crate::ReconnectingWriter) for how events are
+    /// handled while disconnected
+    pub fn stream_to(mut self, address: SocketAddr) -> Self {
+        self.transport = Some(Transport::Reconnecting(address));
+        self
+    }
+
+    /// Sends events to ddshow over a channel of the given [`Multiplexer`],
+    /// which every worker of the process can share
+    pub fn multiplex_over(mut self, multiplexer: &Multiplexer) -> Self {
+        self.transport = Some(Transport::Multiplexed(multiplexer.clone()));
+        self
+    }
+
+    /// Records events into the given [`FlightRecorder`], which only
+    /// writes them out when it's dumped
+    pub fn record_into(mut self, recorder: &FlightRecorder) -> Self {
+        self.transport = Some(Transport::Recorded(recorder.clone()));
+        self
+    }
+
+    /// Sets the compression of the log files written by [`SinkConfig::save_to_disk()`],
+    /// events sent over any other transport or writer aren't compressed
+    pub const fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Writes events from a background thread instead of from the logging workers,
+    /// queueing up to `capacity` batches of events before applying the given
+    /// [`BackpressurePolicy`], see [`AsyncEventWriter`](crate::AsyncEventWriter)
+    ///
+    /// Only applies to log files and to the writers given to `enable_*_with_config()`,
+    /// the other transports already send events from a background thread
+    pub const fn write_in_background(
+        mut self,
+        capacity: NonZeroUsize,
+        policy: BackpressurePolicy,
+    ) -> Self {
+        self.background_writer = Some((capacity, policy));
+        self
+    }
+
+    /// Doesn't start the writers given to the `enable_*_with_config()` functions
+    /// with the worker's [`Handshake`](crate::Handshake), for writers that have
+    /// already sent one like those from [`Handshake::connect()`](crate::Handshake::connect)
+    pub const fn skip_handshake(mut self) -> Self {
+        self.send_handshake = false;
+        self
+    }
+
+    pub(crate) const fn compression_codec(&self) -> Compression {
+        self.compression
+    }
+
+    pub(crate) const fn background_writer(&self) -> Option<(NonZeroUsize, BackpressurePolicy)> {
+        self.background_writer
+    }
+
+    pub(crate) const fn schedule_sample_rate(&self) -> NonZeroUsize {
+        self.schedule_sample_rate
+    }
+
+    pub(crate) const fn sends_handshake(&self) -> bool {
+        self.send_handshake
+    }
+
+    pub(crate) fn transport(&self) -> io::Result<&Transport> {
+        self.transport.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the sink config doesn't have a transport to send events over",
+            )
+        })
+    }
+
+    /// Returns `true` if the config drops any timely events
+    fn filters_timely_events(&self) -> bool {
+        !self.dropped_events.is_empty()
+            || !self.addr_prefixes.is_empty()
+            || self.schedule_sample_rate.get() != 1
+    }
+
+    /// Returns `true` if the given address is under one of the captured prefixes
+    fn captures(&self, addr: &[usize]) -> bool {
+        self.addr_prefixes.is_empty()
+            || self
+                .addr_prefixes
+                .iter()
+                .any(|prefix| addr.starts_with(prefix))
+    }
+
+    /// Returns `true` if the given address is a scope that contains one of the captured prefixes
+    fn contains_captured(&self, addr: &[usize]) -> bool {
+        self.addr_prefixes
+            .iter()
+            .any(|prefix| prefix.starts_with(addr))
+    }
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The kinds of timely events that a [`SinkConfig`] can drop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimelyEventKind {
+    Operates,
+    Channels,
+    PushProgress,
+    Messages,
+    Schedule,
+    Shutdown,
+    Application,
+    GuardedMessage,
+    GuardedProgress,
+    CommChannels,
+    Input,
+    Park,
+    Text,
+}
+
+impl TimelyEventKind {
+    /// Gets the kind of a timely event
+    pub const fn of(event: &RawTimelyEvent) -> Self {
+        match event {
+            RawTimelyEvent::Operates(_) => Self::Operates,
+            RawTimelyEvent::Channels(_) => Self::Channels,
+            RawTimelyEvent::PushProgress(_) => Self::PushProgress,
+            RawTimelyEvent::Messages(_) => Self::Messages,
+            RawTimelyEvent::Schedule(_) => Self::Schedule,
+            RawTimelyEvent::Shutdown(_) => Self::Shutdown,
+            RawTimelyEvent::Application(_) => Self::Application,
+            RawTimelyEvent::GuardedMessage(_) => Self::GuardedMessage,
+            RawTimelyEvent::GuardedProgress(_) => Self::GuardedProgress,
+            RawTimelyEvent::CommChannels(_) => Self::CommChannels,
+            RawTimelyEvent::Input(_) => Self::Input,
+            RawTimelyEvent::Park(_) => Self::Park,
+            RawTimelyEvent::Text(_) => Self::Text,
+        }
+    }
+}
+
+/// Applies a [`SinkConfig`] to the timely events of a single worker
+#[derive(Debug)]
+pub(crate) struct TimelyEventFilter {
+    config: SinkConfig,
+    /// The operators under the captured prefixes
+    captured_operators: HashSet<usize>,
+    /// The channels touching the captured operators
+    captured_channels: HashSet<usize>,
+    /// The number of activations each operator has started and
+    /// whether its current activation is being saved
+    activations: HashMap<usize, (usize, bool)>,
+}
+
+impl TimelyEventFilter {
+    pub(crate) fn new(config: &SinkConfig) -> Self {
+        Self {
+            config: config.clone(),
+            captured_operators: HashSet::new(),
+            captured_channels: HashSet::new(),
+            activations: HashMap::new(),
+        }
+    }
+
+    /// Removes all events that the config doesn't save
+    pub(crate) fn retain(&mut self, events: &mut Vec<(Duration, usize, RawTimelyEvent)>) {
+        if !self.config.filters_timely_events() {
+            return;
+        }
+
+        events.retain(|(_, _, event)| self.keep(event));
+    }
+
+    fn keep(&mut self, event: &RawTimelyEvent) -> bool {
+        if self
+            .config
+            .dropped_events
+            .contains(&TimelyEventKind::of(event))
+        {
+            return false;
+        }
+
+        let filter_addrs = !self.config.addr_prefixes.is_empty();
+        let captured =
+            |operator: &usize| !filter_addrs || self.captured_operators.contains(operator);
+
+        match event {
+            RawTimelyEvent::Operates(operator) => {
+                if self.config.captures(&operator.addr) {
+                    self.captured_operators.insert(operator.id);
+                    true
+                } else {
+                    self.config.contains_captured(&operator.addr)
+                }
+            }
+
+            RawTimelyEvent::Channels(channel) => {
+                // Port zero is the boundary of the channel's scope
+                let endpoint = |node: usize| {
+                    let mut addr = channel.scope_addr.clone();
+                    if node != 0 {
+                        addr.push(node);
+                    }
+
+                    addr
+                };
+
+                let keep = self.config.captures(&endpoint(channel.source.0))
+                    || self.config.captures(&endpoint(channel.target.0));
+                if keep {
+                    self.captured_channels.insert(channel.id);
+                }
+
+                keep
+            }
+
+            RawTimelyEvent::Messages(messages) => {
+                !filter_addrs || self.captured_channels.contains(&messages.channel)
+            }
+
+            RawTimelyEvent::PushProgress(progress) => captured(&progress.op_id),
+            RawTimelyEvent::Shutdown(shutdown) => captured(&shutdown.id),
+
+            RawTimelyEvent::Schedule(schedule) => {
+                if !captured(&schedule.id) {
+                    return false;
+                }
+
+                let sample_rate = self.config.schedule_sample_rate.get();
+                let (started, sampled) = self.activations.entry(schedule.id).or_default();
+
+                match schedule.start_stop {
+                    StartStop::Start => {
+                        *sampled = *started % sample_rate == 0;
+                        *started += 1;
+                    }
+                    StartStop::Stop => {}
+                }
+
+                *sampled
+            }
+
+            RawTimelyEvent::Application(_)
+            | RawTimelyEvent::GuardedMessage(_)
+            | RawTimelyEvent::GuardedProgress(_)
+            | RawTimelyEvent::CommChannels(_)
+            | RawTimelyEvent::Input(_)
+            | RawTimelyEvent::Park(_)
+            | RawTimelyEvent::Text(_) => true,
+        }
+    }
+}
+
+/// Applies a [`SinkConfig`] to the progress events of a single worker,
+/// events are kept if their scope is under or contains a captured prefix
+#[derive(Debug)]
+pub(crate) struct ProgressEventFilter {
+    config: SinkConfig,
+}
+
+impl ProgressEventFilter {
+    pub(crate) fn new(config: &SinkConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Removes all events that the config doesn't save
+    pub(crate) fn retain(&self, events: &mut Vec<(Duration, usize, RawTimelyProgressEvent)>) {
+        if self.config.addr_prefixes.is_empty() {
+            return;
+        }
+
+        events.retain(|(_, _, event)| {
+            self.config.captures(&event.addr) || self.config.contains_captured(&event.addr)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{SinkConfig, TimelyEventFilter, TimelyEventKind};
+    use std::{io, num::NonZeroUsize, time::Duration};
+    use timely::logging::{
+        ChannelsEvent, MessagesEvent, OperatesEvent, ParkEvent, ScheduleEvent,
+        TimelyEvent as RawTimelyEvent,
+    };
+
+    fn operates(id: usize, addr: &[usize]) -> RawTimelyEvent {
+        RawTimelyEvent::Operates(OperatesEvent {
+            id,
+            addr: addr.to_vec(),
+            name: format!("{:?}", addr),
+        })
+    }
+
+    fn channel(id: usize, scope_addr: &[usize], source: usize, target: usize) -> RawTimelyEvent {
+        RawTimelyEvent::Channels(ChannelsEvent {
+            id,
+            scope_addr: scope_addr.to_vec(),
+            source: (source, 0),
+            target: (target, 0),
+        })
+    }
+
+    fn message(channel: usize) -> RawTimelyEvent {
+        RawTimelyEvent::Messages(MessagesEvent {
+            is_send: true,
+            channel,
+            source: 0,
+            target: 0,
+            seq_no: 0,
+            length: 1,
+        })
+    }
+
+    /// Filters the events through the config, returning the ones it keeps
+    fn retain(config: &SinkConfig, events: Vec<RawTimelyEvent>) -> Vec<RawTimelyEvent> {
+        let mut events = events
+            .into_iter()
+            .map(|event| (Duration::from_secs(0), 0, event))
+            .collect();
+        TimelyEventFilter::new(config).retain(&mut events);
+
+        events.into_iter().map(|(_, _, event)| event).collect()
+    }
+
+    #[test]
+    fn unfiltered_configs_keep_everything() {
+        let events = vec![
+            operates(0, &[0]),
+            message(1),
+            RawTimelyEvent::Park(ParkEvent::park(None)),
+        ];
+
+        assert_eq!(retain(&SinkConfig::new(), events.clone()), events);
+    }
+
+    #[test]
+    fn dropped_event_kinds_are_removed() {
+        let config =
+            SinkConfig::new().drop_events(vec![TimelyEventKind::Messages, TimelyEventKind::Park]);
+        let events = vec![
+            operates(0, &[0]),
+            message(1),
+            RawTimelyEvent::Park(ParkEvent::park(None)),
+            RawTimelyEvent::Text("text".to_owned()),
+        ];
+
+        assert_eq!(
+            retain(&config, events),
+            vec![operates(0, &[0]), RawTimelyEvent::Text("text".to_owned())],
+        );
+    }
+
+    #[test]
+    fn only_operators_under_captured_prefixes_are_kept() {
+        let config = SinkConfig::new().capture_operators_under(vec![1, 1]);
+
+        // Two dataflows with an operator feeding into another within each of them
+        let events = vec![
+            operates(0, &[0]),
+            operates(1, &[0, 1]),
+            operates(2, &[0, 2]),
+            channel(3, &[0], 1, 2),
+            operates(4, &[1]),
+            operates(5, &[1, 1]),
+            operates(6, &[1, 2]),
+            channel(7, &[1], 1, 2),
+            message(3),
+            message(7),
+            RawTimelyEvent::Schedule(ScheduleEvent::start(2)),
+            RawTimelyEvent::Schedule(ScheduleEvent::start(5)),
+            RawTimelyEvent::Schedule(ScheduleEvent::start(6)),
+        ];
+
+        // The dataflow containing the captured operator is kept so it has somewhere to live,
+        // along with the channel and messages leaving the captured operator
+        assert_eq!(
+            retain(&config, events),
+            vec![
+                operates(4, &[1]),
+                operates(5, &[1, 1]),
+                channel(7, &[1], 1, 2),
+                message(7),
+                RawTimelyEvent::Schedule(ScheduleEvent::start(5)),
+            ],
+        );
+    }
+
+    #[test]
+    fn sampled_activations_keep_their_start_and_stop() {
+        let config = SinkConfig::new().sample_schedules(NonZeroUsize::new(4).unwrap());
+
+        let mut events = Vec::new();
+        for _ in 0..10 {
+            for &operator in &[0, 1] {
+                events.push(RawTimelyEvent::Schedule(ScheduleEvent::start(operator)));
+                events.push(RawTimelyEvent::Schedule(ScheduleEvent::stop(operator)));
+            }
+        }
+
+        // The first, fifth and ninth activations of each operator are kept
+        let sampled: Vec<_> = (0..3)
+            .flat_map(|_| {
+                vec![
+                    RawTimelyEvent::Schedule(ScheduleEvent::start(0)),
+                    RawTimelyEvent::Schedule(ScheduleEvent::stop(0)),
+                    RawTimelyEvent::Schedule(ScheduleEvent::start(1)),
+                    RawTimelyEvent::Schedule(ScheduleEvent::stop(1)),
+                ]
+            })
+            .collect();
+        assert_eq!(retain(&config, events), sampled);
+    }
+
+    #[test]
+    fn installing_requires_a_transport() {
+        let err = SinkConfig::new().transport().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        assert!(SinkConfig::new().save_to_disk("logs").transport().is_ok());
+    }
+}
@@ -0,0 +1,840 @@
+use crate::writer::{archive_dropped, EventWriter};
+use rkyv::{ser::serializers::AlignedSerializer, AlignedVec, Serialize};
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    convert::TryInto,
+    env,
+    fmt::{self, Display},
+    hash::{BuildHasher, Hash, Hasher},
+    io::{self, Read, Write},
+    mem,
+    net::{SocketAddr, TcpStream},
+    num::NonZeroUsize,
+    process,
+    sync::OnceLock,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use timely::{
+    communication::Allocate,
+    dataflow::operators::capture::{Event as RawEvent, EventPusher},
+    progress::ChangeBatch,
+    worker::Worker,
+};
+
+/// The magic bytes at the start of every handshake
+pub const HANDSHAKE_MAGIC: [u8; 8] = *b"DDSHOWHS";
+
+/// The current version of the handshake format
+pub const HANDSHAKE_VERSION: u32 = 2;
+
+/// The length of a handshake's fixed-size header, which is followed by the program name
+///
+/// ```text
+/// offset  size  field
+/// 0       8     magic bytes, see `HANDSHAKE_MAGIC`
+/// 8       4     handshake version, see `HANDSHAKE_VERSION`
+/// 12      1     stream encoding, 0 for rkyv and 1 for abomonation
+/// 13      1     stream kind, 0 for timely, 1 for differential and 2 for progress
+/// 14      2     length of the program name in bytes
+/// 16      4     worker index
+/// 20      4     total number of workers (peers)
+/// 24      8     session id
+/// 32      8     start time, seconds since the unix epoch
+/// 40      4     start time, subsecond nanoseconds
+/// 44      ..    program name, utf8
+/// ```
+///
+/// All integers are little endian
+pub const HANDSHAKE_HEADER_LEN: usize = 44;
+
+/// The default time between attempts to reconnect to ddshow
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The time to wait for ddshow to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most events that a [`ReconnectingWriter`] holds onto while it's connecting
+const MAX_CONNECTING_EVENTS: usize = 1024;
+
+/// The encoding of the events sent over a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StreamEncoding {
+    /// Events written by `ddshow-sink`
+    Rkyv,
+    /// Events written by timely and differential's builtin loggers
+    Abomonation,
+}
+
+impl StreamEncoding {
+    const fn to_byte(self) -> u8 {
+        match self {
+            Self::Rkyv => 0,
+            Self::Abomonation => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Self::Rkyv),
+            1 => Ok(Self::Abomonation),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the handshake has an unknown stream encoding {}", byte),
+            )),
+        }
+    }
+}
+
+impl Display for StreamEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rkyv => "rkyv",
+            Self::Abomonation => "abomonation",
+        })
+    }
+}
+
+/// The log stream carried by a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StreamKind {
+    /// Timely events, see [`TIMELY_LOGGER_NAME`](crate::TIMELY_LOGGER_NAME)
+    Timely,
+    /// Differential arrangement events,
+    /// see [`DIFFERENTIAL_ARRANGEMENT_LOGGER_NAME`](crate::DIFFERENTIAL_ARRANGEMENT_LOGGER_NAME)
+    Differential,
+    /// Timely progress events, see [`TIMELY_PROGRESS_LOGGER_NAME`](crate::TIMELY_PROGRESS_LOGGER_NAME)
+    Progress,
+}
+
+impl StreamKind {
+    const fn to_byte(self) -> u8 {
+        match self {
+            Self::Timely => 0,
+            Self::Differential => 1,
+            Self::Progress => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Self::Timely),
+            1 => Ok(Self::Differential),
+            2 => Ok(Self::Progress),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the handshake has an unknown stream kind {}", byte),
+            )),
+        }
+    }
+}
+
+impl Display for StreamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Timely => "timely",
+            Self::Differential => "differential",
+            Self::Progress => "progress",
+        })
+    }
+}
+
+/// Describes the events sent over a connection, sent before the event stream
+/// so that ddshow can work out the stream's encoding, how many workers to wait
+/// for and which log stream the events belong to
+///
+/// Every worker of a process shares the same session, so a reconnection from
+/// the same session continues where the old connection left off while a new
+/// session means that the target process was restarted
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Handshake {
+    /// The encoding of the events that follow the handshake
+    pub encoding: StreamEncoding,
+    /// The log stream that the events belong to
+    pub stream: StreamKind,
+    /// The index of the worker that's sending events
+    pub worker: usize,
+    /// The total number of workers in the target computation
+    pub peers: usize,
+    /// The session of the process that's sending events
+    pub session: u64,
+    /// The wall-clock time that the process started streaming its logs at
+    pub started_at: SystemTime,
+    /// The name of the program that's sending events
+    pub program: String,
+}
+
+impl Handshake {
+    /// Creates a handshake for a worker of the current process sending rkyv
+    /// encoded events, the program name defaults to the name of the current executable
+    pub fn new(stream: StreamKind, worker: usize, peers: usize) -> Self {
+        let process = process_info();
+
+        Self {
+            encoding: StreamEncoding::Rkyv,
+            stream,
+            worker,
+            peers,
+            session: process.session,
+            started_at: process.started_at,
+            program: process.program.clone(),
+        }
+    }
+
+    /// Creates a handshake for the given worker's log stream
+    pub fn for_worker<A>(worker: &Worker<A>, stream: StreamKind) -> Self
+    where
+        A: Allocate,
+    {
+        Self::new(stream, worker.index(), worker.peers())
+    }
+
+    /// Sets the encoding of the events sent after the handshake,
+    /// defaults to [`StreamEncoding::Rkyv`]
+    pub const fn encoding(mut self, encoding: StreamEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the name of the program that's sending events, names longer
+    /// than [`u16::MAX`] bytes are truncated
+    pub fn program<S>(mut self, program: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.program = program.into();
+        self
+    }
+
+    /// Encodes the handshake
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Truncate the program name to the longest name that fits without splitting a char
+        let mut program_len = self.program.len().min(u16::MAX as usize);
+        while !self.program.is_char_boundary(program_len) {
+            program_len -= 1;
+        }
+
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut bytes = Vec::with_capacity(HANDSHAKE_HEADER_LEN + program_len);
+        bytes.extend_from_slice(&HANDSHAKE_MAGIC);
+        bytes.extend_from_slice(&HANDSHAKE_VERSION.to_le_bytes());
+        bytes.push(self.encoding.to_byte());
+        bytes.push(self.stream.to_byte());
+        bytes.extend_from_slice(&(program_len as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.worker as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.peers as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.session.to_le_bytes());
+        bytes.extend_from_slice(&started_at.as_secs().to_le_bytes());
+        bytes.extend_from_slice(&started_at.subsec_nanos().to_le_bytes());
+        bytes.extend_from_slice(&self.program.as_bytes()[..program_len]);
+
+        bytes
+    }
+
+    /// Gets the full length of a handshake from its header, returning an error if the
+    /// bytes aren't a handshake or were written by an unsupported version of `ddshow-sink`
+    pub fn encoded_len(header: &[u8; HANDSHAKE_HEADER_LEN]) -> io::Result<usize> {
+        if header[..8] != HANDSHAKE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the connection didn't start with a ddshow handshake",
+            ));
+        }
+
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != HANDSHAKE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported handshake version {}, expected version {}",
+                    version, HANDSHAKE_VERSION,
+                ),
+            ));
+        }
+
+        let program_len = u16::from_le_bytes(header[14..16].try_into().unwrap());
+        Ok(HANDSHAKE_HEADER_LEN + program_len as usize)
+    }
+
+    /// Decodes a handshake, returning an error if the bytes aren't a handshake
+    /// or were written by an unsupported version of `ddshow-sink`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let header: &[u8; HANDSHAKE_HEADER_LEN] = bytes
+            .get(..HANDSHAKE_HEADER_LEN)
+            .and_then(|header| header.try_into().ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "the handshake was cut short")
+            })?;
+
+        let len = Self::encoded_len(header)?;
+        if bytes.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the handshake should be {} bytes long but was {} bytes",
+                    len,
+                    bytes.len(),
+                ),
+            ));
+        }
+
+        let started_at = Duration::new(
+            u64::from_le_bytes(bytes[32..40].try_into().unwrap()),
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+        );
+
+        Ok(Self {
+            encoding: StreamEncoding::from_byte(bytes[12])?,
+            stream: StreamKind::from_byte(bytes[13])?,
+            worker: u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize,
+            peers: u32::from_le_bytes(bytes[20..24].try_into().unwrap()) as usize,
+            session: u64::from_le_bytes(bytes[24..32].try_into().unwrap()),
+            started_at: UNIX_EPOCH + started_at,
+            program: String::from_utf8_lossy(&bytes[HANDSHAKE_HEADER_LEN..]).into_owned(),
+        })
+    }
+
+    /// Reads a handshake from the given reader
+    pub fn read_from<R>(reader: &mut R) -> io::Result<Self>
+    where
+        R: Read,
+    {
+        let mut header = [0; HANDSHAKE_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+
+        let mut bytes = vec![0; Self::encoded_len(&header)?];
+        bytes[..HANDSHAKE_HEADER_LEN].copy_from_slice(&header);
+        reader.read_exact(&mut bytes[HANDSHAKE_HEADER_LEN..])?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Writes the handshake to the given writer
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Connects to ddshow at the given address and sends the handshake,
+    /// returning a stream that the handshake's events can be written to
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use ddshow_sink::{Handshake, SinkConfig, StreamKind};
+    /// use std::{env, net::SocketAddr};
+    ///
+    /// timely::execute_directly(|worker| {
+    ///     if let Ok(addr) = env::var("TIMELY_WORKER_LOG_ADDR") {
+    ///         let addr: SocketAddr = addr.parse().unwrap();
+    ///         let handshake = Handshake::for_worker(worker, StreamKind::Timely).program("my-service");
+    ///
+    ///         // The handshake was already sent, so the logger mustn't send another
+    ///         if let Ok(stream) = handshake.connect(addr) {
+    ///             let config = SinkConfig::new().skip_handshake();
+    ///             ddshow_sink::enable_timely_logging_with_config(worker, stream, &config);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn connect(&self, address: SocketAddr) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        self.write_to(&mut stream)?;
+
+        Ok(stream)
+    }
+}
+
+/// Sends a [`Handshake`] ahead of the first bytes written to or flushed through the
+/// inner writer, so that background writers send it from their own thread
+#[derive(Debug)]
+pub(crate) struct HandshakeWriter<W> {
+    handshake: Vec<u8>,
+    writer: W,
+}
+
+impl<W> HandshakeWriter<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(handshake: Option<&Handshake>, writer: W) -> Self {
+        Self {
+            handshake: handshake.map(Handshake::to_bytes).unwrap_or_default(),
+            writer,
+        }
+    }
+
+    /// Sends the handshake if it hasn't been sent yet, it's only attempted once
+    fn send_handshake(&mut self) -> io::Result<()> {
+        if self.handshake.is_empty() {
+            return Ok(());
+        }
+
+        let handshake = mem::take(&mut self.handshake);
+        self.writer.write_all(&handshake)
+    }
+}
+
+impl<W> Write for HandshakeWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_handshake()?;
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_handshake()?;
+        self.writer.flush()
+    }
+}
+
+/// Information about the current process shared by all of its handshakes
+#[derive(Debug)]
+struct ProcessInfo {
+    session: u64,
+    started_at: SystemTime,
+    program: String,
+}
+
+/// Gets the session id, start time and program name of the current process
+fn process_info() -> &'static ProcessInfo {
+    static PROCESS: OnceLock<ProcessInfo> = OnceLock::new();
+
+    PROCESS.get_or_init(|| {
+        let started_at = SystemTime::now();
+
+        let mut hasher = RandomState::new().build_hasher();
+        process::id().hash(&mut hasher);
+        started_at.hash(&mut hasher);
+
+        let program = env::current_exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        ProcessInfo {
+            session: hasher.finish(),
+            started_at,
+            program,
+        }
+    })
+}
+
+/// An event pusher that streams a worker's events to ddshow over tcp,
+/// reconnecting whenever the connection is lost
+///
+/// Every connection starts with a [`Handshake`] so that ddshow can stitch the
+/// worker's connections back into a single stream, which requires running
+/// ddshow with `--reconnect`. Messages pushed while disconnected are dropped
+/// and reported to ddshow once the writer reconnects, progress updates are
+/// re-sent so that the reconnected stream's frontier stays consistent
+///
+/// Connections are made on a background thread so that the worker never waits
+/// on ddshow. Up to 1024 events pushed while connecting are held onto and sent
+/// once the connection is made, they're dropped if the connection fails or if
+/// more events are pushed before it's made
+#[derive(Debug)]
+pub struct ReconnectingWriter<D> {
+    address: SocketAddr,
+    handshake: Handshake,
+    writer: Option<EventWriter<Duration, D, TcpStream>>,
+    retry_interval: Duration,
+    last_attempt: Option<Instant>,
+    /// The connection that's currently being made, if any
+    connecting: Option<JoinHandle<io::Result<TcpStream>>>,
+    /// Events pushed while connecting, oldest first
+    pending: VecDeque<RawEvent<Duration, D>>,
+    /// The number of messages written so far, dropped messages aren't
+    /// counted since they're reported to ddshow as dropped
+    sequence: u64,
+    /// The capabilities held by the stream, including the initial one
+    capabilities: ChangeBatch<Duration>,
+    /// The time of the last dropped batch and the number of events dropped
+    /// since the writer was last connected
+    dropped: Option<(Duration, u64)>,
+    /// Written at the start of every connection, see [`EventWriter::sampled_schedules()`]
+    schedule_sample_rate: NonZeroUsize,
+}
+
+impl<D> ReconnectingWriter<D> {
+    /// Creates a writer that streams events to the given address, starting every
+    /// connection with the given handshake. The writer connects the first time an
+    /// event is pushed to it
+    pub fn new(address: SocketAddr, handshake: Handshake) -> Self {
+        Self {
+            address,
+            handshake,
+            writer: None,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            last_attempt: None,
+            connecting: None,
+            pending: VecDeque::new(),
+            sequence: 0,
+            capabilities: ChangeBatch::new_from(Duration::default(), 1),
+            dropped: None,
+            schedule_sample_rate: NonZeroUsize::new(1).unwrap(),
+        }
+    }
+
+    /// Sets the time between attempts to reconnect,
+    /// defaults to [`DEFAULT_RETRY_INTERVAL`]
+    pub const fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Marks every connection's stream as only holding one in every `one_in` of each operator's
+    /// activations, see [`SinkConfig::sample_schedules()`](crate::SinkConfig::sample_schedules)
+    pub(crate) const fn sampling_schedules(mut self, one_in: NonZeroUsize) -> Self {
+        self.schedule_sample_rate = one_in;
+        self
+    }
+
+    /// Returns `true` if the writer is currently connected to ddshow
+    pub const fn is_connected(&self) -> bool {
+        self.writer.is_some()
+    }
+}
+
+impl<D> ReconnectingWriter<D>
+where
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    /// Starts connecting to ddshow if the writer isn't connected and enough
+    /// time has passed since the last attempt, taking over the connection
+    /// once it's been made
+    fn connect(&mut self) {
+        if self.writer.is_some() {
+            return;
+        }
+
+        match self.connecting.take() {
+            Some(connecting) if connecting.is_finished() => {
+                let result = connecting.join().unwrap_or_else(|_| {
+                    Err(io::Error::other(
+                        "the ddshow-sink reconnecting thread panicked",
+                    ))
+                });
+
+                self.finish_connecting(result);
+            }
+
+            // Still connecting
+            Some(connecting) => self.connecting = Some(connecting),
+
+            None => {
+                let retry = self
+                    .last_attempt
+                    .is_none_or(|attempt| attempt.elapsed() >= self.retry_interval);
+
+                if retry {
+                    self.last_attempt = Some(Instant::now());
+                    self.start_connecting();
+                }
+            }
+        }
+    }
+
+    /// Connects to ddshow and sends the handshake on a background thread
+    fn start_connecting(&mut self) {
+        let (address, handshake) = (self.address, self.handshake.clone());
+
+        let connecting = thread::Builder::new()
+            .name("ddshow-sink-reconnect".to_owned())
+            .spawn(move || {
+                let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+                stream.set_nodelay(true)?;
+                handshake.write_to(&mut stream)?;
+
+                Ok(stream)
+            });
+
+        match connecting {
+            Ok(connecting) => self.connecting = Some(connecting),
+
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                tracing_dep::error!(
+                    "failed to spawn the ddshow-sink reconnecting thread: {:?}",
+                    _err,
+                );
+            }
+        }
+    }
+
+    /// Takes over the connection made by the background thread
+    fn finish_connecting(&mut self, stream: io::Result<TcpStream>) {
+        match stream.and_then(|stream| self.open(stream)) {
+            Ok(writer) => {
+                #[cfg(feature = "tracing")]
+                tracing_dep::info!(
+                    address = %self.address,
+                    worker = self.handshake.worker,
+                    "connected to ddshow at {}",
+                    self.address,
+                );
+
+                self.writer = Some(writer);
+                self.dropped = None;
+            }
+
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                tracing_dep::debug!(
+                    address = %self.address,
+                    worker = self.handshake.worker,
+                    "failed to connect to ddshow at {}: {:?}",
+                    self.address,
+                    _err,
+                );
+            }
+        }
+
+        // Sends the pending events if the connection was made, otherwise they're dropped
+        for event in mem::take(&mut self.pending) {
+            self.write(event);
+        }
+    }
+
+    /// Catches a new connection up on the stream's
+    /// current frontier and any dropped events
+    fn open(&self, stream: TcpStream) -> io::Result<EventWriter<Duration, D, TcpStream>> {
+        let mut writer = EventWriter::new(stream)
+            .starting_at(self.sequence)
+            .sampled_schedules(self.schedule_sample_rate);
+
+        // Every stream starts out holding the default capability, so
+        // move it to wherever the stream's capabilities are now
+        let mut capabilities = self.capabilities.clone();
+        capabilities.update(Duration::default(), -1);
+        if !capabilities.is_empty() {
+            writer.try_push(RawEvent::Progress(capabilities.into_inner()))?;
+        }
+
+        if let Some((time, events)) = self.dropped {
+            let mut archive = AlignedVec::new();
+            archive_dropped::<Duration, D>(time, events, &mut archive);
+            writer.write_archive(&archive)?;
+        }
+
+        Ok(writer)
+    }
+
+    /// Writes an event if the writer is connected, keeping track of
+    /// the events that are lost if it isn't
+    fn write(&mut self, event: RawEvent<Duration, D>) {
+        let (messages, progress) = match &event {
+            RawEvent::Messages(time, data) => (Some((*time, data.len() as u64)), None),
+            RawEvent::Progress(updates) => (None, Some(updates.clone())),
+        };
+
+        let pushed = match self.writer.as_mut() {
+            Some(writer) => match writer.try_push(event) {
+                Ok(()) => true,
+
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing_dep::warn!(
+                        address = %self.address,
+                        worker = self.handshake.worker,
+                        "lost the connection to ddshow at {}: {:?}",
+                        self.address,
+                        _err,
+                    );
+
+                    self.writer = None;
+                    false
+                }
+            },
+
+            None => false,
+        };
+
+        // Progress updates are re-sent when reconnecting, so only messages are lost
+        if let Some((time, events)) = messages {
+            if pushed {
+                self.sequence += events;
+            } else {
+                self.drop_events(time, events);
+            }
+        }
+
+        if let Some(updates) = progress {
+            self.capabilities.extend(updates.into_iter());
+        }
+    }
+
+    fn drop_events(&mut self, time: Duration, events: u64) {
+        let dropped = self.dropped.take().map_or(0, |(_, dropped)| dropped);
+        self.dropped = Some((time, dropped + events));
+    }
+}
+
+impl<D> EventPusher<Duration, D> for ReconnectingWriter<D>
+where
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    fn push(&mut self, event: RawEvent<Duration, D>) {
+        self.connect();
+
+        // Events have to be written in order, so once too many events are
+        // pending they're dropped to make room for the ones that follow
+        if self.connecting.is_some() && self.pending.len() < MAX_CONNECTING_EVENTS {
+            self.pending.push_back(event);
+        } else {
+            for event in mem::take(&mut self.pending) {
+                self.write(event);
+            }
+
+            self.write(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{
+            Handshake, HandshakeWriter, ReconnectingWriter, StreamEncoding, StreamKind,
+            HANDSHAKE_HEADER_LEN,
+        },
+        writer::tests::{read_events, SharedBuffer},
+    };
+    use ddshow_types::Event;
+    use std::{
+        convert::TryInto,
+        io::{self, Read, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+    use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+    #[test]
+    fn handshake_roundtrip() {
+        let handshake = Handshake {
+            session: 42,
+            ..Handshake::new(StreamKind::Progress, 3, 4)
+                .encoding(StreamEncoding::Abomonation)
+                .program("target")
+        };
+
+        let bytes = handshake.to_bytes();
+        assert_eq!(bytes.len(), HANDSHAKE_HEADER_LEN + "target".len());
+
+        let header = bytes[..HANDSHAKE_HEADER_LEN].try_into().unwrap();
+        assert_eq!(Handshake::encoded_len(header).unwrap(), bytes.len());
+        assert_eq!(Handshake::from_bytes(&bytes).unwrap(), handshake);
+    }
+
+    #[test]
+    fn long_program_names_are_truncated_between_chars() {
+        // Every char is two bytes long and the longest name that fits is an odd length
+        let handshake =
+            Handshake::new(StreamKind::Timely, 0, 1).program("é".repeat(u16::MAX as usize));
+        let decoded = Handshake::from_bytes(&handshake.to_bytes()).unwrap();
+
+        assert_eq!(decoded.program.len(), u16::MAX as usize - 1);
+        assert!(decoded.program.chars().all(|char| char == 'é'));
+    }
+
+    #[test]
+    fn invalid_handshakes_are_rejected() {
+        let bytes = Handshake::new(StreamKind::Timely, 0, 1).to_bytes();
+
+        let err = Handshake::from_bytes(&bytes[..HANDSHAKE_HEADER_LEN - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let err = Handshake::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let err = Handshake::from_bytes(&wrong_magic).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut newer_version = bytes;
+        newer_version[8..12].copy_from_slice(&99u32.to_le_bytes());
+        let err = Handshake::from_bytes(&newer_version).unwrap_err();
+        assert!(
+            err.to_string().contains("unsupported handshake version 99"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn handshake_writer_sends_its_handshake_once() {
+        let handshake = Handshake::new(StreamKind::Differential, 1, 2);
+        let buffer = SharedBuffer::default();
+        let mut writer = HandshakeWriter::new(Some(&handshake), buffer.clone());
+
+        // Nothing is sent until the writer is used
+        assert!(buffer.contents().is_empty());
+
+        writer.flush().unwrap();
+        writer.write_all(b"events").unwrap();
+        writer.flush().unwrap();
+
+        let mut expected = handshake.to_bytes();
+        expected.extend_from_slice(b"events");
+        assert_eq!(buffer.contents(), expected);
+
+        // Writers without a handshake only send what's written to them
+        let buffer = SharedBuffer::default();
+        let mut writer = HandshakeWriter::new(None, buffer.clone());
+        writer.write_all(b"events").unwrap();
+        assert_eq!(buffer.contents(), b"events");
+    }
+
+    #[test]
+    fn reconnecting_writer_keeps_events_logged_while_connecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let handshake = Handshake::new(StreamKind::Timely, 0, 1);
+        let mut writer = ReconnectingWriter::new(listener.local_addr().unwrap(), handshake.clone());
+
+        // The connection is made in the background, events pushed
+        // in the meantime are sent once it's been made
+        let mut pushed = Vec::new();
+        while !writer.is_connected() {
+            let time = pushed.len() as u64;
+            writer.push(RawEvent::Messages(Duration::from_millis(time), vec![time]));
+            pushed.push(time);
+
+            assert!(pushed.len() < 1000, "the writer never connected");
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(writer);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+
+        let mut received = &received[..];
+        assert_eq!(Handshake::read_from(&mut received).unwrap(), handshake);
+
+        let events = read_events::<u64>(received);
+        assert_eq!(events[0], Event::Sequence(0));
+
+        let written: Vec<_> = events[1..]
+            .iter()
+            .flat_map(|event| match event {
+                Event::Messages(_, data) => data.clone(),
+                event => panic!("expected only messages, got {:?}", event),
+            })
+            .collect();
+        assert_eq!(written, pushed);
+    }
+}
@@ -0,0 +1,473 @@
+use crate::{
+    connection::Handshake,
+    writer::{archive_event, archive_sampled_schedules, EventWriter},
+};
+use rkyv::{ser::serializers::AlignedSerializer, AlignedVec, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter},
+    marker::PhantomData,
+    num::NonZeroUsize,
+    panic,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+/// The default number of bytes kept for each worker's event stream
+pub const DEFAULT_RECORDER_BYTES: usize = 64 * 1024 * 1024;
+
+/// Keeps the most recent events of every worker in memory and only
+/// writes them out when asked to
+///
+/// Events are kept until they're older than the recorder's max age or until
+/// the events of a worker's stream take up more than its max bytes, both
+/// limits apply to each worker's timely, differential and progress streams
+/// separately. Timely `Operates` and `Channels` events are always kept so that
+/// the dumped events still describe the whole dataflow graph, they don't count
+/// towards the max bytes and grow with the size of the dataflow graph, so
+/// programs that keep building new dataflows will keep growing the recorder
+///
+/// Dumps are written to files named the same as the ones written by the
+/// `save_*_to_disk()` functions, so they can be replayed with `--replay-logs`.
+/// Workers record into a recorder with the `record_*()` functions or with a
+/// [`SinkConfig`](crate::SinkConfig) set up by [`SinkConfig::record_into()`](crate::SinkConfig::record_into)
+///
+/// ## Examples
+///
+/// ```rust
+/// use ddshow_sink::FlightRecorder;
+/// use std::{env, time::Duration};
+/// use timely::dataflow::operators::{Inspect, ToStream};
+///
+/// let recorder = FlightRecorder::new(env::temp_dir().join("ddshow-incident"))
+///     .max_age(Duration::from_secs(30));
+///
+/// // Dump the recorded events if the program panics
+/// recorder.dump_on_panic();
+///
+/// let worker_recorder = recorder.clone();
+/// timely::execute_directly(move |worker| {
+///     ddshow_sink::record_timely_logs(worker, &worker_recorder).unwrap();
+///
+///     worker.dataflow::<(),_,_>(|scope| {
+///         (0..10).to_stream(scope)
+///             .inspect(|x| println!("seen: {:?}", x));
+///     });
+/// });
+///
+/// // Dump the recorded events on demand
+/// recorder.dump().unwrap();
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct FlightRecorder {
+    directory: PathBuf,
+    max_age: Option<Duration>,
+    max_bytes: usize,
+    streams: Arc<Mutex<Vec<RecordedStream>>>,
+}
+
+impl FlightRecorder {
+    /// Creates a flight recorder that dumps its events into the given directory,
+    /// keeping up to [`DEFAULT_RECORDER_BYTES`] of each stream's events
+    pub fn new<P>(directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            directory: directory.into(),
+            max_age: None,
+            max_bytes: DEFAULT_RECORDER_BYTES,
+            streams: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Drops events once they're older than the given age, where an event's
+    /// age is measured from the newest event of the same stream
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Drops the oldest events of a stream once it takes up more than the given
+    /// number of bytes, the newest batch of events is always kept
+    ///
+    /// Pinned dataflow graph events are never dropped and aren't counted
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Writes the recorded events to the recorder's directory,
+    /// overwriting the files of any previous dump
+    pub fn dump(&self) -> io::Result<()> {
+        self.dump_to(&self.directory)
+    }
+
+    /// Writes the recorded events to the given directory,
+    /// overwriting the files of any previous dump
+    pub fn dump_to<P>(&self, directory: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let directory = directory.as_ref();
+
+        #[cfg(feature = "tracing")]
+        tracing_dep::info!(
+            directory = ?directory,
+            "dumping the flight recorder to {}",
+            directory.display(),
+        );
+
+        fs::create_dir_all(directory)?;
+        for stream in lock(&self.streams).iter() {
+            let path = directory.join(format!(
+                "{}.worker-{}.ddshow",
+                stream.file_prefix, stream.handshake.worker,
+            ));
+
+            let mut file = BufWriter::new(File::create(path)?);
+            stream.handshake.write_to(&mut file)?;
+
+            // The events are already archived, so the writer's event types are irrelevant
+            let mut writer: EventWriter<(), (), _> = EventWriter::new(file);
+            lock(&stream.buffer).write_to(&mut writer)?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Dumps the recorded events whenever any thread panics,
+    /// the previous panic hook is called after the dump
+    pub fn dump_on_panic(&self) {
+        let recorder = self.clone();
+        let previous_hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if let Err(_err) = recorder.dump() {
+                #[cfg(feature = "tracing")]
+                tracing_dep::error!("failed to dump the flight recorder on panic: {:?}", _err);
+            }
+
+            previous_hook(info);
+        }));
+    }
+
+    /// Dumps the recorded events every time the process receives the given
+    /// signal, the dumps are written from a background thread
+    #[cfg(all(feature = "signals", unix))]
+    pub fn dump_on_signal(&self, signal: std::os::raw::c_int) -> io::Result<()> {
+        use signal_hook::iterator::Signals;
+        use std::thread;
+
+        let mut signals = Signals::new([signal])?;
+        let recorder = self.clone();
+
+        thread::Builder::new()
+            .name("ddshow-flight-recorder".to_owned())
+            .spawn(move || {
+                for _signal in signals.forever() {
+                    if let Err(_err) = recorder.dump() {
+                        #[cfg(feature = "tracing")]
+                        tracing_dep::error!(
+                            signal = _signal,
+                            "failed to dump the flight recorder on signal {}: {:?}",
+                            _signal,
+                            _err,
+                        );
+                    }
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Creates the event pusher that records the event stream described by the
+    /// given handshake, `pinned` selects the events that are never dropped
+    pub(crate) fn recorder<D>(
+        &self,
+        file_prefix: &'static str,
+        handshake: Handshake,
+        pinned: fn(&D) -> bool,
+    ) -> RecordingPusher<D>
+    where
+        D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    {
+        // Every batch is recorded at a time greater than or equal to the default
+        // time, so holding onto the default capability until the end of the dump
+        // keeps the dumped stream's progress consistent
+        let mut closing = AlignedVec::new();
+        archive_event(
+            RawEvent::<Duration, D>::Progress(vec![(Duration::default(), -1)]),
+            &mut closing,
+        );
+
+        let buffer = Arc::new(Mutex::new(RingBuffer {
+            max_age: self.max_age,
+            max_bytes: self.max_bytes,
+            pinned: Vec::new(),
+            batches: VecDeque::new(),
+            bytes: 0,
+            closing,
+        }));
+
+        lock(&self.streams).push(RecordedStream {
+            file_prefix,
+            handshake,
+            buffer: buffer.clone(),
+        });
+
+        RecordingPusher {
+            buffer,
+            pinned,
+            __type: PhantomData,
+        }
+    }
+}
+
+/// Locks a mutex, ignoring any poisoning since dumps are
+/// most useful right after something has panicked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The recorded events of a single worker's event stream
+#[derive(Debug)]
+struct RecordedStream {
+    file_prefix: &'static str,
+    /// Written at the start of the stream's dumps
+    handshake: Handshake,
+    buffer: Arc<Mutex<RingBuffer>>,
+}
+
+/// Archived batches of events, oldest first
+#[derive(Debug)]
+struct RingBuffer {
+    max_age: Option<Duration>,
+    max_bytes: usize,
+    /// Events that are never dropped, which aren't counted towards `bytes`
+    pinned: Vec<AlignedVec>,
+    /// The time of each batch along with the batch itself
+    batches: VecDeque<(Duration, AlignedVec)>,
+    /// The total size of all of the batches
+    bytes: usize,
+    /// The progress update that closes the dumped stream
+    closing: AlignedVec,
+}
+
+impl RingBuffer {
+    fn push(&mut self, time: Duration, batch: AlignedVec) {
+        self.bytes += batch.len();
+        self.batches.push_back((time, batch));
+
+        while self.batches.len() > 1 {
+            let oldest = self.batches[0].0;
+            let expired = self.max_age.is_some_and(|max_age| oldest + max_age < time);
+
+            if self.bytes > self.max_bytes || expired {
+                if let Some((_, batch)) = self.batches.pop_front() {
+                    self.bytes -= batch.len();
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Writes the recorded events, followed by the progress
+    /// update that closes the stream
+    fn write_to<W>(&self, writer: &mut EventWriter<(), (), W>) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let batches = self.batches.iter().map(|(_, batch)| batch);
+        for archive in self.pinned.iter().chain(batches) {
+            writer.write_archive(archive)?;
+        }
+
+        writer.write_archive(&self.closing)
+    }
+}
+
+/// Records the events of a single worker's stream into a [`FlightRecorder`]
+#[derive(Debug)]
+pub struct RecordingPusher<D> {
+    buffer: Arc<Mutex<RingBuffer>>,
+    pinned: fn(&D) -> bool,
+    __type: PhantomData<D>,
+}
+
+impl<D> RecordingPusher<D> {
+    /// Marks the recorded stream as only holding one in every `one_in` of each
+    /// operator's activations, the mark is pinned so that every dump starts with it
+    pub(crate) fn sampling_schedules(self, one_in: NonZeroUsize) -> Self
+    where
+        D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    {
+        if one_in.get() != 1 {
+            let mut archive = AlignedVec::new();
+            archive_sampled_schedules::<Duration, D>(one_in, &mut archive);
+            lock(&self.buffer).pinned.insert(0, archive);
+        }
+
+        self
+    }
+}
+
+impl<D> EventPusher<Duration, D> for RecordingPusher<D>
+where
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    fn push(&mut self, event: RawEvent<Duration, D>) {
+        // Progress updates are recreated when the events are dumped
+        if let RawEvent::Messages(time, data) = event {
+            let (pinned, data): (Vec<_>, Vec<_>) =
+                data.into_iter().partition(|data| (self.pinned)(data));
+
+            let archive = |data| {
+                let mut archive = AlignedVec::new();
+                archive_event(RawEvent::Messages(time, data), &mut archive);
+                archive
+            };
+
+            // Archive the events before locking the buffer, panicking while the
+            // buffer is locked would deadlock the dump made by `dump_on_panic()`
+            let pinned = (!pinned.is_empty()).then(|| archive(pinned));
+            let data = (!data.is_empty()).then(|| archive(data));
+
+            let mut buffer = lock(&self.buffer);
+            if let Some(pinned) = pinned {
+                buffer.pinned.push(pinned);
+            }
+
+            if let Some(data) = data {
+                buffer.push(time, data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{Handshake, StreamKind},
+        flight_recorder::{FlightRecorder, RecordingPusher},
+        writer::tests::read_events,
+    };
+    use ddshow_types::Event;
+    use std::{
+        env, fs,
+        num::NonZeroUsize,
+        path::{Path, PathBuf},
+        process,
+        time::Duration,
+    };
+    use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+    fn dump_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ddshow-sink-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    /// Records a stream where zeroes stand in for the pinned dataflow graph events
+    fn record(recorder: &FlightRecorder) -> RecordingPusher<u64> {
+        let handshake = Handshake::new(StreamKind::Timely, 0, 1);
+        recorder.recorder("timely", handshake, |&data| data == 0)
+    }
+
+    fn messages(millis: u64, data: Vec<u64>) -> RawEvent<Duration, u64> {
+        RawEvent::Messages(Duration::from_millis(millis), data)
+    }
+
+    /// Dumps the recorder, returning the handshake and events of its single stream
+    fn dump(recorder: &FlightRecorder, dir: &Path) -> (Handshake, Vec<Event<Duration, u64>>) {
+        recorder.dump().unwrap();
+
+        let contents = fs::read(dir.join("timely.worker-0.ddshow")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        let mut contents = &contents[..];
+        let handshake = Handshake::read_from(&mut contents).unwrap();
+
+        (handshake, read_events(contents))
+    }
+
+    /// The progress update that closes every dumped stream
+    fn closing() -> Event<Duration, u64> {
+        Event::Progress(vec![(Duration::default(), -1)])
+    }
+
+    #[test]
+    fn dumps_pinned_events_and_the_newest_batch() {
+        let dir = dump_dir("recorder-bytes");
+        let recorder = FlightRecorder::new(&dir).max_bytes(0);
+        let mut pusher = record(&recorder);
+
+        pusher.push(messages(1, vec![0, 1]));
+        pusher.push(messages(2, vec![2]));
+        pusher.push(RawEvent::Progress(vec![(Duration::from_millis(2), 1)]));
+        pusher.push(messages(3, vec![3, 0]));
+
+        // Nothing is written until the recorder is dumped
+        assert!(!dir.exists());
+
+        let (handshake, events) = dump(&recorder, &dir);
+        assert_eq!(handshake, Handshake::new(StreamKind::Timely, 0, 1));
+        assert_eq!(
+            events,
+            vec![
+                Event::Messages(Duration::from_millis(1), vec![0]),
+                Event::Messages(Duration::from_millis(3), vec![0]),
+                Event::Messages(Duration::from_millis(3), vec![3]),
+                closing(),
+            ],
+        );
+    }
+
+    #[test]
+    fn old_batches_expire() {
+        let dir = dump_dir("recorder-age");
+        let recorder = FlightRecorder::new(&dir).max_age(Duration::from_millis(10));
+        let mut pusher = record(&recorder);
+
+        for &millis in &[1, 5, 20, 25] {
+            pusher.push(messages(millis, vec![millis]));
+        }
+
+        let (_, events) = dump(&recorder, &dir);
+        assert_eq!(
+            events,
+            vec![
+                Event::Messages(Duration::from_millis(20), vec![20]),
+                Event::Messages(Duration::from_millis(25), vec![25]),
+                closing(),
+            ],
+        );
+    }
+
+    #[test]
+    fn sampled_dumps_start_with_their_sample_rate() {
+        let dir = dump_dir("recorder-sampled");
+        let recorder = FlightRecorder::new(&dir);
+        let mut pusher = record(&recorder).sampling_schedules(NonZeroUsize::new(4).unwrap());
+
+        pusher.push(messages(1, vec![0, 1]));
+
+        let (_, events) = dump(&recorder, &dir);
+        assert_eq!(
+            events,
+            vec![
+                Event::SampledSchedules(4),
+                Event::Messages(Duration::from_millis(1), vec![0]),
+                Event::Messages(Duration::from_millis(1), vec![1]),
+                closing(),
+            ],
+        );
+    }
+}
@@ -1,9 +1,33 @@
+mod async_writer;
 mod batch_logger;
+mod compression;
+mod config;
+mod connection;
+mod flight_recorder;
+mod multiplex;
 mod writer;
 
+pub use async_writer::{AsyncEventWriter, BackpressurePolicy};
 pub use batch_logger::BatchLogger;
-pub use writer::EventWriter;
+pub use compression::Compression;
+pub use config::{SinkConfig, TimelyEventKind};
+pub use connection::{
+    Handshake, ReconnectingWriter, StreamEncoding, StreamKind, DEFAULT_RETRY_INTERVAL,
+    HANDSHAKE_HEADER_LEN, HANDSHAKE_MAGIC, HANDSHAKE_VERSION,
+};
+pub use flight_recorder::{FlightRecorder, RecordingPusher, DEFAULT_RECORDER_BYTES};
+pub use multiplex::{
+    check_multiplex_preamble, multiplex_preamble, FrameHeader, FrameKind, MultiplexedWriter,
+    Multiplexer, DEFAULT_MULTIPLEX_CAPACITY, FRAME_HEADER_LEN, MULTIPLEX_MAGIC,
+    MULTIPLEX_PREAMBLE_LEN, MULTIPLEX_VERSION,
+};
+pub use writer::{
+    event_format_version, event_header, EventWriter, EVENT_FORMAT_VERSION, EVENT_HEADER_LEN,
+    EVENT_MAGIC,
+};
 
+use config::{ProgressEventFilter, TimelyEventFilter, Transport};
+use connection::HandshakeWriter;
 #[cfg(feature = "ddflow")]
 use ddshow_types::differential_logging::DifferentialEvent;
 use ddshow_types::{progress_logging::TimelyProgressEvent, timely_logging::TimelyEvent, WorkerId};
@@ -13,16 +37,17 @@ use std::{
     any::Any,
     fs::{self, File},
     io::{self, BufWriter, Write},
+    net::SocketAddr,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use timely::{
     communication::Allocate,
+    dataflow::operators::capture::EventPusher,
     logging::{TimelyEvent as RawTimelyEvent, TimelyProgressEvent as RawTimelyProgressEvent},
     worker::Worker,
 };
 
-// TODO: Allow configuring what events are saved and support compression
-
 /// The name of the timely log stream for timely events
 pub const TIMELY_LOGGER_NAME: &str = "timely";
 
@@ -49,7 +74,61 @@ where
     dir.join(format!("{}.worker-{}.ddshow", file_prefix, worker.index()))
 }
 
-/// Writes all timely event logs to the given writer
+/// Starts a writer's events with the worker's [`Handshake`] so that ddshow can route
+/// them without waiting to see whether the connection sends one. Failures are logged
+/// the same as failed events since the logger is installed either way
+fn send_handshake<W>(handshake: &Handshake, writer: &mut W)
+where
+    W: Write,
+{
+    if let Err(_err) = handshake.write_to(writer) {
+        #[cfg(feature = "tracing")]
+        tracing_dep::error!(
+            worker = handshake.worker,
+            "failed to write the {} handshake of worker {}: {:?}",
+            handshake.stream,
+            handshake.worker,
+            _err,
+        );
+    }
+}
+
+/// Creates the log file of one of a worker's log streams within the given
+/// directory, starting it with the worker's [`Handshake`]
+fn create_log_file(
+    directory: &Path,
+    file_prefix: &str,
+    handshake: &Handshake,
+    compression: Compression,
+) -> io::Result<Box<dyn Write + Send + 'static>> {
+    let path = directory.join(format!(
+        "{}.worker-{}.{}",
+        file_prefix,
+        handshake.worker,
+        compression.extension(),
+    ));
+
+    #[cfg(feature = "tracing")]
+    tracing_dep::info!(
+        worker = handshake.worker,
+        stream = %handshake.stream,
+        directory = ?directory,
+        path = ?path,
+        "installing a disk backed {} logger on worker {} pointed at {}",
+        handshake.stream,
+        handshake.worker,
+        path.display(),
+    );
+
+    fs::create_dir_all(directory)?;
+    let mut writer = compression.compress(BufWriter::new(File::create(path)?))?;
+    handshake.write_to(&mut writer)?;
+
+    Ok(writer)
+}
+
+/// Writes all timely event logs to the given writer, starting
+/// with the worker's [`Handshake`]
 ///
 /// See [`TimelyEvent`] for the events logged
 ///
@@ -81,7 +160,129 @@ pub fn enable_timely_logging<A, W>(
 ) -> Option<Box<dyn Any + 'static>>
 where
     A: Allocate,
-    W: Write + 'static,
+    W: Write + Send + 'static,
+{
+    enable_timely_logging_with_config(worker, writer, &SinkConfig::default())
+}
+
+/// Writes the timely event logs allowed by the given [`SinkConfig`] to the given
+/// writer, starting with the worker's [`Handshake`] unless the config skips it.
+/// The config's transport and compression don't apply to the writer
+pub fn enable_timely_logging_with_config<A, W>(
+    worker: &mut Worker<A>,
+    writer: W,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    let handshake = config
+        .sends_handshake()
+        .then(|| Handshake::for_worker(worker, StreamKind::Timely));
+
+    write_timely_logs(worker, writer, handshake.as_ref(), config)
+}
+
+/// Sends the timely event logs allowed by the given [`SinkConfig`] over the config's
+/// transport, failing if the config doesn't have one or if it couldn't be opened
+pub fn install_timely_logging<A>(
+    worker: &mut Worker<A>,
+    config: &SinkConfig,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    let handshake = Handshake::for_worker(worker, StreamKind::Timely);
+    let sample_rate = config.schedule_sample_rate();
+
+    let logger = match config.transport()? {
+        Transport::Disk(directory) => {
+            let writer = create_log_file(
+                directory,
+                TIMELY_LOG_FILE,
+                &handshake,
+                config.compression_codec(),
+            )?;
+
+            write_timely_logs(worker, writer, None, config)
+        }
+
+        Transport::Reconnecting(address) => {
+            let writer =
+                ReconnectingWriter::new(*address, handshake).sampling_schedules(sample_rate);
+            register_timely_logger(worker, writer, config)
+        }
+
+        Transport::Multiplexed(multiplexer) => {
+            let writer = multiplexer
+                .channel(&handshake)?
+                .sampled_schedules(sample_rate);
+            register_timely_logger(worker, writer, config)
+        }
+
+        Transport::Recorded(recorder) => {
+            // The dataflow graph is needed to make sense of any other events
+            let is_graph_event = |(_, _, event): &(Duration, WorkerId, TimelyEvent)| {
+                event.is_operates() || event.is_channels()
+            };
+            let recorder = recorder
+                .recorder(TIMELY_LOG_FILE, handshake, is_graph_event)
+                .sampling_schedules(sample_rate);
+
+            register_timely_logger(worker, recorder, config)
+        }
+    };
+
+    Ok(logger)
+}
+
+/// Installs a timely logger writing to the given writer, background writers send
+/// the handshake from their own thread so that the worker never waits on them
+fn write_timely_logs<A, W>(
+    worker: &mut Worker<A>,
+    mut writer: W,
+    handshake: Option<&Handshake>,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    match config.background_writer() {
+        Some((capacity, policy)) => register_timely_logger(
+            worker,
+            AsyncEventWriter::sampling_schedules(
+                HandshakeWriter::new(handshake, writer),
+                capacity,
+                policy,
+                config.schedule_sample_rate(),
+            ),
+            config,
+        ),
+
+        None => {
+            if let Some(handshake) = handshake {
+                send_handshake(handshake, &mut writer);
+            }
+
+            register_timely_logger(
+                worker,
+                EventWriter::new(writer).sampled_schedules(config.schedule_sample_rate()),
+                config,
+            )
+        }
+    }
+}
+
+fn register_timely_logger<A, P>(
+    worker: &mut Worker<A>,
+    pusher: P,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    P: EventPusher<Duration, (Duration, WorkerId, TimelyEvent)> + 'static,
 {
     #[cfg(feature = "tracing")]
     tracing_dep::info!(
@@ -92,16 +293,18 @@ where
         worker.index(),
     );
 
-    let mut logger: BatchLogger<TimelyEvent, WorkerId, _> =
-        BatchLogger::new(EventWriter::new(writer));
+    let mut logger: BatchLogger<TimelyEvent, WorkerId, _> = BatchLogger::new(pusher);
+    let mut filter = TimelyEventFilter::new(config);
 
     worker
         .log_register()
         .insert::<RawTimelyEvent, _>(TIMELY_LOGGER_NAME, move |time, data| {
+            filter.retain(data);
             logger.publish_batch(time, data)
         })
 }
 
+/// Writes all timely event logs to a file within the given directory
 pub fn save_timely_logs_to_disk<P, A>(
     worker: &mut Worker<A>,
     directory: P,
@@ -110,31 +313,14 @@ where
     P: AsRef<Path>,
     A: Allocate,
 {
-    let directory = directory.as_ref();
-    let path = directory.join(format!(
-        "{}.worker-{}.ddshow",
-        TIMELY_LOG_FILE,
-        worker.index()
-    ));
-
-    #[cfg(feature = "tracing")]
-    tracing_dep::info!(
-        worker = worker.index(),
-        logging_stream = TIMELY_LOGGER_NAME,
-        directory = ?directory,
-        path = ?path,
-        "installing a disk backed {} event logger on worker {} pointed at {}",
-        TIMELY_LOGGER_NAME,
-        worker.index(),
-        path.display(),
-    );
-
-    fs::create_dir_all(directory)?;
-    let writer = BufWriter::new(File::create(path)?);
-    Ok(enable_timely_logging(worker, writer))
+    install_timely_logging(
+        worker,
+        &SinkConfig::default().save_to_disk(directory.as_ref()),
+    )
 }
 
-/// Writes all differential dataflow event logs to the given writer
+/// Writes all differential dataflow event logs to the given writer,
+/// starting with the worker's [`Handshake`]
 ///
 /// See [`DifferentialEvent`] for the events logged
 ///
@@ -167,7 +353,107 @@ pub fn enable_differential_logging<A, W>(
 ) -> Option<Box<dyn Any + 'static>>
 where
     A: Allocate,
-    W: Write + 'static,
+    W: Write + Send + 'static,
+{
+    enable_differential_logging_with_config(worker, writer, &SinkConfig::default())
+}
+
+/// Writes all differential dataflow event logs to the given writer
+/// as configured by the given [`SinkConfig`], differential events
+/// are never filtered
+#[cfg(feature = "ddflow")]
+pub fn enable_differential_logging_with_config<A, W>(
+    worker: &mut Worker<A>,
+    writer: W,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    let handshake = config
+        .sends_handshake()
+        .then(|| Handshake::for_worker(worker, StreamKind::Differential));
+
+    write_differential_logs(worker, writer, handshake.as_ref(), config)
+}
+
+/// Sends all differential dataflow event logs over the given [`SinkConfig`]'s transport,
+/// failing if the config doesn't have one or if it couldn't be opened
+#[cfg(feature = "ddflow")]
+pub fn install_differential_logging<A>(
+    worker: &mut Worker<A>,
+    config: &SinkConfig,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    let handshake = Handshake::for_worker(worker, StreamKind::Differential);
+
+    let logger = match config.transport()? {
+        Transport::Disk(directory) => {
+            let writer = create_log_file(
+                directory,
+                DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+                &handshake,
+                config.compression_codec(),
+            )?;
+
+            write_differential_logs(worker, writer, None, config)
+        }
+
+        Transport::Reconnecting(address) => {
+            register_differential_logger(worker, ReconnectingWriter::new(*address, handshake))
+        }
+
+        Transport::Multiplexed(multiplexer) => {
+            register_differential_logger(worker, multiplexer.channel(&handshake)?)
+        }
+
+        Transport::Recorded(recorder) => register_differential_logger(
+            worker,
+            recorder.recorder(DIFFERENTIAL_ARRANGEMENT_LOG_FILE, handshake, |_| false),
+        ),
+    };
+
+    Ok(logger)
+}
+
+#[cfg(feature = "ddflow")]
+fn write_differential_logs<A, W>(
+    worker: &mut Worker<A>,
+    mut writer: W,
+    handshake: Option<&Handshake>,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    match config.background_writer() {
+        Some((capacity, policy)) => register_differential_logger(
+            worker,
+            AsyncEventWriter::new(HandshakeWriter::new(handshake, writer), capacity, policy),
+        ),
+
+        None => {
+            if let Some(handshake) = handshake {
+                send_handshake(handshake, &mut writer);
+            }
+
+            register_differential_logger(worker, EventWriter::new(writer))
+        }
+    }
+}
+
+#[cfg(feature = "ddflow")]
+fn register_differential_logger<A, P>(
+    worker: &mut Worker<A>,
+    pusher: P,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    P: EventPusher<Duration, (Duration, WorkerId, DifferentialEvent)> + 'static,
 {
     #[cfg(feature = "tracing")]
     tracing_dep::info!(
@@ -177,8 +463,7 @@ where
         worker.index(),
     );
 
-    let mut logger: BatchLogger<DifferentialEvent, WorkerId, _> =
-        BatchLogger::new(EventWriter::new(writer));
+    let mut logger: BatchLogger<DifferentialEvent, WorkerId, _> = BatchLogger::new(pusher);
 
     worker.log_register().insert::<RawDifferentialEvent, _>(
         DIFFERENTIAL_ARRANGEMENT_LOGGER_NAME,
@@ -188,6 +473,8 @@ where
     )
 }
 
+/// Writes all differential dataflow event logs to a file within the given directory
+#[cfg(feature = "ddflow")]
 pub fn save_differential_logs_to_disk<P, A>(
     worker: &mut Worker<A>,
     directory: P,
@@ -196,37 +483,123 @@ where
     P: AsRef<Path>,
     A: Allocate,
 {
-    let directory = directory.as_ref();
-    let path = directory.join(format!(
-        "{}.worker-{}.ddshow",
-        DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
-        worker.index()
-    ));
-
-    #[cfg(feature = "tracing")]
-    tracing_dep::info!(
-        worker = worker.index(),
-        logging_stream = DIFFERENTIAL_ARRANGEMENT_LOGGER_NAME,
-        directory = ?directory,
-        path = ?path,
-        "installing a disk backed {} event logger on worker {} pointed at {}",
-        DIFFERENTIAL_ARRANGEMENT_LOGGER_NAME,
-        worker.index(),
-        path.display(),
-    );
-
-    fs::create_dir_all(directory)?;
-    let writer = BufWriter::new(File::create(path)?);
-    Ok(enable_differential_logging(worker, writer))
+    install_differential_logging(
+        worker,
+        &SinkConfig::default().save_to_disk(directory.as_ref()),
+    )
 }
 
+/// Writes all timely progress logs to the given writer, starting
+/// with the worker's [`Handshake`]
+///
+/// See [`TimelyProgressEvent`] for the events logged
 pub fn enable_timely_progress_logging<A, W>(
     worker: &mut Worker<A>,
     writer: W,
 ) -> Option<Box<dyn Any + 'static>>
 where
     A: Allocate,
-    W: Write + 'static,
+    W: Write + Send + 'static,
+{
+    enable_timely_progress_logging_with_config(worker, writer, &SinkConfig::default())
+}
+
+/// Writes the timely progress logs allowed by the given [`SinkConfig`] to the given writer,
+/// only the config's address prefixes apply to progress events
+pub fn enable_timely_progress_logging_with_config<A, W>(
+    worker: &mut Worker<A>,
+    writer: W,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    let handshake = config
+        .sends_handshake()
+        .then(|| Handshake::for_worker(worker, StreamKind::Progress));
+
+    write_timely_progress(worker, writer, handshake.as_ref(), config)
+}
+
+/// Sends the timely progress logs allowed by the given [`SinkConfig`] over the config's
+/// transport, failing if the config doesn't have one or if it couldn't be opened
+pub fn install_timely_progress_logging<A>(
+    worker: &mut Worker<A>,
+    config: &SinkConfig,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    let handshake = Handshake::for_worker(worker, StreamKind::Progress);
+
+    let logger = match config.transport()? {
+        Transport::Disk(directory) => {
+            let writer = create_log_file(
+                directory,
+                TIMELY_PROGRESS_LOG_FILE,
+                &handshake,
+                config.compression_codec(),
+            )?;
+
+            write_timely_progress(worker, writer, None, config)
+        }
+
+        Transport::Reconnecting(address) => register_timely_progress_logger(
+            worker,
+            ReconnectingWriter::new(*address, handshake),
+            config,
+        ),
+
+        Transport::Multiplexed(multiplexer) => {
+            register_timely_progress_logger(worker, multiplexer.channel(&handshake)?, config)
+        }
+
+        Transport::Recorded(recorder) => register_timely_progress_logger(
+            worker,
+            recorder.recorder(TIMELY_PROGRESS_LOG_FILE, handshake, |_| false),
+            config,
+        ),
+    };
+
+    Ok(logger)
+}
+
+fn write_timely_progress<A, W>(
+    worker: &mut Worker<A>,
+    mut writer: W,
+    handshake: Option<&Handshake>,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    W: Write + Send + 'static,
+{
+    match config.background_writer() {
+        Some((capacity, policy)) => register_timely_progress_logger(
+            worker,
+            AsyncEventWriter::new(HandshakeWriter::new(handshake, writer), capacity, policy),
+            config,
+        ),
+
+        None => {
+            if let Some(handshake) = handshake {
+                send_handshake(handshake, &mut writer);
+            }
+
+            register_timely_progress_logger(worker, EventWriter::new(writer), config)
+        }
+    }
+}
+
+fn register_timely_progress_logger<A, P>(
+    worker: &mut Worker<A>,
+    pusher: P,
+    config: &SinkConfig,
+) -> Option<Box<dyn Any + 'static>>
+where
+    A: Allocate,
+    P: EventPusher<Duration, (Duration, WorkerId, TimelyProgressEvent)> + 'static,
 {
     #[cfg(feature = "tracing")]
     tracing_dep::info!(
@@ -237,16 +610,19 @@ where
         worker.index(),
     );
 
-    let mut logger: BatchLogger<TimelyProgressEvent, WorkerId, _> =
-        BatchLogger::new(EventWriter::new(writer));
+    let mut logger: BatchLogger<TimelyProgressEvent, WorkerId, _> = BatchLogger::new(pusher);
+    let filter = ProgressEventFilter::new(config);
 
-    worker
-        .log_register()
-        .insert::<RawTimelyProgressEvent, _>(TIMELY_PROGRESS_LOGGER_NAME, move |time, data| {
+    worker.log_register().insert::<RawTimelyProgressEvent, _>(
+        TIMELY_PROGRESS_LOGGER_NAME,
+        move |time, data| {
+            filter.retain(data);
             logger.publish_batch(time, data)
-        })
+        },
+    )
 }
 
+/// Writes all timely progress logs to a file within the given directory
 pub fn save_timely_progress_to_disk<P, A>(
     worker: &mut Worker<A>,
     directory: P,
@@ -255,26 +631,163 @@ where
     P: AsRef<Path>,
     A: Allocate,
 {
-    let directory = directory.as_ref();
-    let path = directory.join(format!(
-        "{}.worker-{}.ddshow",
-        TIMELY_PROGRESS_LOG_FILE,
-        worker.index()
-    ));
+    install_timely_progress_logging(
+        worker,
+        &SinkConfig::default().save_to_disk(directory.as_ref()),
+    )
+}
 
-    #[cfg(feature = "tracing")]
-    tracing_dep::info!(
-        worker = worker.index(),
-        logging_stream = TIMELY_PROGRESS_LOGGER_NAME,
-        directory = ?directory,
-        path = ?path,
-        "installing a disk backed {} logger on worker {} pointed at {}",
-        TIMELY_PROGRESS_LOGGER_NAME,
-        worker.index(),
-        path.display(),
-    );
+/// Records all timely event logs into the given [`FlightRecorder`]
+pub fn record_timely_logs<A>(
+    worker: &mut Worker<A>,
+    recorder: &FlightRecorder,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_logging(worker, &SinkConfig::default().record_into(recorder))
+}
 
-    fs::create_dir_all(directory)?;
-    let writer = BufWriter::new(File::create(path)?);
-    Ok(enable_timely_progress_logging(worker, writer))
+/// Records all differential dataflow event logs into the given [`FlightRecorder`]
+#[cfg(feature = "ddflow")]
+pub fn record_differential_logs<A>(
+    worker: &mut Worker<A>,
+    recorder: &FlightRecorder,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_differential_logging(worker, &SinkConfig::default().record_into(recorder))
+}
+
+/// Records all timely progress logs into the given [`FlightRecorder`]
+pub fn record_timely_progress<A>(
+    worker: &mut Worker<A>,
+    recorder: &FlightRecorder,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_progress_logging(worker, &SinkConfig::default().record_into(recorder))
+}
+
+/// Streams all timely event logs to ddshow at the given address, reconnecting
+/// whenever the connection drops. ddshow must be run with `--reconnect`
+///
+/// See [`ReconnectingWriter`] for how events are handled while disconnected
+///
+/// ## Examples
+///
+/// ```rust
+/// use std::{env, net::SocketAddr};
+/// use timely::dataflow::operators::{Inspect, ToStream};
+///
+/// timely::execute_directly(|worker| {
+///     if let Ok(addr) = env::var("TIMELY_WORKER_LOG_ADDR") {
+///         let addr: SocketAddr = addr.parse().unwrap();
+///         ddshow_sink::stream_timely_logs(worker, addr).unwrap();
+///     }
+///
+///     worker.dataflow::<(),_,_>(|scope| {
+///         (0..10).to_stream(scope)
+///             .inspect(|x| println!("seen: {:?}", x));
+///     });
+/// });
+/// ```
+///
+pub fn stream_timely_logs<A>(
+    worker: &mut Worker<A>,
+    address: SocketAddr,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_logging(worker, &SinkConfig::default().stream_to(address))
+}
+
+/// Streams all differential dataflow event logs to ddshow at the given
+/// address, reconnecting whenever the connection drops
+#[cfg(feature = "ddflow")]
+pub fn stream_differential_logs<A>(
+    worker: &mut Worker<A>,
+    address: SocketAddr,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_differential_logging(worker, &SinkConfig::default().stream_to(address))
+}
+
+/// Streams all timely progress logs to ddshow at the given address,
+/// reconnecting whenever the connection drops
+pub fn stream_timely_progress<A>(
+    worker: &mut Worker<A>,
+    address: SocketAddr,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_progress_logging(worker, &SinkConfig::default().stream_to(address))
+}
+
+/// Sends all timely event logs to ddshow over the given [`Multiplexer`],
+/// which every worker of the process can share
+///
+/// ## Examples
+///
+/// ```rust
+/// use ddshow_sink::Multiplexer;
+/// use std::{env, net::SocketAddr};
+/// use timely::dataflow::operators::{Inspect, ToStream};
+///
+/// // Open a single connection that every worker's logs are sent over
+/// let multiplexer = env::var("TIMELY_WORKER_LOG_ADDR").ok().and_then(|addr| {
+///     let addr: SocketAddr = addr.parse().unwrap();
+///     Multiplexer::connect(addr).ok()
+/// });
+///
+/// timely::execute(timely::Config::process(2), move |worker| {
+///     if let Some(multiplexer) = multiplexer.as_ref() {
+///         ddshow_sink::multiplex_timely_logs(worker, multiplexer).unwrap();
+///     }
+///
+///     worker.dataflow::<(),_,_>(|scope| {
+///         (0..10).to_stream(scope)
+///             .inspect(|x| println!("seen: {:?}", x));
+///     });
+/// })
+/// .unwrap();
+/// ```
+///
+pub fn multiplex_timely_logs<A>(
+    worker: &mut Worker<A>,
+    multiplexer: &Multiplexer,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_logging(worker, &SinkConfig::default().multiplex_over(multiplexer))
+}
+
+/// Sends all differential dataflow event logs to ddshow over the given [`Multiplexer`]
+#[cfg(feature = "ddflow")]
+pub fn multiplex_differential_logs<A>(
+    worker: &mut Worker<A>,
+    multiplexer: &Multiplexer,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_differential_logging(worker, &SinkConfig::default().multiplex_over(multiplexer))
+}
+
+/// Sends all timely progress logs to ddshow over the given [`Multiplexer`]
+pub fn multiplex_timely_progress<A>(
+    worker: &mut Worker<A>,
+    multiplexer: &Multiplexer,
+) -> io::Result<Option<Box<dyn Any + 'static>>>
+where
+    A: Allocate,
+{
+    install_timely_progress_logging(worker, &SinkConfig::default().multiplex_over(multiplexer))
 }
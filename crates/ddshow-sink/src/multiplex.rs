@@ -0,0 +1,703 @@
+use crate::{
+    async_writer::BackpressurePolicy,
+    connection::Handshake,
+    writer::{archive_dropped, EventWriter},
+};
+use rkyv::{ser::serializers::AlignedSerializer, AlignedVec, Serialize};
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    fmt::{self, Debug},
+    io::{self, Write},
+    mem,
+    net::{SocketAddr, TcpStream},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+/// The default number of frames that a [`Multiplexer`] queues for its writer thread
+pub const DEFAULT_MULTIPLEX_CAPACITY: usize = 1024;
+
+/// The magic bytes at the start of every multiplexed connection
+pub const MULTIPLEX_MAGIC: [u8; 8] = *b"DDSHOWMX";
+
+/// The current version of the multiplexed framing
+pub const MULTIPLEX_VERSION: u32 = 1;
+
+/// The length of the preamble at the start of every multiplexed connection, made up
+/// of [`MULTIPLEX_MAGIC`] and the [`MULTIPLEX_VERSION`] as a little endian `u32`
+pub const MULTIPLEX_PREAMBLE_LEN: usize = 12;
+
+/// The length of the header before every frame's payload, made up of the frame's
+/// channel as a `u32`, its [`FrameKind`] as a `u8` and the length of its payload as
+/// a `u32`, all little endian
+pub const FRAME_HEADER_LEN: usize = 9;
+
+/// Creates the preamble that's written to the start of every multiplexed connection
+pub fn multiplex_preamble() -> [u8; MULTIPLEX_PREAMBLE_LEN] {
+    let mut preamble = [0; MULTIPLEX_PREAMBLE_LEN];
+    preamble[..MULTIPLEX_MAGIC.len()].copy_from_slice(&MULTIPLEX_MAGIC);
+    preamble[MULTIPLEX_MAGIC.len()..].copy_from_slice(&MULTIPLEX_VERSION.to_le_bytes());
+
+    preamble
+}
+
+/// Checks that a multiplexed connection's preamble was written by
+/// a supported version of `ddshow-sink`
+pub fn check_multiplex_preamble(preamble: &[u8; MULTIPLEX_PREAMBLE_LEN]) -> io::Result<()> {
+    if preamble[..MULTIPLEX_MAGIC.len()] != MULTIPLEX_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the connection didn't start with a ddshow multiplexing preamble",
+        ));
+    }
+
+    let version = u32::from_le_bytes(preamble[MULTIPLEX_MAGIC.len()..].try_into().unwrap());
+    if version != MULTIPLEX_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported multiplexing version {}, expected version {}",
+                version, MULTIPLEX_VERSION,
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The kind of a frame sent over a multiplexed connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    /// Opens a channel, the payload is the channel's [`Handshake`]
+    Open,
+    /// The next chunk of a channel's event stream
+    Data,
+    /// Closes a channel, the payload is empty
+    Close,
+}
+
+/// The header of a frame sent over a multiplexed connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameHeader {
+    /// The channel that the frame belongs to
+    pub channel: u32,
+    /// The kind of the frame
+    pub kind: FrameKind,
+    /// The length of the frame's payload
+    pub len: u32,
+}
+
+impl FrameHeader {
+    /// Encodes the frame header
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_LEN] {
+        let kind = match self.kind {
+            FrameKind::Open => 0,
+            FrameKind::Data => 1,
+            FrameKind::Close => 2,
+        };
+
+        let mut bytes = [0; FRAME_HEADER_LEN];
+        bytes[..4].copy_from_slice(&self.channel.to_le_bytes());
+        bytes[4] = kind;
+        bytes[5..].copy_from_slice(&self.len.to_le_bytes());
+
+        bytes
+    }
+
+    /// Decodes a frame header, returning an error if the frame's kind is unknown
+    pub fn from_bytes(bytes: &[u8; FRAME_HEADER_LEN]) -> io::Result<Self> {
+        let kind = match bytes[4] {
+            0 => FrameKind::Open,
+            1 => FrameKind::Data,
+            2 => FrameKind::Close,
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown multiplexed frame kind {}", kind),
+                ))
+            }
+        };
+
+        Ok(Self {
+            channel: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            kind,
+            len: u32::from_le_bytes(bytes[5..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Carries the log streams of every worker within a process over a single connection
+///
+/// Every worker and log stream gets its own channel which is opened with the
+/// stream's [`Handshake`], ddshow splits the connection back up into one stream
+/// per channel. Frames are written to the connection by a dedicated thread so
+/// that a slow connection doesn't stall the workers, up to [`DEFAULT_MULTIPLEX_CAPACITY`]
+/// frames are queued for it before the multiplexer's [`BackpressurePolicy`] kicks in
+///
+/// The multiplexer can be cloned to share it between workers, the connection
+/// is closed once every clone and channel has been dropped and every queued
+/// frame has been written
+#[derive(Clone)]
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+}
+
+/// Shared between every clone of a multiplexer and its channels,
+/// the writer thread is joined once the last of them is dropped
+struct Shared {
+    frames: Arc<Frames>,
+    next_channel: AtomicU32,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The frames queued for a multiplexer's writer thread
+struct Frames {
+    state: Mutex<FramesState>,
+    /// Notified when a frame is pushed or the queue is closed
+    pushed: Condvar,
+    /// Notified when frames are popped or writing fails
+    popped: Condvar,
+}
+
+struct FramesState {
+    frames: VecDeque<Vec<u8>>,
+    closed: bool,
+    /// Set once writing to the connection fails, after which nothing else is sent
+    failed: bool,
+}
+
+impl Multiplexer {
+    /// Multiplexes log streams over the given connection
+    pub fn new<W>(mut connection: W) -> io::Result<Self>
+    where
+        W: Write + Send + 'static,
+    {
+        connection.write_all(&multiplex_preamble())?;
+
+        let frames = Arc::new(Frames {
+            state: Mutex::new(FramesState {
+                frames: VecDeque::new(),
+                closed: false,
+                failed: false,
+            }),
+            pushed: Condvar::new(),
+            popped: Condvar::new(),
+        });
+
+        let thread_frames = frames.clone();
+        let thread = thread::Builder::new()
+            .name("ddshow-sink-multiplexer".to_owned())
+            .spawn(move || write_frames(&thread_frames, connection))?;
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                frames,
+                next_channel: AtomicU32::new(0),
+                thread: Some(thread),
+            }),
+            capacity: DEFAULT_MULTIPLEX_CAPACITY,
+            policy: BackpressurePolicy::Block,
+        })
+    }
+
+    /// Connects to ddshow at the given address
+    pub fn connect(address: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+
+        Self::new(stream)
+    }
+
+    /// Connects to ddshow over the unix socket at the given path,
+    /// which ddshow listens on when it's given `--unix-socket`
+    #[cfg(unix)]
+    pub fn connect_unix<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(UnixStream::connect(path)?)
+    }
+
+    /// Sets the number of frames that are queued for the writer thread and
+    /// what happens to new events once the queue is full, defaults to
+    /// [`DEFAULT_MULTIPLEX_CAPACITY`] frames and [`BackpressurePolicy::Block`]
+    ///
+    /// Progress updates are never dropped and frames that are already queued
+    /// can't be dropped without corrupting their channel's stream, so
+    /// [`BackpressurePolicy::DropOldest`] drops new events the same way that
+    /// [`BackpressurePolicy::DropNewest`] does. Only channels opened after
+    /// this is called use the new settings
+    pub const fn backpressure(
+        mut self,
+        capacity: NonZeroUsize,
+        policy: BackpressurePolicy,
+    ) -> Self {
+        self.capacity = capacity.get();
+        self.policy = policy;
+        self
+    }
+
+    /// Opens a channel for the log stream described by the given handshake
+    pub fn channel<D>(&self, handshake: &Handshake) -> io::Result<MultiplexedWriter<D>> {
+        let channel = self.shared.next_channel.fetch_add(1, Ordering::Relaxed);
+        self.send(channel, FrameKind::Open, &handshake.to_bytes())?;
+
+        Ok(MultiplexedWriter {
+            writer: EventWriter::new(ChannelWriter {
+                channel,
+                multiplexer: self.clone(),
+                buffer: Vec::new(),
+            }),
+            multiplexer: self.clone(),
+            dropped: None,
+        })
+    }
+
+    /// Queues a single frame for the writer thread, blocking while the queue is full
+    fn send(&self, channel: u32, kind: FrameKind, payload: &[u8]) -> io::Result<()> {
+        let header = FrameHeader {
+            channel,
+            kind,
+            len: payload.len().try_into().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "multiplexed frames can't be longer than 4GiB",
+                )
+            })?,
+        };
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(payload);
+
+        self.shared.frames.push(frame, self.capacity)
+    }
+
+    /// Returns `true` if new events should be dropped since the queue is full
+    fn is_backed_up(&self) -> bool {
+        self.policy != BackpressurePolicy::Block
+            && self.shared.frames.state().frames.len() >= self.capacity
+    }
+}
+
+impl Debug for Multiplexer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.frames.state();
+
+        f.debug_struct("Multiplexer")
+            .field("connected", &!state.failed)
+            .field("queued_frames", &state.frames.len())
+            .field(
+                "channels",
+                &self.shared.next_channel.load(Ordering::Relaxed),
+            )
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.frames.state().closed = true;
+        self.frames.pushed.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            if let Err(_err) = thread.join() {
+                #[cfg(feature = "tracing")]
+                tracing_dep::error!("the ddshow-sink multiplexer thread panicked: {:?}", _err);
+            }
+        }
+    }
+}
+
+impl Frames {
+    /// Locks the queue's state, the state is never left half-updated
+    /// so any poisoning is ignored
+    fn state(&self) -> MutexGuard<'_, FramesState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, frame: Vec<u8>, capacity: usize) -> io::Result<()> {
+        let mut state = self.state();
+        while state.frames.len() >= capacity && !state.failed {
+            state = self
+                .popped
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        if state.failed {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the multiplexed connection to ddshow was lost",
+            ));
+        }
+
+        state.frames.push_back(frame);
+        drop(state);
+
+        self.pushed.notify_one();
+        Ok(())
+    }
+
+    /// Stops sending anything once the connection has failed, since
+    /// a partially written frame can't be recovered from
+    fn fail(&self) {
+        let mut state = self.state();
+        state.failed = true;
+        state.frames.clear();
+        drop(state);
+
+        self.popped.notify_all();
+    }
+}
+
+/// Writes queued frames until the queue is closed and empty
+fn write_frames<W>(frames: &Frames, mut connection: W)
+where
+    W: Write,
+{
+    loop {
+        let frame = {
+            let mut state = frames.state();
+
+            if state.frames.is_empty() && !state.closed {
+                // Flush while there's nothing to write so that
+                // streamed events don't sit around in a buffer
+                drop(state);
+                if let Err(_err) = connection.flush() {
+                    #[cfg(feature = "tracing")]
+                    tracing_dep::error!("failed to flush the multiplexed connection: {:?}", _err);
+
+                    frames.fail();
+                    return;
+                }
+
+                state = frames.state();
+                while state.frames.is_empty() && !state.closed {
+                    state = frames
+                        .pushed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+
+            state.frames.pop_front()
+        };
+        frames.popped.notify_all();
+
+        match frame {
+            Some(frame) => {
+                if let Err(_err) = connection.write_all(&frame) {
+                    #[cfg(feature = "tracing")]
+                    tracing_dep::error!(
+                        "failed to write to the multiplexed connection: {:?}",
+                        _err
+                    );
+
+                    frames.fail();
+                    return;
+                }
+            }
+            // The queue is closed and everything has been written
+            None => break,
+        }
+    }
+
+    if let Err(_err) = connection.flush() {
+        #[cfg(feature = "tracing")]
+        tracing_dep::error!("failed to flush the multiplexed connection: {:?}", _err);
+    }
+}
+
+/// An event pusher that sends a single worker's log stream over a [`Multiplexer`],
+/// each pushed event is sent as a single frame
+///
+/// Events that are dropped because the multiplexer's queue is full are reported to
+/// ddshow as a [`ddshow_types::Event::Dropped`] before the next event that's sent
+#[derive(Debug)]
+pub struct MultiplexedWriter<D> {
+    writer: EventWriter<Duration, D, ChannelWriter>,
+    multiplexer: Multiplexer,
+    /// The time of the last dropped batch and the number of
+    /// events dropped since an event was last sent
+    dropped: Option<(Duration, u64)>,
+}
+
+impl<D> MultiplexedWriter<D>
+where
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    /// Marks the channel's stream as only holding one in every `one_in` of each operator's
+    /// activations, see [`SinkConfig::sample_schedules()`](crate::SinkConfig::sample_schedules)
+    pub(crate) fn sampled_schedules(mut self, one_in: NonZeroUsize) -> Self {
+        self.writer = self.writer.sampled_schedules(one_in);
+        self
+    }
+
+    fn try_push(&mut self, event: RawEvent<Duration, D>) -> io::Result<()> {
+        if let Some((time, events)) = self.dropped.take() {
+            #[cfg(feature = "tracing")]
+            tracing_dep::warn!(
+                dropped_events = events,
+                "the ddshow-sink multiplexer's queue was full, dropped {} events",
+                events,
+            );
+
+            let mut archive = AlignedVec::new();
+            archive_dropped::<Duration, D>(time, events, &mut archive);
+            self.writer.write_archive(&archive)?;
+        }
+
+        self.writer.try_push(event)?;
+        self.writer.flush()
+    }
+}
+
+impl<D> EventPusher<Duration, D> for MultiplexedWriter<D>
+where
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    fn push(&mut self, event: RawEvent<Duration, D>) {
+        // Progress updates are never dropped so that the stream's frontiers stay consistent
+        if let RawEvent::Messages(time, data) = &event {
+            if self.multiplexer.is_backed_up() {
+                let dropped = self.dropped.take().map_or(0, |(_, dropped)| dropped);
+                self.dropped = Some((*time, dropped + data.len() as u64));
+
+                return;
+            }
+        }
+
+        if let Err(_err) = self.try_push(event) {
+            #[cfg(feature = "tracing")]
+            tracing_dep::error!(
+                "failed to write event to the multiplexed stream: {:?}",
+                _err
+            );
+        }
+    }
+}
+
+/// Buffers the bytes of a channel's event stream until they're flushed as a frame
+#[derive(Debug)]
+struct ChannelWriter {
+    channel: u32,
+    multiplexer: Multiplexer,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let buffer = mem::take(&mut self.buffer);
+        let result = self
+            .multiplexer
+            .send(self.channel, FrameKind::Data, &buffer);
+
+        // Reuse the buffer's allocation for the next frame
+        self.buffer = buffer;
+        self.buffer.clear();
+
+        result
+    }
+}
+
+impl Drop for ChannelWriter {
+    fn drop(&mut self) {
+        let result = self
+            .flush()
+            .and_then(|()| self.multiplexer.send(self.channel, FrameKind::Close, &[]));
+
+        if let Err(_err) = result {
+            #[cfg(feature = "tracing")]
+            tracing_dep::debug!(
+                channel = self.channel,
+                "failed to close multiplexed channel: {:?}",
+                _err,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{Handshake, StreamKind},
+        multiplex::{
+            check_multiplex_preamble, FrameHeader, FrameKind, Multiplexer, FRAME_HEADER_LEN,
+            MULTIPLEX_PREAMBLE_LEN,
+        },
+        writer::tests::{read_events, SharedBuffer},
+    };
+    use ddshow_types::Event;
+    use std::{collections::BTreeMap, convert::TryInto, io, num::NonZeroUsize, time::Duration};
+    use timely::dataflow::operators::capture::{Event as RawEvent, EventPusher};
+
+    /// A channel's handshake, the bytes of its event stream and whether it was closed
+    type Channel = (Handshake, Vec<u8>, bool);
+
+    /// Splits a multiplexed connection back up into its channels
+    fn demultiplex(mut bytes: &[u8]) -> BTreeMap<u32, Channel> {
+        check_multiplex_preamble(bytes[..MULTIPLEX_PREAMBLE_LEN].try_into().unwrap()).unwrap();
+        bytes = &bytes[MULTIPLEX_PREAMBLE_LEN..];
+
+        let mut channels = BTreeMap::new();
+        while !bytes.is_empty() {
+            let header =
+                FrameHeader::from_bytes(bytes[..FRAME_HEADER_LEN].try_into().unwrap()).unwrap();
+            let payload_end = FRAME_HEADER_LEN + header.len as usize;
+            let payload = &bytes[FRAME_HEADER_LEN..payload_end];
+            bytes = &bytes[payload_end..];
+
+            if header.kind == FrameKind::Open {
+                let handshake = Handshake::from_bytes(payload).unwrap();
+                let opened = channels.insert(header.channel, (handshake, Vec::new(), false));
+                assert!(
+                    opened.is_none(),
+                    "channel {} was opened twice",
+                    header.channel
+                );
+
+                continue;
+            }
+
+            let (_, stream, closed) = channels
+                .get_mut(&header.channel)
+                .unwrap_or_else(|| panic!("channel {} was never opened", header.channel));
+            assert!(
+                !*closed,
+                "channel {} was used after closing",
+                header.channel
+            );
+
+            match header.kind {
+                FrameKind::Data => stream.extend_from_slice(payload),
+                FrameKind::Close => *closed = true,
+                FrameKind::Open => unreachable!(),
+            }
+        }
+
+        channels
+    }
+
+    #[test]
+    fn frame_header_roundtrip() {
+        for &kind in &[FrameKind::Open, FrameKind::Data, FrameKind::Close] {
+            let header = FrameHeader {
+                channel: 7,
+                kind,
+                len: 1 << 20,
+            };
+
+            assert_eq!(FrameHeader::from_bytes(&header.to_bytes()).unwrap(), header);
+        }
+
+        let mut unknown_kind = FrameHeader {
+            channel: 0,
+            kind: FrameKind::Data,
+            len: 0,
+        }
+        .to_bytes();
+        unknown_kind[4] = 3;
+
+        let err = FrameHeader::from_bytes(&unknown_kind).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unsupported_preambles_are_rejected() {
+        let buffer = SharedBuffer::default();
+        drop(Multiplexer::new(buffer.clone()).unwrap());
+
+        let preamble: [u8; MULTIPLEX_PREAMBLE_LEN] = buffer.contents().try_into().unwrap();
+        check_multiplex_preamble(&preamble).unwrap();
+
+        let mut newer_version = preamble;
+        newer_version[8..].copy_from_slice(&99u32.to_le_bytes());
+        let err = check_multiplex_preamble(&newer_version).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unsupported multiplexing version 99"),
+            "{}",
+            err
+        );
+
+        let mut wrong_magic = preamble;
+        wrong_magic[0] = b'X';
+        let err = check_multiplex_preamble(&wrong_magic).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn channels_carry_their_own_streams() {
+        let buffer = SharedBuffer::default();
+        let multiplexer = Multiplexer::new(buffer.clone()).unwrap();
+
+        let timely = Handshake::new(StreamKind::Timely, 0, 2);
+        let differential = Handshake::new(StreamKind::Differential, 1, 2);
+        let mut timely_writer = multiplexer.channel::<u64>(&timely).unwrap();
+        let mut differential_writer = multiplexer
+            .channel::<u64>(&differential)
+            .unwrap()
+            .sampled_schedules(NonZeroUsize::new(4).unwrap());
+
+        // Interleave the channels' events
+        for time in 0..3 {
+            let time = Duration::from_millis(time);
+            timely_writer.push(RawEvent::Messages(time, vec![1]));
+            differential_writer.push(RawEvent::Messages(time, vec![2, 2]));
+        }
+
+        // The connection is finished once every channel and the multiplexer are dropped
+        drop((timely_writer, differential_writer, multiplexer));
+
+        let channels = demultiplex(&buffer.contents());
+        assert_eq!(channels.len(), 2);
+
+        let messages = |data: Vec<u64>| {
+            (0..3).map(move |time| Event::Messages(Duration::from_millis(time), data.clone()))
+        };
+
+        let (handshake, stream, closed) = &channels[&0];
+        assert_eq!((handshake, *closed), (&timely, true));
+        assert_eq!(
+            read_events::<u64>(stream),
+            Some(Event::Sequence(0))
+                .into_iter()
+                .chain(messages(vec![1]))
+                .collect::<Vec<_>>(),
+        );
+
+        // Sampled channels start with their sample rate
+        let (handshake, stream, closed) = &channels[&1];
+        assert_eq!((handshake, *closed), (&differential, true));
+        assert_eq!(
+            read_events::<u64>(stream),
+            vec![Event::SampledSchedules(4), Event::Sequence(0)]
+                .into_iter()
+                .chain(messages(vec![2, 2]))
+                .collect::<Vec<_>>(),
+        );
+    }
+}
@@ -5,17 +5,76 @@ use rkyv::{
     validation::DefaultArchiveValidator,
     AlignedVec, Serialize,
 };
-use std::{fmt::Debug, io::Write, marker::PhantomData, mem};
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+    marker::PhantomData,
+    mem,
+    num::NonZeroUsize,
+};
 use timely::dataflow::operators::capture::event::{
     Event as TimelyEvent, EventPusher as TimelyEventPusher,
 };
 
+/// The magic bytes at the start of every rkyv event file or stream
+pub const EVENT_MAGIC: [u8; 8] = *b"DDSHOWEV";
+
+/// The current version of the rkyv event format, this must be bumped
+/// whenever the archived layout of any of the types in `ddshow-types` changes
+///
+/// Files without a header are from before the header was introduced and
+/// are treated as version zero
+pub const EVENT_FORMAT_VERSION: u32 = 4;
+
+/// The length of the header at the start of every event file, made
+/// up of [`EVENT_MAGIC`], the [`EVENT_FORMAT_VERSION`] as a little endian
+/// `u32` and four reserved bytes which keep the events that follow the
+/// header 16-byte aligned
+pub const EVENT_HEADER_LEN: usize = 16;
+
+/// The most events that are written between each [`Event::Sequence`]
+pub(crate) const SEQUENCE_INTERVAL: usize = 256;
+
+/// Creates the header that's written to the start of every event file
+pub fn event_header() -> [u8; EVENT_HEADER_LEN] {
+    let mut header = [0; EVENT_HEADER_LEN];
+    header[..EVENT_MAGIC.len()].copy_from_slice(&EVENT_MAGIC);
+    header[EVENT_MAGIC.len()..EVENT_MAGIC.len() + 4]
+        .copy_from_slice(&EVENT_FORMAT_VERSION.to_le_bytes());
+
+    header
+}
+
+/// Gets the format version of an event file from its first [`EVENT_HEADER_LEN`]
+/// bytes, returning `0` for legacy files that don't have a header
+pub fn event_format_version(header: &[u8]) -> u32 {
+    if header.len() >= EVENT_HEADER_LEN && header.starts_with(&EVENT_MAGIC) {
+        let mut version = [0; 4];
+        version.copy_from_slice(&header[EVENT_MAGIC.len()..EVENT_MAGIC.len() + 4]);
+
+        u32::from_le_bytes(version)
+    } else {
+        0
+    }
+}
+
 /// A wrapper for a writer that serializes [`rkyv`] encoded types that are FFI compatible
+///
+/// The first event written will be preceded by a header, see [`EVENT_HEADER_LEN`].
+/// The first event and every [`SEQUENCE_INTERVAL`]th event after it are preceded
+/// by an [`Event::Sequence`] holding the number of messages written before it,
+/// which lets readers notice messages that never made it to them. Streams that
+/// sample their schedule events start with an [`Event::SampledSchedules`]
 #[derive(Debug)]
 pub struct EventWriter<T, D, W> {
     stream: W,
     buffer: AlignedVec,
     position: usize,
+    sequence: u64,
+    /// The number of events written since the last [`Event::Sequence`]
+    unsequenced: usize,
+    /// The archived [`Event::SampledSchedules`] that's written after the header
+    sampled_schedules: Option<AlignedVec>,
     __type: PhantomData<(T, D)>,
 }
 
@@ -26,6 +85,9 @@ impl<T, D, W> EventWriter<T, D, W> {
             stream,
             buffer: AlignedVec::with_capacity(512),
             position: 0,
+            sequence: 0,
+            unsequenced: SEQUENCE_INTERVAL,
+            sampled_schedules: None,
             __type: PhantomData,
         }
     }
@@ -40,7 +102,92 @@ where
     D::Archived: CheckBytes<DefaultArchiveValidator>,
 {
     fn push(&mut self, event: TimelyEvent<T, D>) {
-        let event: Event<T, D> = event.into();
+        if let Err(_err) = self.try_push(event) {
+            #[cfg(feature = "tracing")]
+            tracing_dep::error!("failed to write event to stream: {:?}", _err);
+        }
+    }
+}
+
+impl<T, D, W> EventWriter<T, D, W>
+where
+    W: Write,
+{
+    /// Starts the writer's sequence numbers at the given number of messages,
+    /// used when a stream is continued over a new writer
+    pub(crate) const fn starting_at(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Marks the stream as only holding one in every `one_in` of each operator's
+    /// activations, streams that hold every activation aren't marked
+    pub(crate) fn sampled_schedules(mut self, one_in: NonZeroUsize) -> Self
+    where
+        T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+        D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    {
+        if one_in.get() != 1 {
+            let mut archived = AlignedVec::new();
+            archive_sampled_schedules::<T, D>(one_in, &mut archived);
+            self.sampled_schedules = Some(archived);
+        }
+
+        self
+    }
+
+    /// Writes an event, returning any errors from the wrapped writer
+    pub(crate) fn try_push(&mut self, event: TimelyEvent<T, D>) -> io::Result<()>
+    where
+        T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+        D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    {
+        let messages = match &event {
+            TimelyEvent::Messages(_, data) => data.len() as u64,
+            TimelyEvent::Progress(_) => 0,
+        };
+
+        let mut buffer = mem::take(&mut self.buffer);
+        archive_event(event, &mut buffer);
+        let result = self.write_event(&buffer, messages);
+
+        self.buffer = buffer;
+        result
+    }
+
+    /// Writes an event that was already archived by [`archive_event()`] and holds the
+    /// given number of messages, writing an [`Event::Sequence`] before it when one is due
+    pub(crate) fn write_event(&mut self, archive: &[u8], messages: u64) -> io::Result<()>
+    where
+        T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+        D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    {
+        if self.unsequenced >= SEQUENCE_INTERVAL {
+            let mut sequence = AlignedVec::new();
+            archive_sequence::<T, D>(self.sequence, &mut sequence);
+
+            self.write_archive(&sequence)?;
+            self.unsequenced = 0;
+        }
+
+        self.write_archive(archive)?;
+        self.sequence += messages;
+        self.unsequenced += 1;
+
+        Ok(())
+    }
+
+    /// Writes an event that was already archived by [`archive_event()`]
+    pub(crate) fn write_archive(&mut self, archive: &[u8]) -> io::Result<()> {
+        // Write the header before the first event
+        if self.position == 0 {
+            self.stream.write_all(&event_header())?;
+            self.position += EVENT_HEADER_LEN;
+
+            if let Some(sampled_schedules) = self.sampled_schedules.take() {
+                self.write_archive(&sampled_schedules)?;
+            }
+        }
 
         // Align to 16
         const PADDING: [u8; 15] = [0; 15];
@@ -49,47 +196,233 @@ where
             x => {
                 let padding = 16 - x;
 
-                if let Err(err) = self.stream.write_all(&PADDING[..padding]) {
-                    #[cfg(feature = "tracing")]
-                    tracing_dep::error!(
-                        padding_len = padding,
-                        "failed to write padding to stream: {:?}",
-                        err,
-                    );
-
-                    return;
-                }
-
+                self.stream.write_all(&PADDING[..padding])?;
                 self.position += padding;
             }
         }
 
         // Write archive
-        self.buffer.clear();
-
-        let mut serializer = AlignedSerializer::new(&mut self.buffer);
-        serializer
-            .serialize_value(&event)
-            .unwrap_or_else(|unreachable| match unreachable {});
-
-        let archive_len = serializer.pos() as u128;
-        let result = self
-            .stream
+        let archive_len = archive.len() as u128;
+        self.stream
             // This will keep 16-byte alignment because archive_len is a u128
             .write_all(&archive_len.to_le_bytes())
-            .and_then(|_| self.stream.write_all(&self.buffer));
+            .and_then(|_| self.stream.write_all(archive))?;
 
-        if let Err(err) = result {
-            #[cfg(feature = "tracing")]
-            tracing_dep::error!(
-                archive_len = %archive_len,
-                "failed to write buffer data to stream: {:?}",
-                err,
-            );
+        self.position += mem::size_of::<u128>() + archive_len as usize;
+        Ok(())
+    }
+
+    /// Flushes the wrapped writer
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Archives an event into the given buffer, clearing it beforehand
+pub(crate) fn archive_event<T, D>(event: TimelyEvent<T, D>, buffer: &mut AlignedVec)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    let event: Event<T, D> = event.into();
+    archive(&event, buffer);
+}
+
+/// Archives an [`Event::Dropped`] into the given buffer, clearing it beforehand
+pub(crate) fn archive_dropped<T, D>(time: T, events: u64, buffer: &mut AlignedVec)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    archive(&Event::<T, D>::Dropped(time, events), buffer);
+}
+
+/// Archives an [`Event::SampledSchedules`] into the given buffer, clearing it beforehand
+pub(crate) fn archive_sampled_schedules<T, D>(one_in: NonZeroUsize, buffer: &mut AlignedVec)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    archive(
+        &Event::<T, D>::SampledSchedules(one_in.get() as u64),
+        buffer,
+    );
+}
+
+/// Archives an [`Event::Sequence`] into the given buffer, clearing it beforehand
+fn archive_sequence<T, D>(sequence: u64, buffer: &mut AlignedVec)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    archive(&Event::<T, D>::Sequence(sequence), buffer);
+}
 
-            return;
+fn archive<T, D>(event: &Event<T, D>, buffer: &mut AlignedVec)
+where
+    T: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+    D: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>>,
+{
+    buffer.clear();
+
+    let mut serializer = AlignedSerializer::new(buffer);
+    serializer
+        .serialize_value(event)
+        .unwrap_or_else(|unreachable| match unreachable {});
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::writer::{
+        event_format_version, EventWriter, EVENT_FORMAT_VERSION, EVENT_HEADER_LEN,
+        SEQUENCE_INTERVAL,
+    };
+    use bytecheck::CheckBytes;
+    use ddshow_types::Event;
+    use rkyv::{
+        check_archived_root, de::deserializers::AllocDeserializer,
+        validation::DefaultArchiveValidator, AlignedVec, Archive, Deserialize,
+    };
+    use std::{
+        convert::TryInto,
+        io::{self, Write},
+        mem,
+        num::NonZeroUsize,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use timely::dataflow::operators::capture::event::{
+        Event as TimelyEvent, EventPusher as TimelyEventPusher,
+    };
+
+    /// A writer that shares its buffer so that what was written can be read back
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub(crate) fn contents(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
         }
+    }
 
-        self.position += mem::size_of::<u128>() + archive_len as usize;
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads back the events of an rkyv event stream that starts with an event header
+    pub(crate) fn read_events<D>(bytes: &[u8]) -> Vec<Event<Duration, D>>
+    where
+        D: Archive,
+        D::Archived: Deserialize<D, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
+    {
+        assert_eq!(event_format_version(bytes), EVENT_FORMAT_VERSION);
+
+        let (mut events, mut position) = (Vec::new(), EVENT_HEADER_LEN);
+        while position < bytes.len() {
+            position += (16 - (position & 15)) & 15;
+
+            let len_end = position + mem::size_of::<u128>();
+            let len = u128::from_le_bytes(bytes[position..len_end].try_into().unwrap()) as usize;
+
+            let mut archive = AlignedVec::new();
+            archive.extend_from_slice(&bytes[len_end..len_end + len]);
+            let event = check_archived_root::<Event<Duration, D>>(&archive)
+                .unwrap()
+                .deserialize(&mut AllocDeserializer)
+                .unwrap_or_else(|unreachable| match unreachable {});
+
+            events.push(event);
+            position = len_end + len;
+        }
+
+        events
+    }
+
+    fn messages(time: u64, data: Vec<u64>) -> TimelyEvent<Duration, u64> {
+        TimelyEvent::Messages(Duration::from_millis(time), data)
+    }
+
+    #[test]
+    fn sequence_numbers_count_written_messages() {
+        let buffer = SharedBuffer::default();
+        let mut writer = EventWriter::new(buffer.clone());
+
+        for time in 0..=SEQUENCE_INTERVAL as u64 {
+            writer.push(messages(time, vec![time, time]));
+        }
+        writer.push(TimelyEvent::Progress(vec![(Duration::from_millis(1), -1)]));
+
+        let events = read_events::<u64>(&buffer.contents());
+        assert_eq!(events.len(), SEQUENCE_INTERVAL + 4);
+        assert_eq!(events[0], Event::Sequence(0));
+        assert!(events[1..=SEQUENCE_INTERVAL]
+            .iter()
+            .all(|event| event.is_messages()));
+
+        // The sequence number after an interval counts every message written before it
+        assert_eq!(
+            events[SEQUENCE_INTERVAL + 1],
+            Event::Sequence(2 * SEQUENCE_INTERVAL as u64),
+        );
+        assert_eq!(
+            events[SEQUENCE_INTERVAL + 2],
+            Event::Messages(
+                Duration::from_millis(SEQUENCE_INTERVAL as u64),
+                vec![SEQUENCE_INTERVAL as u64; 2],
+            ),
+        );
+        assert!(events[SEQUENCE_INTERVAL + 3].is_progress());
+    }
+
+    #[test]
+    fn continued_streams_keep_their_sequence() {
+        let buffer = SharedBuffer::default();
+        let mut writer = EventWriter::new(buffer.clone()).starting_at(10);
+        writer.push(messages(0, vec![1]));
+
+        assert_eq!(
+            read_events::<u64>(&buffer.contents()),
+            vec![
+                Event::Sequence(10),
+                Event::Messages(Duration::from_millis(0), vec![1]),
+            ],
+        );
+    }
+
+    #[test]
+    fn sampled_streams_start_with_their_sample_rate() {
+        let buffer = SharedBuffer::default();
+        let mut writer =
+            EventWriter::new(buffer.clone()).sampled_schedules(NonZeroUsize::new(4).unwrap());
+        writer.push(messages(0, vec![1]));
+
+        let events = read_events::<u64>(&buffer.contents());
+        assert_eq!(
+            events[..2],
+            [Event::SampledSchedules(4), Event::Sequence(0)]
+        );
+
+        // Streams that keep every activation aren't marked
+        let buffer = SharedBuffer::default();
+        let mut writer =
+            EventWriter::new(buffer.clone()).sampled_schedules(NonZeroUsize::new(1).unwrap());
+        writer.push(messages(0, vec![1]));
+
+        let events = read_events::<u64>(&buffer.contents());
+        assert_eq!(events[0], Event::Sequence(0));
+    }
+
+    #[test]
+    fn nothing_is_written_without_events() {
+        let buffer = SharedBuffer::default();
+        drop(EventWriter::<Duration, u64, _>::new(buffer.clone()));
+
+        assert!(buffer.contents().is_empty());
     }
 }
@@ -99,6 +99,15 @@ pub enum Event<T, D> {
     Progress(Vec<(T, i64)>),
     /// Messages received via the data stream
     Messages(T, Vec<D>),
+    /// The number of messages that were dropped instead of being written,
+    /// along with the time of the most recently dropped messages
+    Dropped(T, u64),
+    /// The number of messages that were handed to the writer before the event
+    /// that follows, allowing readers to notice messages that never arrived
+    Sequence(u64),
+    /// Only one in every given number of each operator's activations was
+    /// written, so the stream undercounts operator activations
+    SampledSchedules(u64),
 }
 
 impl<T, D> Event<T, D> {
@@ -129,6 +138,48 @@ impl<T, D> Event<T, D> {
             None
         }
     }
+
+    /// Returns `true` if the event is [`Event::Dropped`]
+    pub const fn is_dropped(&self) -> bool {
+        matches!(self, Self::Dropped(..))
+    }
+
+    /// Returns the number of dropped messages if the event is [`Event::Dropped`]
+    pub const fn as_dropped(&self) -> Option<(&T, u64)> {
+        if let Self::Dropped(time, dropped) = self {
+            Some((time, *dropped))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the event is [`Event::Sequence`]
+    pub const fn is_sequence(&self) -> bool {
+        matches!(self, Self::Sequence(..))
+    }
+
+    /// Returns the sequence number if the event is [`Event::Sequence`]
+    pub const fn as_sequence(&self) -> Option<u64> {
+        if let Self::Sequence(sequence) = self {
+            Some(*sequence)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the event is [`Event::SampledSchedules`]
+    pub const fn is_sampled_schedules(&self) -> bool {
+        matches!(self, Self::SampledSchedules(..))
+    }
+
+    /// Returns the schedule sample rate if the event is [`Event::SampledSchedules`]
+    pub const fn as_sampled_schedules(&self) -> Option<u64> {
+        if let Self::SampledSchedules(one_in) = self {
+            Some(*one_in)
+        } else {
+            None
+        }
+    }
 }
 
 impl<T, D> From<TimelyEvent<T, D>> for Event<T, D> {
@@ -140,11 +191,16 @@ impl<T, D> From<TimelyEvent<T, D>> for Event<T, D> {
     }
 }
 
+/// Timely has no equivalent of [`Event::Dropped`], [`Event::Sequence`] or [`Event::SampledSchedules`],
+/// so they're turned into empty progress updates that don't change the replayed stream
 impl<T, D> From<Event<T, D>> for TimelyEvent<T, D> {
     fn from(val: Event<T, D>) -> Self {
         match val {
             Event::Progress(progress) => Self::Progress(progress),
             Event::Messages(time, messages) => Self::Messages(time, messages),
+            Event::Dropped(..) | Event::Sequence(..) | Event::SampledSchedules(..) => {
+                Self::Progress(Vec::new())
+            }
         }
     }
 }
@@ -1,4 +1,6 @@
+// Mirrors `src/ui/ddshow-stats.schema.json`
 export type DDShowStats = {
+    schema_version: number;
     program: ProgramStats;
     workers: WorkerStats[];
     dataflows: DataflowStats[];
@@ -6,6 +8,11 @@ export type DDShowStats = {
     channels: ChannelStats[];
     arrangements: ArrangementStats[];
     events: TimelineEvent[];
+    lints: Lint[];
+    skew: OperatorSkew[];
+    // Empty when progress logging is disabled
+    critical_paths: DataflowCriticalPath[];
+    data_quality: StreamQuality[];
     differential_enabled: boolean;
     progress_enabled: boolean;
     ddshow_version: string;
@@ -107,6 +114,23 @@ export type ProgramStats = {
     runtime: Duration;
 };
 
+export type StreamQuality = {
+    stream: StreamKind;
+    worker: number;
+    missing: MissingEvents[];
+    dropped: number;
+    truncated: boolean;
+    sequenced: boolean;
+    schedule_sample_rate: number;
+};
+
+export type StreamKind = "Timely" | "Differential" | "Progress";
+
+export type MissingEvents = {
+    start: number;
+    end: number;
+};
+
 export type WorkerStats = {
     id: WorkerId;
     dataflows: number;
@@ -139,6 +163,17 @@ export type NodeStats = {
     lifespan: Lifespan;
     kind: NodeKind;
     activations: AggregatedStats<Duration>;
+    activation_histogram: ActivationHistogram;
+};
+
+export type ActivationHistogram = {
+    buckets: HistogramBucket[];
+};
+
+export type HistogramBucket = {
+    lower: Duration;
+    upper: Duration;
+    count: number;
 };
 
 export type NodeKind = "Operator" | "Subgraph" | "Dataflow";
@@ -156,6 +191,8 @@ export type ChannelStats = {
 export type ChannelKind = "Ingress" | "Egress" | "Normal";
 
 export type ArrangementStats = {
+    id: OperatorId;
+    worker: WorkerId;
     operator_addr: OperatorAddr;
     size_stats: AggregatedStats<number>;
     merge_stats: AggregatedStats<Duration>;
@@ -165,11 +202,82 @@ export type ArrangementStats = {
 };
 
 export type TimelineEvent = {
-    event_id: number;
-    worker: number;
-    start_time: number;
-    duration: number;
-    event: any;
+    worker: WorkerId;
+    event: EventKind;
+    lifespan: Lifespan;
+};
+
+export type EventKind =
+    | "Parked"
+    | "Input"
+    | "Message"
+    | "Progress"
+    | { OperatorActivation: { operator_id: OperatorId } }
+    | { Application: { id: number } }
+    | { Merge: { operator_id: OperatorId } };
+
+export type Lint = {
+    rule: LintRule;
+    severity: Severity;
+    addr: OperatorAddr;
+    // Empty if the lint applies to every worker
+    workers: WorkerId[];
+    explanation: string;
+};
+
+export type LintRule =
+    | "inconsistent-workers"
+    | "arrange-before-feedback"
+    | "undelivered-messages"
+    | "hot-operator";
+
+export type Severity = "Info" | "Warning" | "Critical";
+
+export type OperatorSkew = {
+    // The operator's id on the reference worker
+    id: OperatorId;
+    addr: OperatorAddr;
+    name: string;
+    workers: number;
+    runtime: Skew;
+    activations: Skew;
+    arrangement_size: Skew | null;
+    highlighted: boolean;
+};
+
+export type Skew = {
+    max_worker: WorkerId;
+    // Null if the median is zero while the max isn't
+    max_median_ratio: number | null;
+    coefficient_of_variation: number;
+};
+
+export type DataflowCriticalPath = {
+    dataflow: OperatorAddr;
+    name: string;
+    // Counted once per worker
+    timestamps: number;
+    average_latency: Duration;
+    max_latency: Duration;
+    slowest: CriticalTimestamp | null;
+    // The operators that contributed the most latency come first
+    contributors: CriticalContributor[];
+};
+
+export type CriticalTimestamp = {
+    worker: WorkerId;
+    timestamp: string;
+    latency: Duration;
+    // From the input to the probe
+    path: OperatorAddr[];
+};
+
+export type CriticalContributor = {
+    addr: OperatorAddr;
+    name: string;
+    timestamps: number;
+    latency: Duration;
+    busy: Duration;
 };
 
 export type WorkerId = number;
@@ -184,6 +292,10 @@ export type Duration = {
     nanos: number;
 };
 
+export function duration_nanos(duration: Duration): number {
+    return duration.secs * 1_000_000_000 + duration.nanos;
+}
+
 export type Lifespan = {
     birth: Duration;
     death: Duration;
@@ -194,5 +306,14 @@ export type AggregatedStats<T> = {
     max: T;
     min: T;
     average: T;
-    data_points: T;
+    std_dev: T;
+    percentiles: Percentiles<T>;
+    data_points: T[];
+};
+
+export type Percentiles<T> = {
+    p50: T;
+    p90: T;
+    p99: T;
+    p999: T;
 };
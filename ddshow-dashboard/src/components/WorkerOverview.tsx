@@ -17,6 +17,7 @@ import { Tooltip } from "@visx/tooltip";
 // import Gantt from "./Gantt";
 import {
     Duration,
+    duration_nanos,
     OperatorAddr,
     WorkerStats,
     TimelineEvent,
@@ -112,8 +113,8 @@ export default function WorkerOverview(props: WorkerOverviewProps) {
         props.data.length >= 1
             ? props.data[0].events.map(event => {
                   return {
-                      start: event.start_time,
-                      end: event.start_time + event.duration,
+                      start: duration_nanos(event.lifespan.birth),
+                      end: duration_nanos(event.lifespan.death),
                       // FIXME: This is bad
                       name: "Eeee",
                       group: event.worker,
@@ -0,0 +1,521 @@
+//! Single-file trace archives that bundle a directory of saved logs
+//!
+//! An archive starts with a header containing the archive's format version,
+//! the number of workers that logs were recorded from, the log streams that
+//! are present, the name of the traced program and the time the capture
+//! started at. The header is followed by an index of sections, each of which holds
+//! the (possibly compressed) log file of a single stream from a single worker.
+//!
+//! ```text
+//! magic               [u8; 8]     b"DDSHOWAR"
+//! version             u16
+//! workers             u32
+//! streams             u8          bitflags, see `StreamKind`
+//! start time secs     u64         since the unix epoch
+//! start time nanos    u32
+//! program name len    u32
+//! program name        [u8]        utf8
+//! section count       u32
+//! sections            [Section]
+//!     stream          u8
+//!     worker          u32
+//!     compression     u8
+//!     offset          u64         from the start of the archive
+//!     length          u64
+//! section data        [u8]
+//! ```
+//!
+//! All integers are little endian
+
+use crate::replay_loading::check_event_file;
+use abomonation_derive::Abomonation;
+use anyhow::{Context, Result};
+use bytecheck::CheckBytes;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ddshow_sink::{
+    Compression, Handshake, DIFFERENTIAL_ARRANGEMENT_LOG_FILE, TIMELY_LOG_FILE,
+    TIMELY_PROGRESS_LOG_FILE,
+};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// The magic bytes at the start of every trace archive
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"DDSHOWAR";
+
+/// The current version of the trace archive format
+pub const ARCHIVE_VERSION: u16 = 1;
+
+/// The log streams that can be stored within an archive
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub enum StreamKind {
+    Timely,
+    Differential,
+    Progress,
+}
+
+impl StreamKind {
+    const ALL: [Self; 3] = [Self::Timely, Self::Differential, Self::Progress];
+
+    /// The prefix of log files that contain the current stream
+    pub const fn file_prefix(self) -> &'static str {
+        match self {
+            Self::Timely => TIMELY_LOG_FILE,
+            Self::Differential => DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+            Self::Progress => TIMELY_PROGRESS_LOG_FILE,
+        }
+    }
+
+    /// Gets the stream kind of the given log file prefix
+    pub fn from_file_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.file_prefix() == prefix)
+    }
+
+    const fn flag(self) -> u8 {
+        match self {
+            Self::Timely => 1 << 0,
+            Self::Differential => 1 << 1,
+            Self::Progress => 1 << 2,
+        }
+    }
+
+    fn from_flag(flag: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.flag() == flag)
+    }
+}
+
+impl From<ddshow_sink::StreamKind> for StreamKind {
+    fn from(stream: ddshow_sink::StreamKind) -> Self {
+        match stream {
+            ddshow_sink::StreamKind::Timely => Self::Timely,
+            ddshow_sink::StreamKind::Differential => Self::Differential,
+            ddshow_sink::StreamKind::Progress => Self::Progress,
+        }
+    }
+}
+
+impl Display for StreamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Timely => "timely",
+            Self::Differential => "differential",
+            Self::Progress => "progress",
+        })
+    }
+}
+
+/// A single worker's log file for a single stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub stream: StreamKind,
+    pub worker: u32,
+    pub compression: Compression,
+    /// The offset of the section's data from the start of the archive
+    pub offset: u64,
+    /// The length of the section's data in bytes
+    pub len: u64,
+}
+
+/// The header of a trace archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveHeader {
+    pub version: u16,
+    /// The number of workers that logs were recorded from
+    pub workers: u32,
+    pub program_name: String,
+    /// The time that the capture was started at
+    pub start_time: SystemTime,
+    pub sections: Vec<Section>,
+}
+
+impl ArchiveHeader {
+    /// Returns `true` if the archive contains the given stream
+    pub fn has_stream(&self, stream: StreamKind) -> bool {
+        self.sections.iter().any(|section| section.stream == stream)
+    }
+
+    /// Returns the streams contained within the archive
+    pub fn streams(&self) -> Vec<StreamKind> {
+        StreamKind::ALL
+            .iter()
+            .copied()
+            .filter(|&stream| self.has_stream(stream))
+            .collect()
+    }
+
+    /// Reads and validates the header of the archive at the given path
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open trace archive {}", path.display()))?;
+        let archive_len = file.metadata()?.len();
+
+        let header = Self::read(&mut BufReader::new(file))
+            .with_context(|| format!("failed to read trace archive {}", path.display()))?;
+        header
+            .validate(archive_len)
+            .with_context(|| format!("invalid trace archive {}", path.display()))?;
+
+        Ok(header)
+    }
+
+    /// Opens a reader for every section of the given stream, sorted by worker
+    pub fn open_sections(
+        &self,
+        path: &Path,
+        stream: StreamKind,
+    ) -> Result<Vec<Box<dyn Read + Send + 'static>>> {
+        let mut sections: Vec<&Section> = self
+            .sections
+            .iter()
+            .filter(|section| section.stream == stream)
+            .collect();
+        sections.sort_unstable_by_key(|section| section.worker);
+
+        sections
+            .into_iter()
+            .map(|section| {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(section.offset))?;
+
+                let reader = BufReader::new(file.take(section.len));
+                section.compression.decompress(reader).with_context(|| {
+                    format!(
+                        "failed to decompress the {} section for worker {}",
+                        section.stream, section.worker,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        anyhow::ensure!(magic == ARCHIVE_MAGIC, "not a ddshow trace archive");
+
+        let version = reader.read_u16::<LittleEndian>()?;
+        anyhow::ensure!(
+            version == ARCHIVE_VERSION,
+            "unsupported trace archive version {}, this version of ddshow supports version {}",
+            version,
+            ARCHIVE_VERSION,
+        );
+
+        let workers = reader.read_u32::<LittleEndian>()?;
+        let streams = reader.read_u8()?;
+
+        let secs = reader.read_u64::<LittleEndian>()?;
+        let nanos = reader.read_u32::<LittleEndian>()?;
+        let start_time = SystemTime::UNIX_EPOCH + Duration::new(secs, nanos);
+
+        let name_len = reader.read_u32::<LittleEndian>()?;
+        let mut program_name = Vec::new();
+        reader
+            .take(name_len as u64)
+            .read_to_end(&mut program_name)?;
+        anyhow::ensure!(
+            program_name.len() == name_len as usize,
+            "the archive's header was truncated",
+        );
+        let program_name =
+            String::from_utf8(program_name).context("the program name isn't valid utf8")?;
+
+        let num_sections = reader.read_u32::<LittleEndian>()?;
+        let mut sections = Vec::with_capacity(num_sections.min(1024) as usize);
+        for _ in 0..num_sections {
+            let stream = reader.read_u8()?;
+            let stream = StreamKind::from_flag(stream)
+                .ok_or_else(|| anyhow::format_err!("unknown stream kind {}", stream))?;
+            let worker = reader.read_u32::<LittleEndian>()?;
+
+            let compression = match reader.read_u8()? {
+                0 => Compression::None,
+                1 => Compression::Zstd,
+                2 => Compression::Gzip,
+                unknown => anyhow::bail!("unknown compression kind {}", unknown),
+            };
+
+            let offset = reader.read_u64::<LittleEndian>()?;
+            let len = reader.read_u64::<LittleEndian>()?;
+
+            sections.push(Section {
+                stream,
+                worker,
+                compression,
+                offset,
+                len,
+            });
+        }
+
+        let header = Self {
+            version,
+            workers,
+            program_name,
+            start_time,
+            sections,
+        };
+
+        let present = header
+            .streams()
+            .into_iter()
+            .fold(0, |flags, stream| flags | stream.flag());
+        anyhow::ensure!(
+            present == streams,
+            "the archive's streams don't match its sections",
+        );
+
+        Ok(header)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        writer.write_u16::<LittleEndian>(self.version)?;
+        writer.write_u32::<LittleEndian>(self.workers)?;
+        writer.write_u8(
+            self.streams()
+                .into_iter()
+                .fold(0, |flags, stream| flags | stream.flag()),
+        )?;
+
+        let start_time = self
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        writer.write_u64::<LittleEndian>(start_time.as_secs())?;
+        writer.write_u32::<LittleEndian>(start_time.subsec_nanos())?;
+
+        writer.write_u32::<LittleEndian>(self.program_name.len() as u32)?;
+        writer.write_all(self.program_name.as_bytes())?;
+
+        writer.write_u32::<LittleEndian>(self.sections.len() as u32)?;
+        for section in self.sections.iter() {
+            writer.write_u8(section.stream.flag())?;
+            writer.write_u32::<LittleEndian>(section.worker)?;
+            writer.write_u8(match section.compression {
+                Compression::None => 0,
+                Compression::Zstd => 1,
+                Compression::Gzip => 2,
+            })?;
+            writer.write_u64::<LittleEndian>(section.offset)?;
+            writer.write_u64::<LittleEndian>(section.len)?;
+        }
+
+        Ok(())
+    }
+
+    /// The length of the header in bytes
+    fn len(&self) -> u64 {
+        const SECTION_LEN: u64 = 1 + 4 + 1 + 8 + 8;
+
+        ARCHIVE_MAGIC.len() as u64
+            + 2
+            + 4
+            + 1
+            + 8
+            + 4
+            + 4
+            + self.program_name.len() as u64
+            + 4
+            + SECTION_LEN * self.sections.len() as u64
+    }
+
+    /// Makes sure that every section lies within the archive and that every
+    /// worker has exactly one section for each stream in the archive
+    fn validate(&self, archive_len: u64) -> Result<()> {
+        anyhow::ensure!(self.workers != 0, "the archive contains zero workers");
+        anyhow::ensure!(
+            self.has_stream(StreamKind::Timely),
+            "the archive doesn't contain any timely logs",
+        );
+
+        for section in self.sections.iter() {
+            anyhow::ensure!(
+                section.worker < self.workers,
+                "the {} section for worker {} is out of range, the archive only has {} workers",
+                section.stream,
+                section.worker,
+                self.workers,
+            );
+
+            let end = section.offset.checked_add(section.len);
+            anyhow::ensure!(
+                section.offset >= self.len() && matches!(end, Some(end) if end <= archive_len),
+                "the {} section for worker {} lies outside of the archive, the archive may be truncated",
+                section.stream,
+                section.worker,
+            );
+        }
+
+        for stream in self.streams() {
+            for worker in 0..self.workers {
+                let count = self
+                    .sections
+                    .iter()
+                    .filter(|section| section.stream == stream && section.worker == worker)
+                    .count();
+
+                anyhow::ensure!(
+                    count == 1,
+                    "expected one {} section for worker {} but found {}",
+                    stream,
+                    worker,
+                    count,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Bundles a directory of log files saved by `--save-logs` or `ddshow-sink`
+/// into a single trace archive
+///
+/// Log files are found by their names, but everything else is taken from the
+/// handshake at the start of each file. The handshakes give the stream and worker
+/// each file belongs to and the number of workers, and the capture's start time is
+/// the earliest time that any of the workers started at
+pub fn bundle(log_dir: &Path, archive: &Path, program_name: &str) -> Result<ArchiveHeader> {
+    let mut files = Vec::new();
+    let mut workers: Option<u32> = None;
+    let mut start_time: Option<SystemTime> = None;
+
+    for entry in fs::read_dir(log_dir)
+        .with_context(|| format!("failed to read log directory {}", log_dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        // Log files are named `{prefix}.{worker}.ddshow`, optionally followed by
+        // the extension of the compression they use
+        let is_log_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split('.').next())
+            .and_then(StreamKind::from_file_prefix)
+            .is_some()
+            && Compression::from_path(&path).is_some();
+
+        if !is_log_file {
+            tracing::warn!("skipping {} while bundling logs", path.display());
+            continue;
+        }
+
+        let len = entry.metadata()?.len();
+        let (compression, handshake) = read_handshake(&path)
+            .with_context(|| format!("failed to read log file {}", path.display()))?;
+        let handshake = handshake.with_context(|| {
+            format!(
+                "{} doesn't start with a handshake, only logs saved by this version of ddshow \
+                 or ddshow-sink can be bundled",
+                path.display(),
+            )
+        })?;
+
+        let peers = handshake.peers as u32;
+        let expected = *workers.get_or_insert(peers);
+        anyhow::ensure!(
+            peers == expected,
+            "{} was saved by one of {} workers but other log files were saved by one of {} workers",
+            path.display(),
+            peers,
+            expected,
+        );
+
+        start_time = Some(start_time.map_or(handshake.started_at, |start| {
+            start.min(handshake.started_at)
+        }));
+
+        let stream = StreamKind::from(handshake.stream);
+        files.push((stream, handshake.worker as u32, compression, path, len));
+    }
+
+    files.sort_unstable_by_key(|&(stream, worker, ..)| (stream, worker));
+
+    let mut header = ArchiveHeader {
+        version: ARCHIVE_VERSION,
+        workers: workers.unwrap_or(0),
+        program_name: program_name.to_owned(),
+        start_time: start_time.unwrap_or_else(SystemTime::now),
+        sections: Vec::with_capacity(files.len()),
+    };
+
+    // Lay the sections out directly after the header, the header's length
+    // doesn't depend on the section offsets so we can compute it up front
+    header.sections = files
+        .iter()
+        .map(|&(stream, worker, compression, _, len)| Section {
+            stream,
+            worker,
+            compression,
+            offset: 0,
+            len,
+        })
+        .collect();
+
+    let mut offset = header.len();
+    for section in header.sections.iter_mut() {
+        section.offset = offset;
+        offset += section.len;
+    }
+
+    header
+        .validate(offset)
+        .with_context(|| format!("failed to bundle the logs in {}", log_dir.display()))?;
+
+    let mut writer = BufWriter::new(
+        File::create(archive)
+            .with_context(|| format!("failed to create trace archive {}", archive.display()))?,
+    );
+    header.write(&mut writer)?;
+
+    for (stream, worker, _, path, len) in files {
+        let copied = io::copy(&mut File::open(&path)?.take(len), &mut writer)?;
+        anyhow::ensure!(
+            copied == len,
+            "the {} log file for worker {} changed while it was being bundled",
+            stream,
+            worker,
+        );
+    }
+    writer.flush()?;
+
+    Ok(header)
+}
+
+/// Reads the compression and handshake of a saved log file
+fn read_handshake(path: &Path) -> Result<(Compression, Option<Handshake>)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(&mut file, path)?;
+    let (handshake, _) = check_event_file(compression.decompress(file)?)?;
+
+    Ok((compression, handshake))
+}
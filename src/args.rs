@@ -1,6 +1,15 @@
 pub use colorous::Gradient;
-
-use std::{net::SocketAddr, num::NonZeroUsize, path::PathBuf, str::FromStr};
+pub use ddshow_sink::Compression;
+
+use crate::{config::Config, dataflow::PROGRAM_NS_GRANULARITY, lint::LintRule};
+use anyhow::Result;
+use std::{
+    fmt::{self, Display},
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+};
 use structopt::StructOpt;
 use timely::{CommunicationConfig, WorkerConfig};
 
@@ -9,7 +18,12 @@ use timely::{CommunicationConfig, WorkerConfig};
 /// Set the `TIMELY_WORKER_LOG_ADDR` environmental variable to `127.0.0.1:51317` (or whatever
 /// address you customized it to using `--listen`) to listen for Timely Dataflow computations
 /// and the `DIFFERENTIAL_LOG_ADDR` variable to gather data on Differential Dataflow computations.
-/// Set `--connections` to the number of timely workers that the target computation is using.
+/// Set `--connections` to the number of timely workers that the target computation is using,
+/// connections made by `ddshow-sink` send a handshake that tells ddshow the number of workers.
+///
+/// Any of these options can also be set within a toml config file given by `--config`,
+/// `./ddshow.toml` will be used if it exists. Args given on the command line take precedence
+/// over the config file.
 ///
 // TODO: Better docs
 // TODO: Number of workers
@@ -19,16 +33,19 @@ use timely::{CommunicationConfig, WorkerConfig};
 // TODO: Build info in help message
 // TODO: Reachability logging
 // TODO: Disable timeline events
-// TODO: This is complex enough to where it may need an
-//       actual config file
 #[derive(Debug, Clone, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Args {
+    /// The config file to read options from, defaults to `./ddshow.toml` if it exists
+    #[structopt(long)]
+    pub config: Option<PathBuf>,
+
     /// The number of ddshow workers to run
     #[structopt(short = "w", long, default_value = "1")]
     pub workers: NonZeroUsize,
 
-    /// The number of timely workers running in the target computation
+    /// The number of timely workers running in the target computation, only
+    /// used for connections that don't start with a `ddshow-sink` handshake
     #[structopt(short = "c", long = "connections", default_value = "1")]
     pub timely_connections: NonZeroUsize,
 
@@ -54,6 +71,11 @@ pub struct Args {
     #[structopt(long, default_value = "127.0.0.1:51319")]
     pub progress_address: SocketAddr,
 
+    /// A unix socket to listen on for multiplexed connections made
+    /// with `ddshow_sink::Multiplexer::connect_unix()`
+    #[structopt(long, conflicts_with_all(&["replay-logs", "reconnect"]))]
+    pub unix_socket: Option<PathBuf>,
+
     /// The color palette to use for the generated graphs
     #[structopt(
         long,
@@ -67,23 +89,71 @@ pub struct Args {
     #[structopt(long, default_value = "dataflow-graph")]
     pub output_dir: PathBuf,
 
-    /// The path to dump the json data to
+    /// The file to write the program's stats to as json
     ///
-    /// The format is currently unstable, so don't depend on it too hard
-    #[structopt(long, hidden(true))]
+    /// The json is versioned and described by the JSON Schema
+    /// in `src/ui/ddshow-stats.schema.json`
+    #[structopt(long)]
     pub dump_json: Option<PathBuf>,
 
+    /// The file to write the workers' timelines to in the Chrome Trace Event
+    /// format, which can be opened in Perfetto or `chrome://tracing`
+    #[structopt(long, conflicts_with = "disable-timeline")]
+    pub chrome_trace: Option<PathBuf>,
+
+    /// The file to write the dataflow graph to in Graphviz's DOT format
+    #[structopt(long)]
+    pub dot: Option<PathBuf>,
+
     /// The folder to save the target process's logs to
     #[structopt(long)]
     pub save_logs: Option<PathBuf>,
 
-    /// The directory to replay a recorded set of logs from
+    /// The compression to use for logs saved with `--save-logs`
+    #[structopt(
+        long,
+        default_value = "none",
+        possible_values = &["none", "zstd", "gzip"],
+    )]
+    pub log_compression: Compression,
+
+    /// The directory or trace archive to replay a recorded set of logs from
     #[structopt(
         long,
         conflicts_with_all(&["save-logs", "connections", "address", "differential-address", "progress-address"]),
     )]
     pub replay_logs: Option<PathBuf>,
 
+    /// Bundles the logs saved with `--save-logs` or replayed with `--replay-logs`
+    /// into a single trace archive file that can be replayed with `--replay-logs`
+    #[structopt(long)]
+    pub bundle_logs: Option<PathBuf>,
+
+    /// The name of the target program, recorded within trace archives
+    /// created with `--bundle-logs`
+    #[structopt(long)]
+    pub program_name: Option<String>,
+
+    /// Stops collecting trace data after the given number of seconds
+    /// instead of waiting for user input
+    #[structopt(long, value_name = "SECONDS")]
+    pub stop_after: Option<u64>,
+
+    /// Stops collecting trace data once no events have been received for
+    /// the given number of seconds instead of waiting for user input
+    #[structopt(long, value_name = "SECONDS")]
+    pub stop_when_idle: Option<u64>,
+
+    /// Stops collecting trace data once every source connection has closed
+    /// instead of waiting for user input
+    #[structopt(long)]
+    pub stop_on_disconnect: bool,
+
+    /// Serves a live, auto-refreshing version of the graph and dashboard over
+    /// http at the given address, e.g. `127.0.0.1:8080`
+    #[structopt(long)]
+    pub serve: Option<SocketAddr>,
+
     /// The file to output a text report to
     #[structopt(long, default_value = "report.txt")]
     pub report_file: PathBuf,
@@ -105,6 +175,13 @@ pub struct Args {
     #[structopt(long)]
     pub disable_timeline: bool,
 
+    /// Records the start time and duration of every operator activation
+    /// in addition to the activation histograms, this can use a lot of
+    /// memory on long-running target programs
+    #[structopt(long)]
+    pub activation_durations: bool,
+
+    /// The encoding of connections that don't start with a `ddshow-sink` handshake
     #[structopt(
         long,
         default_value = "abomonation",
@@ -112,6 +189,12 @@ pub struct Args {
     )]
     pub stream_encoding: StreamEncoding,
 
+    /// Keeps accepting connections from target workers that reconnect or restart,
+    /// stitching each worker's connections into a single replay. Requires workers
+    /// that connect with `ddshow_sink::stream_*()`
+    #[structopt(long, conflicts_with_all(&["replay-logs", "stop-on-disconnect"]))]
+    pub reconnect: bool,
+
     /// The time between updating the report file in seconds
     #[structopt(long, conflicts_with("no-report-file"), hidden(true))]
     pub report_update_duration: Option<u8>,
@@ -119,9 +202,67 @@ pub struct Args {
     /// Disables ddshow's terminal output
     #[structopt(long, short = "q", hidden(true))]
     pub quiet: bool,
+
+    /// The size of the time windows (in nanoseconds) that program statistics
+    /// and timeline events are bucketed into
+    #[structopt(long, default_value = "5000000000", hidden(true))]
+    pub program_ns_granularity: NonZeroU64,
+
+    /// Disables a lint, can be given multiple times
+    #[structopt(
+        long = "disable-lint",
+        number_of_values = 1,
+        possible_values = LintRule::NAMES,
+    )]
+    pub disabled_lints: Vec<LintRule>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum Command {
+    /// Compares the operator stats of two saved traces, useful for catching
+    /// performance regressions between versions of a dataflow
+    Diff(DiffArgs),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DiffArgs {
+    /// The baseline trace, either a directory of saved logs or a trace archive
+    pub trace_a: PathBuf,
+
+    /// The trace to compare against the baseline
+    pub trace_b: PathBuf,
+
+    /// The file to write the diff to as json
+    #[structopt(long)]
+    pub json: Option<PathBuf>,
 }
 
 impl Args {
+    /// Parses the command line args, layering them on top of the
+    /// config file if there is one
+    pub fn from_args_and_config() -> Result<Self> {
+        let matches = Self::clap().get_matches();
+        let mut args = Self::from_clap(&matches);
+
+        let config_file = args.config.clone().or_else(|| {
+            let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+            default.is_file().then_some(default)
+        });
+
+        if let Some(config_file) = config_file {
+            tracing::debug!("loading config file from {}", config_file.display());
+
+            Config::load(&config_file)?.apply(&mut args, &matches)?;
+        }
+
+        Ok(args)
+    }
+
     pub fn timely_config(&self) -> (CommunicationConfig, WorkerConfig) {
         let communication = if self.workers.get() == 1 {
             CommunicationConfig::Thread
@@ -136,6 +277,12 @@ impl Args {
         (communication, worker_config)
     }
 
+    /// Returns `true` if the user should be prompted to stop collecting trace data,
+    /// `false` if any of the non-interactive stop conditions were given
+    pub const fn is_interactive(&self) -> bool {
+        self.stop_after.is_none() && self.stop_when_idle.is_none() && !self.stop_on_disconnect
+    }
+
     /// Returns `true` if the program is replaying logs from a file
     pub const fn is_file_sourced(&self) -> bool {
         self.replay_logs.is_some()
@@ -147,7 +294,13 @@ impl Default for Args {
         // Safety: One isn't zero
         const ONE: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(1) };
 
+        const GRANULARITY: NonZeroU64 = match NonZeroU64::new(PROGRAM_NS_GRANULARITY as u64) {
+            Some(granularity) => granularity,
+            None => panic!("the default program stats granularity can't be zero"),
+        };
+
         Self {
+            config: None,
             workers: ONE,
             timely_connections: ONE,
             timely_address: "127.0.0.1:51317".parse().unwrap(),
@@ -155,23 +308,41 @@ impl Default for Args {
             differential_address: "127.0.0.1:51318".parse().unwrap(),
             progress_enabled: false,
             progress_address: "127.0.0.1:51319".parse().unwrap(),
+            unix_socket: None,
             palette: colorous::INFERNO,
             output_dir: PathBuf::from("dataflow-graph"),
             dump_json: None,
+            chrome_trace: None,
+            dot: None,
             save_logs: None,
+            log_compression: Compression::None,
             replay_logs: None,
+            bundle_logs: None,
+            program_name: None,
+            stop_after: None,
+            stop_when_idle: None,
+            stop_on_disconnect: false,
+            serve: None,
             report_file: PathBuf::from("report.txt"),
             no_report_file: false,
             color: TerminalColor::Auto,
             dataflow_profiling: false,
             disable_timeline: false,
+            activation_durations: false,
             stream_encoding: StreamEncoding::Abomonation,
+            reconnect: false,
             report_update_duration: None,
             quiet: false,
+            program_ns_granularity: GRANULARITY,
+            disabled_lints: Vec::new(),
+            command: None,
         }
     }
 }
 
+/// The config file that's loaded when `--config` isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "ddshow.toml";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum StreamEncoding {
     Abomonation,
@@ -194,6 +365,24 @@ impl FromStr for StreamEncoding {
     }
 }
 
+impl From<ddshow_sink::StreamEncoding> for StreamEncoding {
+    fn from(encoding: ddshow_sink::StreamEncoding) -> Self {
+        match encoding {
+            ddshow_sink::StreamEncoding::Rkyv => Self::Rkyv,
+            ddshow_sink::StreamEncoding::Abomonation => Self::Abomonation,
+        }
+    }
+}
+
+impl Display for StreamEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Abomonation => "abomonation",
+            Self::Rkyv => "rkyv",
+        })
+    }
+}
+
 impl Default for StreamEncoding {
     fn default() -> Self {
         Self::Abomonation
@@ -249,7 +438,7 @@ impl Default for TerminalColor {
 
 macro_rules! parse_gradient {
     ($($lower:literal => $gradient:ident),* $(,)?) => {
-        fn gradient_from_str(src: &str) -> Result<Gradient, String> {
+        pub(crate) fn gradient_from_str(src: &str) -> Result<Gradient, String> {
             let gradient = src.to_lowercase();
 
             let gradient = match gradient.as_str() {
@@ -0,0 +1,214 @@
+//! Exports the workers' timelines in the [Chrome Trace Event] format, which
+//! can be opened by [Perfetto] or `chrome://tracing`
+//!
+//! Each worker gets its own thread track, with operator activations, merges,
+//! parks and the other timeline events each in their own category
+//!
+//! [Chrome Trace Event]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+//! [Perfetto]: https://ui.perfetto.dev
+
+use crate::{
+    args::Args,
+    dataflow::{DataflowData, EventKind},
+};
+use anyhow::{Context, Result};
+use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// The process id given to every event, the target program is a single process
+const TRACE_PID: usize = 0;
+
+/// Writes the timeline to the given file as a Chrome trace
+pub(crate) fn write_trace_file(
+    args: &Args,
+    file: &Path,
+    data: &DataflowData,
+    name_lookup: &HashMap<(WorkerId, OperatorId), String>,
+    addr_lookup: &HashMap<(WorkerId, OperatorId), OperatorAddr>,
+) -> Result<()> {
+    if let Some(parent) = file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))?;
+    }
+
+    let mut writer = BufWriter::new(
+        File::create(file)
+            .with_context(|| format!("failed to create chrome trace file {}", file.display()))?,
+    );
+    write_trace(args, data, name_lookup, addr_lookup, &mut writer)?;
+    writer.flush().context("failed to write chrome trace")?;
+
+    Ok(())
+}
+
+/// Writes the timeline as a Chrome trace, events are streamed out one
+/// at a time so that huge timelines don't need to be buffered
+fn write_trace<W>(
+    args: &Args,
+    data: &DataflowData,
+    name_lookup: &HashMap<(WorkerId, OperatorId), String>,
+    addr_lookup: &HashMap<(WorkerId, OperatorId), OperatorAddr>,
+    writer: &mut W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let mut events: Vec<_> = data.timeline_events.iter().collect();
+    events.sort_unstable_by_key(|event| (event.worker, event.start_time));
+
+    let workers: BTreeSet<_> = events.iter().map(|event| event.worker).collect();
+    let program_name = args.program_name.as_deref().unwrap_or("Timely Program");
+
+    writer.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+
+    let mut first = true;
+    let mut write_event = |event: &TraceEvent<'_>, writer: &mut W| -> Result<()> {
+        if !first {
+            writer.write_all(b",\n")?;
+        }
+        first = false;
+
+        serde_json::to_writer(&mut *writer, event).context("failed to write chrome trace event")
+    };
+
+    // Name the process and give each worker its own named track
+    write_event(
+        &TraceEvent::metadata("process_name", 0, program_name.to_owned()),
+        writer,
+    )?;
+    for &worker in workers.iter() {
+        let tid = worker.into_inner();
+
+        write_event(
+            &TraceEvent::metadata("thread_name", tid, format!("Worker {}", tid)),
+            writer,
+        )?;
+        write_event(
+            &TraceEvent {
+                args: TraceArgs {
+                    sort_index: Some(tid),
+                    ..Default::default()
+                },
+                ..TraceEvent::metadata("thread_sort_index", tid, String::new())
+            },
+            writer,
+        )?;
+    }
+
+    for event in events {
+        let operator = |operator_id: OperatorId| {
+            let key = (event.worker, operator_id);
+            let name = name_lookup
+                .get(&key)
+                .map_or("Operator", |name| name.as_str());
+
+            match addr_lookup.get(&key) {
+                Some(addr) => (format!("{} {}", name, addr), Some(addr)),
+                None => (name.to_owned(), None),
+            }
+        };
+
+        let (name, category, operator_id, addr) = match event.event {
+            EventKind::OperatorActivation { operator_id } => {
+                let (name, addr) = operator(operator_id);
+                (name, "activation", Some(operator_id), addr)
+            }
+            EventKind::Merge { operator_id } => {
+                let (name, addr) = operator(operator_id);
+                (format!("Merge {}", name), "merge", Some(operator_id), addr)
+            }
+            EventKind::Application { id } => {
+                (format!("Application {}", id), "application", None, None)
+            }
+            EventKind::Parked => ("Parked".to_owned(), "park", None, None),
+            EventKind::Input => ("Input".to_owned(), "input", None, None),
+            EventKind::Message => ("Message".to_owned(), "message", None, None),
+            EventKind::Progress => ("Progress".to_owned(), "progress", None, None),
+        };
+
+        write_event(
+            &TraceEvent {
+                name,
+                cat: Some(category),
+                ph: "X",
+                ts: Some(nanos_to_micros(event.start_time)),
+                dur: Some(nanos_to_micros(event.duration)),
+                pid: TRACE_PID,
+                tid: event.worker.into_inner(),
+                args: TraceArgs {
+                    operator_id,
+                    addr,
+                    collapsed_events: Some(event.collapsed_events),
+                    ..Default::default()
+                },
+            },
+            writer,
+        )?;
+    }
+
+    writer.write_all(b"]}\n")?;
+
+    Ok(())
+}
+
+/// Trace event timestamps are in (fractional) microseconds
+fn nanos_to_micros(nanos: u64) -> f64 {
+    nanos as f64 / 1000.0
+}
+
+#[derive(Debug, Serialize)]
+struct TraceEvent<'a> {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+    ph: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: usize,
+    tid: usize,
+    args: TraceArgs<'a>,
+}
+
+impl TraceEvent<'_> {
+    fn metadata(kind: &'static str, tid: usize, name: String) -> Self {
+        Self {
+            name: kind.to_owned(),
+            cat: None,
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: TRACE_PID,
+            tid,
+            args: TraceArgs {
+                name: Some(name).filter(|name| !name.is_empty()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TraceArgs<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operator_id: Option<OperatorId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<&'a OperatorAddr>,
+    /// The number of raw events that were merged into this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collapsed_events: Option<usize>,
+}
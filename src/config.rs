@@ -0,0 +1,525 @@
+//! The toml config file that can be used in place of command line args
+//!
+//! ```toml
+//! workers = 4
+//! connections = 4
+//! palette = "viridis"
+//! color = "always"
+//! program-name = "my-dataflow"
+//!
+//! [listen]
+//! timely = "127.0.0.1:51317"
+//! differential = "127.0.0.1:51318"
+//! progress = "127.0.0.1:51319"
+//! unix-socket = "/tmp/ddshow.sock"
+//!
+//! [streams]
+//! differential = true
+//! progress = false
+//! encoding = "rkyv"
+//! reconnect = false
+//!
+//! [output]
+//! dir = "dataflow-graph"
+//! report = true
+//! report-file = "report.txt"
+//! dump-json = "ddshow.json"
+//! chrome-trace = "trace.json"
+//! dot = "graph.dot"
+//! save-logs = "logs"
+//! log-compression = "zstd"
+//! bundle-logs = "trace.ddarchive"
+//! serve = "127.0.0.1:8080"
+//!
+//! [timeline]
+//! enabled = true
+//!
+//! [stop]
+//! after = 60
+//! when-idle = 5
+//! on-disconnect = true
+//!
+//! [extraction]
+//! program-ns-granularity = 5_000_000_000
+//! activation-durations = false
+//!
+//! [lints]
+//! disable = ["hot-operator"]
+//! ```
+
+use crate::{
+    args::{gradient_from_str, Args, Compression, StreamEncoding, TerminalColor},
+    lint::LintRule,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+};
+use structopt::clap::ArgMatches;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The number of ddshow workers to run
+    pub workers: Option<NonZeroUsize>,
+    /// The number of timely workers running in the target computation
+    pub connections: Option<NonZeroUsize>,
+    /// The color palette to use for the generated graphs
+    pub palette: Option<String>,
+    /// The coloring to use for terminal output
+    pub color: Option<String>,
+    /// Disables ddshow's terminal output
+    pub quiet: Option<bool>,
+    /// The name of the target program, recorded within trace archives
+    pub program_name: Option<String>,
+    pub listen: ListenConfig,
+    pub streams: StreamsConfig,
+    pub output: OutputConfig,
+    pub timeline: TimelineConfig,
+    pub stop: StopConfig,
+    pub extraction: ExtractionConfig,
+    pub lints: LintsConfig,
+}
+
+/// The addresses to listen for log streams on
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ListenConfig {
+    pub timely: Option<SocketAddr>,
+    pub differential: Option<SocketAddr>,
+    pub progress: Option<SocketAddr>,
+    /// The unix socket to listen on for multiplexed connections
+    pub unix_socket: Option<PathBuf>,
+}
+
+/// The log streams to read from the target computation
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StreamsConfig {
+    pub differential: Option<bool>,
+    pub progress: Option<bool>,
+    /// The encoding of the network streams, either `abomonation` or `rkyv`
+    pub encoding: Option<String>,
+    /// Whether or not to accept workers that reconnect or restart
+    pub reconnect: Option<bool>,
+}
+
+/// The artifacts that ddshow generates
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OutputConfig {
+    /// The directory to generate artifacts in
+    pub dir: Option<PathBuf>,
+    /// Whether or not to generate a text report
+    pub report: Option<bool>,
+    pub report_file: Option<PathBuf>,
+    /// The time between updating the report file in seconds
+    pub report_update_duration: Option<u8>,
+    pub dump_json: Option<PathBuf>,
+    /// The file to write the timeline to as a Chrome trace
+    pub chrome_trace: Option<PathBuf>,
+    /// The file to write the dataflow graph to as DOT
+    pub dot: Option<PathBuf>,
+    pub save_logs: Option<PathBuf>,
+    /// The compression to use for saved logs, either `none`, `zstd` or `gzip`
+    pub log_compression: Option<String>,
+    /// The trace archive to bundle saved or replayed logs into
+    pub bundle_logs: Option<PathBuf>,
+    /// The address to serve the live graph and dashboard on
+    pub serve: Option<SocketAddr>,
+}
+
+/// Timeline analysis, `--disable-timeline` is the only timeline flag so `enabled`
+/// is the only option here. The timeline's chrome trace is set by `chrome-trace`
+/// in `[output]` and its granularity and activation durations by `[extraction]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TimelineConfig {
+    /// Whether or not to run timeline analysis
+    pub enabled: Option<bool>,
+}
+
+/// When to stop collecting trace data, the user is
+/// prompted if none of these are set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StopConfig {
+    /// Stop after this many seconds
+    pub after: Option<u64>,
+    /// Stop once no events have been received for this many seconds
+    pub when_idle: Option<u64>,
+    /// Stop once every source connection has closed
+    pub on_disconnect: Option<bool>,
+}
+
+/// Tuning knobs for ddshow's internal dataflow
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExtractionConfig {
+    /// The size of the time windows (in nanoseconds) that program statistics
+    /// and timeline events are bucketed into
+    pub program_ns_granularity: Option<NonZeroU64>,
+    /// Whether or not to record every operator activation
+    pub activation_durations: Option<bool>,
+}
+
+/// The lints to run over the target dataflow
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LintsConfig {
+    /// The names of the lints to disable
+    pub disable: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Reads and parses the config file at the given path
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+        Self::parse(&source)
+            .with_context(|| format!("failed to parse config file {}", path.display()))
+    }
+
+    /// Parses a config file from its source text
+    pub fn parse(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    /// Applies the config's options to `args`, skipping any options that
+    /// were explicitly given on the command line
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches<'_>) -> Result<()> {
+        let mut overlay = Overlay { args, matches };
+
+        overlay.set("workers", self.workers, |args| &mut args.workers);
+        overlay.set("timely-connections", self.connections, |args| {
+            &mut args.timely_connections
+        });
+        overlay.set("quiet", self.quiet, |args| &mut args.quiet);
+        overlay.set("program-name", self.program_name.map(Some), |args| {
+            &mut args.program_name
+        });
+
+        if let Some(palette) = self.palette {
+            let palette = gradient_from_str(&palette).map_err(anyhow::Error::msg)?;
+            overlay.set("palette", Some(palette), |args| &mut args.palette);
+        }
+
+        if let Some(color) = self.color {
+            let color: TerminalColor = color.parse().map_err(anyhow::Error::msg)?;
+            overlay.set("color", Some(color), |args| &mut args.color);
+        }
+
+        overlay.set("timely-address", self.listen.timely, |args| {
+            &mut args.timely_address
+        });
+        overlay.set("differential-address", self.listen.differential, |args| {
+            &mut args.differential_address
+        });
+        overlay.set("progress-address", self.listen.progress, |args| {
+            &mut args.progress_address
+        });
+
+        overlay.set("differential-enabled", self.streams.differential, |args| {
+            &mut args.differential_enabled
+        });
+        overlay.set("progress-enabled", self.streams.progress, |args| {
+            &mut args.progress_enabled
+        });
+
+        if let Some(encoding) = self.streams.encoding {
+            let encoding: StreamEncoding = encoding.parse().map_err(anyhow::Error::msg)?;
+            overlay.set("stream-encoding", Some(encoding), |args| {
+                &mut args.stream_encoding
+            });
+        }
+
+        // Replayed logs can't reconnect or be multiplexed,
+        // so only apply these to live sources
+        if !overlay.args.is_file_sourced() {
+            overlay.set("reconnect", self.streams.reconnect, |args| {
+                &mut args.reconnect
+            });
+            overlay.set("unix-socket", self.listen.unix_socket.map(Some), |args| {
+                &mut args.unix_socket
+            });
+        }
+
+        overlay.set("output-dir", self.output.dir, |args| &mut args.output_dir);
+        overlay.set("report-file", self.output.report_file, |args| {
+            &mut args.report_file
+        });
+        overlay.set(
+            "report-update-duration",
+            self.output.report_update_duration.map(Some),
+            |args| &mut args.report_update_duration,
+        );
+        overlay.set("dump-json", self.output.dump_json.map(Some), |args| {
+            &mut args.dump_json
+        });
+        overlay.set("chrome-trace", self.output.chrome_trace.map(Some), |args| {
+            &mut args.chrome_trace
+        });
+        overlay.set("dot", self.output.dot.map(Some), |args| &mut args.dot);
+
+        // Saving and replaying logs conflict with each other, so only save logs
+        // if we're not already replaying them
+        if !overlay.args.is_file_sourced() {
+            overlay.set("save-logs", self.output.save_logs.map(Some), |args| {
+                &mut args.save_logs
+            });
+        }
+
+        overlay.set("bundle-logs", self.output.bundle_logs.map(Some), |args| {
+            &mut args.bundle_logs
+        });
+
+        overlay.set("serve", self.output.serve.map(Some), |args| &mut args.serve);
+
+        if let Some(compression) = self.output.log_compression {
+            let compression: Compression = compression.parse().map_err(anyhow::Error::msg)?;
+            overlay.set("log-compression", Some(compression), |args| {
+                &mut args.log_compression
+            });
+        }
+
+        // An explicitly given report file takes precedence over disabling the report
+        if !overlay.is_given("report-file") {
+            overlay.set(
+                "no-report-file",
+                self.output.report.map(|report| !report),
+                |args| &mut args.no_report_file,
+            );
+        }
+
+        overlay.set(
+            "disable-timeline",
+            self.timeline.enabled.map(|enabled| !enabled),
+            |args| &mut args.disable_timeline,
+        );
+
+        overlay.set("stop-after", self.stop.after.map(Some), |args| {
+            &mut args.stop_after
+        });
+        overlay.set("stop-when-idle", self.stop.when_idle.map(Some), |args| {
+            &mut args.stop_when_idle
+        });
+        overlay.set("stop-on-disconnect", self.stop.on_disconnect, |args| {
+            &mut args.stop_on_disconnect
+        });
+
+        overlay.set(
+            "program-ns-granularity",
+            self.extraction.program_ns_granularity,
+            |args| &mut args.program_ns_granularity,
+        );
+        overlay.set(
+            "activation-durations",
+            self.extraction.activation_durations,
+            |args| &mut args.activation_durations,
+        );
+
+        if let Some(disable) = self.lints.disable {
+            let disabled_lints = disable
+                .iter()
+                .map(|lint| lint.parse())
+                .collect::<Result<Vec<LintRule>, _>>()
+                .map_err(anyhow::Error::msg)?;
+
+            overlay.set("disabled-lints", Some(disabled_lints), |args| {
+                &mut args.disabled_lints
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes config values into the args unless the user
+/// explicitly gave them on the command line
+struct Overlay<'a, 'b> {
+    args: &'a mut Args,
+    matches: &'a ArgMatches<'b>,
+}
+
+impl Overlay<'_, '_> {
+    fn is_given(&self, arg: &str) -> bool {
+        self.matches.occurrences_of(arg) != 0
+    }
+
+    fn set<T, F>(&mut self, arg: &str, value: Option<T>, field: F)
+    where
+        F: FnOnce(&mut Args) -> &mut T,
+    {
+        if let Some(value) = value {
+            if !self.is_given(arg) {
+                *field(self.args) = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        args::{Args, StreamEncoding},
+        config::Config,
+        lint::LintRule,
+    };
+    use std::net::SocketAddr;
+    use structopt::StructOpt;
+
+    const CONFIG: &str = r#"
+        workers = 4
+        connections = 2
+        palette = "viridis"
+
+        [listen]
+        timely = "127.0.0.1:9000"
+
+        [streams]
+        differential = true
+        encoding = "rkyv"
+
+        [output]
+        report = false
+        serve = "127.0.0.1:8080"
+
+        [timeline]
+        enabled = false
+
+        [stop]
+        when-idle = 5
+
+        [extraction]
+        program-ns-granularity = 1_000
+        activation-durations = true
+
+        [lints]
+        disable = ["hot-operator"]
+    "#;
+
+    fn layered(cli: &[&str]) -> Args {
+        let matches = Args::clap().get_matches_from(cli);
+        let mut args = Args::from_clap(&matches);
+
+        Config::parse(CONFIG)
+            .unwrap()
+            .apply(&mut args, &matches)
+            .unwrap();
+
+        args
+    }
+
+    #[test]
+    fn config_fills_in_args() {
+        let args = layered(&["ddshow"]);
+
+        assert_eq!(args.workers.get(), 4);
+        assert_eq!(args.timely_connections.get(), 2);
+        assert_eq!(
+            format!("{:?}", args.palette),
+            format!("{:?}", colorous::VIRIDIS)
+        );
+        assert_eq!(
+            args.timely_address,
+            "127.0.0.1:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert!(args.differential_enabled);
+        assert!(!args.progress_enabled);
+        assert_eq!(args.stream_encoding, StreamEncoding::Rkyv);
+        assert!(args.no_report_file);
+        assert_eq!(
+            args.serve,
+            Some("127.0.0.1:8080".parse::<SocketAddr>().unwrap())
+        );
+        assert!(args.disable_timeline);
+        assert_eq!(args.stop_when_idle, Some(5));
+        assert!(!args.is_interactive());
+        assert_eq!(args.program_ns_granularity.get(), 1_000);
+        assert!(args.activation_durations);
+        assert_eq!(args.disabled_lints, vec![LintRule::HotOperator]);
+    }
+
+    #[test]
+    fn cli_overrides_config() {
+        let args = layered(&[
+            "ddshow",
+            "--workers",
+            "8",
+            "--connections",
+            "3",
+            "--address",
+            "127.0.0.1:9001",
+            "--palette",
+            "magma",
+            "--report-file",
+            "other.txt",
+            "--disable-lint",
+            "inconsistent-workers",
+            "--disable-lint",
+            "undelivered-messages",
+        ]);
+
+        assert_eq!(args.workers.get(), 8);
+        assert_eq!(args.timely_connections.get(), 3);
+        assert_eq!(
+            args.timely_address,
+            "127.0.0.1:9001".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            format!("{:?}", args.palette),
+            format!("{:?}", colorous::MAGMA)
+        );
+        assert!(!args.no_report_file);
+        assert_eq!(args.report_file.to_str(), Some("other.txt"));
+        assert_eq!(
+            args.disabled_lints,
+            vec![LintRule::InconsistentWorkers, LintRule::UndeliveredMessages],
+        );
+
+        // Things not given on the command line still come from the config
+        assert!(args.differential_enabled);
+        assert_eq!(args.program_ns_granularity.get(), 1_000);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse("wokers = 4").is_err());
+        assert!(Config::parse("[listen]\ntimley = \"127.0.0.1:9000\"").is_err());
+    }
+
+    #[test]
+    fn unknown_lints_are_rejected() {
+        let matches = Args::clap().get_matches_from(["ddshow"]);
+        let mut args = Args::from_clap(&matches);
+
+        let config = Config::parse("[lints]\ndisable = [\"hot-operater\"]").unwrap();
+        assert!(config.apply(&mut args, &matches).is_err());
+    }
+
+    #[test]
+    fn reconnect_only_applies_to_live_sources() {
+        const CONFIG: &str = "[streams]\nencoding = \"rkyv\"\nreconnect = true";
+
+        let matches = Args::clap().get_matches_from(["ddshow"]);
+        let mut args = Args::from_clap(&matches);
+        Config::parse(CONFIG)
+            .unwrap()
+            .apply(&mut args, &matches)
+            .unwrap();
+        assert!(args.reconnect);
+
+        let matches = Args::clap().get_matches_from(["ddshow", "--replay-logs", "logs"]);
+        let mut args = Args::from_clap(&matches);
+        Config::parse(CONFIG)
+            .unwrap()
+            .apply(&mut args, &matches)
+            .unwrap();
+        assert!(!args.reconnect);
+    }
+}
@@ -0,0 +1,429 @@
+//! Finds the chain of operators that dominated the latency of each timestamp
+//!
+//! For every timestamp of a dataflow, the operator that released it last is
+//! where the frontier finally advanced. Walking backwards from there along
+//! the dataflow's channels, always to the upstream operator that released the
+//! timestamp last, gives the critical path from the input to the probe. Each
+//! operator on the path is charged the time between its upstream operator
+//! letting go of the timestamp and it doing the same, so the charges of a path
+//! add up to the timestamp's end-to-end latency
+//!
+//! Paths are traced and combined within the dataflow, so only the
+//! critical path of each dataflow is extracted from it. Each path's charges
+//! are added to running totals as they come in, so new timestamps don't
+//! recombine every path that came before them
+
+use crate::dataflow::{DataflowData, OperatorTimestamp};
+use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
+use ddshow_types::{OperatorAddr, WorkerId};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    time::Duration,
+};
+
+/// The most contributors that will be reported for each dataflow
+pub const MAX_CONTRIBUTORS: usize = 5;
+
+/// The critical paths of every timestamp of a single dataflow
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct DataflowCriticalPath {
+    pub dataflow: OperatorAddr,
+    pub name: String,
+    /// The number of timestamps that passed through the dataflow, counted once per worker
+    pub timestamps: usize,
+    pub average_latency: Duration,
+    pub max_latency: Duration,
+    /// The timestamp with the highest latency
+    pub slowest: Option<CriticalTimestamp>,
+    /// The operators that were on the critical path, the ones
+    /// that contributed the most latency come first
+    pub contributors: Vec<CriticalContributor>,
+}
+
+/// The critical path of a single timestamp on a single worker
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct CriticalTimestamp {
+    pub worker: WorkerId,
+    /// The timestamp as formatted by timely
+    pub timestamp: String,
+    /// The time between the timestamp's first and last progress updates
+    pub latency: Duration,
+    /// The operators on the critical path, from the input to the probe
+    pub path: Vec<OperatorAddr>,
+}
+
+/// An operator's contribution to a dataflow's critical paths
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct CriticalContributor {
+    pub addr: OperatorAddr,
+    pub name: String,
+    /// The number of critical paths the operator was on
+    pub timestamps: usize,
+    /// The total latency the operator added to the critical paths it was on
+    pub latency: Duration,
+    /// How long the operator was scheduled for while it held on to those timestamps
+    pub busy: Duration,
+}
+
+/// A piece of a dataflow's critical paths, keyed by the
+/// address of its timestamp's dataflow or of its operator
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation)]
+pub(crate) enum PathPart {
+    /// The critical path of a single timestamp
+    Timestamp(CriticalTimestamp),
+    /// A single operator's charge on the critical path of a timestamp
+    Hop { latency: Duration, busy: Duration },
+}
+
+impl PathPart {
+    /// The amounts that the part adds to each of its dataflow's or operator's running totals
+    pub(crate) fn stats(&self) -> [(PathStat, isize); 3] {
+        let (latency, busy) = match self {
+            Self::Timestamp(path) => (path.latency, Duration::from_secs(0)),
+            Self::Hop { latency, busy } => (*latency, *busy),
+        };
+
+        [
+            (PathStat::Timestamps, 1),
+            (PathStat::Latency, duration_nanos(latency)),
+            (PathStat::Busy, duration_nanos(busy)),
+        ]
+    }
+}
+
+/// The running totals kept for every dataflow and every operator on its critical paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation)]
+pub(crate) enum PathStat {
+    /// The number of critical paths
+    Timestamps,
+    /// The total latency of the critical paths, in nanoseconds
+    Latency,
+    /// The total time spent scheduled while holding on to
+    /// the critical paths' timestamps, in nanoseconds
+    Busy,
+}
+
+/// A dataflow's slowest timestamp or one of the running totals
+/// of the dataflow or of an operator on its critical paths
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation)]
+pub(crate) enum PathSummary {
+    Slowest(CriticalTimestamp),
+    Total {
+        addr: OperatorAddr,
+        name: String,
+        stat: PathStat,
+        total: isize,
+    },
+}
+
+/// A single operator's charge on a critical path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hop {
+    pub(crate) addr: OperatorAddr,
+    pub(crate) latency: Duration,
+    pub(crate) busy: Duration,
+}
+
+/// Gets the critical paths of every dataflow that progress was logged for,
+/// dataflows are ordered by their address
+pub fn critical_paths(data: &DataflowData) -> Vec<DataflowCriticalPath> {
+    let mut paths = data.critical_paths.clone();
+    paths.sort_unstable_by(|a, b| a.dataflow.cmp(&b.dataflow));
+    paths
+}
+
+/// Traces the critical path of a single timestamp on a single worker from
+/// the operators that handled it and the channels between them, given as
+/// pairs of target and source operators
+pub(crate) fn timestamp_path(
+    worker: WorkerId,
+    operators: &[OperatorTimestamp],
+    channels: &[(OperatorAddr, OperatorAddr)],
+) -> (CriticalTimestamp, Vec<Hop>) {
+    let mut predecessors: HashMap<&OperatorAddr, HashSet<OperatorAddr>> = HashMap::new();
+    for (target, source) in channels {
+        predecessors
+            .entry(target)
+            .or_default()
+            .insert(source.clone());
+    }
+
+    let operators: Vec<_> = operators.iter().collect();
+    let hops = trace_path(&operators, |addr| predecessors.get(addr));
+
+    let critical = CriticalTimestamp {
+        worker,
+        timestamp: operators
+            .first()
+            .map(|operator| operator.timestamp.clone())
+            .unwrap_or_default(),
+        latency: hops.iter().map(|hop| hop.latency).sum(),
+        path: hops.iter().map(|hop| hop.addr.clone()).collect(),
+    };
+
+    (critical, hops)
+}
+
+/// Combines a dataflow's slowest timestamp with the running totals of
+/// the dataflow and of the operators on its critical paths
+pub(crate) fn dataflow_critical_path<'a, I>(
+    dataflow: &OperatorAddr,
+    summaries: I,
+) -> DataflowCriticalPath
+where
+    I: IntoIterator<Item = &'a PathSummary>,
+{
+    let mut name = String::new();
+    let (mut timestamps, mut total_latency) = (0, Duration::from_secs(0));
+    let mut slowest: Option<&CriticalTimestamp> = None;
+    let mut contributors: HashMap<&OperatorAddr, CriticalContributor> = HashMap::new();
+
+    for summary in summaries {
+        match summary {
+            PathSummary::Slowest(path) => slowest = Some(path),
+
+            PathSummary::Total {
+                addr,
+                name: operator_name,
+                stat,
+                total,
+            } if addr == dataflow => {
+                name = operator_name.clone();
+
+                match stat {
+                    PathStat::Timestamps => timestamps += *total as usize,
+                    PathStat::Latency => total_latency += nanos_duration(*total),
+                    PathStat::Busy => {}
+                }
+            }
+
+            PathSummary::Total {
+                addr,
+                name: operator_name,
+                stat,
+                total,
+            } => {
+                let contributor = contributors
+                    .entry(addr)
+                    .or_insert_with(|| CriticalContributor {
+                        addr: addr.clone(),
+                        name: operator_name.clone(),
+                        timestamps: 0,
+                        latency: Duration::from_secs(0),
+                        busy: Duration::from_secs(0),
+                    });
+
+                match stat {
+                    PathStat::Timestamps => contributor.timestamps += *total as usize,
+                    PathStat::Latency => contributor.latency += nanos_duration(*total),
+                    PathStat::Busy => contributor.busy += nanos_duration(*total),
+                }
+            }
+        }
+    }
+
+    let mut contributors: Vec<_> = contributors.into_values().collect();
+    contributors.sort_by(|a, b| b.latency.cmp(&a.latency).then_with(|| a.addr.cmp(&b.addr)));
+
+    DataflowCriticalPath {
+        dataflow: dataflow.clone(),
+        name,
+        timestamps,
+        average_latency: total_latency
+            .checked_div(timestamps as u32)
+            .unwrap_or_default(),
+        max_latency: slowest.map(|path| path.latency).unwrap_or_default(),
+        slowest: slowest.cloned(),
+        contributors,
+    }
+}
+
+/// Gets a duration in nanoseconds so that it can be summed up as a difference
+pub(crate) fn duration_nanos(duration: Duration) -> isize {
+    isize::try_from(duration.as_nanos()).unwrap_or(isize::MAX)
+}
+
+fn nanos_duration(nanos: isize) -> Duration {
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or_default())
+}
+
+/// Walks backwards from the operator that released a timestamp last, always
+/// stepping to the upstream operator that released it last. The hops are
+/// returned from the input to the probe
+pub(crate) fn trace_path<'a, 'b, P>(
+    operators: &[&'a OperatorTimestamp],
+    predecessors: P,
+) -> Vec<Hop>
+where
+    P: Fn(&OperatorAddr) -> Option<&'b HashSet<OperatorAddr>>,
+{
+    let start = match operators.iter().map(|operator| operator.first_update).min() {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+    let operators: HashMap<_, _> = operators
+        .iter()
+        .map(|&operator| (&operator.addr, operator))
+        .collect();
+
+    // Ties go to the lowest address so that paths are deterministic
+    let latest = |candidates: &mut dyn Iterator<Item = &'a OperatorTimestamp>| {
+        candidates.max_by(|a, b| {
+            a.last_update
+                .cmp(&b.last_update)
+                .then_with(|| b.addr.cmp(&a.addr))
+        })
+    };
+
+    let mut current = match latest(&mut operators.values().copied()) {
+        Some(current) => current,
+        None => return Vec::new(),
+    };
+
+    let mut visited = HashSet::new();
+    let mut hops = Vec::new();
+    loop {
+        visited.insert(&current.addr);
+
+        let upstream = predecessors(&current.addr).and_then(|predecessors| {
+            latest(
+                &mut predecessors
+                    .iter()
+                    .filter(|addr| !visited.contains(addr))
+                    .filter_map(|addr| operators.get(addr).copied())
+                    .filter(|predecessor| predecessor.last_update <= current.last_update),
+            )
+        });
+
+        let released_upstream = upstream.map_or(start, |upstream| upstream.last_update);
+        hops.push(Hop {
+            addr: current.addr.clone(),
+            latency: current.last_update - released_upstream,
+            busy: current.busy,
+        });
+
+        match upstream {
+            Some(upstream) => current = upstream,
+            None => break,
+        }
+    }
+
+    hops.reverse();
+    hops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trace_path, Hop};
+    use crate::dataflow::OperatorTimestamp;
+    use ddshow_types::{OperatorAddr, WorkerId};
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    fn timestamp(node: usize, first: u64, last: u64, busy: u64) -> OperatorTimestamp {
+        OperatorTimestamp {
+            worker: WorkerId::new(0),
+            addr: OperatorAddr::from(vec![0, node]),
+            timestamp: "0".to_owned(),
+            first_update: Duration::from_millis(first),
+            last_update: Duration::from_millis(last),
+            busy: Duration::from_millis(busy),
+        }
+    }
+
+    #[test]
+    fn path_follows_the_slowest_branch() {
+        // input (1) fans out to a fast (2) and a slow (3) branch that join at the probe (4)
+        let operators = [
+            timestamp(1, 0, 2, 1),
+            timestamp(2, 1, 3, 1),
+            timestamp(3, 1, 8, 6),
+            timestamp(4, 3, 10, 1),
+        ];
+        let operators: Vec<_> = operators.iter().collect();
+
+        let addr = |node: usize| OperatorAddr::from(vec![0, node]);
+        let mut predecessors: HashMap<_, HashSet<_>> = HashMap::new();
+        predecessors.insert(addr(2), vec![addr(1)].into_iter().collect());
+        predecessors.insert(addr(3), vec![addr(1)].into_iter().collect());
+        predecessors.insert(addr(4), vec![addr(2), addr(3)].into_iter().collect());
+
+        let hops = trace_path(&operators, |addr| predecessors.get(addr));
+        let hop = |node, latency, busy| Hop {
+            addr: addr(node),
+            latency: Duration::from_millis(latency),
+            busy: Duration::from_millis(busy),
+        };
+        assert_eq!(hops, vec![hop(1, 2, 1), hop(3, 6, 6), hop(4, 2, 1)]);
+
+        // The path's latency covers the whole timestamp
+        let latency: Duration = hops.iter().map(|hop| hop.latency).sum();
+        assert_eq!(latency, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn cycles_are_only_walked_once() {
+        let operators = [timestamp(1, 0, 4, 1), timestamp(2, 2, 5, 1)];
+        let operators: Vec<_> = operators.iter().collect();
+
+        let addr = |node: usize| OperatorAddr::from(vec![0, node]);
+        let mut predecessors: HashMap<_, HashSet<_>> = HashMap::new();
+        predecessors.insert(addr(1), vec![addr(2)].into_iter().collect());
+        predecessors.insert(addr(2), vec![addr(1)].into_iter().collect());
+
+        let hops = trace_path(&operators, |addr| predecessors.get(addr));
+        let path: Vec<_> = hops.iter().map(|hop| hop.addr.clone()).collect();
+        assert_eq!(path, vec![addr(1), addr(2)]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Checks that every worker built the same dataflows
+//!
+//! Every worker is compared against the reference worker (the lowest worker
+//! id within the logs), operators are matched up by their address and name
+//! and channels by their scope and endpoints. Operators that match the
+//! reference worker's are used to aggregate stats across workers and anything
+//! that doesn't match is reported as a [`WorkerDivergence`]
+
+use crate::dataflow::{operators::Keys, Diff};
+use abomonation_derive::Abomonation;
+use ddshow_types::{
+    timely_logging::{ChannelsEvent, OperatesEvent},
+    OperatorAddr, OperatorId, PortId, WorkerId,
+};
+use differential_dataflow::{
+    lattice::Lattice,
+    operators::{Join, Reduce, ThresholdTotal},
+    Collection,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use timely::{dataflow::Scope, order::TotalOrder};
+
+/// A difference between a worker's dataflow graph and the reference worker's
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation, Deserialize, Serialize,
+)]
+pub struct WorkerDivergence {
+    /// The worker that diverged
+    pub worker: WorkerId,
+    /// The worker that every other worker is compared against
+    pub reference: WorkerId,
+    /// The address of the diverging operator, or the source
+    /// operator of the diverging channel
+    pub addr: OperatorAddr,
+    pub kind: DivergenceKind,
+}
+
+impl Display for WorkerDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (worker, reference, addr) = (self.worker, self.reference, &self.addr);
+
+        match &self.kind {
+            DivergenceKind::MissingOperator { name } => write!(
+                f,
+                "worker {} is missing the {} operator at {} that worker {} has",
+                worker, name, addr, reference,
+            ),
+            DivergenceKind::ExtraOperator { name } => write!(
+                f,
+                "worker {} has a {} operator at {} that worker {} doesn't",
+                worker, name, addr, reference,
+            ),
+            DivergenceKind::RenamedOperator { name, expected } => write!(
+                f,
+                "the operator at {} is named {} on worker {} but {} on worker {}",
+                addr, name, worker, expected, reference,
+            ),
+            DivergenceKind::MissingChannel {
+                source_port,
+                target,
+                target_port,
+            } => write!(
+                f,
+                "worker {} is missing the channel from port {} of {} to port {} of {} \
+                 that worker {} has",
+                worker, source_port, addr, target_port, target, reference,
+            ),
+            DivergenceKind::ExtraChannel {
+                source_port,
+                target,
+                target_port,
+            } => write!(
+                f,
+                "worker {} has a channel from port {} of {} to port {} of {} \
+                 that worker {} doesn't",
+                worker, source_port, addr, target_port, target, reference,
+            ),
+        }
+    }
+}
+
+/// The ways that a worker's dataflow graph can differ from the reference worker's
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation, Deserialize, Serialize,
+)]
+pub enum DivergenceKind {
+    /// The reference worker has an operator that this worker doesn't
+    MissingOperator { name: String },
+    /// This worker has an operator that the reference worker doesn't
+    ExtraOperator { name: String },
+    /// Both workers have an operator at the same address but with different names
+    RenamedOperator { name: String, expected: String },
+    /// The reference worker has a channel that this worker doesn't
+    MissingChannel {
+        source_port: PortId,
+        target: OperatorAddr,
+        target_port: PortId,
+    },
+    /// This worker has a channel that the reference worker doesn't
+    ExtraChannel {
+        source_port: PortId,
+        target: OperatorAddr,
+        target_port: PortId,
+    },
+}
+
+/// A channel's scope along with its source and target `[operator index, port]`s
+type ChannelKey = (OperatorAddr, [PortId; 2], [PortId; 2]);
+
+/// Matches every worker's operators to the reference worker's, producing the
+/// reference worker's id for each matched `(worker, operator)` along with all
+/// of the places where workers diverge from the reference worker
+#[allow(clippy::type_complexity)]
+pub fn worker_consistency<S>(
+    scope: &mut S,
+    operators: &Collection<S, (WorkerId, OperatesEvent), Diff>,
+    channels: &Collection<S, (WorkerId, ChannelsEvent), Diff>,
+) -> (
+    Collection<S, ((WorkerId, OperatorId), OperatorId), Diff>,
+    Collection<S, WorkerDivergence, Diff>,
+)
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+{
+    scope.region_named("Check Worker Consistency", |region| {
+        let (operators, channels) = (operators.enter(region), channels.enter(region));
+
+        // Reduce's input is sorted, so the first worker is the lowest
+        let reference = operators
+            .map(|(worker, _)| ((), worker))
+            .reduce_named("Reduce: Select Reference Worker", |&(), workers, output| {
+                output.push((*workers[0].0, 1))
+            })
+            .map(|((), worker)| worker);
+
+        let other_workers = operators
+            .map(|(worker, _)| worker)
+            .distinct_total()
+            .map(|worker| (worker, ()))
+            .antijoin(&reference)
+            .map(|(worker, ())| ((), worker));
+
+        let addressed_operators = operators
+            .map(|(worker, operator)| (operator.addr, (worker, operator.id, operator.name)));
+        let reference_operators = operators
+            .map(|(worker, operator)| (worker, (operator.addr, operator.id, operator.name)))
+            .semijoin(&reference)
+            .map(|(_, (addr, id, name))| (addr, (id, name)));
+
+        let matched_operators = addressed_operators.join(&reference_operators);
+        let matching = matched_operators.flat_map(
+            |(_, ((worker, id, name), (reference_id, reference_name)))| {
+                if name == reference_name {
+                    Some(((worker, id), reference_id))
+                } else {
+                    None
+                }
+            },
+        );
+
+        let renamed_operators =
+            matched_operators.flat_map(|(addr, ((worker, _, name), (_, expected)))| {
+                if name != expected {
+                    Some((
+                        (worker, addr),
+                        DivergenceKind::RenamedOperator { name, expected },
+                    ))
+                } else {
+                    None
+                }
+            });
+
+        let missing_operators = reference_operators
+            .map(|(addr, (_, name))| ((), (addr, name)))
+            .join_map(&other_workers, |&(), (addr, name), &worker| {
+                ((worker, addr.clone()), name.clone())
+            })
+            .antijoin(
+                &addressed_operators
+                    .map(|(addr, (worker, _, _))| (worker, addr))
+                    .distinct_total(),
+            );
+
+        let extra_operators = addressed_operators
+            .antijoin(&reference_operators.keys().distinct_total())
+            .map(|(addr, (worker, _, name))| ((worker, addr), name));
+
+        let operator_divergences = missing_operators
+            .map(|(key, name)| (key, DivergenceKind::MissingOperator { name }))
+            .concat(
+                &extra_operators.map(|(key, name)| (key, DivergenceKind::ExtraOperator { name })),
+            )
+            .concat(&renamed_operators);
+
+        let channel_keys = channels
+            .map(|(worker, channel)| (worker, (channel.scope_addr, channel.source, channel.target)))
+            .distinct_total();
+        let reference_channels = channel_keys
+            .semijoin(&reference)
+            .map(|(_, channel)| channel);
+
+        let missing_channels = reference_channels
+            .map(|channel| ((), channel))
+            .join_map(&other_workers, |&(), channel, &worker| {
+                ((worker, channel.clone()), ())
+            })
+            .antijoin(&channel_keys)
+            .map(|((worker, channel), ())| channel_divergence(worker, channel, true));
+
+        let extra_channels = channel_keys
+            .map(|(worker, channel)| (channel, worker))
+            .antijoin(&reference_channels)
+            .map(|(channel, worker)| channel_divergence(worker, channel, false));
+
+        let divergences = operator_divergences
+            .concat(&missing_channels)
+            .concat(&extra_channels)
+            .map(|((worker, addr), kind)| ((), (worker, addr, kind)))
+            .join_map(
+                &reference.map(|worker| ((), worker)),
+                |&(), (worker, addr, kind), &reference| WorkerDivergence {
+                    worker: *worker,
+                    reference,
+                    addr: addr.clone(),
+                    kind: kind.clone(),
+                },
+            );
+
+        (matching.leave_region(), divergences.leave_region())
+    })
+}
+
+/// Turns a channel that one worker has and another doesn't into a divergence
+fn channel_divergence(
+    worker: WorkerId,
+    (scope_addr, source, target): ChannelKey,
+    missing: bool,
+) -> ((WorkerId, OperatorAddr), DivergenceKind) {
+    let (source_port, target_port) = (source[1], target[1]);
+    let (source, target) = (
+        scope_addr.push_imm(source[0]),
+        scope_addr.push_imm(target[0]),
+    );
+
+    let kind = if missing {
+        DivergenceKind::MissingChannel {
+            source_port,
+            target,
+            target_port,
+        }
+    } else {
+        DivergenceKind::ExtraChannel {
+            source_port,
+            target,
+            target_port,
+        }
+    };
+
+    ((worker, source), kind)
+}
@@ -2,9 +2,9 @@
 
 use std::{num::NonZeroUsize, time::Duration};
 
-/// Only cause the program stats to update every N milliseconds to
-/// prevent this from absolutely thrashing the scheduler
-// TODO: Make this configurable by the user
+/// Only cause the program stats to update every N nanoseconds to
+/// prevent this from absolutely thrashing the scheduler, can be
+/// overridden with `--program-ns-granularity` or the config file
 pub const PROGRAM_NS_GRANULARITY: u128 = 5_000_000_000;
 
 /// The default capacity to initialize extractor maps to
@@ -16,6 +16,13 @@ pub(crate) const EVENT_NS_MARGIN: u64 = 500_000;
 /// The read timeout to impose on tcp connections
 pub(crate) const TCP_READ_TIMEOUT: Option<Duration> = Some(Duration::from_millis(200));
 
+/// The time that a reconnecting worker has to send its handshake
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time a worker's old connection has to go quiet before ddshow
+/// switches over to the worker's new connection
+pub(crate) const RECONNECT_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// The fuel used to extract data from the dataflow within the
 /// main thread's spin loop
 // Safety: 1,000,000 isn't zero
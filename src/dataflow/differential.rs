@@ -72,6 +72,45 @@ where
                 ));
             });
 
+        let batch_sizes = differential_trace
+            .filter_map(|(time, worker, event)| match event {
+                DifferentialEvent::Batch(batch) => {
+                    Some((((worker, batch.operator), batch.length), time, 1isize))
+                }
+
+                DifferentialEvent::Merge(_)
+                | DifferentialEvent::MergeShortfall(_)
+                | DifferentialEvent::Drop(_)
+                | DifferentialEvent::TraceShare(_) => None,
+            })
+            .as_collection()
+            .reduce(|_, sizes, output| {
+                output.push((
+                    sizes
+                        .iter()
+                        .flat_map(|&(&size, diff)| (0..diff).map(move |_| size))
+                        .collect::<Vec<_>>(),
+                    1isize,
+                ));
+            });
+
+        // Only count the times the trace was shared, not when those shares were dropped
+        let trace_shares = differential_trace
+            .filter_map(|(time, worker, event)| match event {
+                DifferentialEvent::TraceShare(share) if share.diff > 0 => {
+                    Some(((worker, share.operator), time, share.diff))
+                }
+
+                DifferentialEvent::Batch(_)
+                | DifferentialEvent::Merge(_)
+                | DifferentialEvent::MergeShortfall(_)
+                | DifferentialEvent::Drop(_)
+                | DifferentialEvent::TraceShare(_) => None,
+            })
+            .as_collection()
+            .count_total()
+            .map(|(key, shares)| (key, shares as usize));
+
         #[cfg(feature = "timely-next")]
         let merge_stats = merge_diffs
             .explode(|(key, (size, batches))| {
@@ -85,6 +124,9 @@ where
                     max_size: max.value as usize,
                     min_size: min.value as usize,
                     batches: batches as usize,
+                    spline_levels: Vec::new(),
+                    batch_sizes: Vec::new(),
+                    trace_shares: 0,
                 };
 
                 (key, stats)
@@ -129,13 +171,15 @@ where
                         min_size: min.value as usize,
                         batches: batches as usize,
                         spline_levels: Vec::new(),
+                        batch_sizes: Vec::new(),
+                        trace_shares: 0,
                     };
 
                     (key, stats)
                 },
             );
 
-        merge_stats
+        let merge_stats = merge_stats
             .join_map(&spline_levels, |&key, stats, spline_levels| {
                 let stats = ArrangementStats {
                     spline_levels: spline_levels.clone(),
@@ -144,6 +188,27 @@ where
 
                 (key, stats)
             })
+            .join_map(&batch_sizes, |&key, stats, batch_sizes| {
+                let stats = ArrangementStats {
+                    batch_sizes: batch_sizes.clone(),
+                    ..stats.clone()
+                };
+
+                (key, stats)
+            });
+
+        let shared_stats = merge_stats.join_map(&trace_shares, |&key, stats, &trace_shares| {
+            let stats = ArrangementStats {
+                trace_shares,
+                ..stats.clone()
+            };
+
+            (key, stats)
+        });
+
+        merge_stats
+            .antijoin(&shared_stats.map(|(key, _)| key))
+            .concat(&shared_stats)
             .leave_region()
     })
 }
@@ -157,6 +222,9 @@ pub struct ArrangementStats {
     pub batches: usize,
     /// Merge time, merge scale and the completed size of the merge
     pub spline_levels: Vec<(Duration, usize, usize)>,
-    // TODO: Max/min/average batch size
+    /// The length of every batch the arrangement received
+    pub batch_sizes: Vec<usize>,
+    /// The number of times the arrangement's trace was shared
+    pub trace_shares: usize,
     // TODO: Arrangement growth trend (Linear, logarithmic, quadratic, etc.)?
 }
@@ -0,0 +1,315 @@
+//! Log-bucketed histograms of operator activation durations
+//!
+//! Durations are bucketed HDR-style, every power of two is split into
+//! [`SUB_BUCKETS`] equally sized buckets so that a bucket is never wider than
+//! ~3% of the durations it holds. Histograms are maintained incrementally by
+//! counting the activations that land within each bucket, so they grow with
+//! the number of distinct buckets an operator's activations fall into
+//! instead of with the number of activations
+
+use crate::dataflow::{operator_stats::ActivationTimes, Diff, OperatorId, Percentiles, WorkerId};
+use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
+use differential_dataflow::{
+    lattice::Lattice,
+    operators::{CountTotal, Reduce},
+    Collection, ExchangeData,
+};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, hash::Hash, iter, time::Duration};
+use timely::{dataflow::Scope, order::TotalOrder};
+
+/// The number of bits of precision each bucket keeps
+const SUB_BUCKET_BITS: u32 = 5;
+
+/// The number of buckets each power of two is split into
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// A histogram of an operator's activation durations
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct ActivationHistogram {
+    /// The non-empty buckets of the histogram, sorted from shortest to longest
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// A single bucket of an [`ActivationHistogram`]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct HistogramBucket {
+    /// The shortest duration within the bucket
+    pub lower: Duration,
+    /// The end of the bucket, exclusive
+    pub upper: Duration,
+    /// The number of activations within the bucket
+    pub count: usize,
+}
+
+impl ActivationHistogram {
+    /// Creates a histogram from `(bucket index, count)` pairs sorted by their index
+    fn from_counts<I>(counts: I) -> Self
+    where
+        I: IntoIterator<Item = (u64, Diff)>,
+    {
+        let buckets = counts
+            .into_iter()
+            .filter(|&(_, count)| count > 0)
+            .map(|(index, count)| {
+                let (lower, upper) = bucket_bounds(index);
+
+                HistogramBucket {
+                    lower: Duration::from_nanos(lower),
+                    upper: Duration::from_nanos(upper),
+                    count: count as usize,
+                }
+            })
+            .collect();
+
+        Self { buckets }
+    }
+
+    /// The total number of activations within the histogram
+    pub fn total(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.count).sum()
+    }
+
+    /// Takes the nearest-rank percentiles of the histogram, each percentile is
+    /// the longest duration of the bucket it falls into (clamped to the real
+    /// `min` and `max` durations) so that tail latencies are never understated
+    pub fn percentiles(&self, min: Duration, max: Duration) -> Percentiles<Duration> {
+        let total = self.total();
+
+        let rank = |per_mille: usize| {
+            let rank = (total * per_mille).div_ceil(1000).max(1);
+
+            let mut seen = 0;
+            self.buckets
+                .iter()
+                .find(|bucket| {
+                    seen += bucket.count;
+                    seen >= rank
+                })
+                .map(|bucket| {
+                    let highest = bucket.upper.saturating_sub(Duration::from_nanos(1));
+                    highest.clamp(min, max.max(min))
+                })
+                .unwrap_or_default()
+        };
+
+        Percentiles {
+            p50: rank(500),
+            p90: rank(900),
+            p99: rank(990),
+            p999: rank(999),
+        }
+    }
+
+    /// Approximates the standard deviation of the histogram's durations by
+    /// treating every activation as if it took the middle of its bucket
+    /// (clamped to the real `min` and `max` durations)
+    pub fn std_dev(&self, min: Duration, max: Duration) -> Duration {
+        let total = self.total();
+        if total == 0 {
+            return Duration::from_secs(0);
+        }
+
+        let (min, max) = (min.as_nanos() as f64, max.as_nanos() as f64);
+        let midpoints = self.buckets.iter().map(|bucket| {
+            let midpoint = (bucket.lower.as_nanos() as f64 + bucket.upper.as_nanos() as f64) / 2.0;
+            (midpoint.max(min).min(max.max(min)), bucket.count as f64)
+        });
+
+        let mean = midpoints
+            .clone()
+            .map(|(midpoint, count)| midpoint * count)
+            .sum::<f64>()
+            / total as f64;
+        let variance = midpoints
+            .map(|(midpoint, count)| (midpoint - mean).powi(2) * count)
+            .sum::<f64>()
+            / total as f64;
+
+        Duration::from_nanos(variance.sqrt().round() as u64)
+    }
+}
+
+/// Builds the activation duration histogram of every operator
+pub fn activation_histograms<S>(
+    activation_times: &ActivationTimes<S>,
+) -> Collection<S, ((WorkerId, OperatorId), ActivationHistogram), Diff>
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+{
+    collect_histograms(&activation_times.explode(|(operator, (_start, duration))| {
+        iter::once(((operator, bucket_index(duration)), 1))
+    }))
+}
+
+/// Merges all of the histograms that share a key into a single histogram
+pub fn merge_histograms<S, K>(
+    histograms: &Collection<S, (K, ActivationHistogram), Diff>,
+) -> Collection<S, (K, ActivationHistogram), Diff>
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+    K: ExchangeData + Hash,
+{
+    collect_histograms(&histograms.explode(|(key, histogram)| {
+        histogram.buckets.into_iter().map(move |bucket| {
+            (
+                (key.clone(), bucket_index(bucket.lower)),
+                bucket.count as Diff,
+            )
+        })
+    }))
+}
+
+/// Gathers the activation counts of each bucket into a histogram
+fn collect_histograms<S, K>(
+    buckets: &Collection<S, (K, u64), Diff>,
+) -> Collection<S, (K, ActivationHistogram), Diff>
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+    K: ExchangeData + Hash,
+{
+    buckets
+        .count_total()
+        .map(|((key, index), count)| (key, (index, count)))
+        .reduce_named("Collect Activation Histograms", |_, buckets, output| {
+            // Reduce's input is sorted, so the buckets are already in order
+            let histogram =
+                ActivationHistogram::from_counts(buckets.iter().map(|&(&bucket, _)| bucket));
+
+            output.push((histogram, 1));
+        })
+}
+
+/// Gets the index of the bucket that the given duration falls into,
+/// bucket indices are in the same order as the buckets' durations
+fn bucket_index(duration: Duration) -> u64 {
+    let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+
+    // Durations that fit within the sub-buckets are counted exactly
+    if nanos < SUB_BUCKETS {
+        return nanos;
+    }
+
+    let shift = (63 - nanos.leading_zeros()) - SUB_BUCKET_BITS;
+    let sub_bucket = (nanos >> shift) - SUB_BUCKETS;
+
+    (shift as u64 + 1) * SUB_BUCKETS + sub_bucket
+}
+
+/// Gets the `[lower, upper)` bounds of a bucket in nanoseconds
+fn bucket_bounds(index: u64) -> (u64, u64) {
+    if index < SUB_BUCKETS {
+        return (index, index + 1);
+    }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+
+    (lower, lower.saturating_add(1 << shift))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_bounds, bucket_index, ActivationHistogram, SUB_BUCKETS};
+    use crate::dataflow::Percentiles;
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_hold_their_durations() {
+        for nanos in (0..100_000)
+            .chain((0..64).map(|shift| 1u64 << shift))
+            .chain([999_999, 1_000_000, 1_000_001, u64::MAX].iter().copied())
+        {
+            let index = bucket_index(Duration::from_nanos(nanos));
+            let (lower, upper) = bucket_bounds(index);
+            assert!(
+                lower <= nanos && (nanos < upper || upper == u64::MAX),
+                "{} isn't within [{}, {})",
+                nanos,
+                lower,
+                upper,
+            );
+
+            // Buckets are at most 1/32nd as wide as their durations
+            assert!((upper - lower) * SUB_BUCKETS <= lower.max(SUB_BUCKETS));
+            assert_eq!(bucket_index(Duration::from_nanos(lower)), index);
+        }
+
+        let indices: Vec<_> = (0..100_000)
+            .map(|nanos| bucket_index(Duration::from_nanos(nanos)))
+            .collect();
+        assert!(indices.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        // 99 activations taking 1µs and a single one taking 1ms
+        let fast = bucket_index(Duration::from_micros(1));
+        let slow = bucket_index(Duration::from_millis(1));
+        let histogram = ActivationHistogram::from_counts(vec![(fast, 99), (slow, 1)]);
+        assert_eq!(histogram.total(), 100);
+
+        let (min, max) = (Duration::from_micros(1), Duration::from_millis(1));
+        let percentiles = histogram.percentiles(min, max);
+        assert_eq!(
+            (percentiles.p50, percentiles.p90, percentiles.p99),
+            (
+                Duration::from_nanos(bucket_bounds(fast).1 - 1),
+                Duration::from_nanos(bucket_bounds(fast).1 - 1),
+                Duration::from_nanos(bucket_bounds(fast).1 - 1),
+            ),
+        );
+        assert_eq!(percentiles.p999, max);
+
+        // Every activation taking the same time has no deviation
+        let histogram = ActivationHistogram::from_counts(vec![(slow, 10)]);
+        assert_eq!(histogram.std_dev(max, max), Duration::from_secs(0));
+        assert_eq!(
+            histogram.percentiles(max, max),
+            Percentiles {
+                p50: max,
+                p90: max,
+                p99: max,
+                p999: max,
+            },
+        );
+    }
+}
@@ -1,6 +1,8 @@
 // mod channel_stats;
+mod consistency;
 pub(crate) mod constants;
 mod differential;
+mod histogram;
 mod operator_stats;
 pub mod operators;
 mod program_stats;
@@ -13,23 +15,29 @@ mod subgraphs;
 mod summation;
 mod tests;
 mod timely_source;
+mod timestamp_progress;
 pub(crate) mod utils;
 mod worker;
 mod worker_timeline;
 
+pub use consistency::{DivergenceKind, WorkerDivergence};
 pub use constants::PROGRAM_NS_GRANULARITY;
-pub use operator_stats::OperatorStats;
+pub use differential::ArrangementStats;
+pub use histogram::{ActivationHistogram, HistogramBucket};
+pub(crate) use operator_stats::std_dev;
+pub use operator_stats::{AggregatedOperatorStats, OperatorStats, Percentiles};
 pub use progress_stats::OperatorProgress;
 pub use progress_stats::{Channel, ProgressInfo};
 pub use send_recv::{DataflowData, DataflowExtractor, DataflowReceivers, DataflowSenders};
 pub use shape::OperatorShape;
+pub use timestamp_progress::OperatorTimestamp;
 pub use worker::worker_runtime;
 pub use worker_timeline::{EventKind, TimelineEvent};
 
 use crate::{
     args::Args,
+    critical_path::DataflowCriticalPath,
     dataflow::{
-        operator_stats::AggregatedOperatorStats,
         operators::{FilterMap, JoinArranged, Multiply, SortBy},
         send_recv::ChannelAddrs,
         subgraphs::rewire_channels,
@@ -61,9 +69,8 @@ use timely::{
     order::TotalOrder,
 };
 
-// TODO: Dataflow lints
-//  - Inconsistent dataflows across workers
-//  - Not arranging before a loop feedback
+// TODO: More dataflow lints (see `crate::lint`), these need the operator connectivity
+//       that only timely's reachability logging records
 //  - you aren't supposed to be able to forge capabilities,
 //    but you can take an incoming CapabilityRef and turn
 //    it in to a Capability for any output, even those that
@@ -86,6 +93,7 @@ where
     S: Scope<Timestamp = Time>,
 {
     let mut probe = ProbeHandle::new();
+    let granularity = args.program_ns_granularity.get() as u128;
 
     let (
         operator_lifespans,
@@ -103,17 +111,37 @@ where
         channel_scopes,
         dataflow_ids,
         timeline_events,
-    ) = timely_source::extract_timely_info(scope, timely_stream, args.disable_timeline);
+    ) = timely_source::extract_timely_info(
+        scope,
+        timely_stream,
+        args.disable_timeline,
+        granularity,
+    );
 
     // FIXME: `invocations` looks off, figure that out
-    let operator_stats =
-        operator_stats::operator_stats(scope, &operator_activations, differential_stream);
+    let operator_stats = operator_stats::operator_stats(
+        scope,
+        &operator_activations,
+        differential_stream,
+        args.activation_durations,
+    );
+
+    // Operators are matched across workers by comparing every worker's dataflow graph
+    // against the reference worker's, operators that diverge aren't aggregated
+    let (operator_matching, worker_divergences) =
+        consistency::worker_consistency(scope, &raw_operators, &raw_channels);
 
-    // FIXME: This is pretty much a guess since there's no way to actually associate
-    //        operators/arrangements/channels across workers
     // TODO: This should use a specialized struct to hold relevant things like "total size across workers"
     //       in addition to per-worker stats
-    let aggregated_operator_stats = operator_stats::aggregate_operator_stats(&operator_stats);
+    let aggregated_operator_stats = operator_stats::aggregate_operator_stats(
+        &operator_stats.join_map(
+            &operator_matching,
+            |&(worker, _), stats, &reference_operator| {
+                ((worker, reference_operator), stats.clone())
+            },
+        ),
+        args.activation_durations,
+    );
 
     // TODO: Turn these into collections of `(WorkerId, OperatorId)` and arrange them
     let (leaves, subgraphs) = sift_leaves_and_scopes(scope, &operator_addrs_by_self);
@@ -135,24 +163,44 @@ where
     let operator_progress = progress_stream.map(|progress_stream| {
         progress_stats::aggregate_channel_messages(progress_stream, &operator_shapes)
     });
+    let channel_progress = progress_stream
+        .map(|progress_stream| progress_stats::channel_messages(progress_stream, &raw_channels));
+    let operator_timestamps = progress_stream.map(|progress_stream| {
+        timestamp_progress::operator_timestamps(
+            scope,
+            progress_stream,
+            &operator_activations,
+            &operator_ids,
+        )
+    });
 
     // TODO: Make `extract_timely_info()` get the relevant event information
     // TODO: Grabbing events absolutely shits the bed when it comes to large dataflows,
     //       it needs a serious, intrinsic rework and/or disk backed arrangements
     let timeline_events = timeline_events.as_ref().map(|timeline_events| {
-        worker_timeline::worker_timeline(scope, timeline_events, differential_stream)
+        worker_timeline::worker_timeline(scope, timeline_events, differential_stream, granularity)
     });
 
     let addressed_operators = raw_operators
         .map(|(worker, operator)| ((worker, operator.addr.clone()), operator))
         .arrange_by_key_named("ArrangeByKey: Addressed Operators");
 
+    let critical_paths = operator_timestamps.map(|operator_timestamps| {
+        timestamp_progress::critical_paths(
+            scope,
+            &operator_timestamps,
+            &edges,
+            &addressed_operators,
+        )
+    });
+
     let (program_stats, worker_stats) = program_stats::aggregate_worker_stats(
         &timely_stream,
         differential_stream,
         &channels,
         &subgraphs_arranged,
         &operator_addrs_by_self,
+        granularity,
     );
 
     let dataflow_stats = dataflow_stats(
@@ -179,9 +227,13 @@ where
         timeline_events,
         operator_names,
         operator_ids,
-        None,
+        channel_progress,
         &operator_shapes,
         operator_progress.as_ref(),
+        &operator_lifespans,
+        &worker_divergences,
+        &operator_matching,
+        critical_paths.as_ref(),
     );
 
     // TODO: Save ddflow logs
@@ -203,6 +255,8 @@ where
 
         utils::logging_event_sink(
             save_logs,
+            args.log_compression,
+            args.program_name.as_deref().unwrap_or_default(),
             scope,
             timely_stream,
             &mut probe,
@@ -233,6 +287,10 @@ fn install_data_extraction<S>(
     channel_progress: Option<Collection<S, (OperatorAddr, ProgressInfo), Diff>>,
     operator_shapes: &Collection<S, OperatorShape, Diff>,
     operator_progress: Option<&Collection<S, OperatorProgress, Diff>>,
+    operator_lifespans: &Collection<S, ((WorkerId, OperatorId), Lifespan), Diff>,
+    worker_divergences: &Collection<S, WorkerDivergence, Diff>,
+    operator_matching: &Collection<S, ((WorkerId, OperatorId), OperatorId), Diff>,
+    critical_paths: Option<&Collection<S, DataflowCriticalPath, Diff>>,
 ) where
     S: Scope<Timestamp = Duration>,
 {
@@ -258,6 +316,12 @@ fn install_data_extraction<S>(
         let operator_progress = operator_progress
             .map(|progress| progress.enter_region(region))
             .unwrap_or_else(|| operator::empty(region).as_collection());
+        let operator_lifespans = operator_lifespans.enter_region(region);
+        let worker_divergences = worker_divergences.enter_region(region);
+        let operator_matching = operator_matching.enter_region(region);
+        let critical_paths = critical_paths
+            .map(|paths| paths.enter_region(region))
+            .unwrap_or_else(|| operator::empty(region).as_collection());
 
         let worker_stats = worker_stats
             .map(|(worker, stats)| ((), (worker, stats)))
@@ -293,6 +357,10 @@ fn install_data_extraction<S>(
             (&channel_progress, true),
             (&operator_shapes, true),
             (&operator_progress, true),
+            (&operator_lifespans, true),
+            (&worker_divergences, true),
+            (&operator_matching, true),
+            (&critical_paths, true),
         );
     })
 }
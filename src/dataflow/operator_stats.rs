@@ -1,16 +1,19 @@
 use crate::dataflow::{
     differential::{self, ArrangementStats},
-    operators::{DiffDuration, HierarchicalReduce, Keys, Max, Maybe, Min},
+    histogram::{self, ActivationHistogram},
+    operators::{DiffDuration, HierarchicalReduce, Max, Maybe, Min},
     summation::{summation, Summation},
     utils::{Diff, DifferentialLogBundle, Time},
     OperatorId, WorkerId,
 };
 use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
 use differential_dataflow::{
     difference::DiffPair,
-    operators::{Consolidate, CountTotal, Join, Reduce},
+    operators::{CountTotal, Join, Reduce},
     Collection,
 };
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
 use std::{iter, time::Duration};
 use timely::dataflow::{operators::Enter, Scope, Stream};
@@ -25,8 +28,13 @@ pub struct OperatorStats {
     pub min: Duration,
     pub average: Duration,
     pub total: Duration,
+    /// The standard deviation of the operator's activation durations
+    pub std_dev: Duration,
+    pub percentiles: Percentiles<Duration>,
     pub activations: usize,
-    /// Operator activation times `(start, duration)`
+    pub activation_histogram: ActivationHistogram,
+    /// Operator activation times `(start, duration)`, only
+    /// recorded when `--activation-durations` is given
     pub activation_durations: Vec<(Duration, Duration)>,
     pub arrangement_size: Option<ArrangementStats>,
     // pub messages_sent: usize,
@@ -42,20 +50,101 @@ pub struct AggregatedOperatorStats {
     pub min: Duration,
     pub average: Duration,
     pub total: Duration,
+    /// The standard deviation of the operator's activation durations
+    pub std_dev: Duration,
+    pub percentiles: Percentiles<Duration>,
     pub activations: usize,
-    /// Operator activation times `(start, duration)`
+    pub activation_histogram: ActivationHistogram,
+    /// Operator activation times `(start, duration)`, only
+    /// recorded when `--activation-durations` is given
     pub activation_durations: Vec<(Duration, Duration)>,
     pub arrangement_size: Option<ArrangementStats>,
     // pub messages_sent: usize,
     // pub messages_received: usize,
 }
 
-type ActivationTimes<S> = Collection<S, ((WorkerId, OperatorId), (Duration, Duration)), Diff>;
+/// Nearest-rank percentiles of a set of data points
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct Percentiles<T> {
+    pub p50: T,
+    pub p90: T,
+    pub p99: T,
+    pub p999: T,
+}
+
+impl<T> Percentiles<T>
+where
+    T: Copy + Default,
+{
+    /// Takes the percentiles of the given data points, which must already be sorted
+    pub fn from_sorted(sorted: &[T]) -> Self {
+        // The nearest rank of a percentile is `⌈n * p⌉`, taken in per-mille
+        // so that floating point error can't skew the rank
+        let rank = |per_mille: usize| {
+            let rank = (sorted.len() * per_mille).div_ceil(1000);
+            sorted
+                .get(rank.saturating_sub(1))
+                .copied()
+                .unwrap_or_default()
+        };
+
+        Self {
+            p50: rank(500),
+            p90: rank(900),
+            p99: rank(990),
+            p999: rank(999),
+        }
+    }
+}
+
+/// Calculates the population standard deviation of the given data points
+pub(crate) fn std_dev<I>(data_points: I) -> f64
+where
+    I: IntoIterator<Item = f64>,
+    I::IntoIter: Clone,
+{
+    let data_points = data_points.into_iter();
+
+    let (count, sum) = data_points
+        .clone()
+        .fold((0usize, 0.0), |(count, sum), point| {
+            (count + 1, sum + point)
+        });
+    if count == 0 {
+        return 0.0;
+    }
+
+    let mean = sum / count as f64;
+    let variance = data_points.map(|point| (point - mean).powi(2)).sum::<f64>() / count as f64;
+
+    variance.sqrt()
+}
+
+pub(super) type ActivationTimes<S> =
+    Collection<S, ((WorkerId, OperatorId), (Duration, Duration)), Diff>;
 
 pub fn operator_stats<S>(
     scope: &mut S,
     activation_times: &ActivationTimes<S>,
     differential_stream: Option<&Stream<S, DifferentialLogBundle>>,
+    record_durations: bool,
 ) -> Collection<S, ((WorkerId, OperatorId), OperatorStats), Diff>
 where
     S: Scope<Timestamp = Duration>,
@@ -69,26 +158,10 @@ where
         let execution_statistics =
             summation(&activation_times.map(|(operator, (_start, duration))| (operator, duration)));
 
-        let mut activation_durations = activation_times.reduce_named(
-            "Aggregate Activation Times",
-            |_, activations, output| {
-                let mut activations: Vec<_> = activations
-                    .iter()
-                    .map(|&(&activation, _)| activation)
-                    .collect();
-                activations.sort_unstable_by_key(|activation| activation.0);
+        let histograms = histogram::activation_histograms(&activation_times);
 
-                output.push((activations, 1));
-            },
-        );
-        activation_durations = activation_durations
-            .map(|(operator, _)| (operator, Vec::new()))
-            .antijoin(&activation_durations.keys())
-            .concat(&activation_durations)
-            .consolidate_named("Consolidate Activation Durations");
-
-        let operator_stats = execution_statistics.join_map(
-            &activation_durations,
+        let mut operator_stats = execution_statistics.join_map(
+            &histograms,
             |&(worker, id),
              &Summation {
                  max,
@@ -97,7 +170,7 @@ where
                  average,
                  count: activations,
              },
-             activation_durations| {
+             histogram| {
                 let stats = OperatorStats {
                     id,
                     worker,
@@ -105,9 +178,11 @@ where
                     min,
                     average,
                     total,
+                    std_dev: histogram.std_dev(min, max),
+                    percentiles: histogram.percentiles(min, max),
                     activations,
-                    // TODO: Populate this
-                    activation_durations: activation_durations.clone(),
+                    activation_histogram: histogram.clone(),
+                    activation_durations: Vec::new(),
                     arrangement_size: None,
                 };
 
@@ -115,6 +190,35 @@ where
             },
         );
 
+        // Keeping every activation around takes a lot of memory
+        // on long-running programs, so it's opt-in
+        if record_durations {
+            let activation_durations = activation_times.reduce_named(
+                "Aggregate Activation Times",
+                |_, activations, output| {
+                    let mut activations: Vec<_> = activations
+                        .iter()
+                        .map(|&(&activation, _)| activation)
+                        .collect();
+                    activations.sort_unstable_by_key(|activation| activation.0);
+
+                    output.push((activations, 1));
+                },
+            );
+
+            operator_stats = operator_stats.join_map(
+                &activation_durations,
+                |&operator, stats, activation_durations| {
+                    let stats = OperatorStats {
+                        activation_durations: activation_durations.clone(),
+                        ..stats.clone()
+                    };
+
+                    (operator, stats)
+                },
+            );
+        }
+
         differential_stream
             .as_ref()
             .map(|stream| {
@@ -141,6 +245,7 @@ where
 
 pub(crate) fn aggregate_operator_stats<S>(
     operator_stats: &Collection<S, ((WorkerId, OperatorId), OperatorStats), Diff>,
+    record_durations: bool,
 ) -> Collection<S, (OperatorId, AggregatedOperatorStats), Diff>
 where
     S: Scope<Timestamp = Time>,
@@ -148,36 +253,6 @@ where
     let operator_stats_without_worker =
         operator_stats.map(|((_worker, operator), stats)| (operator, stats));
 
-    let mut activation_durations = operator_stats
-        .map(|((_, operator), stats)| (operator, stats.activation_durations))
-        .hierarchical_reduce_named(
-            "Aggregate Operator Activation Durations",
-            |_, activations, output| {
-                let activations: Vec<_> = activations
-                    .iter()
-                    .flat_map(|&(activation, _)| activation)
-                    .copied()
-                    .collect();
-
-                output.push((activations, 1));
-            },
-            |_, activations, output| {
-                let mut activations: Vec<_> = activations
-                    .iter()
-                    .flat_map(|&(activation, _)| activation)
-                    .copied()
-                    .collect();
-                activations.sort_unstable_by_key(|activation| activation.0);
-
-                output.push((activations, 1));
-            },
-        );
-    activation_durations = operator_stats
-        .map(|((_, operator), _)| (operator, Vec::new()))
-        .antijoin(&activation_durations.keys())
-        .concat(&activation_durations)
-        .consolidate_named("Consolidate Aggregated Activation Durations");
-
     let aggregated = operator_stats_without_worker
         .explode(
             |(
@@ -296,6 +371,8 @@ where
                                 batches: batches as usize,
                                 // FIXME: Aggregate all spline levels?
                                 spline_levels: Vec::new(),
+                                batch_sizes: Vec::new(),
+                                trace_shares: 0,
                             })
                     } else {
                         None
@@ -308,7 +385,12 @@ where
                     min: min.to_duration(),
                     average,
                     total,
+                    // The standard deviation and percentiles are calculated
+                    // from every worker's activations below
+                    std_dev: Duration::from_secs(0),
+                    percentiles: Percentiles::default(),
                     activations: activations as usize,
+                    activation_histogram: ActivationHistogram::default(),
                     activation_durations: Vec::new(),
                     arrangement_size,
                 };
@@ -317,12 +399,93 @@ where
             },
         );
 
-    aggregated.join_map(&activation_durations, |&operator, stats, activations| {
+    let histograms = histogram::merge_histograms(
+        &operator_stats.map(|((_, operator), stats)| (operator, stats.activation_histogram)),
+    );
+
+    let mut aggregated = aggregated.join_map(&histograms, |&operator, stats, histogram| {
         let stats = AggregatedOperatorStats {
-            activation_durations: activations.clone(),
+            std_dev: histogram.std_dev(stats.min, stats.max),
+            percentiles: histogram.percentiles(stats.min, stats.max),
+            activation_histogram: histogram.clone(),
             ..stats.clone()
         };
 
         (operator, stats)
-    })
+    });
+
+    if record_durations {
+        let activation_durations = operator_stats
+            .map(|((_, operator), stats)| (operator, stats.activation_durations))
+            .hierarchical_reduce_named(
+                "Aggregate Operator Activation Durations",
+                |_, activations, output| {
+                    let activations: Vec<_> = activations
+                        .iter()
+                        .flat_map(|&(activation, _)| activation)
+                        .copied()
+                        .collect();
+
+                    output.push((activations, 1));
+                },
+                |_, activations, output| {
+                    let mut activations: Vec<_> = activations
+                        .iter()
+                        .flat_map(|&(activation, _)| activation)
+                        .copied()
+                        .collect();
+                    activations.sort_unstable_by_key(|activation| activation.0);
+
+                    output.push((activations, 1));
+                },
+            );
+
+        aggregated = aggregated.join_map(&activation_durations, |&operator, stats, activations| {
+            let stats = AggregatedOperatorStats {
+                activation_durations: activations.clone(),
+                ..stats.clone()
+            };
+
+            (operator, stats)
+        });
+    }
+
+    aggregated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{std_dev, Percentiles};
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let points: Vec<usize> = (1..=1000).collect();
+        let percentiles = Percentiles::from_sorted(&points);
+        assert_eq!(
+            percentiles,
+            Percentiles {
+                p50: 500,
+                p90: 900,
+                p99: 990,
+                p999: 999,
+            },
+        );
+
+        // Small samples round up to the next data point
+        let percentiles = Percentiles::from_sorted(&[1, 2, 3, 100]);
+        assert_eq!((percentiles.p50, percentiles.p90), (2, 100));
+        assert_eq!((percentiles.p99, percentiles.p999), (100, 100));
+
+        assert_eq!(
+            Percentiles::<usize>::from_sorted(&[]),
+            Percentiles::default()
+        );
+    }
+
+    #[test]
+    fn population_std_dev() {
+        let points = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert!((std_dev(points.iter().copied()) - 2.0).abs() < 1e-9);
+        assert_eq!(std_dev(Vec::new()), 0.0);
+    }
 }
@@ -169,6 +169,7 @@ where
             "ReplayWithShutdown",
             scope,
             is_running,
+            Arc::new(AtomicBool::new(false)),
             Fuel::unlimited(),
             DEFAULT_REACTIVATION_DELAY,
             None,
@@ -180,6 +181,7 @@ where
         name: N,
         scope: &mut S,
         is_running: Arc<AtomicBool>,
+        replay_stopped: Arc<AtomicBool>,
         fuel: Fuel,
         progress_bar: Option<ProgressBar>,
     ) -> Stream<S, D>
@@ -192,17 +194,20 @@ where
             name,
             scope,
             is_running,
+            replay_stopped,
             fuel,
             DEFAULT_REACTIVATION_DELAY,
             progress_bar,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn replay_with_shutdown_into_core<N, S>(
         self,
         name: N,
         scope: &mut S,
         is_running: Arc<AtomicBool>,
+        replay_stopped: Arc<AtomicBool>,
         fuel: Fuel,
         reactivation_delay: Duration,
         progress_bar: Option<ProgressBar>,
@@ -220,11 +225,13 @@ where
     <I as IntoIterator>::Item: EventIterator<T, D> + 'static,
 {
     #[track_caller]
+    #[allow(clippy::too_many_arguments)]
     fn replay_with_shutdown_into_core<N, S>(
         self,
         name: N,
         scope: &mut S,
         is_running: Arc<AtomicBool>,
+        replay_stopped: Arc<AtomicBool>,
         mut fuel: Fuel,
         reactivation_delay: Duration,
         progress_bar: Option<ProgressBar>,
@@ -320,7 +327,9 @@ where
                         },
 
                         Ok(None) => {
-                            if !is_running.load(Ordering::Acquire) {
+                            if !is_running.load(Ordering::Acquire)
+                                || replay_stopped.load(Ordering::Acquire)
+                            {
                                 break 'event_loop;
                             } else {
                                 break 'stream_loop;
@@ -349,69 +358,72 @@ where
             }
 
             let all_streams_finished = streams_finished.iter().copied().all(identity);
+            let stopped = replay_stopped.load(Ordering::Acquire);
 
             // If we're supposed to be running and haven't completed our input streams,
             // flush the output & re-activate ourselves after a delay
-            let needs_reactivation = if is_running.load(Ordering::Acquire) && !all_streams_finished
-            {
-                output.cease();
-                output
-                    .inner()
-                    .produced()
-                    .borrow_mut()
-                    .drain_into(&mut progress.produceds[0]);
-
-                // Reactivate according to the re-activation delay
-                activator.activate_after(reactivation_delay);
-
-                // Tell timely we have work left to do
-                true
-
-            // If we're not supposed to be running or all input streams are finished,
-            // flush our outputs and release all outstanding capabilities so that
-            // any downstream consumers know we're done
-            } else {
-                let reason = if all_streams_finished {
-                    "all streams have finished"
+            let needs_reactivation =
+                if is_running.load(Ordering::Acquire) && !stopped && !all_streams_finished {
+                    output.cease();
+                    output
+                        .inner()
+                        .produced()
+                        .borrow_mut()
+                        .drain_into(&mut progress.produceds[0]);
+
+                    // Reactivate according to the re-activation delay
+                    activator.activate_after(reactivation_delay);
+
+                    // Tell timely we have work left to do
+                    true
+
+                // If we're not supposed to be running, the replay was stopped or all input
+                // streams are finished, flush our outputs and release all outstanding
+                // capabilities so that any downstream consumers know we're done
                 } else {
-                    "is_running was set to false"
-                };
-
-                tracing::info!(
-                    worker = worker_index,
-                    is_running = is_running.load(Ordering::Acquire),
-                    all_streams_finished = all_streams_finished,
-                    "received shutdown signal within event replay: {}",
-                    reason,
-                );
-
-                // Flush the output stream
-                output.cease();
-
-                // Release all outstanding capabilities
-                while !antichain.is_empty() {
-                    let elements = antichain
-                        .frontier()
-                        .iter()
-                        .map(|time| (time.clone(), -1))
-                        .collect::<Vec<_>>();
+                    let reason = if all_streams_finished {
+                        "all streams have finished"
+                    } else if stopped {
+                        "the replay was stopped"
+                    } else {
+                        "is_running was set to false"
+                    };
+
+                    tracing::info!(
+                        worker = worker_index,
+                        is_running = is_running.load(Ordering::Acquire),
+                        all_streams_finished = all_streams_finished,
+                        "received shutdown signal within event replay: {}",
+                        reason,
+                    );
+
+                    // Flush the output stream
+                    output.cease();
+
+                    // Release all outstanding capabilities
+                    while !antichain.is_empty() {
+                        let elements = antichain
+                            .frontier()
+                            .iter()
+                            .map(|time| (time.clone(), -1))
+                            .collect::<Vec<_>>();
+
+                        for (time, change) in elements.iter() {
+                            progress.internals[0].update(time.clone(), *change);
+                        }
 
-                    for (time, change) in elements.iter() {
-                        progress.internals[0].update(time.clone(), *change);
+                        antichain.update_iter(elements);
                     }
 
-                    antichain.update_iter(elements);
-                }
-
-                if let Some(bar) = progress_bar.as_ref() {
-                    if started {
-                        bar.finish_using_style();
+                    if let Some(bar) = progress_bar.as_ref() {
+                        if started {
+                            bar.finish_using_style();
+                        }
                     }
-                }
 
-                // Tell timely we're completely done
-                false
-            };
+                    // Tell timely we're completely done
+                    false
+                };
 
             if let Some(logger) = logger.as_ref() {
                 logger.log(RawTimelyEvent::Input(RawInputEvent {
@@ -1,5 +1,12 @@
-use crate::dataflow::operators::EventIterator;
+use crate::{
+    dataflow::operators::EventIterator,
+    ui::{MissingEvents, StreamQuality},
+};
 use bytecheck::CheckBytes;
+use ddshow_sink::{
+    event_format_version, Handshake, EVENT_FORMAT_VERSION, EVENT_HEADER_LEN, HANDSHAKE_HEADER_LEN,
+    HANDSHAKE_MAGIC,
+};
 use ddshow_types::Event;
 use rkyv::{
     check_archived_root, de::deserializers::AllocDeserializer, validation::DefaultArchiveValidator,
@@ -11,9 +18,31 @@ use std::{
     io::{self, Read},
     marker::PhantomData,
     mem,
+    sync::{Arc, Mutex, PoisonError},
 };
 use timely::dataflow::operators::capture::event::Event as TimelyEvent;
 
+/// The format version of event files written before
+/// event files had headers
+pub const LEGACY_EVENT_FORMAT_VERSION: u32 = 0;
+
+/// Returns an error if the given event format version can't be read
+pub fn check_event_format_version(version: u32) -> io::Result<()> {
+    // Newer versions only add event variants, so every
+    // version up to the current one can still be read
+    if (LEGACY_EVENT_FORMAT_VERSION..=EVENT_FORMAT_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported event format version {}, this version of ddshow supports versions {} through {}",
+                version, LEGACY_EVENT_FORMAT_VERSION, EVENT_FORMAT_VERSION,
+            ),
+        ))
+    }
+}
+
 /// A Wrapper for `R: Read` implementing `EventIterator<T, D>`.
 pub struct RkyvEventReader<T, D, R> {
     reader: R,
@@ -22,6 +51,15 @@ pub struct RkyvEventReader<T, D, R> {
     buffer2: AlignedVec,
     consumed: usize,
     peer_finished: bool,
+    header_checked: bool,
+    quality: Option<Arc<Mutex<StreamQuality>>>,
+    /// The sequence number of the next message, if the stream has sequence numbers
+    next_sequence: Option<u64>,
+    /// The net change in capabilities made by the stream's progress updates,
+    /// streams that were closed by the target computation end at `-1`
+    capability_changes: i64,
+    /// Whether streams that end early are recorded as truncated
+    records_truncation: bool,
     __type: PhantomData<(T, D)>,
 }
 
@@ -35,9 +73,92 @@ impl<T, D, R> RkyvEventReader<T, D, R> {
             buffer2: AlignedVec::new(),
             consumed: 0,
             peer_finished: false,
+            header_checked: false,
+            quality: None,
+            next_sequence: None,
+            capability_changes: 0,
+            records_truncation: true,
             __type: PhantomData,
         }
     }
+
+    /// Records the events that are missing from the stream, dropped by the
+    /// sink or cut off by the stream ending early within the given quality
+    pub fn track_quality(mut self, quality: Arc<Mutex<StreamQuality>>) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Continues the sequence numbers of a previous stream, used when a
+    /// worker reconnects and picks up where its old connection left off
+    pub(crate) const fn resume_sequence(mut self, next_sequence: Option<u64>) -> Self {
+        self.next_sequence = next_sequence;
+        self
+    }
+
+    /// Leaves recording truncated streams up to the caller, a stream that's
+    /// resumed over a new connection isn't truncated even if its old
+    /// connection ended early
+    pub(crate) const fn defer_truncation(mut self) -> Self {
+        self.records_truncation = false;
+        self
+    }
+
+    /// The sequence number of the next message, if the stream has sequence numbers
+    pub(crate) const fn next_sequence(&self) -> Option<u64> {
+        self.next_sequence
+    }
+
+    fn update_quality<F>(&self, update: F)
+    where
+        F: FnOnce(&mut StreamQuality),
+    {
+        if let Some(quality) = self.quality.as_ref() {
+            update(&mut quality.lock().unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+
+    /// Records the messages skipped over by the given sequence number
+    fn sequence(&mut self, sequence: u64) {
+        let expected = self.next_sequence.unwrap_or(0);
+        if self.next_sequence.is_none() {
+            self.update_quality(|quality| quality.sequenced = true);
+        }
+
+        if sequence > expected {
+            tracing::warn!(
+                expected = expected,
+                sequence = sequence,
+                "{} events are missing from the stream",
+                sequence - expected,
+            );
+
+            self.update_quality(|quality| {
+                quality.missing.push(MissingEvents {
+                    start: expected,
+                    end: sequence,
+                });
+            });
+        }
+
+        self.next_sequence = Some(sequence);
+    }
+
+    /// Marks the stream as finished, recording whether it was cut short
+    fn finish(&mut self, is_finished: &mut bool, partial_event: bool) {
+        let truncated = partial_event || self.capability_changes != -1;
+        if truncated && self.records_truncation {
+            tracing::warn!(
+                partial_event = partial_event,
+                capability_changes = self.capability_changes,
+                "the stream ended before it was closed by the target computation",
+            );
+
+            self.update_quality(|quality| quality.truncated = true);
+        }
+
+        *is_finished = true;
+    }
 }
 
 impl<T, D, R> EventIterator<T, D> for RkyvEventReader<T, D, R>
@@ -49,12 +170,47 @@ where
     D::Archived: Deserialize<D, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
 {
     fn next(&mut self, is_finished: &mut bool) -> io::Result<Option<TimelyEvent<T, D>>> {
-        if self.peer_finished && self.buffer1[self.consumed..].is_empty() && self.buffer2.is_empty()
+        if *is_finished {
+            return Ok(None);
+        } else if self.peer_finished
+            && self.buffer1[self.consumed..].is_empty()
+            && self.buffer2.is_empty()
         {
-            *is_finished = true;
+            self.finish(is_finished, false);
             return Ok(None);
         }
 
+        // Streams written straight from `ddshow-sink` start with the worker's handshake,
+        // it's skipped so that events are aligned from the start of the event stream
+        if !self.header_checked && self.buffer1.starts_with(&HANDSHAKE_MAGIC) {
+            if let Some(header) = self.buffer1.get(..HANDSHAKE_HEADER_LEN) {
+                let handshake_len = Handshake::encoded_len(header.try_into().unwrap())?;
+
+                if self.buffer1.len() >= handshake_len {
+                    self.buffer2
+                        .extend_from_slice(&self.buffer1[handshake_len..]);
+
+                    mem::swap(&mut self.buffer1, &mut self.buffer2);
+                    self.buffer2.clear();
+                }
+            }
+        }
+
+        // Check the stream's header before reading any events from it, streams
+        // without a header are from before headers were added
+        if !self.header_checked
+            && !self.buffer1.starts_with(&HANDSHAKE_MAGIC)
+            && (self.buffer1.len() >= EVENT_HEADER_LEN || self.peer_finished)
+        {
+            let version = event_format_version(&self.buffer1);
+            check_event_format_version(version)?;
+
+            if version != LEGACY_EVENT_FORMAT_VERSION {
+                self.consumed += EVENT_HEADER_LEN;
+            }
+            self.header_checked = true;
+        }
+
         // Align to read
         let alignment_offset = match self.consumed & 15 {
             0 => 0,
@@ -62,10 +218,14 @@ where
         };
         let consumed = self.consumed + alignment_offset;
 
-        if let Some(header_slice) = self
-            .buffer1
-            .get(consumed..consumed + mem::size_of::<u128>())
-        {
+        let archive_header = if self.header_checked {
+            self.buffer1
+                .get(consumed..consumed + mem::size_of::<u128>())
+        } else {
+            None
+        };
+
+        if let Some(header_slice) = archive_header {
             let archive_length = u128::from_le_bytes(header_slice.try_into().unwrap()) as usize;
             let archive_start = consumed + mem::size_of::<u128>();
 
@@ -85,12 +245,57 @@ where
                     Ok(archive) => {
                         let event = archive
                             .deserialize(&mut AllocDeserializer)
-                            .unwrap_or_else(|unreachable| match unreachable {})
-                            .into();
+                            .unwrap_or_else(|unreachable| match unreachable {});
 
                         self.consumed += alignment_offset + archive_length + mem::size_of::<u128>();
 
-                        return Ok(Some(event));
+                        return match event {
+                            Event::Messages(time, data) => {
+                                if let Some(sequence) = self.next_sequence.as_mut() {
+                                    *sequence += data.len() as u64;
+                                }
+
+                                Ok(Some(TimelyEvent::Messages(time, data)))
+                            }
+
+                            Event::Progress(updates) => {
+                                self.capability_changes +=
+                                    updates.iter().map(|&(_, diff)| diff).sum::<i64>();
+
+                                Ok(Some(TimelyEvent::Progress(updates)))
+                            }
+
+                            // Dropped events, sequence numbers and sample rates are only recorded, they're
+                            // always followed by another event or the end of the stream
+                            Event::Dropped(_, dropped) => {
+                                tracing::warn!(
+                                    dropped_events = dropped,
+                                    "the sink dropped {} events",
+                                    dropped,
+                                );
+
+                                self.update_quality(|quality| quality.dropped += dropped);
+                                EventIterator::next(self, is_finished)
+                            }
+
+                            Event::Sequence(sequence) => {
+                                self.sequence(sequence);
+                                EventIterator::next(self, is_finished)
+                            }
+
+                            Event::SampledSchedules(one_in) => {
+                                tracing::info!(
+                                    schedule_sample_rate = one_in,
+                                    "the sink only logged one in every {} operator activations",
+                                    one_in,
+                                );
+
+                                self.update_quality(|quality| {
+                                    quality.schedule_sample_rate = one_in;
+                                });
+                                EventIterator::next(self, is_finished)
+                            }
+                        };
                     }
 
                     Err(err) => {
@@ -114,6 +319,13 @@ where
             }
         }
 
+        // The stream ended without a complete event left in it, so
+        // whatever's left over is part of an event that was cut off
+        if self.peer_finished {
+            self.finish(is_finished, true);
+            return Ok(None);
+        }
+
         // if we exhaust data we should shift back while preserving our alignment
         // of 16 bytes
         if self.consumed > 15 {
@@ -125,12 +337,29 @@ where
             self.buffer2.clear();
         }
 
-        if let Ok(len) = self.reader.read(&mut self.bytes[..]) {
-            if len == 0 {
-                self.peer_finished = true;
+        match self.reader.read(&mut self.bytes[..]) {
+            Ok(len) => {
+                if len == 0 {
+                    self.peer_finished = true;
+                }
+
+                self.buffer1.extend_from_slice(&self.bytes[..len]);
             }
 
-            self.buffer1.extend_from_slice(&self.bytes[..len]);
+            // Sockets are read with a timeout, so there's just nothing to read yet
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted,
+                ) => {}
+
+            // Anything else (like a reset connection) ends the stream
+            Err(err) => {
+                tracing::error!("failed to read from event stream: {:?}", err);
+                self.peer_finished = true;
+            }
         }
 
         Ok(None)
@@ -158,20 +387,35 @@ impl<T, D, R> Debug for RkyvEventReader<T, D, R> {
             .field("buffer1", &self.buffer1)
             .field("consumed", &self.consumed)
             .field("peer_finished", &self.peer_finished)
+            .field("header_checked", &self.header_checked)
+            .field("quality", &self.quality)
+            .field("next_sequence", &self.next_sequence)
+            .field("capability_changes", &self.capability_changes)
+            .field("records_truncation", &self.records_truncation)
             .finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::dataflow::{operators::RkyvEventReader, tests::init_test_logging};
-    use ddshow_sink::EventWriter;
+    use crate::{
+        archive::StreamKind,
+        dataflow::{operators::RkyvEventReader, tests::init_test_logging},
+        ui::StreamQuality,
+    };
+    use ddshow_sink::{
+        event_header, EventWriter, EVENT_FORMAT_VERSION, EVENT_HEADER_LEN, EVENT_MAGIC,
+    };
     use ddshow_types::{
         differential_logging::{DifferentialEvent, MergeEvent},
         timely_logging::{OperatesEvent, TimelyEvent},
         OperatorAddr, OperatorId,
     };
-    use std::time::Duration;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
     use timely::dataflow::operators::capture::{Event, EventPusher};
 
     // FIXME: Make this a proptest
@@ -322,4 +566,105 @@ mod tests {
 
         assert_eq!(events, vec![first, second, third]);
     }
+
+    #[test]
+    fn legacy_headerless_events() {
+        init_test_logging();
+
+        let event = Event::Progress(vec![(Duration::from_secs(0), 1)]);
+
+        let mut buffer = Vec::new();
+        EventWriter::<Duration, TimelyEvent, _>::new(&mut buffer).push(event.clone());
+        assert_eq!(buffer[..EVENT_HEADER_LEN], event_header());
+
+        // Strip the header off to get an event from before headers were added
+        let mut reader = RkyvEventReader::new(&buffer[EVENT_HEADER_LEN..]);
+        assert!(reader.next().is_none());
+        assert_eq!(reader.next(), Some(event));
+    }
+
+    #[test]
+    fn unsupported_format_version() {
+        init_test_logging();
+
+        let mut buffer = Vec::new();
+        EventWriter::<Duration, TimelyEvent, _>::new(&mut buffer)
+            .push(Event::Progress(vec![(Duration::from_secs(0), 1)]));
+        buffer[EVENT_MAGIC.len()..EVENT_MAGIC.len() + 4]
+            .copy_from_slice(&(EVENT_FORMAT_VERSION + 1).to_le_bytes());
+
+        let mut reader = RkyvEventReader::<Duration, TimelyEvent, _>::new(&buffer[..]);
+        let next = |reader: &mut RkyvEventReader<_, _, _>| {
+            crate::dataflow::operators::EventIterator::next(reader, &mut false)
+        };
+
+        // Fill the buffers and then check the header
+        assert!(next(&mut reader).unwrap().is_none());
+        let err = next(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn read_with_quality(buffer: &[u8]) -> (Vec<Event<Duration, TimelyEvent>>, StreamQuality) {
+        let quality = Arc::new(Mutex::new(StreamQuality::new(StreamKind::Timely, 0)));
+        let mut reader = RkyvEventReader::new(buffer).track_quality(quality.clone());
+        let events = crate::dataflow::operators::EventIterator::take_events(&mut reader).unwrap();
+
+        let quality = quality.lock().unwrap().clone();
+        (events, quality)
+    }
+
+    #[test]
+    fn truncated_streams() {
+        init_test_logging();
+
+        let operates = |id| {
+            TimelyEvent::Operates(OperatesEvent::new(
+                OperatorId::new(id),
+                OperatorAddr::from_elem(OperatorId::new(id)),
+                "foobar".to_owned(),
+            ))
+        };
+        let events = vec![
+            Event::Messages(Duration::from_secs(0), vec![operates(0), operates(1)]),
+            Event::Progress(vec![
+                (Duration::from_secs(1), 1),
+                (Duration::from_secs(0), -1),
+            ]),
+            Event::Messages(Duration::from_secs(1), vec![operates(2)]),
+            Event::Progress(vec![(Duration::from_secs(1), -1)]),
+        ];
+
+        let mut buffer = Vec::new();
+        {
+            let mut writer = EventWriter::new(&mut buffer);
+            for event in events.iter().cloned() {
+                writer.push(event);
+            }
+        }
+
+        // A stream that was closed by its writer
+        let (replayed, quality) = read_with_quality(&buffer);
+        assert_eq!(replayed, events);
+        assert!(quality.sequenced);
+        assert!(!quality.has_issues());
+
+        // A stream that's cut off partway through its last event
+        let (replayed, quality) = read_with_quality(&buffer[..buffer.len() - 4]);
+        assert_eq!(replayed, events[..3]);
+        assert!(quality.truncated);
+        assert!(quality.missing.is_empty());
+
+        // A stream that ends before the writer closed it
+        let mut unclosed = Vec::new();
+        {
+            let mut writer = EventWriter::new(&mut unclosed);
+            for event in events[..3].iter().cloned() {
+                writer.push(event);
+            }
+        }
+
+        let (replayed, quality) = read_with_quality(&unclosed);
+        assert_eq!(replayed, events[..3]);
+        assert!(quality.truncated);
+    }
 }
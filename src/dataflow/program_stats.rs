@@ -50,6 +50,7 @@ pub fn aggregate_worker_stats<S>(
     channels: &Collection<S, (WorkerId, Channel), Diff>,
     subgraph_addresses: &ChannelAddrs<S, Diff>,
     operator_addrs_by_self: &ArrangedKey<S, (WorkerId, OperatorAddr), Diff>,
+    granularity: u128,
 ) -> AggregatedStats<S>
 where
     S: Scope<Timestamp = Duration>,
@@ -131,7 +132,7 @@ where
         |(time, worker, _)| (worker, time, 1),
     )
     .as_collection()
-    .delay_fast(granulate)
+    .delay_fast(move |time| granulate(time, granularity))
     .count_total();
 
     let create_timestamps = |time, worker| {
@@ -150,7 +151,7 @@ where
         move |(time, worker, _)| create_timestamps(time, worker),
     )
     .as_collection()
-    .delay_fast(granulate)
+    .delay_fast(move |time| granulate(time, granularity))
     .count_total();
 
     // TODO: For whatever reason this part of the dataflow graph is de-prioritized,
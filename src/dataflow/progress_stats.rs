@@ -4,14 +4,16 @@ use crate::dataflow::{
     Diff, OperatorShape,
 };
 use abomonation_derive::Abomonation;
-use ddshow_types::{ChannelId, OperatorAddr, OperatorId, PortId, WorkerId};
+use ddshow_types::{
+    timely_logging::ChannelsEvent, ChannelId, OperatorAddr, OperatorId, PortId, WorkerId,
+};
 use differential_dataflow::{
-    operators::{arrange::ArrangeByKey, CountTotal, Join, Reduce},
+    operators::{arrange::ArrangeByKey, CountTotal, Join, Reduce, ThresholdTotal},
     AsCollection, Collection,
 };
 use serde::{Deserialize, Serialize};
 use std::{iter, time::Duration};
-use timely::dataflow::{Scope, Stream};
+use timely::dataflow::{operators::Map, Scope, Stream};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation)]
 pub struct ChannelMessageStats {
@@ -269,3 +271,79 @@ where
             )
         })
 }
+
+/// Totals up the messages sent over each channel across all workers
+///
+/// Timely records messages in progress updates against the input port that
+/// they're sent to, so each channel's messages are found by matching its
+/// target port. Progress updates are consolidated before they're sent, so
+/// messages that are sent and received within a single batch cancel out and
+/// channels that never leave a worker won't have any recorded messages
+pub fn channel_messages<S>(
+    progress_stream: &Stream<S, ProgressLogBundle>,
+    channels: &Collection<S, (WorkerId, ChannelsEvent), Diff>,
+) -> Collection<S, (OperatorAddr, ProgressInfo), Diff>
+where
+    S: Scope<Timestamp = Duration>,
+{
+    // Only sent updates are used since every received update
+    // is a copy of an update sent by some worker
+    let message_updates = progress_stream
+        .flat_map(|(time, _worker, event)| {
+            let scope_addr = event.addr;
+            let messages = if event.is_send {
+                event.messages
+            } else {
+                Vec::new()
+            };
+
+            messages.into_iter().map(move |message| {
+                (
+                    (
+                        (scope_addr.clone(), [message.node, message.port]),
+                        message.diff as isize,
+                    ),
+                    time,
+                    1,
+                )
+            })
+        })
+        .as_collection();
+
+    // Channels are the same on every worker
+    let channel_targets = channels
+        .map(|(_, channel)| ((channel.scope_addr, channel.target), channel.id))
+        .distinct_total();
+
+    message_updates
+        .join_map(
+            &channel_targets,
+            |(scope_addr, [node, _port]), &diff, &channel| {
+                ((channel, scope_addr.push_imm(*node)), diff)
+            },
+        )
+        .reduce(|_, diffs, output| {
+            let (mut produced, mut consumed) = (ProgressStats::default(), ProgressStats::default());
+            for &(&diff, count) in diffs {
+                let stats = if diff > 0 {
+                    &mut produced
+                } else {
+                    &mut consumed
+                };
+                stats.messages += diff.unsigned_abs() * count as usize;
+                stats.capability_updates += count as usize;
+            }
+
+            output.push(((produced, consumed), 1));
+        })
+        .map(|((channel_id, target_addr), (produced, consumed))| {
+            (
+                target_addr,
+                ProgressInfo {
+                    consumed,
+                    produced,
+                    channel_id,
+                },
+            )
+        })
+}
@@ -1,4 +1,5 @@
 use crate::{
+    critical_path::DataflowCriticalPath,
     dataflow::{
         constants::DEFAULT_EXTRACTOR_CAPACITY,
         operator_stats::{AggregatedOperatorStats, OperatorStats},
@@ -6,9 +7,9 @@ use crate::{
         progress_stats::{Channel, OperatorProgress, ProgressInfo},
         utils::{channel_sink, Diff, Time},
         worker_timeline::TimelineEvent,
-        OperatorShape,
+        OperatorShape, WorkerDivergence,
     },
-    ui::{DataflowStats, ProgramStats, WorkerStats},
+    ui::{DataflowStats, Lifespan, ProgramStats, StreamQuality, WorkerStats},
 };
 use crossbeam_channel::{Receiver, Sender};
 use ddshow_types::{timely_logging::OperatesEvent, OperatorAddr, OperatorId, WorkerId};
@@ -261,9 +262,13 @@ macro_rules! make_send_recv {
             }
         }
 
-        #[derive(Clone, Debug, Serialize, Deserialize)]
+        #[derive(Clone, Debug, Default, Serialize, Deserialize)]
         pub struct DataflowData {
             $(pub $name: Vec<$ty>,)*
+            /// The quality of each replayed log stream, reported by the
+            /// replay sources rather than produced by the dataflow
+            #[serde(default)]
+            pub data_quality: Vec<StreamQuality>,
         }
 
         impl DataflowData {
@@ -273,6 +278,7 @@ macro_rules! make_send_recv {
             ) -> Self {
                 Self {
                     $($name,)*
+                    data_quality: Vec::new(),
                 }
             }
         }
@@ -304,6 +310,8 @@ type TimelineEventData = TimelineEvent;
 type NameLookupData = ((WorkerId, OperatorId), String);
 type AddrLookupData = ((WorkerId, OperatorId), OperatorAddr);
 type ChannelProgressData = (OperatorAddr, ProgressInfo);
+type OperatorLifespanData = ((WorkerId, OperatorId), Lifespan);
+type OperatorMatchingData = ((WorkerId, OperatorId), OperatorId);
 
 make_send_recv! {
     program_stats: ProgramStats,
@@ -320,4 +328,8 @@ make_send_recv! {
     channel_progress: ChannelProgressData,
     operator_shapes: OperatorShape,
     operator_progress: OperatorProgress,
+    operator_lifespans: OperatorLifespanData,
+    worker_divergences: WorkerDivergence,
+    operator_matching: OperatorMatchingData,
+    critical_paths: DataflowCriticalPath,
 }
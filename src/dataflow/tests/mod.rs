@@ -9,7 +9,7 @@ use crate::dataflow::{
     worker_timeline::{
         collect_differential_events, process_timely_event, EventKind, EventProcessor, TimelineEvent,
     },
-    TimelyLogBundle,
+    TimelyLogBundle, PROGRAM_NS_GRANULARITY,
 };
 use ddshow_types::{
     differential_logging::{DifferentialEvent, MergeEvent, MergeShortfall},
@@ -76,7 +76,7 @@ fn timely_event_association() {
 
     let data = recv.extract();
     let expected = vec![(
-        granulate(&Duration::from_nanos(2)),
+        granulate(&Duration::from_nanos(2), PROGRAM_NS_GRANULARITY),
         vec![(
             TimelineEvent::new(
                 WorkerId::new(0),
@@ -84,7 +84,7 @@ fn timely_event_association() {
                 Duration::from_nanos(1000),
                 Duration::from_nanos(9000),
             ),
-            granulate(&Duration::from_nanos(2)),
+            granulate(&Duration::from_nanos(2), PROGRAM_NS_GRANULARITY),
             Present,
         )],
     )];
@@ -101,7 +101,7 @@ fn differential_event_association() {
     timely::execute_directly(move |worker| {
         let (mut input, probe) = worker.dataflow(|scope| {
             let (input, stream) = scope.new_input();
-            let partial_events = collect_differential_events(&stream);
+            let partial_events = collect_differential_events(&stream, PROGRAM_NS_GRANULARITY);
             partial_events
                 .inner
                 .capture_into(send.lock().unwrap().take().unwrap());
@@ -164,12 +164,12 @@ fn differential_event_association() {
     });
 
     assert_eq!(
-        granulate(&Duration::from_nanos(2)),
-        granulate(&Duration::from_nanos(4)),
+        granulate(&Duration::from_nanos(2), PROGRAM_NS_GRANULARITY),
+        granulate(&Duration::from_nanos(4), PROGRAM_NS_GRANULARITY),
         "need to update the layout of ddflow tests",
     );
     let expected = vec![(
-        granulate(&Duration::from_nanos(2)),
+        granulate(&Duration::from_nanos(2), PROGRAM_NS_GRANULARITY),
         vec![
             (
                 TimelineEvent::new(
@@ -178,7 +178,7 @@ fn differential_event_association() {
                     Duration::from_nanos(1000),
                     Duration::from_nanos(9000),
                 ),
-                granulate(&Duration::from_nanos(2)),
+                granulate(&Duration::from_nanos(2), PROGRAM_NS_GRANULARITY),
                 Present,
             ),
             (
@@ -188,7 +188,7 @@ fn differential_event_association() {
                     Duration::from_nanos(20_000),
                     Duration::from_nanos(1000),
                 ),
-                granulate(&Duration::from_nanos(4)),
+                granulate(&Duration::from_nanos(4), PROGRAM_NS_GRANULARITY),
                 Present,
             ),
         ],
@@ -255,5 +255,5 @@ where
             },
         )
         .as_collection()
-        .delay_fast(granulate)
+        .delay_fast(|time| granulate(time, PROGRAM_NS_GRANULARITY))
 }
@@ -2,6 +2,7 @@ use crate::dataflow::{
     operators::ActivateCapabilitySet,
     utils::granulate,
     worker_timeline::{EventKind, TimelineEvent},
+    PROGRAM_NS_GRANULARITY,
 };
 use ddshow_types::{
     differential_logging::{DifferentialEvent, DropEvent, MergeEvent, MergeShortfall},
@@ -34,7 +35,7 @@ pub struct EventPair<E> {
 impl<E> EventPair<E> {
     fn build_expected(&self, event: EventKind) -> ExpectedEvent {
         (
-            granulate(&self.end.recv_timestamp),
+            granulate(&self.end.recv_timestamp, PROGRAM_NS_GRANULARITY),
             (
                 TimelineEvent::new(
                     self.worker,
@@ -42,7 +43,7 @@ impl<E> EventPair<E> {
                     self.start.timestamp,
                     self.end.timestamp - self.start.timestamp,
                 ),
-                granulate(&self.end.recv_timestamp),
+                granulate(&self.end.recv_timestamp, PROGRAM_NS_GRANULARITY),
                 Present,
             ),
         )
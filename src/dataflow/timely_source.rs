@@ -77,6 +77,7 @@ pub(super) fn extract_timely_info<S>(
     scope: &mut S,
     timely_stream: &Stream<S, TimelyLogBundle>,
     disable_timeline: bool,
+    granularity: u128,
 ) -> TimelyCollections<S>
 where
     S: Scope<Timestamp = Duration>,
@@ -175,7 +176,7 @@ where
         channel_scope_addrs,
         dataflow_ids,
         worker_events,
-    } = streams.into_collections();
+    } = streams.into_collections(granularity);
 
     // TODO: Granulate the times within the operator
     let operator_names = operator_names.arrange_by_key_named("ArrangeByKey: Operator Names");
@@ -602,9 +603,9 @@ macro_rules! timely_source_processor {
         where
             S: Scope<Timestamp = Time>,
         {
-            fn into_collections(self) -> Collections<S> {
+            fn into_collections(self, granularity: u128) -> Collections<S> {
                 Collections {
-                    $($name: timely_source_processor!(@as_collection self, $name, $($cond)?),)*
+                    $($name: timely_source_processor!(@as_collection self, $name, granularity, $($cond)?),)*
                 }
             }
         }
@@ -780,12 +781,19 @@ macro_rules! timely_source_processor {
         Collection<S, $data, $diff>
     };
 
-    (@as_collection $self:ident, $name:ident, $cond:ident) => {
-        $self.$name.map(|$name| $name.as_collection().delay_fast(granulate))
+    (@as_collection $self:ident, $name:ident, $granularity:ident, $cond:ident) => {
+        $self.$name.map(|$name| {
+            $name
+                .as_collection()
+                .delay_fast(move |time| granulate(time, $granularity))
+        })
     };
 
-    (@as_collection $self:ident, $name:ident,) => {
-        $self.$name.as_collection().delay_fast(granulate)
+    (@as_collection $self:ident, $name:ident, $granularity:ident,) => {
+        $self
+            .$name
+            .as_collection()
+            .delay_fast(move |time| granulate(time, $granularity))
     };
 }
 
@@ -0,0 +1,328 @@
+//! Tracks how long each top-level operator held on to every timestamp of its dataflow
+//!
+//! The progress updates that a worker sends name the operators that are
+//! holding capabilities or receiving messages at a timestamp, so the span
+//! between an operator's first and last update at a timestamp is how long the
+//! timestamp was in that operator's hands. The operator's activations within
+//! that span are how long it was actually busy. Each timestamp's operators are
+//! then traced into its critical path, and only the combined critical paths of
+//! each dataflow leave the dataflow
+
+use crate::{
+    critical_path::{self, DataflowCriticalPath, PathPart, PathSummary},
+    dataflow::{
+        operator_stats::ActivationTimes,
+        operators::Max,
+        progress_stats::Channel,
+        utils::{ArrangedVal, Diff, ProgressLogBundle},
+    },
+};
+use abomonation_derive::Abomonation;
+use ddshow_types::{timely_logging::OperatesEvent, OperatorAddr, OperatorId, PortId, WorkerId};
+use differential_dataflow::{
+    operators::{CountTotal, Join, JoinCore, Reduce},
+    AsCollection, Collection,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use timely::dataflow::{
+    operators::{Enter, Map},
+    Scope, Stream,
+};
+
+/// A single top-level operator's handling of a single timestamp
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Abomonation, Deserialize, Serialize,
+)]
+pub struct OperatorTimestamp {
+    pub worker: WorkerId,
+    /// The address of the operator, its first element is its dataflow
+    pub addr: OperatorAddr,
+    /// The timestamp as formatted by timely
+    pub timestamp: String,
+    /// The time of the operator's first progress update at the timestamp
+    pub first_update: Duration,
+    /// The time of the operator's last progress update at the timestamp
+    pub last_update: Duration,
+    /// How long the operator was scheduled for between its first and last updates
+    pub busy: Duration,
+}
+
+/// A span of time that's either an operator activation or
+/// the span an operator held on to a timestamp for
+type Span = (Duration, Duration, Option<String>);
+
+pub fn operator_timestamps<S>(
+    scope: &mut S,
+    progress_stream: &Stream<S, ProgressLogBundle>,
+    activation_times: &ActivationTimes<S>,
+    operator_addrs: &ArrangedVal<S, (WorkerId, OperatorId), OperatorAddr, Diff>,
+) -> Collection<S, OperatorTimestamp, Diff>
+where
+    S: Scope<Timestamp = Duration>,
+{
+    scope.region_named("Track Timestamp Progress", |region| {
+        let (progress_stream, activation_times, operator_addrs) = (
+            progress_stream.enter(region),
+            activation_times.enter_region(region),
+            operator_addrs.enter_region(region),
+        );
+
+        // Received updates are copies of the updates other workers sent,
+        // so only sent updates are attributed to their worker
+        let timestamp_spans = progress_stream
+            .flat_map(|(time, worker, event)| {
+                let scope_addr = event.addr;
+                let updates: Vec<_> = if event.is_send && scope_addr.len() == 1 {
+                    event
+                        .messages
+                        .into_iter()
+                        .map(|message| (message.node, message.timestamp))
+                        .chain(
+                            event
+                                .internal
+                                .into_iter()
+                                .map(|update| (update.node, update.timestamp)),
+                        )
+                        // Node zero is the dataflow's own boundary
+                        .filter(|&(node, _)| node != PortId::new(0))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+                updates.into_iter().map(move |(node, timestamp)| {
+                    (
+                        ((worker, scope_addr.push_imm(node), timestamp), time),
+                        time,
+                        1,
+                    )
+                })
+            })
+            .as_collection()
+            .reduce_named("Reduce: Timestamp Spans", |_, times, output| {
+                // Reduce's input is sorted, so the first and last times are the min and max
+                let (first, last) = (*times[0].0, *times[times.len() - 1].0);
+                output.push(((first, last), 1));
+            })
+            .map(|((worker, addr, timestamp), (first, last))| {
+                ((worker, addr), (first, last, Some(timestamp)))
+            });
+
+        // Only the activations of operators directly within a dataflow are used since
+        // the activations of a subgraph's operators are within the subgraph's activations
+        let top_level_activations = activation_times.join_core(
+            &operator_addrs,
+            |&(worker, _), &(start, duration), addr| {
+                if addr.len() == 2 {
+                    Some(((worker, addr.clone()), (start, start + duration, None)))
+                } else {
+                    None
+                }
+            },
+        );
+
+        timestamp_spans
+            .concat(&top_level_activations)
+            .reduce_named(
+                "Reduce: Busy Time Within Timestamps",
+                |&(worker, ref addr), spans: &[(&Span, Diff)], output| {
+                    // Activations sort before timestamps and an operator's activations
+                    // never overlap, so they're sorted by both their starts and ends
+                    let activations: Vec<_> = spans
+                        .iter()
+                        .filter(|(span, _)| span.2.is_none())
+                        .map(|&(&(start, end, _), _)| (start, end))
+                        .collect();
+
+                    for &(&(first, last, ref timestamp), _) in spans {
+                        if let Some(timestamp) = timestamp {
+                            let overlapping = activations.partition_point(|&(_, end)| end <= first);
+                            let busy = activations[overlapping..]
+                                .iter()
+                                .take_while(|&&(start, _)| start < last)
+                                .map(|&(start, end)| end.min(last) - start.max(first))
+                                .sum();
+
+                            let timestamp = OperatorTimestamp {
+                                worker,
+                                addr: addr.clone(),
+                                timestamp: timestamp.clone(),
+                                first_update: first,
+                                last_update: last,
+                                busy,
+                            };
+                            output.push((timestamp, 1));
+                        }
+                    }
+                },
+            )
+            .map(|(_, timestamp)| timestamp)
+            .leave_region()
+    })
+}
+
+/// Traces the critical path of every timestamp and combines
+/// them into the critical paths of each dataflow
+pub fn critical_paths<S>(
+    scope: &mut S,
+    operator_timestamps: &Collection<S, OperatorTimestamp, Diff>,
+    edges: &Collection<S, (WorkerId, OperatesEvent, Channel, OperatesEvent), Diff>,
+    addressed_operators: &ArrangedVal<S, (WorkerId, OperatorAddr), OperatesEvent, Diff>,
+) -> Collection<S, DataflowCriticalPath, Diff>
+where
+    S: Scope<Timestamp = Duration>,
+{
+    scope.region_named("Find Critical Paths", |region| {
+        let (operator_timestamps, edges, addressed_operators) = (
+            operator_timestamps.enter_region(region),
+            edges.enter_region(region),
+            addressed_operators.enter_region(region),
+        );
+
+        // Channels within subgraphs are irrelevant to the top-level path, so
+        // every channel is attributed to the top-level operators it connects
+        let dataflow_channels = edges
+            .flat_map(|(worker, _, channel, _)| {
+                let (source, target) = (channel.source_addr(), channel.target_addr());
+                if source.len() < 2 || target.len() < 2 {
+                    return None;
+                }
+
+                let (source, target) = (
+                    OperatorAddr::from(&source[..2]),
+                    OperatorAddr::from(&target[..2]),
+                );
+                (source != target)
+                    .then(|| ((worker, OperatorAddr::from(&target[..1])), (target, source)))
+            })
+            .reduce_named("Reduce: Dataflow Channels", |_, channels, output| {
+                let channels: Vec<_> = channels
+                    .iter()
+                    .map(|&(channel, _)| channel.clone())
+                    .collect();
+                output.push((channels, 1));
+            });
+
+        let timestamp_operators = operator_timestamps
+            .map(|timestamp| {
+                (
+                    (
+                        timestamp.worker,
+                        OperatorAddr::from(&timestamp.addr[..1]),
+                        timestamp.timestamp.clone(),
+                    ),
+                    timestamp,
+                )
+            })
+            .reduce_named("Reduce: Timestamp Operators", |_, operators, output| {
+                let operators: Vec<_> = operators
+                    .iter()
+                    .map(|&(operator, _)| operator.clone())
+                    .collect();
+                output.push((operators, 1));
+            })
+            .map(|((worker, dataflow, _), operators)| ((worker, dataflow), operators));
+
+        // Dataflows without any channels between their operators
+        // have critical paths made of a single operator
+        let channelless = timestamp_operators
+            .antijoin(&dataflow_channels.map(|(dataflow, _)| dataflow))
+            .map(|(dataflow, operators)| (dataflow, operators, Vec::new()));
+        let paths = timestamp_operators
+            .join_map(&dataflow_channels, |dataflow, operators, channels| {
+                (dataflow.clone(), operators.clone(), channels.clone())
+            })
+            .concat(&channelless)
+            .flat_map(|((worker, dataflow), operators, channels)| {
+                let (path, hops) = critical_path::timestamp_path(worker, &operators, &channels);
+
+                hops.into_iter()
+                    .map(move |hop| {
+                        let part = PathPart::Hop {
+                            latency: hop.latency,
+                            busy: hop.busy,
+                        };
+
+                        ((worker, hop.addr), part)
+                    })
+                    .chain(Some(((worker, dataflow), PathPart::Timestamp(path))))
+            });
+
+        // Name every timestamp's dataflow and every operator on its path
+        let parts = paths.join_core(&addressed_operators, |(_, addr), part, operator| {
+            Some((
+                (
+                    OperatorAddr::from(&addr[..1]),
+                    addr.clone(),
+                    operator.name.clone(),
+                ),
+                part.clone(),
+            ))
+        });
+
+        // Every part is added to the running totals of its dataflow or operator
+        // as it comes in, so new timestamps don't recombine every earlier path
+        let totals = parts
+            .explode(|(key, part)| {
+                IntoIterator::into_iter(part.stats())
+                    .map(move |(stat, amount)| ((key.clone(), stat), amount))
+            })
+            .count_total()
+            .map(|(((dataflow, addr, name), stat), total)| {
+                (
+                    dataflow,
+                    PathSummary::Total {
+                        addr,
+                        name,
+                        stat,
+                        total,
+                    },
+                )
+            });
+
+        // The slowest timestamp of each dataflow is found from the running max of
+        // its latencies, only the timestamps tied for the max have to be compared
+        let timestamps = parts.flat_map(|((dataflow, _, _), part)| match part {
+            PathPart::Timestamp(path) => Some((dataflow, path)),
+            PathPart::Hop { .. } => None,
+        });
+        let max_latencies = timestamps
+            .explode(|(dataflow, path)| {
+                Some((
+                    dataflow,
+                    Max::new(critical_path::duration_nanos(path.latency)),
+                ))
+            })
+            .count_total()
+            .map(|(dataflow, max)| (dataflow, max.value));
+        let slowest = timestamps
+            .map(|(dataflow, path)| {
+                (
+                    (dataflow, critical_path::duration_nanos(path.latency)),
+                    path,
+                )
+            })
+            .semijoin(&max_latencies)
+            .map(|((dataflow, _), path)| (dataflow, path))
+            .reduce_named("Reduce: Slowest Timestamps", |_, paths, output| {
+                // Ties go to the first of the tied timestamps
+                output.push((PathSummary::Slowest(paths[0].0.clone()), 1));
+            });
+
+        totals
+            .concat(&slowest)
+            .reduce_named(
+                "Reduce: Dataflow Critical Paths",
+                |dataflow, summaries, output| {
+                    let summaries = summaries.iter().map(|&(summary, _)| summary);
+                    output.push((
+                        critical_path::dataflow_critical_path(dataflow, summaries),
+                        1,
+                    ));
+                },
+            )
+            .map(|(_, path)| path)
+            .leave_region()
+    })
+}
@@ -1,9 +1,12 @@
+use crate::dataflow::operators::CrossbeamPusher;
 #[cfg(feature = "timely-next")]
 use crate::dataflow::reachability::TrackerEvent;
-use crate::dataflow::{operators::CrossbeamPusher, PROGRAM_NS_GRANULARITY};
 use anyhow::{Context, Result};
 use crossbeam_channel::Sender;
-use ddshow_sink::{EventWriter, DIFFERENTIAL_ARRANGEMENT_LOG_FILE, TIMELY_LOG_FILE};
+use ddshow_sink::{
+    Compression, EventWriter, Handshake, StreamKind, DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+    TIMELY_LOG_FILE,
+};
 use ddshow_types::{
     differential_logging::DifferentialEvent, progress_logging::TimelyProgressEvent,
     timely_logging::TimelyEvent, WorkerId,
@@ -50,16 +53,16 @@ pub type ProgressLogBundle<Id = WorkerId> = (Time, Id, TimelyProgressEvent);
 pub type ReachabilityLogBundle<Id = WorkerId> = (Time, Id, TrackerEvent);
 
 /// Puts timestamps into non-overlapping buckets that contain
-/// the timestamps from `last_bucket..PROGRAM_NS_GRANULARITY`
-/// to reduce the load on timely
-pub(crate) fn granulate(&time: &Duration) -> Duration {
+/// the timestamps from `last_bucket..granularity` to reduce
+/// the load on timely, see `PROGRAM_NS_GRANULARITY` for the default
+pub(crate) fn granulate(&time: &Duration, granularity: u128) -> Duration {
     let timestamp = time.as_nanos();
-    let window_idx = (timestamp / PROGRAM_NS_GRANULARITY) + 1;
+    let window_idx = (timestamp / granularity) + 1;
 
-    let minted = Duration::from_nanos((window_idx * PROGRAM_NS_GRANULARITY) as u64);
+    let minted = Duration::from_nanos((window_idx * granularity) as u64);
     debug_assert_eq!(
-        u64::try_from(window_idx * PROGRAM_NS_GRANULARITY).map(|res| res as u128),
-        Ok(window_idx * PROGRAM_NS_GRANULARITY),
+        u64::try_from(window_idx * granularity).map(|res| res as u128),
+        Ok(window_idx * granularity),
     );
     debug_assert!(time <= minted);
 
@@ -95,9 +98,13 @@ pub(super) fn channel_sink<S, D, R>(
     );
 }
 
-/// Store all timely and differential events to disk
+/// Store all timely and differential events to disk, each file starts with a
+/// handshake for the ddshow worker that saved it since a ddshow worker can
+/// replay the events of more than one target worker
 pub(super) fn logging_event_sink<S>(
     save_logs: &Path,
+    compression: Compression,
+    program: &str,
     scope: &mut S,
     timely_stream: &Stream<S, (Duration, WorkerId, TimelyEvent)>,
     probe: &mut ProbeHandle<Duration>,
@@ -109,7 +116,7 @@ where
     // Create the directory for log files to go to
     fs::create_dir_all(&save_logs).context("failed to create `--save-logs` directory")?;
 
-    let timely_path = log_file_path(TIMELY_LOG_FILE, save_logs, scope.index());
+    let timely_path = log_file_path(TIMELY_LOG_FILE, save_logs, scope.index(), compression);
 
     tracing::debug!(
         "installing timely file sink on worker {} pointed at {}",
@@ -117,17 +124,28 @@ where
         timely_path.display(),
     );
 
-    let timely_file = BufWriter::new(
-        File::create(timely_path).context("failed to create `--save-logs` timely file")?,
-    );
+    let mut timely_file = compression
+        .compress(BufWriter::new(
+            File::create(timely_path).context("failed to create `--save-logs` timely file")?,
+        ))
+        .context("failed to compress `--save-logs` timely file")?;
+
+    Handshake::new(StreamKind::Timely, scope.index(), scope.peers())
+        .program(program)
+        .write_to(&mut timely_file)
+        .context("failed to write `--save-logs` timely file")?;
 
     timely_stream
         .probe_with(probe)
         .capture_into(EventWriter::new(timely_file));
 
     if let Some(differential_stream) = differential_stream {
-        let differential_path =
-            log_file_path(DIFFERENTIAL_ARRANGEMENT_LOG_FILE, save_logs, scope.index());
+        let differential_path = log_file_path(
+            DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+            save_logs,
+            scope.index(),
+            compression,
+        );
 
         tracing::debug!(
             "installing differential file sink on worker {} pointed at {}",
@@ -135,10 +153,17 @@ where
             differential_path.display(),
         );
 
-        let differential_file = BufWriter::new(
-            File::create(differential_path)
-                .context("failed to create `--save-logs` differential file")?,
-        );
+        let mut differential_file = compression
+            .compress(BufWriter::new(
+                File::create(differential_path)
+                    .context("failed to create `--save-logs` differential file")?,
+            ))
+            .context("failed to compress `--save-logs` differential file")?;
+
+        Handshake::new(StreamKind::Differential, scope.index(), scope.peers())
+            .program(program)
+            .write_to(&mut differential_file)
+            .context("failed to write `--save-logs` differential file")?;
 
         differential_stream
             .probe_with(probe)
@@ -149,10 +174,17 @@ where
 }
 
 /// Constructs the path to a logging file for the given worker
-pub(super) fn log_file_path(file_prefix: &str, dir: &Path, worker_id: usize) -> PathBuf {
+pub(super) fn log_file_path(
+    file_prefix: &str,
+    dir: &Path,
+    worker_id: usize,
+    compression: Compression,
+) -> PathBuf {
     dir.join(format!(
-        "{}.replay-worker-{}.ddshow",
-        file_prefix, worker_id,
+        "{}.replay-worker-{}.{}",
+        file_prefix,
+        worker_id,
+        compression.extension(),
     ))
 }
 
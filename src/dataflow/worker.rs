@@ -30,7 +30,10 @@ use std::{
 };
 use timely::{
     communication::Allocate,
-    dataflow::{operators::Map, Scope, Stream},
+    dataflow::{
+        operators::{Inspect, Map},
+        Scope, Stream,
+    },
     logging::TimelyEvent as RawTimelyEvent,
     worker::Worker,
 };
@@ -42,7 +45,9 @@ pub fn worker_runtime<A>(
     args: Arc<Args>,
     senders: DataflowSenders,
     replay_shutdown: Arc<AtomicBool>,
+    replay_stopped: Arc<AtomicBool>,
     workers_finished: Arc<AtomicUsize>,
+    events_replayed: Arc<AtomicUsize>,
     multi_progress: Arc<MultiProgress>,
     timely_traces: TimelyReplaySource,
     differential_traces: Option<DifferentialReplaySource>,
@@ -133,12 +138,14 @@ where
                 scope,
                 timely_traces,
                 replay_shutdown.clone(),
+                replay_stopped.clone(),
                 fuel.clone(),
                 &multi_progress,
                 "Timely",
                 &mut progress_bars,
                 &mut source_counter,
                 total_sources,
+                &events_replayed,
             )
         });
 
@@ -149,12 +156,14 @@ where
                     scope,
                     traces,
                     replay_shutdown.clone(),
+                    replay_stopped.clone(),
                     fuel.clone(),
                     &multi_progress,
                     "Differential",
                     &mut progress_bars,
                     &mut source_counter,
                     total_sources,
+                    &events_replayed,
                 );
 
                 Some(stream)
@@ -175,12 +184,14 @@ where
                     scope,
                     traces,
                     replay_shutdown.clone(),
+                    replay_stopped.clone(),
                     fuel.clone(),
                     &multi_progress,
                     "Progress",
                     &mut progress_bars,
                     &mut source_counter,
                     total_sources,
+                    &events_replayed,
                 );
 
                 Ok(Some(stream))
@@ -250,12 +261,14 @@ fn replay_traces<S, Event, RawEvent, R, A>(
     scope: &mut S,
     traces: ReplaySource<R, A>,
     replay_shutdown: Arc<AtomicBool>,
+    replay_stopped: Arc<AtomicBool>,
     fuel: Fuel,
     multi_progress: &MultiProgress,
     source: &'static str,
     progress_bars: &mut Vec<(ProgressBar, ProgressStyle)>,
     source_counter: &mut usize,
     total_sources: usize,
+    events_replayed: &Arc<AtomicUsize>,
 ) -> Stream<S, (Duration, WorkerId, Event)>
 where
     S: Scope<Timestamp = Duration>,
@@ -320,15 +333,31 @@ where
             &name,
             scope,
             replay_shutdown,
+            replay_stopped,
             fuel,
             Some(progress),
         ),
 
         ReplaySource::Abomonation(abomonation) => abomonation
-            .replay_with_shutdown_into_named(&name, scope, replay_shutdown, fuel, Some(progress))
+            .replay_with_shutdown_into_named(
+                &name,
+                scope,
+                replay_shutdown,
+                replay_stopped,
+                fuel,
+                Some(progress),
+            )
             .map(|(time, worker, event): (Duration, usize, RawEvent)| {
                 (time, WorkerId::new(worker), Event::from(event))
             }),
     }
     .debug_inspect(move |x| tracing::trace!("{} event: {:?}", source, x))
+    // Keep track of how many events we've received so that the main thread
+    // can tell when the target computation has gone quiet
+    .inspect_batch({
+        let events_replayed = events_replayed.clone();
+        move |_, events| {
+            events_replayed.fetch_add(events.len(), Ordering::Relaxed);
+        }
+    })
 }
@@ -4,6 +4,7 @@ use crate::dataflow::{
     utils::{granulate, DifferentialLogBundle},
 };
 use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
 use ddshow_types::{
     differential_logging::DifferentialEvent,
     timely_logging::{ParkEvent, StartStop, TimelyEvent},
@@ -14,6 +15,7 @@ use differential_dataflow::{
     lattice::Lattice,
     AsCollection, Collection, ExchangeData,
 };
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, mem, time::Duration};
 use timely::dataflow::{
@@ -30,6 +32,7 @@ pub(super) fn worker_timeline<S>(
     scope: &mut S,
     timely_events: &Collection<S, TimelineEvent, Present>,
     differential_stream: Option<&Stream<S, DifferentialLogBundle>>,
+    granularity: u128,
 ) -> Collection<S, TimelineEvent, Present>
 where
     S: Scope<Timestamp = Duration>,
@@ -43,8 +46,8 @@ where
         // TODO: Emit trace drops & shares to a separate stream so that we can make markers
         //       with `timeline.setCustomTime()`
         // TODO: Emit the # of batches received
-        let differential_events =
-            differential_stream.map(|event_stream| collect_differential_events(&event_stream));
+        let differential_events = differential_stream
+            .map(|event_stream| collect_differential_events(&event_stream, granularity));
 
         differential_events
             .as_ref()
@@ -126,6 +129,7 @@ pub(super) fn process_timely_event(
 // TODO: Wire operator shutdown events into this as well
 pub(super) fn collect_differential_events<S>(
     event_stream: &Stream<S, DifferentialLogBundle>,
+    granularity: u128,
 ) -> Collection<S, TimelineEvent, Present>
 where
     S: Scope<Timestamp = Duration>,
@@ -163,7 +167,7 @@ where
             },
         )
         .as_collection()
-        .delay_fast(granulate)
+        .delay_fast(move |time| granulate(time, granularity))
 }
 
 fn process_differential_event(
@@ -533,8 +537,22 @@ where
 }
 
 #[derive(
-    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Abomonation,
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
 )]
+#[archive(strict, derive(CheckBytes))]
 pub enum EventKind {
     OperatorActivation { operator_id: OperatorId },
     Application { id: usize },
@@ -0,0 +1,302 @@
+//! Splits multiplexed connections from `ddshow-sink` back into the log streams they carry
+//!
+//! A multiplexed connection carries the log streams of every worker within a target
+//! process, each stream is sent over its own channel that's opened by a [`Handshake`].
+//! The connection is read without blocking by whichever of its channels is read from,
+//! so every channel's data is buffered until its own replay source gets to it. Reading
+//! from the connection stops while any channel's buffer is full, so a slow replay
+//! source holds up the connection rather than buffering everything sent over it
+
+use anyhow::{Context, Result};
+use ddshow_sink::{
+    check_multiplex_preamble, FrameHeader, FrameKind, Handshake, FRAME_HEADER_LEN,
+    MULTIPLEX_PREAMBLE_LEN,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    fmt::{self, Debug},
+    io::{self, Read},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// The most that's read from a multiplexed connection at once
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
+/// The most data that's buffered for a channel that's being
+/// replayed before reading from its connection stops
+const MAX_CHANNEL_BUFFER_LEN: usize = 16 * 1024 * 1024;
+
+/// Reads the frames of a multiplexed connection and hands out its channels
+pub(crate) struct Demultiplexer {
+    peer: String,
+    state: Mutex<DemuxState>,
+}
+
+struct DemuxState {
+    connection: Box<dyn Read + Send>,
+    /// Bytes that have been read but not parsed into frames yet
+    unparsed: Vec<u8>,
+    preamble_checked: bool,
+    /// The buffered data of every channel that's being replayed
+    channels: HashMap<u32, ChannelBuffer>,
+    /// Channels that have been opened but not handed out yet
+    opened: VecDeque<(Handshake, u32)>,
+    /// Set once new channels should be ignored
+    closed_to_new_channels: bool,
+    /// Set once the connection has closed or failed
+    finished: bool,
+}
+
+#[derive(Debug, Default)]
+struct ChannelBuffer {
+    data: VecDeque<u8>,
+    closed: bool,
+    /// Set once the channel's replay source starts reading from it, channels
+    /// that are still waiting to be replayed don't hold up their connection
+    replaying: bool,
+}
+
+impl Demultiplexer {
+    /// Demultiplexes the given connection, which must not block when read from
+    pub(crate) fn new<R>(connection: R, peer: String) -> Arc<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        Arc::new(Self {
+            peer,
+            state: Mutex::new(DemuxState {
+                connection: Box::new(connection),
+                unparsed: Vec::new(),
+                preamble_checked: false,
+                channels: HashMap::new(),
+                opened: VecDeque::new(),
+                closed_to_new_channels: false,
+                finished: false,
+            }),
+        })
+    }
+
+    /// Reads whatever is available from the connection,
+    /// returning any channels that were opened
+    pub(crate) fn poll(self: &Arc<Self>) -> Result<Vec<(Handshake, Channel)>> {
+        let mut state = self.state();
+        state
+            .fill()
+            .with_context(|| format!("rejected the multiplexed connection from {}", self.peer))?;
+
+        let opened = state
+            .opened
+            .drain(..)
+            .map(|(handshake, id)| {
+                let channel = Channel {
+                    id,
+                    demux: self.clone(),
+                };
+
+                (handshake, channel)
+            })
+            .collect();
+
+        Ok(opened)
+    }
+
+    /// Ignores any channels opened from now on, along with any
+    /// opened channels that haven't been handed out
+    pub(crate) fn close_to_new_channels(&self) {
+        let mut state = self.state();
+        state.closed_to_new_channels = true;
+
+        while let Some((_, id)) = state.opened.pop_front() {
+            state.channels.remove(&id);
+        }
+    }
+
+    /// The peer that the connection came from
+    pub(crate) fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// Locks the demultiplexer's state, the state is never left
+    /// half-updated so any poisoning is ignored
+    fn state(&self) -> MutexGuard<'_, DemuxState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl DemuxState {
+    /// Reads everything that's currently available and splits it up into
+    /// frames, stopping early if any replayed channel's buffer fills up
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = vec![0; READ_CHUNK_LEN];
+
+        while !self.finished && !self.is_backed_up() {
+            match self.connection.read(&mut chunk) {
+                Ok(0) => self.finished = true,
+                Ok(len) => {
+                    self.unparsed.extend_from_slice(&chunk[..len]);
+
+                    // Give other channels a turn instead of
+                    // reading from a busy connection forever
+                    if len < chunk.len() {
+                        break;
+                    }
+                }
+
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted,
+                    ) =>
+                {
+                    break
+                }
+
+                Err(err) => {
+                    self.finished = true;
+                    return Err(err);
+                }
+            }
+        }
+
+        let result = self.parse();
+
+        // Nothing after a malformed frame can be trusted
+        if result.is_err() {
+            self.finished = true;
+        }
+
+        result
+    }
+
+    /// Returns `true` if a replayed channel's buffer is full
+    fn is_backed_up(&self) -> bool {
+        self.channels
+            .values()
+            .any(|channel| channel.replaying && channel.data.len() >= MAX_CHANNEL_BUFFER_LEN)
+    }
+
+    /// Parses all complete frames
+    fn parse(&mut self) -> io::Result<()> {
+        let mut offset = 0;
+
+        if !self.preamble_checked {
+            let preamble = match self.unparsed.get(..MULTIPLEX_PREAMBLE_LEN) {
+                Some(preamble) => preamble.try_into().unwrap(),
+                None => return Ok(()),
+            };
+
+            check_multiplex_preamble(preamble)?;
+            self.preamble_checked = true;
+            offset += MULTIPLEX_PREAMBLE_LEN;
+        }
+
+        while let Some(header) = self.unparsed.get(offset..offset + FRAME_HEADER_LEN) {
+            let header = FrameHeader::from_bytes(header.try_into().unwrap())?;
+
+            let start = offset + FRAME_HEADER_LEN;
+            let payload = match self.unparsed.get(start..start + header.len as usize) {
+                Some(payload) => payload,
+                None => break,
+            };
+
+            match header.kind {
+                FrameKind::Open => {
+                    let handshake = Handshake::from_bytes(payload)?;
+
+                    if self.closed_to_new_channels {
+                        tracing::warn!(
+                            worker = handshake.worker,
+                            "ignoring the {} logs of worker {} that were opened after all \
+                             connections were received",
+                            handshake.stream,
+                            handshake.worker,
+                        );
+                    } else {
+                        self.channels
+                            .insert(header.channel, ChannelBuffer::default());
+                        self.opened.push_back((handshake, header.channel));
+                    }
+                }
+
+                // Data for channels that aren't being replayed is dropped
+                FrameKind::Data => {
+                    if let Some(channel) = self.channels.get_mut(&header.channel) {
+                        channel.data.extend(payload);
+                    }
+                }
+
+                FrameKind::Close => {
+                    if let Some(channel) = self.channels.get_mut(&header.channel) {
+                        channel.closed = true;
+                    }
+                }
+            }
+
+            offset = start + header.len as usize;
+        }
+
+        self.unparsed.drain(..offset);
+        Ok(())
+    }
+
+    fn channel(&mut self, id: u32) -> &mut ChannelBuffer {
+        self.channels
+            .get_mut(&id)
+            .expect("channels are buffered for as long as they exist")
+    }
+}
+
+impl Debug for Demultiplexer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+
+        f.debug_struct("Demultiplexer")
+            .field("peer", &self.peer)
+            .field("unparsed", &state.unparsed.len())
+            .field("channels", &state.channels)
+            .field("finished", &state.finished)
+            .finish()
+    }
+}
+
+/// A single log stream carried by a multiplexed connection
+#[derive(Debug)]
+pub(crate) struct Channel {
+    id: u32,
+    demux: Arc<Demultiplexer>,
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.demux.state();
+        state.channel(self.id).replaying = true;
+        state.fill()?;
+
+        let finished = state.finished;
+        let channel = state.channel(self.id);
+
+        if channel.data.is_empty() {
+            return if channel.closed || finished {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            };
+        }
+
+        let (front, _) = channel.data.as_slices();
+        let len = front.len().min(buf.len());
+        buf[..len].copy_from_slice(&front[..len]);
+        channel.data.drain(..len);
+
+        Ok(len)
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.demux.state().channels.remove(&self.id);
+    }
+}
@@ -0,0 +1,315 @@
+//! Compares the operator stats of two traces to find performance regressions
+//!
+//! Operators are matched between traces by their address and name, so the
+//! traces should come from (mostly) the same dataflow program
+
+use crate::{
+    archive::{ArchiveHeader, StreamKind},
+    args::Args,
+    dataflow::{AggregatedOperatorStats, DataflowData},
+    report,
+};
+use anyhow::{Context, Result};
+use ddshow_types::OperatorAddr;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// The differences between the operators of two traces
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceDiff {
+    pub trace_a: PathBuf,
+    pub trace_b: PathBuf,
+    /// Operators that appear in both traces, ordered by the
+    /// magnitude of their change in runtime
+    pub operators: Vec<OperatorDiff>,
+    /// Operators that only appear in the first trace
+    pub only_in_a: Vec<OperatorSummary>,
+    /// Operators that only appear in the second trace
+    pub only_in_b: Vec<OperatorSummary>,
+}
+
+impl TraceDiff {
+    /// Replays and analyzes both traces, then compares their operators
+    pub fn new(args: &Args, trace_a: &Path, trace_b: &Path) -> Result<Self> {
+        let operators_a = trace_operators(&load_trace(args, trace_a)?);
+        let operators_b = trace_operators(&load_trace(args, trace_b)?);
+
+        Ok(Self::from_operators(
+            trace_a.to_owned(),
+            trace_b.to_owned(),
+            operators_a,
+            operators_b,
+        ))
+    }
+
+    fn from_operators(
+        trace_a: PathBuf,
+        trace_b: PathBuf,
+        operators_a: BTreeMap<(OperatorAddr, String), OperatorMetrics>,
+        mut operators_b: BTreeMap<(OperatorAddr, String), OperatorMetrics>,
+    ) -> Self {
+        let (mut operators, mut only_in_a) = (Vec::new(), Vec::new());
+        for ((addr, name), a) in operators_a {
+            match operators_b.remove(&(addr.clone(), name.clone())) {
+                Some(b) => operators.push(OperatorDiff::new(name, addr, a, b)),
+                None => only_in_a.push(OperatorSummary {
+                    name,
+                    addr,
+                    metrics: a,
+                }),
+            }
+        }
+
+        let mut only_in_b: Vec<_> = operators_b
+            .into_iter()
+            .map(|((addr, name), metrics)| OperatorSummary {
+                name,
+                addr,
+                metrics,
+            })
+            .collect();
+
+        operators.sort_by_key(|diff| Reverse(diff.runtime_change_ns.abs()));
+        only_in_a.sort_by_key(|summary| Reverse(summary.metrics.total_runtime));
+        only_in_b.sort_by_key(|summary| Reverse(summary.metrics.total_runtime));
+
+        Self {
+            trace_a,
+            trace_b,
+            operators,
+            only_in_a,
+            only_in_b,
+        }
+    }
+
+    /// Writes the diff as a set of human readable tables
+    pub fn write_table<W>(&self, output: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        report::trace_diff(self, output)
+    }
+
+    /// Writes the diff to the given file as json
+    pub fn write_json(&self, file: &Path) -> Result<()> {
+        if let Some(parent) = file
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {}", parent.display()))?;
+        }
+
+        let mut writer = BufWriter::new(
+            File::create(file)
+                .with_context(|| format!("failed to create diff file {}", file.display()))?,
+        );
+        serde_json::to_writer_pretty(&mut writer, self).context("failed to write diff json")?;
+        writer.flush().context("failed to write diff json")?;
+
+        Ok(())
+    }
+}
+
+/// The change in an operator's stats between two traces
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperatorDiff {
+    pub name: String,
+    pub addr: OperatorAddr,
+    pub a: OperatorMetrics,
+    pub b: OperatorMetrics,
+    /// The change in total runtime from `a` to `b`, in nanoseconds
+    pub runtime_change_ns: i64,
+    /// The change in total runtime relative to `a`, `None` if
+    /// the operator never ran in `a`
+    pub runtime_change_percent: Option<f64>,
+    pub activations_change: i64,
+    /// The change in max arrangement size, `None` if the operator
+    /// isn't an arrangement in both traces
+    pub arrangement_size_change: Option<i64>,
+}
+
+impl OperatorDiff {
+    fn new(name: String, addr: OperatorAddr, a: OperatorMetrics, b: OperatorMetrics) -> Self {
+        let (runtime_a, runtime_b) = (a.total_runtime.as_nanos(), b.total_runtime.as_nanos());
+        let runtime_change_percent = if runtime_a == 0 {
+            None
+        } else {
+            Some((runtime_b as f64 - runtime_a as f64) / runtime_a as f64 * 100.0)
+        };
+
+        let runtime_change_ns = change(runtime_a, runtime_b);
+        let activations_change = change(a.activations as u128, b.activations as u128);
+        let arrangement_size_change = a
+            .arrangement_size
+            .zip(b.arrangement_size)
+            .map(|(a, b)| change(a as u128, b as u128));
+
+        Self {
+            name,
+            addr,
+            a,
+            b,
+            runtime_change_ns,
+            runtime_change_percent,
+            activations_change,
+            arrangement_size_change,
+        }
+    }
+}
+
+/// The change from `a` to `b`, saturated to the range of an `i64`
+fn change(a: u128, b: u128) -> i64 {
+    // Durations in nanoseconds and sizes both fit within an i128
+    let change = b as i128 - a as i128;
+
+    i64::try_from(change).unwrap_or(if change < 0 { i64::MIN } else { i64::MAX })
+}
+
+/// An operator that only appears in one of the traces
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperatorSummary {
+    pub name: String,
+    pub addr: OperatorAddr,
+    #[serde(flatten)]
+    pub metrics: OperatorMetrics,
+}
+
+/// The compared stats of a single operator within a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperatorMetrics {
+    pub total_runtime: Duration,
+    pub activations: usize,
+    /// The max size of the operator's arrangement, if it has one
+    pub arrangement_size: Option<usize>,
+}
+
+impl From<&AggregatedOperatorStats> for OperatorMetrics {
+    fn from(stats: &AggregatedOperatorStats) -> Self {
+        Self {
+            total_runtime: stats.total,
+            activations: stats.activations,
+            arrangement_size: stats
+                .arrangement_size
+                .as_ref()
+                .map(|arrange| arrange.max_size),
+        }
+    }
+}
+
+/// Replays a saved trace, enabling whichever log streams it contains
+fn load_trace(args: &Args, trace: &Path) -> Result<DataflowData> {
+    let has_stream = |stream: StreamKind| -> Result<bool> {
+        if trace.is_file() {
+            Ok(ArchiveHeader::open(trace)?.has_stream(stream))
+        } else {
+            let prefix = format!("{}.", stream.file_prefix());
+
+            for entry in fs::read_dir(trace)
+                .with_context(|| format!("failed to read log directory {}", trace.display()))?
+            {
+                let name = entry?.file_name();
+                if matches!(name.to_str(), Some(name) if name.starts_with(&prefix)) {
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        }
+    };
+
+    let args = Arc::new(Args {
+        replay_logs: Some(trace.to_owned()),
+        differential_enabled: has_stream(StreamKind::Differential)?,
+        progress_enabled: has_stream(StreamKind::Progress)?,
+        no_report_file: true,
+        dump_json: None,
+        save_logs: None,
+        bundle_logs: None,
+        stop_after: None,
+        stop_when_idle: None,
+        serve: None,
+        command: None,
+        ..args.clone()
+    });
+
+    let sources = crate::connect_to_sources(&args)?
+        .with_context(|| format!("failed to load trace {}", trace.display()))?;
+
+    crate::analyze(args, sources)
+        .with_context(|| format!("failed to analyze trace {}", trace.display()))
+}
+
+/// Collects the stats of every operator within a trace keyed by their address and name
+fn trace_operators(data: &DataflowData) -> BTreeMap<(OperatorAddr, String), OperatorMetrics> {
+    let (mut addrs, mut names) = (HashMap::new(), HashMap::new());
+    for ((_, operator), addr) in data.addr_lookup.iter() {
+        addrs.entry(*operator).or_insert(addr);
+    }
+    for ((_, operator), name) in data.name_lookup.iter() {
+        names.entry(*operator).or_insert(name);
+    }
+
+    data.aggregated_operator_stats
+        .iter()
+        .filter_map(|(operator, stats)| {
+            let addr = addrs.get(operator)?;
+            let name = names.get(operator).map_or("", |name| name.as_str());
+
+            Some((
+                ((*addr).clone(), name.to_owned()),
+                OperatorMetrics::from(stats),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OperatorDiff, OperatorMetrics};
+    use ddshow_types::OperatorAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn changes_saturate_instead_of_wrapping() {
+        let metrics = |total_runtime, activations| OperatorMetrics {
+            total_runtime,
+            activations,
+            arrangement_size: Some(activations),
+        };
+
+        let (short, long) = (Duration::from_nanos(1), Duration::MAX);
+        let diff = OperatorDiff::new(
+            String::new(),
+            OperatorAddr::new(Default::default()),
+            metrics(short, 0),
+            metrics(long, usize::MAX),
+        );
+
+        assert_eq!(diff.runtime_change_ns, i64::MAX);
+        assert!(diff.runtime_change_percent.unwrap() > 0.0);
+        if usize::MAX as u128 > i64::MAX as u128 {
+            assert_eq!(diff.activations_change, i64::MAX);
+            assert_eq!(diff.arrangement_size_change, Some(i64::MAX));
+        }
+
+        let diff = OperatorDiff::new(
+            String::new(),
+            OperatorAddr::new(Default::default()),
+            metrics(long, usize::MAX),
+            metrics(short, 0),
+        );
+
+        assert_eq!(diff.runtime_change_ns, i64::MIN);
+        assert_eq!(diff.runtime_change_percent, Some(-100.0));
+    }
+}
@@ -0,0 +1,237 @@
+//! Exports the dataflow graph in the [Graphviz] DOT format
+//!
+//! Subgraphs are drawn as nested clusters by their address and operators are
+//! colored by their total runtime, the graph is the same on every worker so
+//! each operator and channel is only drawn once
+//!
+//! [Graphviz]: https://graphviz.org
+
+use crate::{
+    args::Args,
+    colormap::select_color,
+    dataflow::{Channel, DataflowData},
+};
+use anyhow::{Context, Result};
+use ddshow_types::{ChannelId, OperatorAddr, OperatorId};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// Writes the dataflow graph to the given file as DOT
+pub(crate) fn write_dot_file(args: &Args, file: &Path, data: &DataflowData) -> Result<()> {
+    if let Some(parent) = file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))?;
+    }
+
+    let mut writer = BufWriter::new(
+        File::create(file)
+            .with_context(|| format!("failed to create dot file {}", file.display()))?,
+    );
+    write_dot(args, data, &mut writer)?;
+    writer.flush().context("failed to write dot graph")?;
+
+    Ok(())
+}
+
+/// Writes the dataflow graph as DOT
+fn write_dot<W>(args: &Args, data: &DataflowData, writer: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    let runtimes: HashMap<OperatorId, Duration> = data
+        .aggregated_operator_stats
+        .iter()
+        .map(|(operator, stats)| (*operator, stats.total))
+        .collect();
+
+    // Every worker has the same graph, so only keep the first
+    // worker's copy of each operator
+    let mut subgraphs = BTreeMap::new();
+    for ((worker, addr), event) in data.subgraphs.iter() {
+        let entry = subgraphs.entry(addr.clone()).or_insert((*worker, event));
+        if *worker < entry.0 {
+            *entry = (*worker, event);
+        }
+    }
+
+    let mut nodes = BTreeMap::new();
+    for ((worker, addr), event) in data.nodes.iter() {
+        if !subgraphs.contains_key(addr) {
+            let entry = nodes.entry(addr.clone()).or_insert((*worker, event));
+            if *worker < entry.0 {
+                *entry = (*worker, event);
+            }
+        }
+    }
+
+    // Colors are scaled between the fastest and slowest operators,
+    // subgraphs are left out since they contain their operators' runtimes
+    let timings = nodes
+        .values()
+        .filter_map(|(_, event)| runtimes.get(&event.id).copied());
+    let (max_time, min_time) = (
+        timings.clone().max().unwrap_or_default(),
+        timings.min().unwrap_or_default(),
+    );
+
+    // Group every node and subgraph under the closest subgraph that contains it
+    let mut children: BTreeMap<Option<OperatorAddr>, Vec<&OperatorAddr>> = BTreeMap::new();
+    for addr in subgraphs.keys().chain(nodes.keys()) {
+        let mut parent = addr.pop_imm().0;
+        while !parent.is_empty() && !subgraphs.contains_key(&parent) {
+            parent = parent.pop_imm().0;
+        }
+
+        let parent = Some(parent).filter(|parent| !parent.is_empty());
+        children.entry(parent).or_default().push(addr);
+    }
+    for scope in children.values_mut() {
+        scope.sort_unstable();
+    }
+
+    let channel_messages: HashMap<ChannelId, usize> = if args.progress_enabled {
+        data.channel_progress
+            .iter()
+            .map(|(_, progress)| (progress.channel_id, progress.produced.messages))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let graph = DotGraph {
+        args,
+        runtimes: &runtimes,
+        bounds: (max_time, min_time),
+        subgraphs: &subgraphs
+            .iter()
+            .map(|(addr, &(_, event))| (addr, event.name.as_str()))
+            .collect(),
+        nodes: &nodes
+            .iter()
+            .map(|(addr, &(_, event))| (addr, (event.id, event.name.as_str())))
+            .collect(),
+        children: &children,
+    };
+
+    writeln!(writer, "digraph dataflow {{")?;
+    writeln!(writer, "    node [shape=box, style=\"rounded,filled\"];")?;
+    graph.write_scope(writer, None, 1)?;
+
+    // Channels are also the same on every worker
+    let edges: BTreeSet<_> = data
+        .edges
+        .iter()
+        .map(|(_, _, channel, _)| {
+            (
+                channel.channel_id(),
+                channel.source_addr(),
+                channel.target_addr(),
+                matches!(channel, Channel::ScopeCrossing { .. }),
+            )
+        })
+        .collect();
+
+    if !edges.is_empty() {
+        writeln!(writer)?;
+    }
+    for (channel, src, dest, scope_crossing) in edges {
+        let mut attributes = Vec::new();
+        if scope_crossing {
+            attributes.push("style=dashed".to_owned());
+        }
+        if let Some(messages) = channel_messages
+            .get(&channel)
+            .filter(|&&messages| messages != 0)
+        {
+            attributes.push(format!(
+                "label=\"{} message{}\"",
+                messages,
+                if *messages == 1 { "" } else { "s" },
+            ));
+        }
+        attributes.push(format!("tooltip=\"Channel {}\"", channel));
+
+        writeln!(
+            writer,
+            "    \"{}\" -> \"{}\" [{}];",
+            src,
+            dest,
+            attributes.join(", "),
+        )?;
+    }
+
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+struct DotGraph<'a> {
+    args: &'a Args,
+    runtimes: &'a HashMap<OperatorId, Duration>,
+    bounds: (Duration, Duration),
+    subgraphs: &'a BTreeMap<&'a OperatorAddr, &'a str>,
+    nodes: &'a BTreeMap<&'a OperatorAddr, (OperatorId, &'a str)>,
+    children: &'a BTreeMap<Option<OperatorAddr>, Vec<&'a OperatorAddr>>,
+}
+
+impl DotGraph<'_> {
+    /// Writes all of the nodes and clusters directly within the given scope
+    fn write_scope<W>(
+        &self,
+        writer: &mut W,
+        scope: Option<&OperatorAddr>,
+        depth: usize,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
+        let indent = "    ".repeat(depth);
+        let children = match self.children.get(&scope.cloned()) {
+            Some(children) => children,
+            None => return Ok(()),
+        };
+
+        for &addr in children {
+            if let Some(&name) = self.subgraphs.get(addr) {
+                writeln!(writer, "{}subgraph \"cluster_{}\" {{", indent, addr)?;
+                writeln!(writer, "{}    label=\"{} {}\";", indent, escape(name), addr,)?;
+                self.write_scope(writer, Some(addr), depth + 1)?;
+                writeln!(writer, "{}}}", indent)?;
+            } else if let Some(&(id, name)) = self.nodes.get(addr) {
+                let mut attributes = vec![format!("label=\"{}\\n{}\"", escape(name), addr)];
+
+                if let Some(&total) = self.runtimes.get(&id) {
+                    let fill_color = select_color(&self.args.palette, total, self.bounds);
+                    let text_color = fill_color.text_color();
+
+                    attributes.push(format!("fillcolor=\"{}\"", fill_color));
+                    attributes.push(format!("fontcolor=\"{}\"", text_color));
+                    attributes.push(format!("tooltip=\"Total runtime: {:#?}\"", total));
+                }
+
+                writeln!(
+                    writer,
+                    "{}\"{}\" [{}];",
+                    indent,
+                    addr,
+                    attributes.join(", "),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes a string for use within a quoted DOT id
+fn escape(string: &str) -> String {
+    string.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,412 @@
+//! Timely and Differential dataflow log analysis and vizualization
+//!
+//! The analysis pipeline used by the `ddshow` binary, usable from within other
+//! programs to profile a dataflow in-process
+//!
+//! ```rust,no_run
+//! use ddshow::{args::Args, ReplaySources};
+//! use std::{fs::File, sync::Arc};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let args = Arc::new(Args::default());
+//!
+//! // Replay a timely log file saved by `ddshow-sink`
+//! let timely = vec![File::open("timely.worker-0.ddshow")?];
+//! let sources = ReplaySources::from_readers(&args, timely, None, None)?;
+//!
+//! let data = ddshow::analyze(args, sources)?;
+//! for (operator, stats) in data.aggregated_operator_stats.iter() {
+//!     println!("{}: {:#?}", operator, stats.total);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod archive;
+pub mod args;
+mod chrome_trace;
+mod colormap;
+pub mod config;
+pub mod critical_path;
+mod dataflow;
+mod demux;
+pub mod diff;
+mod dot;
+pub mod lint;
+mod logging;
+mod metrics;
+mod reconnect;
+mod replay_loading;
+mod report;
+mod routing;
+mod server;
+pub mod skew;
+mod ui;
+mod vega;
+
+pub use crate::{
+    dataflow::{
+        ActivationHistogram, AggregatedOperatorStats, ArrangementStats, Channel, DataflowData,
+        DivergenceKind, EventKind, HistogramBucket, OperatorProgress, OperatorShape, OperatorStats,
+        Percentiles, ProgressInfo, WorkerDivergence,
+    },
+    logging::init_logging,
+    replay_loading::{connect_to_sources, ReplaySources},
+    ui::{
+        AggregatedStats, ChannelKind, ChannelStats, DDShowStats, DataflowStats, Lifespan,
+        MissingEvents, NodeKind, NodeStats, ProgramStats, StreamQuality, TimelineEvent,
+        WorkerStats, STATS_JSON_SCHEMA, STATS_SCHEMA_VERSION,
+    },
+};
+
+use crate::{
+    args::Args,
+    dataflow::{DataflowReceivers, DataflowSenders},
+    replay_loading::{wait_for_completion, wait_for_input, DataQualityTracker},
+    server::Server,
+};
+use anyhow::{Context, Result};
+use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
+use indicatif::MultiProgress;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufWriter,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use timely::communication::WorkerGuards;
+
+/// Replay the given sources to completion and return the analyzed data
+///
+/// This will block until every replay source has been exhausted, for
+/// tcp sources that means until the target computation disconnects
+pub fn analyze(args: Arc<Args>, sources: ReplaySources) -> Result<DataflowData> {
+    Analysis::start(args, sources)?.wait_for_completion()
+}
+
+/// A running instance of ddshow's analysis dataflow
+// TODO: Set the panic hook to shut down the computation
+//       so that panics don't stick things
+pub struct Analysis {
+    args: Arc<Args>,
+    running: Arc<AtomicBool>,
+    workers_finished: Arc<AtomicUsize>,
+    events_replayed: Arc<AtomicUsize>,
+    data_quality: DataQualityTracker,
+    stop: StopHandle,
+    worker_guards: WorkerGuards<Result<()>>,
+    receivers: DataflowReceivers,
+    server: Option<Server>,
+}
+
+impl Analysis {
+    /// Spin up the analysis dataflow over the given replay sources
+    pub fn start(args: Arc<Args>, sources: ReplaySources) -> Result<Self> {
+        let (communication_config, worker_config) = args.timely_config();
+
+        let ReplaySources {
+            timely: timely_event_receivers,
+            differential: differential_event_receivers,
+            progress: progress_event_receivers,
+            data_quality,
+            ..
+        } = sources;
+
+        let (running, workers_finished, events_replayed, progress_bars) = (
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(MultiProgress::new()),
+        );
+        let stop = StopHandle::new();
+        let (
+            replay_shutdown,
+            replay_stopped,
+            moved_args,
+            moved_workers_finished,
+            moved_events_replayed,
+        ) = (
+            running.clone(),
+            stop.stop.clone(),
+            args.clone(),
+            workers_finished.clone(),
+            events_replayed.clone(),
+        );
+
+        // Start serving the live graph before any data arrives so that
+        // it can be watched from the very beginning
+        let server = args
+            .serve
+            .map(|addr| Server::start(args.clone(), addr))
+            .transpose()?;
+
+        // Create the *many* channels used for extracting data from the dataflow
+        let (senders, receivers) = DataflowSenders::create();
+
+        tracing::info!("starting compute dataflow");
+
+        // Build the timely allocators and loggers
+        let (builders, others) = communication_config.try_build().map_err(|err| {
+            anyhow::anyhow!("failed to build timely communication config: {}", err)
+        })?;
+
+        // Spin up the timely computation
+        // Note: We use `execute_from()` instead of `timely::execute()` because
+        //       `execute()` automatically sets log hooks that connect to
+        //       `TIMELY_WORKER_LOG_ADDR`, meaning that no matter what we do
+        //       our dataflow will always attempt to connect to that address
+        //       if it's present in the env, causing things like ddshow/#7.
+        //       See https://github.com/Kixiron/ddshow/issues/7
+        let worker_guards =
+            timely::execute::execute_from(builders, others, worker_config, move |worker| {
+                // Distribute the tcp streams across workers, converting each of them into an event reader
+                let timely_traces = timely_event_receivers[worker.index()]
+                    .clone()
+                    .recv()
+                    .expect("failed to receive timely event traces");
+
+                let differential_traces = differential_event_receivers.as_ref().map(|recv| {
+                    recv[worker.index()]
+                        .recv()
+                        .expect("failed to receive differential event traces")
+                });
+
+                let progress_traces = progress_event_receivers.as_ref().map(|recv| {
+                    recv[worker.index()]
+                        .recv()
+                        .expect("failed to receive progress traces")
+                });
+
+                // Start the analysis worker's runtime
+                dataflow::worker_runtime(
+                    worker,
+                    moved_args.clone(),
+                    senders.clone(),
+                    replay_shutdown.clone(),
+                    replay_stopped.clone(),
+                    moved_workers_finished.clone(),
+                    moved_events_replayed.clone(),
+                    progress_bars.clone(),
+                    timely_traces,
+                    differential_traces,
+                    progress_traces,
+                )
+            })
+            .map_err(|err| anyhow::anyhow!("failed to start up timely computation: {}", err))?;
+
+        Ok(Self {
+            args,
+            running,
+            workers_finished,
+            events_replayed,
+            data_quality,
+            stop,
+            worker_guards,
+            receivers,
+            server,
+        })
+    }
+
+    /// Returns a handle that can be used to stop the analysis early
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// The address of the http server started by `--serve`, it keeps serving
+    /// the final data after the analysis finishes
+    pub fn server_addr(&self) -> Option<SocketAddr> {
+        self.server.as_ref().map(Server::local_addr)
+    }
+
+    /// Wait for every replay source to be exhausted and return the analyzed data
+    pub fn wait_for_completion(self) -> Result<DataflowData> {
+        wait_for_completion(
+            &self.args,
+            &self.running,
+            &self.workers_finished,
+            &self.events_replayed,
+            &self.data_quality,
+            &self.stop,
+            self.worker_guards,
+            self.receivers,
+            self.server.as_ref(),
+        )
+    }
+
+    /// Prompt the user and wait for either their input or for every replay
+    /// source to be exhausted, returning the analyzed data
+    pub fn wait_for_input(self) -> Result<DataflowData> {
+        wait_for_input(
+            &self.args,
+            &self.running,
+            &self.workers_finished,
+            &self.events_replayed,
+            &self.data_quality,
+            &self.stop,
+            self.worker_guards,
+            self.receivers,
+            self.server.as_ref(),
+        )
+    }
+}
+
+/// A handle for stopping a running [`Analysis`] early, the analysis stops
+/// replaying its sources but still finishes processing whatever data it's
+/// already received
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the analysis
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the analysis has been asked to stop
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Acquire)
+    }
+
+    /// Resets the handle so that it can be waited on again
+    pub fn reset(&self) {
+        self.stop.store(false, Ordering::Release);
+    }
+
+    /// Stops the analysis when the process receives a SIGINT or SIGTERM,
+    /// receiving another one while already stopping kills the process
+    pub fn stop_on_signals(&self) -> Result<()> {
+        for &signal in &[SIGINT, SIGTERM] {
+            // The conditional shutdown has to be registered first so that it
+            // only sees the flag from previous signals
+            signal_hook::flag::register_conditional_shutdown(signal, 1, self.stop.clone())
+                .context("failed to register signal handler")?;
+            signal_hook::flag::register(signal, self.stop.clone())
+                .context("failed to register signal handler")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The files written by [`write_outputs()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFiles {
+    /// The text report, if report generation is enabled
+    pub report_file: Option<PathBuf>,
+    /// The html graph
+    pub graph_file: PathBuf,
+    /// The trace archive, if `--bundle-logs` was given
+    pub archive_file: Option<PathBuf>,
+    /// The timeline as a Chrome trace, if `--chrome-trace` was given
+    pub chrome_trace_file: Option<PathBuf>,
+    /// The dataflow graph as DOT, if `--dot` was given
+    pub dot_file: Option<PathBuf>,
+}
+
+/// Write the report, dashboard, graph and optional json dump for the given data
+// FIXME: Clean this up so much
+pub fn write_outputs(args: &Args, data: DataflowData) -> Result<OutputFiles> {
+    let name_lookup: HashMap<_, _> = data.name_lookup.iter().cloned().collect();
+    let addr_lookup: HashMap<_, _> = data.addr_lookup.iter().cloned().collect();
+
+    let archive_file = bundle_logs(args)?;
+
+    // Build & emit the textual report
+    report::build_report(args, &data, &name_lookup, &addr_lookup)?;
+
+    vega::make_data(args, &data)?;
+
+    if let Some(file) = args.dump_json.as_ref() {
+        dump_program_json(args, file, &data, &name_lookup, &addr_lookup)?;
+    }
+
+    if let Some(file) = args.chrome_trace.as_ref() {
+        chrome_trace::write_trace_file(args, file, &data, &name_lookup, &addr_lookup)?;
+    }
+
+    if let Some(file) = args.dot.as_ref() {
+        dot::write_dot_file(args, file, &data)?;
+    }
+
+    ui::render(args, &ui::GraphData::new(args, &data))?;
+
+    let graph_file = fs::canonicalize(&args.output_dir)
+        .context("failed to get path of output dir")?
+        .join("graph.html");
+
+    Ok(OutputFiles {
+        report_file: if args.no_report_file {
+            None
+        } else {
+            Some(args.report_file.clone())
+        },
+        graph_file,
+        archive_file,
+        chrome_trace_file: args.chrome_trace.clone(),
+        dot_file: args.dot.clone(),
+    })
+}
+
+/// Bundles the saved or replayed log directory into a trace archive
+/// if `--bundle-logs` was given
+fn bundle_logs(args: &Args) -> Result<Option<PathBuf>> {
+    let archive_file = if let Some(archive_file) = args.bundle_logs.as_ref() {
+        archive_file
+    } else {
+        return Ok(None);
+    };
+
+    let log_dir = args
+        .save_logs
+        .as_deref()
+        .or_else(|| args.replay_logs.as_deref().filter(|path| path.is_dir()))
+        .context("`--bundle-logs` requires either `--save-logs` or a `--replay-logs` directory")?;
+
+    let program_name = args.program_name.clone().unwrap_or_else(|| {
+        log_dir
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    });
+
+    let header = archive::bundle(log_dir, archive_file, &program_name)?;
+    tracing::info!(
+        "bundled {} log sections from {} into {}",
+        header.sections.len(),
+        log_dir.display(),
+        archive_file.display(),
+    );
+
+    Ok(Some(archive_file.clone()))
+}
+
+/// Collect the program's statistics into a [`DDShowStats`]
+pub fn ddshow_stats(args: &Args, data: &DataflowData) -> DDShowStats {
+    DDShowStats::new(args, data)
+}
+
+fn dump_program_json(
+    args: &Args,
+    file: &Path,
+    data: &DataflowData,
+    _name_lookup: &HashMap<(WorkerId, OperatorId), String>,
+    _addr_lookup: &HashMap<(WorkerId, OperatorId), OperatorAddr>,
+) -> Result<()> {
+    let file = BufWriter::new(File::create(file).context("failed to create json file")?);
+    let data = ddshow_stats(args, data);
+
+    serde_json::to_writer(file, &data).context("failed to write json to file")?;
+
+    Ok(())
+}
@@ -0,0 +1,429 @@
+//! Lints that look for common mistakes and performance problems within the
+//! target dataflow
+//!
+//! Every [`LintRule`] runs over the operators, channels and progress info of
+//! the analyzed dataflow and produces [`Lint`]s pointing at the operators they
+//! found problems with. Rules can be disabled with `--disable-lint`
+//!
+//! There's no lint for forged capabilities yet, operators that turn an input's
+//! capability into one for an output that the input isn't connected to. Finding
+//! them takes the connectivity between each operator's inputs and outputs, which
+//! timely only logs with reachability logging and ddshow doesn't replay that yet
+
+use crate::{args::Args, dataflow::DataflowData};
+use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
+use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+/// The fraction of the program's total operator runtime that a single
+/// operator has to take up before it's considered hot
+const HOT_OPERATOR_THRESHOLD: f64 = 0.5;
+
+/// A problem found within the target dataflow
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct Lint {
+    pub rule: LintRule,
+    pub severity: Severity,
+    /// The address of the operator the lint was found on
+    pub addr: OperatorAddr,
+    /// The workers the lint was found on, empty if it applies to every worker
+    pub workers: Vec<WorkerId>,
+    pub explanation: String,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        })
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[archive(strict, derive(CheckBytes))]
+pub enum LintRule {
+    /// Workers that have different dataflow graphs
+    InconsistentWorkers,
+    /// Loops that feed data back into themselves without arranging it
+    ArrangeBeforeFeedback,
+    /// Channels with messages that were sent but never received
+    UndeliveredMessages,
+    /// Single operators that take up most of the program's runtime
+    HotOperator,
+}
+
+impl LintRule {
+    /// Every lint rule
+    pub const ALL: &'static [Self] = &[
+        Self::InconsistentWorkers,
+        Self::ArrangeBeforeFeedback,
+        Self::UndeliveredMessages,
+        Self::HotOperator,
+    ];
+
+    /// The names accepted by `--disable-lint`
+    pub const NAMES: &'static [&'static str] = &[
+        "inconsistent-workers",
+        "arrange-before-feedback",
+        "undelivered-messages",
+        "hot-operator",
+    ];
+
+    /// The name of the rule, used to disable it
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::InconsistentWorkers => "inconsistent-workers",
+            Self::ArrangeBeforeFeedback => "arrange-before-feedback",
+            Self::UndeliveredMessages => "undelivered-messages",
+            Self::HotOperator => "hot-operator",
+        }
+    }
+
+    /// The severity of the lints produced by the rule
+    pub const fn severity(self) -> Severity {
+        match self {
+            Self::InconsistentWorkers => Severity::Critical,
+            Self::ArrangeBeforeFeedback | Self::UndeliveredMessages => Severity::Warning,
+            Self::HotOperator => Severity::Info,
+        }
+    }
+
+    /// Runs the rule over the dataflow
+    fn check(self, args: &Args, graph: &LintGraph<'_>, data: &DataflowData) -> Vec<Lint> {
+        match self {
+            Self::InconsistentWorkers => inconsistent_workers(data),
+            Self::ArrangeBeforeFeedback => arrange_before_feedback(graph),
+            Self::UndeliveredMessages if args.progress_enabled => undelivered_messages(graph, data),
+            Self::UndeliveredMessages => Vec::new(),
+            Self::HotOperator => hot_operators(graph, data),
+        }
+    }
+
+    fn lint(self, addr: OperatorAddr, workers: Vec<WorkerId>, explanation: String) -> Lint {
+        Lint {
+            rule: self,
+            severity: self.severity(),
+            addr,
+            workers,
+            explanation,
+        }
+    }
+}
+
+impl Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LintRule {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let lowercase = string.to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.name() == lowercase)
+            .ok_or_else(|| {
+                format!(
+                    "invalid lint {:?}, only {} are supported",
+                    string,
+                    Self::NAMES
+                        .iter()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )
+            })
+    }
+}
+
+/// Runs every enabled lint rule over the dataflow, the most severe lints come first
+pub fn lint(args: &Args, data: &DataflowData) -> Vec<Lint> {
+    let graph = LintGraph::new(data);
+
+    let mut lints: Vec<_> = LintRule::ALL
+        .iter()
+        .filter(|rule| !args.disabled_lints.contains(rule))
+        .flat_map(|rule| rule.check(args, &graph, data))
+        .collect();
+    lints.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| (&a.addr, a.rule).cmp(&(&b.addr, b.rule)))
+    });
+
+    lints
+}
+
+/// The dataflow graph as seen by the first worker, every worker should have the same graph
+struct LintGraph<'a> {
+    /// The name of each leaf operator
+    operators: BTreeMap<&'a OperatorAddr, &'a str>,
+    /// The operators that each operator sends data to
+    successors: HashMap<OperatorAddr, Vec<OperatorAddr>>,
+    /// The address of every operator on every worker
+    addrs: HashMap<(WorkerId, OperatorId), &'a OperatorAddr>,
+    /// The operators that arrangements were created by
+    arrangements: HashSet<&'a OperatorAddr>,
+}
+
+impl<'a> LintGraph<'a> {
+    fn new(data: &'a DataflowData) -> Self {
+        let first_worker = data.nodes.iter().map(|((worker, _), _)| *worker).min();
+        let operators = data
+            .nodes
+            .iter()
+            .filter(|((worker, _), _)| Some(*worker) == first_worker)
+            .map(|((_, addr), event)| (addr, event.name.as_str()))
+            .collect();
+
+        let mut successors: HashMap<_, Vec<_>> = HashMap::new();
+        for (worker, _, channel, _) in data.edges.iter() {
+            if Some(*worker) == first_worker {
+                successors
+                    .entry(channel.source_addr())
+                    .or_default()
+                    .push(channel.target_addr());
+            }
+        }
+
+        let addrs: HashMap<_, _> = data
+            .addr_lookup
+            .iter()
+            .map(|(operator, addr)| (*operator, addr))
+            .collect();
+        let arrangements = data
+            .operator_stats
+            .iter()
+            .filter(|(_, stats)| stats.arrangement_size.is_some())
+            .filter_map(|(operator, _)| addrs.get(operator).copied())
+            .collect();
+
+        Self {
+            operators,
+            successors,
+            addrs,
+            arrangements,
+        }
+    }
+
+    fn name(&self, addr: &OperatorAddr) -> &str {
+        self.operators.get(addr).copied().unwrap_or("Operator")
+    }
+
+    /// Returns `true` if the operator maintains an arrangement of its input,
+    /// consolidating the data that flows through it
+    fn is_arranging(&self, addr: &OperatorAddr) -> bool {
+        let name = self.name(addr);
+
+        self.arrangements.contains(addr)
+            || [
+                "Arrange",
+                "Consolidate",
+                "Reduce",
+                "Distinct",
+                "Threshold",
+                "Count",
+            ]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    }
+}
+
+/// Every worker should build the same dataflows, otherwise they'll never
+/// be able to exchange data or agree on progress
+fn inconsistent_workers(data: &DataflowData) -> Vec<Lint> {
+    data.worker_divergences
+        .iter()
+        .map(|divergence| {
+            LintRule::InconsistentWorkers.lint(
+                divergence.addr.clone(),
+                vec![divergence.worker],
+                format!("{}, every worker must build the same dataflows", divergence),
+            )
+        })
+        .collect()
+}
+
+/// Data that's fed back around a loop without being arranged is never consolidated,
+/// so updates that cancel each other out keep circulating through the loop
+fn arrange_before_feedback(graph: &LintGraph<'_>) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for (&addr, &name) in graph.operators.iter() {
+        if name != "Feedback" {
+            continue;
+        }
+
+        // Search for a way back around to the feedback operator that
+        // doesn't pass through an arrangement
+        let mut stack = vec![addr.clone()];
+        let mut visited = HashSet::new();
+        let mut unarranged_cycle = false;
+
+        while let Some(current) = stack.pop() {
+            for next in graph.successors.get(&current).into_iter().flatten() {
+                if next == addr {
+                    unarranged_cycle = true;
+                    break;
+                }
+
+                if !graph.is_arranging(next) && visited.insert(next.clone()) {
+                    stack.push(next.clone());
+                }
+            }
+
+            if unarranged_cycle {
+                break;
+            }
+        }
+
+        if unarranged_cycle {
+            lints.push(LintRule::ArrangeBeforeFeedback.lint(
+                addr.clone(),
+                Vec::new(),
+                format!(
+                    "the loop fed by {} at {} is never arranged or consolidated, \
+                     so updates that cancel out will keep circulating through it",
+                    name, addr,
+                ),
+            ));
+        }
+    }
+
+    lints
+}
+
+/// Messages that were sent but never received were either still in
+/// flight when the trace ended or were never processed by their target
+fn undelivered_messages(graph: &LintGraph<'_>, data: &DataflowData) -> Vec<Lint> {
+    data.channel_progress
+        .iter()
+        .filter(|(_, progress)| progress.produced.messages > progress.consumed.messages)
+        .map(|(addr, progress)| {
+            let undelivered = progress.produced.messages - progress.consumed.messages;
+
+            LintRule::UndeliveredMessages.lint(
+                addr.clone(),
+                Vec::new(),
+                format!(
+                    "{} of the {} messages sent to {} at {} over channel {} were never received",
+                    undelivered,
+                    progress.produced.messages,
+                    graph.name(addr),
+                    addr,
+                    progress.channel_id,
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Operators that take up the majority of the program's runtime are
+/// the first place to look when optimizing it
+fn hot_operators(graph: &LintGraph<'_>, data: &DataflowData) -> Vec<Lint> {
+    let mut runtimes: BTreeMap<&OperatorAddr, (Duration, Vec<WorkerId>)> = BTreeMap::new();
+    for ((worker, operator), stats) in data.operator_stats.iter() {
+        let addr = graph.addrs.get(&(*worker, *operator)).copied();
+
+        if let Some(addr) = addr.filter(|addr| graph.operators.contains_key(addr)) {
+            let (total, workers) = runtimes.entry(addr).or_default();
+            *total += stats.total;
+            workers.push(*worker);
+        }
+    }
+
+    let program_runtime: Duration = runtimes.values().map(|(total, _)| *total).sum();
+    if program_runtime == Duration::from_secs(0) {
+        return Vec::new();
+    }
+
+    runtimes
+        .into_iter()
+        .filter_map(|(addr, (total, mut workers))| {
+            let fraction = total.as_secs_f64() / program_runtime.as_secs_f64();
+
+            (fraction > HOT_OPERATOR_THRESHOLD).then(|| {
+                workers.sort_unstable();
+
+                LintRule::HotOperator.lint(
+                    addr.clone(),
+                    workers,
+                    format!(
+                        "{} at {} took {:#?} ({:.1}%) of the {:#?} spent within operators",
+                        graph.name(addr),
+                        addr,
+                        total,
+                        fraction * 100.0,
+                        program_runtime,
+                    ),
+                )
+            })
+        })
+        .collect()
+}
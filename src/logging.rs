@@ -10,7 +10,7 @@ use tracing_subscriber::{
     EnvFilter,
 };
 
-pub(crate) fn init_logging(args: &Args) {
+pub fn init_logging(args: &Args) {
     let filter_layer = EnvFilter::from_env("DDSHOW_LOG");
     let fmt_layer = tracing_subscriber::fmt::layer()
         .pretty()
@@ -0,0 +1,290 @@
+//! Exposes the dataflow's stats in the [OpenMetrics] text format so that they
+//! can be scraped by Prometheus while the target computation is running
+//!
+//! [OpenMetrics]: https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+
+use crate::{
+    dataflow::{ArrangementStats, DataflowData},
+    ui::WorkerStats,
+};
+use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Write},
+    time::Duration,
+};
+
+/// The content type of the OpenMetrics text format
+pub(crate) const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Renders the dataflow's program, worker, operator and arrangement stats as OpenMetrics text
+pub(crate) fn render_metrics(data: &DataflowData) -> String {
+    let mut metrics = Metrics::default();
+
+    let program = data.program_stats.last().cloned().unwrap_or_default();
+    for &(name, help, value) in &[
+        ("workers", "The number of timely workers", program.workers),
+        ("dataflows", "The number of dataflows", program.dataflows),
+        ("operators", "The number of operators", program.operators),
+        ("subgraphs", "The number of subgraphs", program.subgraphs),
+        ("channels", "The number of channels", program.channels),
+        (
+            "arrangements",
+            "The number of arrangements",
+            program.arrangements,
+        ),
+    ] {
+        metrics.family(&format!("ddshow_program_{}", name), MetricType::Gauge, help);
+        metrics.sample("", &[], value);
+    }
+
+    metrics.family(
+        "ddshow_program_events",
+        MetricType::Counter,
+        "The number of events logged by all workers",
+    );
+    metrics.sample("_total", &[], program.events);
+
+    metrics.family(
+        "ddshow_program_runtime_seconds",
+        MetricType::Gauge,
+        "The runtime of the timely program",
+    );
+    metrics.sample("", &[], Seconds(program.runtime));
+
+    let workers = data.worker_stats.last().map_or(&[][..], |stats| &stats[..]);
+    for &(name, help, value) in &[
+        (
+            "dataflows",
+            "The number of dataflows on each worker",
+            (|stats| stats.dataflows) as fn(&WorkerStats) -> usize,
+        ),
+        (
+            "operators",
+            "The number of operators on each worker",
+            |stats| stats.operators,
+        ),
+        (
+            "subgraphs",
+            "The number of subgraphs on each worker",
+            |stats| stats.subgraphs,
+        ),
+        (
+            "channels",
+            "The number of channels on each worker",
+            |stats| stats.channels,
+        ),
+        (
+            "arrangements",
+            "The number of arrangements on each worker",
+            |stats| stats.arrangements,
+        ),
+    ] {
+        metrics.family(&format!("ddshow_worker_{}", name), MetricType::Gauge, help);
+        for (worker, stats) in workers {
+            metrics.sample("", &[("worker", &worker)], value(stats));
+        }
+    }
+
+    metrics.family(
+        "ddshow_worker_events",
+        MetricType::Counter,
+        "The number of events logged by each worker",
+    );
+    for (worker, stats) in workers {
+        metrics.sample("_total", &[("worker", &worker)], stats.events);
+    }
+
+    metrics.family(
+        "ddshow_worker_runtime_seconds",
+        MetricType::Gauge,
+        "The runtime of each worker",
+    );
+    for (worker, stats) in workers {
+        metrics.sample("", &[("worker", &worker)], Seconds(stats.runtime));
+    }
+
+    let name_lookup: HashMap<_, _> = data.name_lookup.iter().cloned().collect();
+    let addr_lookup: HashMap<_, _> = data.addr_lookup.iter().cloned().collect();
+
+    let mut operators: Vec<_> = data.operator_stats.iter().collect();
+    operators.sort_unstable_by_key(|&&((worker, operator), _)| (worker, operator));
+
+    let operator_labels = |worker: WorkerId, operator: OperatorId| {
+        let name = name_lookup
+            .get(&(worker, operator))
+            .map_or("", |name| name.as_str());
+        let addr = addr_lookup
+            .get(&(worker, operator))
+            .cloned()
+            .unwrap_or_else(|| OperatorAddr::from_elem(operator));
+
+        (worker, operator, name.to_owned(), addr)
+    };
+
+    metrics.family(
+        "ddshow_operator_activations",
+        MetricType::Counter,
+        "The number of times each operator was activated",
+    );
+    for ((worker, operator), stats) in operators.iter() {
+        let (worker, operator, name, addr) = operator_labels(*worker, *operator);
+        metrics.sample(
+            "_total",
+            &[
+                ("worker", &worker),
+                ("operator", &operator),
+                ("name", &name),
+                ("addr", &addr),
+            ],
+            stats.activations,
+        );
+    }
+
+    metrics.family(
+        "ddshow_operator_runtime_seconds",
+        MetricType::Counter,
+        "The total time each operator spent activated",
+    );
+    for ((worker, operator), stats) in operators.iter() {
+        let (worker, operator, name, addr) = operator_labels(*worker, *operator);
+        metrics.sample(
+            "_total",
+            &[
+                ("worker", &worker),
+                ("operator", &operator),
+                ("name", &name),
+                ("addr", &addr),
+            ],
+            Seconds(stats.total),
+        );
+    }
+
+    let arrangements: Vec<_> = operators
+        .iter()
+        .filter_map(|((worker, operator), stats)| {
+            stats
+                .arrangement_size
+                .as_ref()
+                .map(|arrangement| (operator_labels(*worker, *operator), arrangement))
+        })
+        .collect();
+
+    for &(name, help, value) in &[
+        (
+            "max_size",
+            "The largest number of updates each arrangement has held",
+            (|stats| stats.max_size) as fn(&ArrangementStats) -> usize,
+        ),
+        (
+            "min_size",
+            "The smallest number of updates each arrangement has held",
+            |stats| stats.min_size,
+        ),
+        (
+            "batches",
+            "The number of batches held by each arrangement",
+            |stats| stats.batches,
+        ),
+    ] {
+        metrics.family(
+            &format!("ddshow_arrangement_{}", name),
+            MetricType::Gauge,
+            help,
+        );
+        for ((worker, operator, name, addr), stats) in arrangements.iter() {
+            metrics.sample(
+                "",
+                &[
+                    ("worker", worker),
+                    ("operator", operator),
+                    ("name", name),
+                    ("addr", addr),
+                ],
+                value(stats),
+            );
+        }
+    }
+
+    metrics.finish()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        })
+    }
+}
+
+/// Durations are exposed in (fractional) seconds
+struct Seconds(Duration);
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    output: String,
+    family: String,
+}
+
+impl Metrics {
+    /// Starts a new metric family, all following samples belong to it
+    fn family(&mut self, name: &str, kind: MetricType, help: &str) {
+        self.family = name.to_owned();
+
+        // Writing to a string can't fail
+        let _ = writeln!(self.output, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+    }
+
+    /// Adds a sample to the current family, `suffix` is appended to the
+    /// family's name (counters are suffixed with `_total`)
+    fn sample(&mut self, suffix: &str, labels: &[(&str, &dyn Display)], value: impl Display) {
+        let _ = write!(self.output, "{}{}", self.family, suffix);
+
+        if !labels.is_empty() {
+            self.output.push('{');
+            for (idx, (name, value)) in labels.iter().enumerate() {
+                if idx != 0 {
+                    self.output.push(',');
+                }
+
+                let _ = write!(
+                    self.output,
+                    "{}=\"{}\"",
+                    name,
+                    escape_label(&value.to_string()),
+                );
+            }
+            self.output.push('}');
+        }
+
+        let _ = writeln!(self.output, " {}", value);
+    }
+
+    fn finish(mut self) -> String {
+        self.output.push_str("# EOF\n");
+        self.output
+    }
+}
+
+/// Escapes a label value, backslashes, quotes and newlines must be escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,563 @@
+//! Stitches together the log streams of target workers that reconnect or restart
+//!
+//! Every connection made by a reconnecting `ddshow-sink` writer starts with a
+//! [`Handshake`] naming the worker it belongs to and the session of the process
+//! that sent it. Connections from the same session pick up where the previous
+//! one left off, connections from a new session are a restarted process and
+//! have their timestamps shifted past everything replayed so far
+
+use crate::{
+    archive::StreamKind,
+    dataflow::{
+        constants::RECONNECT_IDLE_TIMEOUT,
+        operators::{EventIterator, RkyvEventReader},
+    },
+    routing::{is_aborted, Identity, PendingConnection},
+    ui::StreamQuality,
+};
+use anyhow::{Context, Result};
+use bytecheck::CheckBytes;
+use ddshow_sink::Handshake;
+use ddshow_types::WorkerId;
+use rkyv::{
+    de::deserializers::AllocDeserializer, validation::DefaultArchiveValidator, Archive, Deserialize,
+};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use timely::{dataflow::operators::capture::Event, progress::frontier::MutableAntichain};
+
+/// Accepts connections from reconnecting workers and hands
+/// them off to the replay source of their worker
+#[derive(Debug)]
+pub(crate) struct ConnectionAcceptor {
+    state: Mutex<AcceptorState>,
+}
+
+#[derive(Debug)]
+struct AcceptorState {
+    listener: TcpListener,
+    address: SocketAddr,
+    /// The log stream that the acceptor's connections must carry
+    stream: StreamKind,
+    /// The number of workers in the target computation,
+    /// known once the first handshake is received
+    workers: Option<usize>,
+    /// Accepted sockets that haven't finished sending their handshake
+    pending: Vec<PendingConnection>,
+    /// The connections waiting to be picked up by each worker's replay source
+    connections: Vec<VecDeque<(Handshake, TcpStream)>>,
+}
+
+impl ConnectionAcceptor {
+    /// Accepts connections carrying the given log stream on the given listener
+    pub(crate) fn new(listener: TcpListener, stream: StreamKind) -> Result<Self> {
+        let address = listener
+            .local_addr()
+            .context("failed to get the listener's address")?;
+        listener
+            .set_nonblocking(true)
+            .context("failed to set listener to non-blocking mode")?;
+
+        Ok(Self {
+            state: Mutex::new(AcceptorState {
+                listener,
+                address,
+                stream,
+                workers: None,
+                pending: Vec::new(),
+                connections: Vec::new(),
+            }),
+        })
+    }
+
+    /// The number of workers in the target computation, if any of them have connected
+    pub(crate) fn workers(&self) -> Option<usize> {
+        self.state().workers
+    }
+
+    /// The number of workers that have a connection waiting to be replayed
+    pub(crate) fn connected_workers(&self) -> usize {
+        self.state()
+            .connections
+            .iter()
+            .filter(|connections| !connections.is_empty())
+            .count()
+    }
+
+    /// Returns `true` if the given worker has a connection waiting to be replayed
+    fn has_connection(&self, worker: usize) -> bool {
+        !self.state().connections[worker].is_empty()
+    }
+
+    /// Takes the oldest connection that's waiting for the given worker
+    fn take(&self, worker: usize) -> Option<(Handshake, TcpStream)> {
+        self.state().connections[worker].pop_front()
+    }
+
+    /// Accepts any new connections and reads their handshakes without blocking
+    ///
+    /// Connections that fail or are rejected are logged and closed without
+    /// affecting any others, only the listener failing returns an error
+    pub(crate) fn poll(&self) -> Result<()> {
+        let mut state = self.state();
+
+        loop {
+            match state.listener.accept() {
+                Ok((socket, peer)) => {
+                    tracing::debug!(peer = %peer, "accepted connection from {}", peer);
+
+                    match PendingConnection::new(socket, peer) {
+                        Ok(pending) => state.pending.push(pending),
+                        Err(err) => tracing::warn!("{:?}", err),
+                    }
+                }
+
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if is_aborted(&err) => {
+                    tracing::debug!(
+                        "failed to accept a connection on {}: {:?}",
+                        state.address,
+                        err,
+                    );
+                }
+                Err(err) => return Err(err).context("failed to accept socket connection"),
+            }
+        }
+
+        let mut idx = 0;
+        while idx < state.pending.len() {
+            let identity = match state.pending[idx].poll() {
+                Ok(Some(identity)) => identity,
+                Ok(None) => {
+                    idx += 1;
+                    continue;
+                }
+
+                Err(err) => {
+                    tracing::warn!("{:?}", err);
+                    state.pending.swap_remove(idx);
+                    continue;
+                }
+            };
+
+            let connection = state.pending.swap_remove(idx);
+            let accepted = match identity {
+                Identity::Handshake(handshake) => state.accept(handshake, connection),
+                Identity::Multiplexed => Err(anyhow::anyhow!(
+                    "rejected the multiplexed connection from {} to {}, multiplexed \
+                     connections can't be used with `--reconnect`",
+                    connection.peer(),
+                    state.address,
+                )),
+                Identity::Plain => Err(anyhow::anyhow!(
+                    "rejected the connection from {} to {} since it didn't send a handshake, \
+                     reconnecting sources must be sent by ddshow-sink's `stream_*()` functions",
+                    connection.peer(),
+                    state.address,
+                )),
+            };
+
+            // Rejected connections are closed by dropping them
+            if let Err(err) = accepted {
+                tracing::warn!("{:?}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Locks the acceptor's state, the state is never left
+    /// half-updated so any poisoning is ignored
+    fn state(&self) -> MutexGuard<'_, AcceptorState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl AcceptorState {
+    /// Checks that a worker's handshake is compatible with
+    /// the acceptor and queues up its connection
+    fn accept(&mut self, handshake: Handshake, connection: PendingConnection) -> Result<()> {
+        let stream = StreamKind::from(handshake.stream);
+        if stream != self.stream {
+            anyhow::bail!(
+                "rejected the {} logs of worker {} from {} since they were sent to the \
+                 {} address {}",
+                stream,
+                handshake.worker,
+                handshake.program,
+                self.stream,
+                self.address,
+            );
+        }
+
+        if handshake.encoding != ddshow_sink::StreamEncoding::Rkyv {
+            anyhow::bail!(
+                "rejected the {} logs of worker {} from {} since they're {} encoded, \
+                 reconnecting sources must be rkyv encoded",
+                stream,
+                handshake.worker,
+                handshake.program,
+                handshake.encoding,
+            );
+        }
+
+        let workers = *self.workers.get_or_insert(handshake.peers);
+        if handshake.peers != workers {
+            anyhow::bail!(
+                "rejected the {} logs of worker {} from {}, it reported {} workers while \
+                 earlier connections reported {} workers",
+                stream,
+                handshake.worker,
+                handshake.program,
+                handshake.peers,
+                workers,
+            );
+        }
+
+        if handshake.worker >= workers {
+            anyhow::bail!(
+                "rejected the {} logs of worker {} from {}, the worker index is out of \
+                 range for {} worker{}",
+                stream,
+                handshake.worker,
+                handshake.program,
+                workers,
+                if workers == 1 { "" } else { "s" },
+            );
+        }
+        self.connections.resize_with(workers, VecDeque::new);
+
+        tracing::info!(
+            worker = handshake.worker,
+            session = handshake.session,
+            "worker {} connected to {}",
+            handshake.worker,
+            self.address,
+        );
+        self.connections[handshake.worker].push_back((handshake, connection.into_socket()));
+
+        Ok(())
+    }
+}
+
+/// Replays every connection made by a single target worker as one stream
+///
+/// The stitched stream holds a single capability at its frontier, which follows
+/// the frontier of the worker's current connection. Each connection's times are
+/// shifted by its segment's offset, and both message and record times are
+/// clamped so they never fall behind the stitched frontier
+pub(crate) struct ReconnectingSource<E> {
+    worker: usize,
+    acceptor: Arc<ConnectionAcceptor>,
+    quality: Arc<Mutex<StreamQuality>>,
+    segment: Option<Segment<E>>,
+    /// The capability held by the stitched stream
+    frontier: Duration,
+    /// The latest time replayed from any connection
+    latest: Duration,
+    /// The last time that the current connection produced an event
+    last_event: Instant,
+}
+
+/// A single connection made by a worker
+struct Segment<E> {
+    session: u64,
+    reader: RkyvEventReader<Duration, (Duration, WorkerId, E), TcpStream>,
+    /// The amount that the connection's times are shifted by
+    offset: Duration,
+    /// The capabilities held by the connection, in its own times
+    capabilities: MutableAntichain<Duration>,
+    finished: bool,
+}
+
+impl<E> ReconnectingSource<E> {
+    /// Replays the connections of the given worker, recording their quality in `quality`
+    pub(crate) fn new(
+        worker: usize,
+        acceptor: Arc<ConnectionAcceptor>,
+        quality: Arc<Mutex<StreamQuality>>,
+    ) -> Self {
+        Self {
+            worker,
+            acceptor,
+            quality,
+            segment: None,
+            frontier: Duration::default(),
+            latest: Duration::default(),
+            last_event: Instant::now(),
+        }
+    }
+
+    /// Switches over to a new connection from the worker
+    fn switch_to(&mut self, handshake: Handshake, socket: TcpStream) {
+        let (offset, next_sequence) = match self.segment.take() {
+            // The same process reconnected, so its times and sequence numbers carry on
+            Some(segment) if segment.session == handshake.session => {
+                (segment.offset, segment.reader.next_sequence())
+            }
+
+            // The process restarted, so its times start over from zero
+            previous => {
+                if previous.is_some_and(|segment| !segment.capabilities.is_empty()) {
+                    tracing::warn!(
+                        worker = self.worker,
+                        "worker {} restarted before its previous connection was closed",
+                        self.worker,
+                    );
+
+                    self.quality
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .truncated = true;
+                }
+
+                (self.latest, None)
+            }
+        };
+
+        tracing::info!(
+            worker = self.worker,
+            session = handshake.session,
+            offset = ?offset,
+            "replaying a new connection from worker {}",
+            self.worker,
+        );
+
+        let reader = RkyvEventReader::new(socket)
+            .track_quality(self.quality.clone())
+            .defer_truncation()
+            .resume_sequence(next_sequence);
+
+        self.segment = Some(Segment {
+            session: handshake.session,
+            reader,
+            offset,
+            capabilities: MutableAntichain::new_bottom(Duration::default()),
+            finished: false,
+        });
+        self.last_event = Instant::now();
+    }
+}
+
+impl<E> EventIterator<Duration, (Duration, WorkerId, E)> for ReconnectingSource<E>
+where
+    (Duration, WorkerId, E): Archive,
+    <(Duration, WorkerId, E) as Archive>::Archived: Deserialize<(Duration, WorkerId, E), AllocDeserializer>
+        + CheckBytes<DefaultArchiveValidator>,
+{
+    fn next(
+        &mut self,
+        _is_finished: &mut bool,
+    ) -> io::Result<Option<Event<Duration, (Duration, WorkerId, E)>>> {
+        if let Err(err) = self.acceptor.poll() {
+            tracing::error!("{:?}", err);
+        }
+
+        // Switch over to the worker's next connection once the current one
+        // has closed or gone quiet
+        let switch = self.segment.as_ref().is_none_or(|segment| {
+            segment.finished || self.last_event.elapsed() >= RECONNECT_IDLE_TIMEOUT
+        });
+        if switch && self.acceptor.has_connection(self.worker) {
+            if let Some((handshake, socket)) = self.acceptor.take(self.worker) {
+                self.switch_to(handshake, socket);
+            }
+        }
+
+        let segment = match self.segment.as_mut() {
+            Some(segment) if !segment.finished => segment,
+            _ => return Ok(None),
+        };
+
+        loop {
+            match EventIterator::next(&mut segment.reader, &mut segment.finished)? {
+                Some(Event::Messages(time, mut data)) => {
+                    self.last_event = Instant::now();
+
+                    // A reconnection can send events from before the frontier that the
+                    // previous connection advanced to, those are moved up to the frontier
+                    let time = (time + segment.offset).max(self.frontier);
+                    for (event_time, _, _) in data.iter_mut() {
+                        *event_time = (*event_time + segment.offset).max(time);
+                    }
+
+                    self.latest = self.latest.max(time);
+
+                    return Ok(Some(Event::Messages(time, data)));
+                }
+
+                Some(Event::Progress(updates)) => {
+                    self.last_event = Instant::now();
+                    segment.capabilities.update_iter(updates);
+
+                    // Follow the connection's frontier, holding onto the current
+                    // frontier once the connection drops all of its capabilities
+                    let current = self.frontier;
+                    let frontier = segment
+                        .capabilities
+                        .frontier()
+                        .iter()
+                        .map(|&time| time + segment.offset)
+                        .min()
+                        .filter(|&frontier| frontier > current);
+
+                    if let Some(frontier) = frontier {
+                        self.frontier = frontier;
+                        self.latest = self.latest.max(frontier);
+
+                        return Ok(Some(Event::Progress(vec![(frontier, 1), (current, -1)])));
+                    }
+                }
+
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<E> Debug for ReconnectingSource<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingSource")
+            .field("worker", &self.worker)
+            .field("acceptor", &self.acceptor)
+            .field("quality", &self.quality)
+            .field(
+                "session",
+                &self.segment.as_ref().map(|segment| segment.session),
+            )
+            .field("frontier", &self.frontier)
+            .field("latest", &self.latest)
+            .field("last_event", &self.last_event)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionAcceptor, ReconnectingSource};
+    use crate::{archive::StreamKind, dataflow::operators::EventIterator, ui::StreamQuality};
+    use ddshow_sink::{EventWriter, Handshake};
+    use ddshow_types::WorkerId;
+    use std::{
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use timely::dataflow::operators::capture::{Event, EventPusher};
+
+    type TestEvent = Event<Duration, (Duration, WorkerId, u64)>;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// Sends the given events as a connection from worker zero of the given session
+    fn connect(address: SocketAddr, session: u64, events: Vec<TestEvent>) {
+        let mut socket = TcpStream::connect(address).unwrap();
+        Handshake {
+            session,
+            ..Handshake::new(ddshow_sink::StreamKind::Timely, 0, 1)
+        }
+        .write_to(&mut socket)
+        .unwrap();
+
+        let mut writer = EventWriter::new(socket);
+        for event in events {
+            writer.push(event);
+        }
+    }
+
+    /// Replays events from the source until the given number of messages were replayed
+    fn replay(source: &mut ReconnectingSource<u64>, messages: usize) -> Vec<TestEvent> {
+        let started = Instant::now();
+        let mut events = Vec::new();
+
+        while events
+            .iter()
+            .filter(|event| matches!(event, Event::Messages(..)))
+            .count()
+            < messages
+        {
+            assert!(started.elapsed() < Duration::from_secs(30), "{:?}", events);
+            if let Some(event) = EventIterator::next(source, &mut false).unwrap() {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn stitched_times_never_fall_behind_the_frontier() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let acceptor = Arc::new(ConnectionAcceptor::new(listener, StreamKind::Timely).unwrap());
+        let quality = Arc::new(Mutex::new(StreamQuality::new(StreamKind::Timely, 0)));
+        let mut source = ReconnectingSource::new(0, acceptor, quality);
+
+        // The first connection advances the stitched frontier to 10 seconds
+        connect(
+            address,
+            1,
+            vec![
+                Event::Messages(secs(0), vec![(secs(1), WorkerId::new(0), 1)]),
+                Event::Progress(vec![(secs(10), 1), (secs(0), -1)]),
+            ],
+        );
+        let mut events = replay(&mut source, 1);
+
+        // The same process reconnects with events from before that frontier
+        connect(
+            address,
+            1,
+            vec![
+                Event::Messages(secs(4), vec![(secs(4), WorkerId::new(0), 2)]),
+                Event::Progress(vec![(secs(12), 1), (secs(0), -1)]),
+            ],
+        );
+        events.extend(replay(&mut source, 1));
+
+        // The process restarts, so its times start over from zero
+        connect(
+            address,
+            2,
+            vec![Event::Messages(
+                secs(0),
+                vec![(secs(0), WorkerId::new(0), 3)],
+            )],
+        );
+        events.extend(replay(&mut source, 1));
+
+        let mut frontier = secs(0);
+        let mut messages = Vec::new();
+        for event in events {
+            match event {
+                Event::Progress(updates) => {
+                    frontier = updates
+                        .iter()
+                        .filter(|&&(_, diff)| diff > 0)
+                        .map(|&(time, _)| time)
+                        .max()
+                        .unwrap_or(frontier);
+                }
+
+                Event::Messages(time, data) => {
+                    assert!(time >= frontier, "{:?} is behind {:?}", time, frontier);
+                    for &(event_time, _, _) in data.iter() {
+                        assert!(event_time >= time, "{:?} is behind {:?}", event_time, time);
+                    }
+
+                    messages.extend(data.into_iter().map(|(time, _, event)| (time, event)));
+                }
+            }
+        }
+
+        assert_eq!(messages, vec![(secs(1), 1), (secs(10), 2), (secs(12), 3)]);
+    }
+}
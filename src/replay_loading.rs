@@ -1,19 +1,31 @@
 use crate::{
+    archive::{ArchiveHeader, StreamKind},
     args::{Args, StreamEncoding},
     dataflow::{
-        constants::{IDLE_EXTRACTION_FUEL, TCP_READ_TIMEOUT},
-        operators::{EventReader, Fuel, RkyvEventReader},
+        constants::IDLE_EXTRACTION_FUEL,
+        operators::{
+            rkyv_capture::check_event_format_version, EventIterator, EventReader, Fuel,
+            RkyvEventReader,
+        },
         utils::{self, DifferentialLogBundle, ProgressLogBundle, TimelyLogBundle},
-        DataflowData, DataflowReceivers,
+        DataflowData, DataflowExtractor, DataflowReceivers,
     },
+    reconnect::{ConnectionAcceptor, ReconnectingSource},
     report,
+    routing::ConnectionRouter,
+    server::{Server, SERVER_UPDATE_INTERVAL},
+    ui::StreamQuality,
+    StopHandle,
 };
 use abomonation::Abomonation;
 use anyhow::{Context, Result};
 use bytecheck::CheckBytes;
 use crossbeam_channel::Receiver;
-use ddshow_sink::{DIFFERENTIAL_ARRANGEMENT_LOG_FILE, TIMELY_LOG_FILE, TIMELY_PROGRESS_LOG_FILE};
-use ddshow_types::progress_logging::TimelyProgressEvent;
+use ddshow_sink::{
+    event_format_version, Compression, Handshake, DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+    EVENT_HEADER_LEN, HANDSHAKE_MAGIC, TIMELY_LOG_FILE, TIMELY_PROGRESS_LOG_FILE,
+};
+use ddshow_types::{progress_logging::TimelyProgressEvent, WorkerId};
 use differential_dataflow::logging::DifferentialEvent as RawDifferentialEvent;
 use indicatif::{ProgressBar, ProgressStyle};
 use rkyv::{
@@ -22,17 +34,17 @@ use rkyv::{
 use std::{
     collections::HashMap,
     ffi::OsStr,
-    fmt::Debug,
+    fmt::{self, Debug},
     fs::{self, File},
     hint,
     io::{self, BufReader, Read, Write},
-    iter,
+    iter, mem,
     net::{SocketAddr, TcpListener, TcpStream},
     num::NonZeroUsize,
     path::Path,
     sync::{
         atomic::{self, AtomicBool, AtomicUsize, Ordering},
-        Arc, Barrier,
+        Arc, Barrier, Mutex, MutexGuard, PoisonError,
     },
     thread,
     time::{Duration, Instant},
@@ -42,35 +54,45 @@ use timely::{
     logging::TimelyEvent as RawTimelyEvent,
 };
 
-type AcquiredStreams<T, D1, D2> = EventReceivers<
-    RkyvEventReader<T, D1, Box<dyn Read + Send + 'static>>,
-    EventReader<T, D2, TcpStream>,
->;
+type AcquiredStreams<E, D> =
+    EventReceivers<RkyvReplaySource<(Duration, WorkerId, E)>, EventReader<Duration, D, TcpStream>>;
+
+/// A replay source for rkyv encoded events, either a single
+/// log file or connection or a reconnecting worker
+pub(crate) type RkyvReplaySource<D> = Box<dyn EventIterator<Duration, D> + Send + 'static>;
 
 pub(crate) type TimelyEventReceivers = Arc<[Receiver<TimelyReplaySource>]>;
 pub(crate) type TimelyReplaySource = ReplaySource<
-    RkyvEventReader<Duration, TimelyLogBundle, Box<dyn Read + Send + 'static>>,
+    RkyvReplaySource<TimelyLogBundle>,
     EventReader<Duration, (Duration, usize, RawTimelyEvent), TcpStream>,
 >;
 
 pub(crate) type DifferentialEventReceivers = Option<Arc<[Receiver<DifferentialReplaySource>]>>;
 pub(crate) type DifferentialReplaySource = ReplaySource<
-    RkyvEventReader<Duration, DifferentialLogBundle, Box<dyn Read + Send + 'static>>,
+    RkyvReplaySource<DifferentialLogBundle>,
     EventReader<Duration, (Duration, usize, RawDifferentialEvent), TcpStream>,
 >;
 
 pub(crate) type ProgressEventReceivers = Option<Arc<[Receiver<ProgressReplaySource>]>>;
 pub(crate) type ProgressReplaySource = ReplaySource<
-    RkyvEventReader<Duration, ProgressLogBundle, Box<dyn Read + Send + 'static>>,
+    RkyvReplaySource<ProgressLogBundle>,
     EventReader<Duration, (Duration, usize, TimelyProgressEvent), TcpStream>,
 >;
 
-#[derive(Debug)]
 pub enum ReplaySource<R, A> {
     Rkyv(Vec<R>),
     Abomonation(Vec<A>),
 }
 
+impl<R, A> Debug for ReplaySource<R, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplaySource")
+            .field("kind", &self.kind())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
 impl<R, A> ReplaySource<R, A> {
     pub fn len(&self) -> usize {
         match self {
@@ -105,58 +127,292 @@ impl<R, A> ReplaySource<R, A> {
     }
 }
 
+/// The replay sources for each log stream of a target computation,
+/// distributed across ddshow's workers
+#[derive(Debug)]
+pub struct ReplaySources {
+    pub(crate) timely: TimelyEventReceivers,
+    pub(crate) differential: DifferentialEventReceivers,
+    pub(crate) progress: ProgressEventReceivers,
+    pub(crate) data_quality: DataQualityTracker,
+    total_sources: usize,
+}
+
+/// Tracks the missing, dropped and truncated events of every rkyv replay source,
+/// each source updates its own [`StreamQuality`] as it's replayed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DataQualityTracker {
+    streams: Arc<Mutex<Vec<Arc<Mutex<StreamQuality>>>>>,
+}
+
+impl DataQualityTracker {
+    /// Starts tracking the quality of a single worker's log stream
+    pub(crate) fn track(&self, stream: StreamKind, worker: usize) -> Arc<Mutex<StreamQuality>> {
+        let quality = Arc::new(Mutex::new(StreamQuality::new(stream, worker)));
+        lock(&self.streams).push(quality.clone());
+
+        quality
+    }
+
+    /// Fills in the quality of every tracked stream
+    pub(crate) fn report(&self, data: &mut DataflowData) {
+        let mut streams: Vec<_> = lock(&self.streams)
+            .iter()
+            .map(|quality| lock(quality).clone())
+            .collect();
+        streams.sort_unstable();
+
+        for quality in streams.iter() {
+            if quality.has_issues() {
+                tracing::warn!(
+                    stream = %quality.stream,
+                    worker = quality.worker,
+                    missing_events = quality.missing_events(),
+                    dropped_events = quality.dropped,
+                    truncated = quality.truncated,
+                    "events were lost from the {} stream of worker {}",
+                    quality.stream,
+                    quality.worker,
+                );
+            }
+        }
+
+        data.data_quality = streams;
+    }
+}
+
+/// Locks a stream's quality, the quality is never left
+/// half-updated so any poisoning is ignored
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ReplaySources {
+    /// Create replay sources from rkyv-encoded readers such as tcp streams or
+    /// log files produced by `ddshow-sink`, one reader per target worker
+    ///
+    /// Differential and progress readers are only replayed if they're
+    /// enabled within `args`
+    pub fn from_readers<R>(
+        args: &Args,
+        timely: Vec<R>,
+        differential: Option<Vec<R>>,
+        progress: Option<Vec<R>>,
+    ) -> Result<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        fn readers<D, A, R>(
+            readers: Vec<R>,
+            stream: StreamKind,
+            data_quality: &DataQualityTracker,
+        ) -> ReplaySource<RkyvReplaySource<D>, A>
+        where
+            R: Read + Send + 'static,
+            D: Archive + Send + 'static,
+            D::Archived: Deserialize<D, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
+        {
+            ReplaySource::Rkyv(
+                readers
+                    .into_iter()
+                    .enumerate()
+                    .map(|(worker, reader)| {
+                        Box::new(
+                            RkyvEventReader::<Duration, D, _>::new(reader)
+                                .track_quality(data_quality.track(stream, worker)),
+                        ) as RkyvReplaySource<D>
+                    })
+                    .collect(),
+            )
+        }
+
+        let data_quality = DataQualityTracker::default();
+
+        let mut total_sources = timely.len();
+        let timely = make_streams(
+            args.workers.get(),
+            readers(timely, StreamKind::Timely, &data_quality),
+        )?;
+
+        let differential = match differential {
+            Some(differential) if args.differential_enabled => {
+                total_sources += differential.len();
+                Some(make_streams(
+                    args.workers.get(),
+                    readers(differential, StreamKind::Differential, &data_quality),
+                )?)
+            }
+            _ => None,
+        };
+
+        let progress = match progress {
+            Some(progress) if args.progress_enabled => {
+                total_sources += progress.len();
+                Some(make_streams(
+                    args.workers.get(),
+                    readers(progress, StreamKind::Progress, &data_quality),
+                )?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            timely,
+            differential,
+            progress,
+            data_quality,
+            total_sources,
+        })
+    }
+
+    /// Create replay sources from abomonation-encoded tcp streams, as produced
+    /// by timely and differential's builtin loggers
+    ///
+    /// Progress logging isn't supported for abomonation sources
+    pub fn from_abomonation_streams(
+        args: &Args,
+        timely: Vec<TcpStream>,
+        differential: Option<Vec<TcpStream>>,
+    ) -> Result<Self> {
+        let mut total_sources = timely.len();
+        let timely = make_streams(
+            args.workers.get(),
+            ReplaySource::Abomonation(timely.into_iter().map(EventReader::new).collect()),
+        )?;
+
+        let differential = match differential {
+            Some(differential) if args.differential_enabled => {
+                total_sources += differential.len();
+
+                Some(make_streams(
+                    args.workers.get(),
+                    ReplaySource::Abomonation(
+                        differential.into_iter().map(EventReader::new).collect(),
+                    ),
+                )?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            timely,
+            differential,
+            progress: None,
+            data_quality: DataQualityTracker::default(),
+            total_sources,
+        })
+    }
+
+    /// The total number of replay sources across all log streams
+    pub const fn total_sources(&self) -> usize {
+        self.total_sources
+    }
+}
+
 #[tracing::instrument(skip(args))]
-pub fn connect_to_sources(
-    args: &Args,
-) -> Result<
-    Option<(
-        TimelyEventReceivers,
-        DifferentialEventReceivers,
-        ProgressEventReceivers,
-        usize,
-    )>,
-> {
+pub fn connect_to_sources(args: &Args) -> Result<Option<ReplaySources>> {
     let mut total_sources = 0;
+    let data_quality = DataQualityTracker::default();
 
-    let timely_listener = if !args.is_file_sourced() {
-        Some(TcpListener::bind(args.timely_address).with_context(|| {
-            anyhow::anyhow!("failed to bind to timely socket {}", args.timely_address)
-        })?)
-    } else {
-        None
-    };
-    let differential_listener = if args.differential_enabled && !args.is_file_sourced() {
-        Some(
-            TcpListener::bind(args.differential_address).with_context(|| {
-                anyhow::anyhow!(
-                    "failed to bind to differential socket {}",
-                    args.differential_address,
-                )
+    if args.reconnect && !args.is_file_sourced() {
+        if args.stop_on_disconnect {
+            anyhow::bail!(
+                "`--reconnect` can't be used with `--stop-on-disconnect` since \
+                 reconnecting workers are never considered disconnected",
+            );
+        }
+        if args.unix_socket.is_some() {
+            anyhow::bail!(
+                "`--reconnect` can't be used with multiplexed `--unix-socket` connections"
+            );
+        }
+    }
+
+    let mut router = if !args.is_file_sourced() {
+        let mut listeners = vec![(
+            StreamKind::Timely,
+            TcpListener::bind(args.timely_address).with_context(|| {
+                anyhow::anyhow!("failed to bind to timely socket {}", args.timely_address)
             })?,
-        )
+        )];
+
+        if args.differential_enabled {
+            listeners.push((
+                StreamKind::Differential,
+                TcpListener::bind(args.differential_address).with_context(|| {
+                    anyhow::anyhow!(
+                        "failed to bind to differential socket {}",
+                        args.differential_address,
+                    )
+                })?,
+            ));
+        }
+
+        if args.progress_enabled {
+            listeners.push((
+                StreamKind::Progress,
+                TcpListener::bind(args.progress_address).with_context(|| {
+                    anyhow::anyhow!(
+                        "failed to bind to progress socket {}",
+                        args.progress_address,
+                    )
+                })?,
+            ));
+        }
+
+        Some(ConnectionRouter::new(
+            listeners,
+            args.unix_socket.as_deref(),
+            args.stream_encoding,
+        )?)
     } else {
         None
     };
-    let progress_listener = if args.progress_enabled && !args.is_file_sourced() {
-        Some(TcpListener::bind(args.progress_address).with_context(|| {
-            anyhow::anyhow!(
-                "failed to bind to progress socket {}",
-                args.progress_address,
-            )
-        })?)
-    } else {
-        None
+
+    // If we're replaying from a trace archive, validate it before loading anything
+    let archive = match args.replay_logs.as_deref() {
+        Some(replay_logs) if replay_logs.is_file() => {
+            let archive = ArchiveHeader::open(replay_logs)?;
+
+            let streams: Vec<String> = archive.streams().iter().map(ToString::to_string).collect();
+            println!(
+                "Loading trace archive of {} captured from {} worker{} with {} logs",
+                archive.program_name,
+                archive.workers,
+                if archive.workers == 1 { "" } else { "s" },
+                streams.join(", "),
+            );
+
+            if args.differential_enabled && !archive.has_stream(StreamKind::Differential) {
+                anyhow::bail!(
+                    "differential logs were requested but {} doesn't contain any",
+                    replay_logs.display(),
+                );
+            }
+            if args.progress_enabled && !archive.has_stream(StreamKind::Progress) {
+                anyhow::bail!(
+                    "progress logs were requested but {} doesn't contain any",
+                    replay_logs.display(),
+                );
+            }
+
+            Some(archive)
+        }
+
+        _ => None,
     };
 
     // Connect to the timely sources
     let (timely_event_receivers, are_timely_sources, num_sources) = acquire_replay_sources(
         &args,
         args.timely_address,
-        timely_listener,
+        router.as_mut(),
         args.timely_connections,
         args.workers,
         args.replay_logs.as_deref(),
+        archive.as_ref(),
         TIMELY_LOG_FILE,
+        &data_quality,
         "Timely",
     )?;
     total_sources += num_sources;
@@ -166,11 +422,13 @@ pub fn connect_to_sources(
         let (receivers, are_sources, num_sources) = acquire_replay_sources(
             &args,
             args.differential_address,
-            differential_listener,
+            router.as_mut(),
             args.timely_connections,
             args.workers,
             args.replay_logs.as_deref(),
+            archive.as_ref(),
             DIFFERENTIAL_ARRANGEMENT_LOG_FILE,
+            &data_quality,
             "Differential",
         )?;
         total_sources += num_sources;
@@ -185,11 +443,13 @@ pub fn connect_to_sources(
         let (receivers, are_sources, num_sources) = acquire_replay_sources(
             &args,
             args.progress_address,
-            progress_listener,
+            router.as_mut(),
             args.timely_connections,
             args.workers,
             args.replay_logs.as_deref(),
+            archive.as_ref(),
             TIMELY_PROGRESS_LOG_FILE,
+            &data_quality,
             "Progress",
         )?;
         total_sources += num_sources;
@@ -212,36 +472,41 @@ pub fn connect_to_sources(
         );
     }
 
-    Ok(Some((
-        timely_event_receivers,
-        differential_event_receivers,
-        progress_event_receivers,
+    Ok(Some(ReplaySources {
+        timely: timely_event_receivers,
+        differential: differential_event_receivers,
+        progress: progress_event_receivers,
+        data_quality,
         total_sources,
-    )))
+    }))
 }
 
 /// Connect to and prepare the replay sources
 #[tracing::instrument(skip(args))]
 #[allow(clippy::too_many_arguments)]
-pub fn acquire_replay_sources<T, D1, D2>(
+pub fn acquire_replay_sources<E, D>(
     args: &Args,
     address: SocketAddr,
-    listener: Option<TcpListener>,
+    router: Option<&mut ConnectionRouter>,
     connections: NonZeroUsize,
     workers: NonZeroUsize,
     log_dir: Option<&Path>,
+    archive: Option<&ArchiveHeader>,
     file_prefix: &str,
+    data_quality: &DataQualityTracker,
     target: &str,
-) -> Result<(AcquiredStreams<T, D1, D2>, bool, usize)>
+) -> Result<(AcquiredStreams<E, D>, bool, usize)>
 where
-    Event<T, D2>: Clone,
-    D2: Abomonation + Send + 'static,
-    T: Archive + Abomonation + Send + 'static,
-    T::Archived: Deserialize<T, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
-    D1: Archive + Send + 'static,
-    D1::Archived: Deserialize<D1, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
+    Event<Duration, D>: Clone,
+    D: Abomonation + Send + 'static,
+    (Duration, WorkerId, E): Archive,
+    <(Duration, WorkerId, E) as Archive>::Archived: Deserialize<(Duration, WorkerId, E), AllocDeserializer>
+        + CheckBytes<DefaultArchiveValidator>,
+    E: Send + 'static,
 {
     let mut num_sources = 0;
+    let stream = StreamKind::from_file_prefix(file_prefix)
+        .expect("replay sources must be for a known log stream");
 
     let plural = if connections.get() == 1 { "" } else { "s" };
     let prefix = if let Some(log_dir) = log_dir {
@@ -274,14 +539,43 @@ where
 
     utils::set_steady_tick(&progress, connections.get());
 
-    let replay_sources = if let Some(log_dir) = log_dir {
+    let replay_sources = if let (Some(archive), Some(archive_path)) = (archive, log_dir) {
+        let replays = archive
+            .open_sections(archive_path, stream)?
+            .into_iter()
+            .enumerate()
+            .map(|(worker, section)| {
+                let (_, section) = check_event_file(section).with_context(|| {
+                    format!(
+                        "failed to load the {} section for worker {} of {}",
+                        stream,
+                        worker,
+                        archive_path.display(),
+                    )
+                })?;
+
+                Ok(Box::new(
+                    RkyvEventReader::new(section).track_quality(data_quality.track(stream, worker)),
+                ) as RkyvReplaySource<_>)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        num_sources += replays.len();
+
+        progress.set_style(finished_style);
+        progress.finish_with_message(format!(
+            "loaded {} archived section{}",
+            replays.len(),
+            if replays.len() == 1 { "" } else { "s" },
+        ));
+
+        ReplaySource::Rkyv(replays)
+    } else if let Some(log_dir) = log_dir {
         let mut replays = Vec::with_capacity(connections.get());
 
-        // Load all files in the directory that have the `.ddshow` extension and a
-        // prefix that matches `file_prefix`
+        // Load all files in the directory that have the `.ddshow` extension (optionally
+        // followed by a compression extension) and a prefix that matches `file_prefix`
         // TODO: Probably want some sort of method to allow distinguishing between
         //       different runs saved to the same folder
-        // TODO: Add support for decompressing archived log files
         let dir = fs::read_dir(log_dir).context("failed to read log directory")?;
         for entry in dir.into_iter().filter_map(|entry| {
             entry.map_or_else(
@@ -297,7 +591,7 @@ where
             let replay_file = entry.path();
 
             let is_file = entry.file_type().map_or(false, |file| file.is_file());
-            let ends_with_ddshow = replay_file.extension() == Some(OsStr::new("ddshow"));
+            let ends_with_ddshow = Compression::from_path(&replay_file).is_some();
             let starts_with_prefix = replay_file
                 .file_name()
                 .and_then(OsStr::to_str)
@@ -309,13 +603,50 @@ where
                 progress.inc_length(1);
 
                 tracing::debug!("loading {} replay from {}", target, replay_file.display());
-                let timely_file = File::open(&replay_file).with_context(|| {
-                    format!("failed to open {} log file within replay directory", target)
+                let mut timely_file =
+                    BufReader::new(File::open(&replay_file).with_context(|| {
+                        format!("failed to open {} log file within replay directory", target)
+                    })?);
+
+                // Figure out if the file is compressed from its magic bytes or extension
+                let compression = Compression::detect(&mut timely_file, &replay_file)
+                    .with_context(|| {
+                        format!("failed to read {} log file within replay directory", target)
+                    })?;
+                tracing::debug!(
+                    "using {} compression for {}",
+                    compression,
+                    replay_file.display(),
+                );
+
+                let timely_file = compression.decompress(timely_file).with_context(|| {
+                    format!(
+                        "failed to decompress {} log file {}",
+                        target,
+                        replay_file.display(),
+                    )
                 })?;
 
-                replays.push(RkyvEventReader::new(
-                    Box::new(BufReader::new(timely_file)) as Box<dyn Read + Send + 'static>
-                ));
+                let (handshake, timely_file) =
+                    check_event_file(timely_file).with_context(|| {
+                        format!(
+                            "failed to load {} log file {}",
+                            target,
+                            replay_file.display()
+                        )
+                    })?;
+
+                // Files written by `ddshow-sink` start with the handshake of the worker that
+                // wrote them, older ones are only named after the worker
+                let worker = handshake
+                    .map(|handshake| handshake.worker)
+                    .or_else(|| worker_index(&replay_file))
+                    .unwrap_or(replays.len());
+
+                replays.push(Box::new(
+                    RkyvEventReader::new(timely_file)
+                        .track_quality(data_quality.track(stream, worker)),
+                ) as RkyvReplaySource<_>);
 
                 progress.inc(1);
                 num_sources += 1;
@@ -333,7 +664,8 @@ where
                 let reason = if is_file {
                     "replay files must be files".to_owned()
                 } else if ends_with_ddshow {
-                    "did not end with the `.ddshow` extension".to_owned()
+                    "did not end with the `.ddshow`, `.ddshow.zst` or `.ddshow.gz` extension"
+                        .to_owned()
                 } else if starts_with_prefix {
                     format!("did not start with the prefix {}", file_prefix)
                 } else {
@@ -357,24 +689,74 @@ where
 
         ReplaySource::Rkyv(replays)
     } else {
-        let listener = listener.expect("a listener must be supplied for stream sources");
+        let router = router.expect("a router must be supplied for stream sources");
 
-        let source = match args.stream_encoding {
-            StreamEncoding::Abomonation => {
-                wait_for_abominated_connections(listener, &address, connections, &progress)?
-            }
-            StreamEncoding::Rkyv => {
-                wait_for_rkyv_connections(listener, &address, connections, &progress)?
+        let source = if args.reconnect {
+            let listener = router
+                .take_listener(stream)
+                .expect("a listener must be bound for every enabled stream");
+
+            wait_for_reconnecting_workers(listener, &address, &progress, stream, data_quality)?
+        } else {
+            let routed = router.wait_for(stream, connections, &progress)?;
+            tracing::info!(
+                "connected to {} {} encoded {} source{}",
+                routed.connections.len(),
+                routed.encoding,
+                target,
+                if routed.connections.len() == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+            );
+
+            match routed.encoding {
+                StreamEncoding::Abomonation => ReplaySource::Abomonation(
+                    routed
+                        .connections
+                        .into_iter()
+                        .map(|(_, connection)| {
+                            EventReader::new(
+                                connection
+                                    .into_socket()
+                                    .expect("multiplexed streams are always rkyv encoded"),
+                            )
+                        })
+                        .collect(),
+                ),
+
+                StreamEncoding::Rkyv => ReplaySource::Rkyv(
+                    routed
+                        .connections
+                        .into_iter()
+                        .map(|(worker, connection)| {
+                            Box::new(
+                                RkyvEventReader::new(connection)
+                                    .track_quality(data_quality.track(stream, worker)),
+                            ) as RkyvReplaySource<_>
+                        })
+                        .collect(),
+                ),
             }
         };
 
-        num_sources += connections.get();
+        num_sources += source.len();
 
         progress.set_style(finished_style);
-        progress.finish_with_message(format!(
-            "connected to {} trace source{}",
-            connections, plural,
-        ));
+        progress.finish_with_message(match router.target() {
+            Some(target) => format!(
+                "connected to {} worker{} of {}",
+                source.len(),
+                if source.len() == 1 { "" } else { "s" },
+                target.program,
+            ),
+            None => format!(
+                "connected to {} trace source{}",
+                source.len(),
+                if source.len() == 1 { "" } else { "s" },
+            ),
+        });
 
         source
     };
@@ -385,6 +767,57 @@ where
     Ok((event_receivers, are_replay_sources, num_sources))
 }
 
+/// Gets the index of the worker that wrote a log file from its name,
+/// e.g. `timely.worker-3.ddshow.zst` was written by worker 3
+fn worker_index(file: &Path) -> Option<usize> {
+    file.file_name()?
+        .to_str()?
+        .split('.')
+        .nth(1)?
+        .strip_prefix("worker-")?
+        .parse()
+        .ok()
+}
+
+/// Checks that an event file was written with a format version that this version
+/// of ddshow can read, returning the file's handshake if it starts with one along
+/// with a reader over the file's events
+pub(crate) fn check_event_file<R>(
+    mut reader: R,
+) -> Result<(Option<Handshake>, Box<dyn Read + Send + 'static>)>
+where
+    R: Read + Send + 'static,
+{
+    let mut header = Vec::with_capacity(EVENT_HEADER_LEN);
+    (&mut reader)
+        .take(EVENT_HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .context("failed to read the event file's header")?;
+
+    // Files saved by `ddshow-sink` start with the handshake of the worker that saved them,
+    // event headers are shorter than handshakes so the header is read again afterwards
+    let handshake = if header.starts_with(&HANDSHAKE_MAGIC) {
+        let handshake =
+            Handshake::read_from(&mut io::Cursor::new(mem::take(&mut header)).chain(&mut reader))
+                .context("failed to read the event file's handshake")?;
+
+        (&mut reader)
+            .take(EVENT_HEADER_LEN as u64)
+            .read_to_end(&mut header)
+            .context("failed to read the event file's header")?;
+
+        Some(handshake)
+    } else {
+        None
+    };
+
+    let version = event_format_version(&header);
+    check_event_format_version(version)?;
+    tracing::debug!("loading event file with format version {}", version);
+
+    Ok((handshake, Box::new(io::Cursor::new(header).chain(reader))))
+}
+
 pub type EventReceivers<R, A> = Arc<[Receiver<ReplaySource<R, A>>]>;
 
 #[tracing::instrument(
@@ -456,146 +889,88 @@ pub fn make_streams<R, A>(
     Ok(Arc::from(receivers))
 }
 
-/// Connect to the given address and collect `connections` streams, returning all of them
-/// in non-blocking mode
+type ConnectedRkyvSource<D, A> = ReplaySource<RkyvReplaySource<D>, A>;
+
+/// Listen on the given address for workers that connect with a handshake, waiting until
+/// every worker of the target computation has connected. Workers can keep reconnecting for
+/// as long as the replay runs, each worker's connections are stitched into a single stream
 #[tracing::instrument(skip(progress))]
-pub fn wait_for_abominated_connections<T, D, R>(
+pub fn wait_for_reconnecting_workers<E, A>(
     listener: TcpListener,
     addr: &SocketAddr,
-    connections: NonZeroUsize,
     progress: &ProgressBar,
-) -> Result<ReplaySource<R, EventReader<T, D, TcpStream>>>
+    stream: StreamKind,
+    data_quality: &DataQualityTracker,
+) -> Result<ConnectedRkyvSource<(Duration, WorkerId, E), A>>
 where
-    Event<T, D>: Clone,
-    T: Abomonation + Send + 'static,
-    D: Abomonation + Send + 'static,
+    (Duration, WorkerId, E): Archive,
+    <(Duration, WorkerId, E) as Archive>::Archived: Deserialize<(Duration, WorkerId, E), AllocDeserializer>
+        + CheckBytes<DefaultArchiveValidator>,
+    E: Send + 'static,
 {
-    progress.set_message(format!(
-        "connected to 0/{} socket{}",
-        connections,
-        if connections.get() == 1 { "" } else { "s" },
-    ));
-    progress.set_length(connections.get() as u64);
-
-    let timely_conns = (0..connections.get())
-        .zip(listener.incoming())
-        .map(|(idx, socket)| {
-            let socket = socket.context("failed to accept socket connection")?;
-
-            socket
-                .set_nonblocking(true)
-                .context("failed to set socket to non-blocking mode")?;
-
-            if let Err(err) = socket.set_read_timeout(TCP_READ_TIMEOUT) {
-                tracing::error!(
-                    "failed to set socket to a read timeout of {:?}: {:?}",
-                    TCP_READ_TIMEOUT,
-                    err,
-                );
-            };
+    progress.set_message("waiting for the first worker to connect");
 
-            tracing::info!(
-                socket = ?socket,
-                "connected to socket {}/{}",
-                idx + 1,
-                connections,
-            );
+    let acceptor = Arc::new(ConnectionAcceptor::new(listener, stream)?);
 
-            progress.set_message(format!(
-                "connected to {}/{} socket{}",
-                idx + 1,
-                connections,
-                if connections.get() == 1 { "" } else { "s" },
-            ));
-            progress.inc(1);
+    // The number of workers isn't known until the first worker sends its handshake
+    let mut connected = None;
+    let workers = loop {
+        acceptor.poll()?;
 
-            Ok(EventReader::new(socket))
-        })
-        .collect::<Result<Vec<_>>>()?;
-
-    Ok(ReplaySource::Abomonation(timely_conns))
-}
+        if let Some(workers) = acceptor.workers() {
+            let plural = if workers == 1 { "" } else { "s" };
 
-type ConnectedRkyvSource<T, D, A> =
-    ReplaySource<RkyvEventReader<T, D, Box<dyn Read + Send + 'static>>, A>;
+            let current = acceptor.connected_workers();
+            if connected != Some(current) {
+                tracing::info!("{}/{} workers connected to {}", current, workers, addr);
 
-/// Connect to the given address and collect `connections` streams, returning all of them
-/// in non-blocking mode
-#[tracing::instrument(skip(progress))]
-pub fn wait_for_rkyv_connections<T, D, A>(
-    listener: TcpListener,
-    addr: &SocketAddr,
-    connections: NonZeroUsize,
-    progress: &ProgressBar,
-) -> Result<ConnectedRkyvSource<T, D, A>>
-where
-    T: Archive,
-    T::Archived: Deserialize<T, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
-    D: Archive,
-    D::Archived: Deserialize<D, AllocDeserializer> + CheckBytes<DefaultArchiveValidator>,
-{
-    progress.set_message(format!(
-        "connected to 0/{} socket{}",
-        connections,
-        if connections.get() == 1 { "" } else { "s" },
-    ));
-    progress.set_length(connections.get() as u64);
-
-    let timely_conns = (0..connections.get())
-        .zip(listener.incoming())
-        .map(|(idx, socket)| {
-            let socket = socket.context("failed to accept socket connection")?;
-
-            socket
-                .set_nonblocking(true)
-                .context("failed to set socket to non-blocking mode")?;
-
-            if let Err(err) = socket.set_read_timeout(TCP_READ_TIMEOUT) {
-                tracing::error!(
-                    "failed to set socket to a read timeout of {:?}: {:?}",
-                    TCP_READ_TIMEOUT,
-                    err,
-                );
-            };
+                progress.set_message(format!(
+                    "{}/{} worker{} connected",
+                    current, workers, plural,
+                ));
+                progress.set_length(workers as u64);
+                progress.set_position(current as u64);
+                connected = Some(current);
+            }
 
-            tracing::info!(
-                socket = ?socket,
-                "connected to socket {}/{}",
-                idx + 1,
-                connections,
-            );
+            if current == workers {
+                break workers;
+            }
+        }
 
-            progress.set_message(format!(
-                "connected to {}/{} socket{}",
-                idx + 1,
-                connections,
-                if connections.get() == 1 { "" } else { "s" },
-            ));
-            progress.inc(1);
+        thread::sleep(Duration::from_millis(10));
+    };
 
-            Ok(RkyvEventReader::new(
-                Box::new(socket) as Box<dyn Read + Send + 'static>
-            ))
+    let sources = (0..workers)
+        .map(|worker| {
+            Box::new(ReconnectingSource::new(
+                worker,
+                acceptor.clone(),
+                data_quality.track(stream, worker),
+            )) as RkyvReplaySource<_>
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect();
 
-    Ok(ReplaySource::Rkyv(timely_conns))
+    Ok(ReplaySource::Rkyv(sources))
 }
 
 /// Wait for user input to terminate the trace replay and wait for all timely
 /// workers to terminate
-// TODO: Add a "haven't received updates in `n` seconds" thingy to tell the user
-//       we're no longer getting data
 #[tracing::instrument(
-    skip(args, worker_guards, receivers),
+    skip(args, events_replayed, data_quality, stop, worker_guards, receivers, server),
     fields(workers = worker_guards.guards().len()),
 )]
+#[allow(clippy::too_many_arguments)]
 pub fn wait_for_input(
     args: &Args,
     running: &AtomicBool,
     workers_finished: &AtomicUsize,
+    events_replayed: &AtomicUsize,
+    data_quality: &DataQualityTracker,
+    stop: &StopHandle,
     worker_guards: WorkerGuards<Result<()>>,
     receivers: DataflowReceivers,
+    server: Option<&Server>,
 ) -> Result<DataflowData> {
     let mut stdin = io::stdin();
 
@@ -625,16 +1000,105 @@ pub fn wait_for_input(
     // Sync up with the user input thread
     barrier.wait();
 
+    let num_threads = worker_guards.guards().len();
+    let extractor = wait_for_shutdown(
+        args,
+        running,
+        workers_finished,
+        events_replayed,
+        stop,
+        num_threads,
+        receivers,
+        Some(&recv),
+        server,
+    )?;
+
+    {
+        let mut stdout = io::stdout();
+        write!(stdout, "Processing data...").context("failed to write to stdout")?;
+        stdout.flush().context("failed to flush stdout")?;
+    }
+
+    let mut data = finish_replay(running, worker_guards, extractor)?;
+    data_quality.report(&mut data);
+    println!(" done!");
+
+    if let Some(server) = server {
+        server.publish(data.clone());
+    }
+
+    Ok(data)
+}
+
+/// Wait for all timely workers to exhaust their replay sources and terminate
+/// without prompting the user
+#[tracing::instrument(
+    skip(args, events_replayed, data_quality, stop, worker_guards, receivers, server),
+    fields(workers = worker_guards.guards().len()),
+)]
+#[allow(clippy::too_many_arguments)]
+pub fn wait_for_completion(
+    args: &Args,
+    running: &AtomicBool,
+    workers_finished: &AtomicUsize,
+    events_replayed: &AtomicUsize,
+    data_quality: &DataQualityTracker,
+    stop: &StopHandle,
+    worker_guards: WorkerGuards<Result<()>>,
+    receivers: DataflowReceivers,
+    server: Option<&Server>,
+) -> Result<DataflowData> {
+    let num_threads = worker_guards.guards().len();
+    let extractor = wait_for_shutdown(
+        args,
+        running,
+        workers_finished,
+        events_replayed,
+        stop,
+        num_threads,
+        receivers,
+        None,
+        server,
+    )?;
+
+    let mut data = finish_replay(running, worker_guards, extractor)?;
+    data_quality.report(&mut data);
+    if let Some(server) = server {
+        server.publish(data.clone());
+    }
+
+    Ok(data)
+}
+
+/// Pull data out of the dataflow until either all workers finish, the dataflow
+/// shuts down, one of the user's stop conditions is met or the user asks to stop
+#[allow(clippy::too_many_arguments)]
+fn wait_for_shutdown(
+    args: &Args,
+    running: &AtomicBool,
+    workers_finished: &AtomicUsize,
+    events_replayed: &AtomicUsize,
+    stop: &StopHandle,
+    num_threads: usize,
+    receivers: DataflowReceivers,
+    user_input: Option<&Receiver<()>>,
+    server: Option<&Server>,
+) -> Result<DataflowExtractor> {
     let (mut fuel, mut extractor) = (
         Fuel::limited(IDLE_EXTRACTION_FUEL),
         receivers.into_extractor(),
     );
-    let num_threads = worker_guards.guards().len();
 
     let report_update_duration = args
         .report_update_duration
         .map(|secs| Duration::from_secs(secs as u64));
     let mut last_report_update = Instant::now();
+    let mut last_server_update = Instant::now();
+
+    let started = Instant::now();
+    let stop_after = args.stop_after.map(Duration::from_secs);
+    let stop_when_idle = args.stop_when_idle.map(Duration::from_secs);
+    let (mut last_events_replayed, mut last_event) = (0, Instant::now());
 
     loop {
         hint::spin_loop();
@@ -663,16 +1127,56 @@ pub fn wait_for_input(
             break;
         }
 
+        // If one of the user's stop conditions was met, stop replaying trace data
+        // and keep going until the workers have processed everything they've
+        // already received
+        if !stop.is_stopped() {
+            let mut reason = None;
+
+            if let Some(stop_after) = stop_after {
+                if started.elapsed() >= stop_after {
+                    reason = Some(format!("{:#?} have passed", stop_after));
+                }
+            }
+
+            if let Some(stop_when_idle) = stop_when_idle {
+                let events = events_replayed.load(Ordering::Relaxed);
+
+                if events != last_events_replayed {
+                    last_events_replayed = events;
+                    last_event = Instant::now();
+                } else if last_event.elapsed() >= stop_when_idle {
+                    reason = Some(format!("no events were received for {:#?}", stop_when_idle));
+                }
+            }
+
+            if let Some(reason) = reason {
+                tracing::info!(
+                    num_threads = num_threads,
+                    workers_finished = workers_finished.load(Ordering::Acquire),
+                    running = running.load(Ordering::Acquire),
+                    "stopping the trace replay, {}",
+                    reason,
+                );
+
+                stop.stop();
+            }
+        }
+
         // If the user shuts down the dataflow
-        if recv.recv_timeout(Duration::from_millis(500)).is_ok() {
-            tracing::info!(
-                num_threads = num_threads,
-                workers_finished = workers_finished.load(Ordering::Acquire),
-                running = running.load(Ordering::Acquire),
-                "main thread got shutdown signal, received input from user",
-            );
+        if let Some(user_input) = user_input {
+            if user_input.recv_timeout(Duration::from_millis(500)).is_ok() {
+                tracing::info!(
+                    num_threads = num_threads,
+                    workers_finished = workers_finished.load(Ordering::Acquire),
+                    running = running.load(Ordering::Acquire),
+                    "main thread got shutdown signal, received input from user",
+                );
 
-            break;
+                break;
+            }
+        } else {
+            thread::sleep(Duration::from_millis(50));
         }
 
         // After we've checked all of our exit conditions we can pull some
@@ -708,18 +1212,30 @@ pub fn wait_for_input(
                 last_report_update = Instant::now();
             }
         }
+
+        // Keep the served graph up to date while the target is still running
+        if let Some(server) = server {
+            if last_server_update.elapsed() >= SERVER_UPDATE_INTERVAL {
+                server.publish(extractor.current_dataflow_data());
+                last_server_update = Instant::now();
+            }
+        }
     }
 
+    Ok(extractor)
+}
+
+/// Terminate the replay, join all worker threads and extract whatever
+/// data remains within the dataflow
+fn finish_replay(
+    running: &AtomicBool,
+    worker_guards: WorkerGuards<Result<()>>,
+    extractor: DataflowExtractor,
+) -> Result<DataflowData> {
     // Terminate the replay
     running.store(false, Ordering::Release);
     atomic::fence(Ordering::Acquire);
 
-    {
-        let mut stdout = io::stdout();
-        write!(stdout, "Processing data...").context("failed to write to stdout")?;
-        stdout.flush().context("failed to flush stdout")?;
-    }
-
     tracing::debug!("unparking all worker threads");
     for thread in worker_guards.guards() {
         thread.thread().unpark();
@@ -733,10 +1249,7 @@ pub fn wait_for_input(
     }
 
     tracing::debug!("extracting all remaining data from the dataflow");
-    let data = extractor.extract_all();
-    println!(" done!");
-
-    Ok(data)
+    Ok(extractor.extract_all())
 }
 
 #[cfg(test)]
@@ -760,8 +1273,7 @@ mod tests {
     };
     use std::{
         fmt::Debug,
-        net::{SocketAddr, TcpStream},
-        sync::{Arc, Barrier},
+        net::{SocketAddr, TcpListener, TcpStream},
         thread,
         time::Duration,
     };
@@ -803,29 +1315,32 @@ mod tests {
 
     #[test]
     fn connection_test() {
+        // Listen on an unused port so that concurrent test runs don't fight over the default one
         let args = Args {
+            timely_address: TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap(),
             stream_encoding: StreamEncoding::Rkyv,
             ..Default::default()
         };
         logging::init_logging(&args);
 
-        let barrier = Arc::new(Barrier::new(2));
         let events = vec![
             TimelyEvent::Input(InputEvent::new(StartStop::start())),
             TimelyEvent::Input(InputEvent::new(StartStop::stop())),
         ];
 
-        target_program(barrier.clone(), args.timely_address, events.clone());
-        barrier.wait();
+        target_program(args.timely_address, events.clone());
 
-        let (timely_recv, differential_recv, progress_recv, total_sources) =
-            connect_to_sources(&args).unwrap().unwrap();
+        let sources = connect_to_sources(&args).unwrap().unwrap();
 
-        assert_eq!(total_sources, 1);
-        assert_matches!(differential_recv, None);
-        assert_matches!(progress_recv, None);
+        assert_eq!(sources.total_sources(), 1);
+        assert_matches!(sources.differential, None);
+        assert_matches!(sources.progress, None);
 
-        let mut sources: Vec<_> = timely_recv
+        let mut sources: Vec<_> = sources
+            .timely
             .iter()
             .flat_map(|recv| recv.try_iter())
             .collect();
@@ -852,18 +1367,21 @@ mod tests {
         assert_eq!(result, events);
     }
 
-    fn target_program<E>(barrier: Arc<Barrier>, address: SocketAddr, events: Vec<E>)
+    fn target_program<E>(address: SocketAddr, events: Vec<E>)
     where
         E: for<'a> Serialize<AlignedSerializer<&'a mut AlignedVec>> + Send + Debug + 'static,
         E::Archived: CheckBytes<DefaultArchiveValidator>,
     {
         thread::spawn(move || {
-            barrier.wait();
-            thread::sleep(Duration::from_millis(200));
+            // Retry until ddshow starts listening
+            let stream = loop {
+                match TcpStream::connect(address) {
+                    Ok(stream) => break stream,
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            };
 
-            let mut writer = BatchLogger::<E, WorkerId, _>::new(RkyvEventWriter::new(
-                TcpStream::connect(address).unwrap(),
-            ));
+            let mut writer = BatchLogger::<E, WorkerId, _>::new(RkyvEventWriter::new(stream));
 
             let mut time = Duration::from_secs(0);
             for event in events {
@@ -1,6 +1,14 @@
 mod tree;
 
-use crate::{args::Args, dataflow::DataflowData, report::tree::Tree};
+use crate::{
+    args::Args,
+    critical_path::{self, MAX_CONTRIBUTORS},
+    dataflow::DataflowData,
+    diff::TraceDiff,
+    lint,
+    report::tree::Tree,
+    skew::{self, Skew},
+};
 use anyhow::{Context, Result};
 use comfy_table::{presets::UTF8_FULL, Cell, ColumnConstraint, Row, Table as InnerTable, Width};
 use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
@@ -10,6 +18,7 @@ use std::{
     fmt::{self, Display},
     fs::{self, File},
     io::Write,
+    time::Duration,
 };
 
 pub fn build_report(
@@ -47,6 +56,7 @@ pub fn build_report(
         let all_workers: HashSet<_> = data.nodes.iter().map(|&((worker, _), _)| worker).collect();
 
         program_overview(args, data, &mut file)?;
+        data_quality(data, &mut file)?;
         worker_stats(args, data, &mut file)?;
         operator_stats(
             args,
@@ -65,12 +75,27 @@ pub fn build_report(
 
         operator_tree(data, &mut file, &name_lookup, &addr_lookup, &all_workers)?;
 
+        if all_workers.len() > 1 {
+            writeln!(&mut file)?;
+            worker_skew(args, data, &mut file)?;
+        } else {
+            tracing::debug!("only one worker was profiled, skipping worker skew table");
+        }
+
         if args.progress_enabled {
             writeln!(&mut file)?;
             channel_traffic(data, &mut file)?;
+
+            writeln!(&mut file)?;
+            critical_paths(data, &mut file)?;
         } else {
-            tracing::debug!("progress logging is disabled, skipping channel stats table");
+            tracing::debug!(
+                "progress logging is disabled, skipping channel stats and critical path tables",
+            );
         }
+
+        writeln!(&mut file)?;
+        lints(args, data, &mut file)?;
     } else {
         tracing::debug!("report files are disabled, skipping generation");
     }
@@ -117,6 +142,102 @@ fn program_overview(args: &Args, data: &DataflowData, file: &mut File) -> Result
     Ok(())
 }
 
+fn data_quality(data: &DataflowData, file: &mut File) -> Result<()> {
+    tracing::debug!("generating data quality table");
+
+    // The most missing ranges that will be listed for each stream
+    const MAX_MISSING_RANGES: usize = 5;
+
+    if data.data_quality.is_empty() {
+        return writeln!(
+            file,
+            "The data quality of the replayed streams isn't known\n"
+        )
+        .context("failed to write to report file");
+    }
+
+    let unsequenced = data
+        .data_quality
+        .iter()
+        .filter(|quality| !quality.sequenced)
+        .count();
+    let unsequenced = if unsequenced != 0 {
+        format!(
+            "{} of the {} log streams didn't contain sequence numbers, so missing \
+            events can only be detected within the rest of them\n",
+            unsequenced,
+            data.data_quality.len(),
+        )
+    } else {
+        String::new()
+    };
+
+    let sampled = data
+        .data_quality
+        .iter()
+        .filter(|quality| quality.schedule_sample_rate != 1)
+        .map(|quality| {
+            format!(
+                "The {} logs of worker {} only hold one in every {} operator activations, \
+                so their activation counts and durations are undercounted\n",
+                quality.stream, quality.worker, quality.schedule_sample_rate,
+            )
+        })
+        .collect::<String>();
+    let notes = unsequenced + &sampled;
+
+    if !data.data_quality.iter().any(|quality| quality.has_issues()) {
+        return writeln!(
+            file,
+            "No missing, dropped or truncated events were found within the {} log streams\n{}",
+            data.data_quality.len(),
+            notes,
+        )
+        .context("failed to write to report file");
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Stream",
+        "Worker",
+        "Missing Events",
+        "Dropped Events",
+        "Truncated",
+    ]);
+
+    for quality in data
+        .data_quality
+        .iter()
+        .filter(|quality| quality.has_issues())
+    {
+        let mut missing = quality
+            .missing
+            .iter()
+            .take(MAX_MISSING_RANGES)
+            .map(|range| format!("{}..{}", range.start, range.end))
+            .collect::<Vec<_>>();
+        if quality.missing.len() > MAX_MISSING_RANGES {
+            missing.push("...".to_owned());
+        }
+
+        let missing = if missing.is_empty() {
+            "0".to_owned()
+        } else {
+            format!("{} ({})", quality.missing_events(), missing.join(", "))
+        };
+
+        table.add_row(vec![
+            Cell::new(quality.stream),
+            Cell::new(quality.worker),
+            Cell::new(missing),
+            Cell::new(quality.dropped),
+            Cell::new(if quality.truncated { "yes" } else { "no" }),
+        ]);
+    }
+
+    writeln!(file, "Data Quality\n{}\n{}", table, notes).context("failed to write to report file")
+}
+
 fn worker_stats(args: &Args, data: &DataflowData, file: &mut File) -> Result<()> {
     tracing::debug!("generating worker stats table");
 
@@ -193,6 +314,11 @@ fn operator_stats(
         "Average Activation Time",
         "Max Activation Time",
         "Min Activation Time",
+        "Activation Time Std Dev",
+        "P50 Activation Time",
+        "P90 Activation Time",
+        "P99 Activation Time",
+        "P99.9 Activation Time",
         "Inputs",
         "Outputs",
     ];
@@ -265,6 +391,11 @@ fn operator_stats(
             Cell::new(format!("{:#?}", stats.average)),
             Cell::new(format!("{:#?}", stats.max)),
             Cell::new(format!("{:#?}", stats.min)),
+            Cell::new(format!("{:#?}", stats.std_dev)),
+            Cell::new(format!("{:#?}", stats.percentiles.p50)),
+            Cell::new(format!("{:#?}", stats.percentiles.p90)),
+            Cell::new(format!("{:#?}", stats.percentiles.p99)),
+            Cell::new(format!("{:#?}", stats.percentiles.p999)),
             Cell::new(inputs),
             Cell::new(outputs),
         ];
@@ -417,6 +548,329 @@ fn channel_traffic(data: &DataflowData, file: &mut File) -> Result<()> {
     writeln!(file, "{}", table).context("failed to write to report file")
 }
 
+fn worker_skew(args: &Args, data: &DataflowData, file: &mut File) -> Result<()> {
+    tracing::debug!("generating worker skew table");
+
+    let skews = skew::operator_skew(data);
+    if skews.is_empty() {
+        return writeln!(file, "No operators ran on more than one worker")
+            .context("failed to write to report file");
+    }
+
+    let mut table = Table::new();
+
+    let mut headers = vec![
+        "Name",
+        "Address",
+        "Workers",
+        "Runtime Max/Median",
+        "Runtime CV",
+        "Slowest Worker",
+        "Activations Max/Median",
+        "Activations CV",
+    ];
+    if args.differential_enabled {
+        headers.extend(["Arrangement Max/Median", "Arrangement CV"].iter());
+    }
+    table.set_header(headers);
+
+    let ratio = |skew: &Skew| {
+        skew.max_median_ratio
+            .map_or_else(|| "∞".to_owned(), |ratio| format!("{:.2}x", ratio))
+    };
+    let cv = |skew: &Skew| format!("{:.2}", skew.coefficient_of_variation);
+
+    for skew in skews.iter() {
+        let name = if skew.highlighted {
+            format!("{} (skewed)", skew.name)
+        } else {
+            skew.name.clone()
+        };
+
+        let mut row = vec![
+            Cell::new(name),
+            Cell::new(&skew.addr),
+            Cell::new(skew.workers),
+            Cell::new(ratio(&skew.runtime)),
+            Cell::new(cv(&skew.runtime)),
+            Cell::new(skew.runtime.max_worker),
+            Cell::new(ratio(&skew.activations)),
+            Cell::new(cv(&skew.activations)),
+        ];
+
+        if args.differential_enabled {
+            let (arrangement_ratio, arrangement_cv) = skew
+                .arrangement_size
+                .as_ref()
+                .map_or_else(Default::default, |size| (ratio(size), cv(size)));
+
+            row.extend(vec![
+                Cell::new(arrangement_ratio),
+                Cell::new(arrangement_cv),
+            ]);
+        }
+
+        table.add_row(row);
+    }
+
+    writeln!(
+        file,
+        "Operators Ranked by Worker Skew
+{}",
+        table
+    )
+    .context("failed to write to report file")
+}
+
+fn critical_paths(data: &DataflowData, file: &mut File) -> Result<()> {
+    tracing::debug!("generating critical path tables");
+
+    let paths = critical_path::critical_paths(data);
+    if paths.is_empty() {
+        return writeln!(file, "No timestamps were tracked through any dataflows")
+            .context("failed to write to report file");
+    }
+
+    for (idx, path) in paths.iter().enumerate() {
+        if idx != 0 {
+            writeln!(file)?;
+        }
+
+        let mut table = Table::new();
+        table.set_header(vec![
+            "Name",
+            "Address",
+            "Timestamps on Path",
+            "Latency Added",
+            "Share of Latency",
+            "Busy Time",
+        ]);
+
+        let total_latency: Duration = path
+            .contributors
+            .iter()
+            .map(|contributor| contributor.latency)
+            .sum();
+        for contributor in path.contributors.iter().take(MAX_CONTRIBUTORS) {
+            let share = if total_latency.as_nanos() != 0 {
+                contributor.latency.as_secs_f64() / total_latency.as_secs_f64() * 100.0
+            } else {
+                0.0
+            };
+
+            table.add_row(vec![
+                Cell::new(&contributor.name),
+                Cell::new(&contributor.addr),
+                Cell::new(contributor.timestamps),
+                Cell::new(format!("{:#?}", contributor.latency)),
+                Cell::new(format!("{:.2}%", share)),
+                Cell::new(format!("{:#?}", contributor.busy)),
+            ]);
+        }
+
+        let slowest = path.slowest.as_ref().map_or_else(String::new, |slowest| {
+            format!(
+                " at timestamp {} on worker {}",
+                slowest.timestamp, slowest.worker,
+            )
+        });
+        writeln!(
+            file,
+            "Critical Path of {} ({}): {} timestamps, {:#?} average latency, {:#?} max latency{}\n{}",
+            path.name,
+            path.dataflow,
+            path.timestamps,
+            path.average_latency,
+            path.max_latency,
+            slowest,
+            table,
+        )
+        .context("failed to write to report file")?;
+    }
+
+    Ok(())
+}
+
+fn lints(args: &Args, data: &DataflowData, file: &mut File) -> Result<()> {
+    tracing::debug!("generating lints table");
+
+    let lints = lint::lint(args, data);
+    if lints.is_empty() {
+        return writeln!(file, "No lints were found").context("failed to write to report file");
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Severity",
+        "Lint",
+        "Operator Address",
+        "Workers",
+        "Explanation",
+    ]);
+
+    for lint in lints {
+        let workers = if lint.workers.is_empty() {
+            "All".to_owned()
+        } else {
+            lint.workers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        table.add_row(vec![
+            Cell::new(lint.severity),
+            Cell::new(lint.rule),
+            Cell::new(lint.addr),
+            Cell::new(workers),
+            Cell::new(lint.explanation),
+        ]);
+    }
+
+    writeln!(file, "{}", table).context("failed to write to report file")
+}
+
+/// Writes the operators of a [`TraceDiff`] as tables
+pub(crate) fn trace_diff<W>(diff: &TraceDiff, output: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    tracing::debug!("generating trace diff tables");
+
+    let with_arrangements = diff
+        .operators
+        .iter()
+        .any(|operator| operator.arrangement_size_change.is_some());
+
+    let mut table = Table::new();
+
+    let mut headers = vec![
+        "Name",
+        "Address",
+        "Runtime A",
+        "Runtime B",
+        "Runtime Change",
+        "Activations A",
+        "Activations B",
+        "Activations Change",
+    ];
+    if with_arrangements {
+        headers.extend(
+            [
+                "Arrangement Size A",
+                "Arrangement Size B",
+                "Arrangement Size Change",
+            ]
+            .iter(),
+        );
+    }
+
+    table.set_header(headers);
+
+    for operator in diff.operators.iter() {
+        let runtime_change = Duration::from_nanos(operator.runtime_change_ns.unsigned_abs());
+        let runtime_change = match operator.runtime_change_percent {
+            Some(percent) => format!(
+                "{}{:#?} ({:+.1}%)",
+                sign(operator.runtime_change_ns),
+                runtime_change,
+                percent,
+            ),
+            None => format!("{}{:#?}", sign(operator.runtime_change_ns), runtime_change),
+        };
+
+        let mut row = vec![
+            Cell::new(&operator.name),
+            Cell::new(&operator.addr),
+            Cell::new(format!("{:#?}", operator.a.total_runtime)),
+            Cell::new(format!("{:#?}", operator.b.total_runtime)),
+            Cell::new(runtime_change),
+            Cell::new(operator.a.activations),
+            Cell::new(operator.b.activations),
+            Cell::new(format!("{:+}", operator.activations_change)),
+        ];
+
+        if with_arrangements {
+            let size = |size: Option<usize>| size.map_or_else(String::new, |size| size.to_string());
+
+            row.extend(vec![
+                Cell::new(size(operator.a.arrangement_size)),
+                Cell::new(size(operator.b.arrangement_size)),
+                Cell::new(
+                    operator
+                        .arrangement_size_change
+                        .map_or_else(String::new, |change| format!("{:+}", change)),
+                ),
+            ]);
+        }
+
+        table.add_row(row);
+    }
+
+    writeln!(
+        output,
+        "Operators Ranked by Runtime Change ({} → {})\n{}\n",
+        diff.trace_a.display(),
+        diff.trace_b.display(),
+        table,
+    )
+    .context("failed to write trace diff")?;
+
+    for (title, operators) in [
+        (
+            format!("Operators Only in {}", diff.trace_a.display()),
+            &diff.only_in_a,
+        ),
+        (
+            format!("Operators Only in {}", diff.trace_b.display()),
+            &diff.only_in_b,
+        ),
+    ]
+    .iter()
+    {
+        if operators.is_empty() {
+            continue;
+        }
+
+        let mut table = Table::new();
+        table.set_header(vec![
+            "Name",
+            "Address",
+            "Total Runtime",
+            "Activations",
+            "Max Arrangement Size",
+        ]);
+
+        for operator in operators.iter() {
+            table.add_row(vec![
+                Cell::new(&operator.name),
+                Cell::new(&operator.addr),
+                Cell::new(format!("{:#?}", operator.metrics.total_runtime)),
+                Cell::new(operator.metrics.activations),
+                Cell::new(
+                    operator
+                        .metrics
+                        .arrangement_size
+                        .map_or_else(String::new, |size| size.to_string()),
+                ),
+            ]);
+        }
+
+        writeln!(output, "{}\n{}\n", title, table).context("failed to write trace diff")?;
+    }
+
+    Ok(())
+}
+
+const fn sign(change: i64) -> &'static str {
+    if change < 0 {
+        "-"
+    } else {
+        "+"
+    }
+}
+
 struct Table {
     inner: InnerTable,
 }
@@ -0,0 +1,796 @@
+//! Identifies incoming connections and routes them to the log streams they carry
+//!
+//! Connections made by `ddshow-sink` start with a [`Handshake`] describing the
+//! worker, log stream and encoding of the events that follow it, so they're routed
+//! to their log stream no matter which address they connect to and the number of
+//! workers to wait for comes from the target computation itself. Multiplexed
+//! connections open a channel with a handshake for each of the log streams
+//! they carry, which are routed the same way. Connections that don't start with
+//! a handshake are plain event streams, which are assumed to carry the log stream
+//! of the address they connected to and use the encoding and number of
+//! connections given by ddshow's args
+
+use crate::{
+    archive::StreamKind,
+    args::StreamEncoding,
+    dataflow::constants::{HANDSHAKE_TIMEOUT, TCP_READ_TIMEOUT},
+    demux::{Channel, Demultiplexer},
+};
+use anyhow::{Context, Result};
+use ddshow_sink::{Handshake, HANDSHAKE_HEADER_LEN, HANDSHAKE_MAGIC, MULTIPLEX_MAGIC};
+use indicatif::ProgressBar;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io::{self, Read},
+    net::{SocketAddr, TcpListener, TcpStream},
+    num::NonZeroUsize,
+    path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+    path::PathBuf,
+};
+
+/// A connection that's been accepted but hasn't been identified yet
+#[derive(Debug)]
+pub(crate) struct PendingConnection {
+    socket: TcpStream,
+    peer: SocketAddr,
+    /// The handshake received so far, empty until the
+    /// connection is known to start with a handshake
+    handshake: Vec<u8>,
+    received: usize,
+    accepted: Instant,
+}
+
+/// What a connection turned out to be
+#[derive(Debug)]
+pub(crate) enum Identity {
+    /// The connection started with a handshake
+    Handshake(Handshake),
+    /// The connection carries many log streams, see [`Demultiplexer`]
+    Multiplexed,
+    /// The connection is a plain event stream without a handshake
+    Plain,
+}
+
+impl PendingConnection {
+    /// Puts an accepted socket into non-blocking mode
+    /// so that its handshake can be read incrementally
+    pub(crate) fn new(socket: TcpStream, peer: SocketAddr) -> Result<Self> {
+        socket
+            .set_nonblocking(true)
+            .context("failed to set socket to non-blocking mode")?;
+
+        if let Err(err) = socket.set_read_timeout(TCP_READ_TIMEOUT) {
+            tracing::error!(
+                "failed to set socket to a read timeout of {:?}: {:?}",
+                TCP_READ_TIMEOUT,
+                err,
+            );
+        };
+
+        Ok(Self {
+            socket,
+            peer,
+            handshake: Vec::new(),
+            received: 0,
+            accepted: Instant::now(),
+        })
+    }
+
+    /// The address of the connection's peer
+    pub(crate) const fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Takes the connection's socket, any handshake it
+    /// sent has already been read from the socket
+    pub(crate) fn into_socket(self) -> TcpStream {
+        self.socket
+    }
+
+    /// Reads whatever is available of the connection's handshake without
+    /// blocking, returning the connection's identity once it's known
+    ///
+    /// Plain event streams are only peeked at, so none of their events are consumed
+    pub(crate) fn poll(&mut self) -> Result<Option<Identity>> {
+        if self.handshake.is_empty() {
+            let mut magic = [0; HANDSHAKE_MAGIC.len()];
+
+            match self.socket.peek(&mut magic) {
+                Ok(0) => anyhow::bail!(
+                    "the connection from {} closed before sending anything",
+                    self.peer,
+                ),
+                Ok(len) => {
+                    let peeked = &magic[..len];
+
+                    if peeked == HANDSHAKE_MAGIC {
+                        self.handshake.resize(HANDSHAKE_HEADER_LEN, 0);
+                    } else if peeked == MULTIPLEX_MAGIC {
+                        // The preamble is read along with the connection's frames
+                        return Ok(Some(Identity::Multiplexed));
+                    } else if !HANDSHAKE_MAGIC.starts_with(peeked)
+                        && !MULTIPLEX_MAGIC.starts_with(peeked)
+                    {
+                        return Ok(Some(Identity::Plain));
+                    }
+                }
+
+                Err(err) if is_transient(&err) => {}
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("failed to read from the connection from {}", self.peer)
+                    })
+                }
+            }
+
+            // Plain event streams don't have to send anything right away,
+            // so they're only identified once the handshake times out
+            if self.handshake.is_empty() {
+                let timed_out = self.accepted.elapsed() >= HANDSHAKE_TIMEOUT;
+                return Ok(if timed_out {
+                    Some(Identity::Plain)
+                } else {
+                    None
+                });
+            }
+        }
+
+        while self.received < self.handshake.len() {
+            match self.socket.read(&mut self.handshake[self.received..]) {
+                Ok(0) => anyhow::bail!(
+                    "the connection from {} closed before finishing its handshake",
+                    self.peer,
+                ),
+                Ok(len) => {
+                    self.received += len;
+
+                    // Once the header has arrived we know how long the full handshake is
+                    if self.received == HANDSHAKE_HEADER_LEN {
+                        let header = self.handshake[..HANDSHAKE_HEADER_LEN].try_into().unwrap();
+                        let len = Handshake::encoded_len(header).with_context(|| {
+                            format!("rejected the connection from {}", self.peer)
+                        })?;
+
+                        self.handshake.resize(len, 0);
+                    }
+                }
+
+                Err(err) if is_transient(&err) => break,
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("failed to read the handshake from {}", self.peer)
+                    })
+                }
+            }
+        }
+
+        if self.received == self.handshake.len() {
+            let handshake = Handshake::from_bytes(&self.handshake)
+                .with_context(|| format!("rejected the connection from {}", self.peer))?;
+
+            Ok(Some(Identity::Handshake(handshake)))
+        } else if self.accepted.elapsed() >= HANDSHAKE_TIMEOUT {
+            anyhow::bail!(
+                "the connection from {} didn't finish its handshake within {:?}",
+                self.peer,
+                HANDSHAKE_TIMEOUT,
+            )
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Returns `true` if a non-blocking read failed because there wasn't anything to read yet
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted,
+    )
+}
+
+/// Returns `true` if accepting a connection failed because of the connection
+/// itself rather than the listener, like when the peer hung up right away
+pub(crate) fn is_aborted(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted,
+    )
+}
+
+/// A connection that's been routed to a log stream
+#[derive(Debug)]
+pub(crate) enum Connection {
+    /// A tcp connection carrying a single log stream
+    Socket(TcpStream),
+    /// A single log stream carried by a multiplexed connection
+    Channel(Channel),
+}
+
+impl Connection {
+    /// Takes the connection's socket if it isn't a multiplexed channel
+    pub(crate) fn into_socket(self) -> Option<TcpStream> {
+        match self {
+            Self::Socket(socket) => Some(socket),
+            Self::Channel(_) => None,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Socket(socket) => socket.read(buf),
+            Self::Channel(channel) => channel.read(buf),
+        }
+    }
+}
+
+/// Accepts connections on all of ddshow's addresses and routes them to their log streams
+#[derive(Debug)]
+pub(crate) struct ConnectionRouter {
+    listeners: Vec<Listener>,
+    /// The unix socket that multiplexed connections can be made to
+    #[cfg(unix)]
+    unix_socket: Option<UnixSocket>,
+    pending: Vec<(StreamKind, PendingConnection)>,
+    /// The multiplexed connections that have been received
+    demuxes: Vec<Arc<Demultiplexer>>,
+    /// The connections received for each enabled log stream
+    streams: HashMap<StreamKind, RoutedStream>,
+    /// The encoding of connections that don't send a handshake
+    plain_encoding: StreamEncoding,
+    /// The first handshake that was received, every other
+    /// handshake must come from the same computation
+    target: Option<Handshake>,
+}
+
+#[derive(Debug)]
+struct Listener {
+    stream: StreamKind,
+    address: SocketAddr,
+    listener: TcpListener,
+}
+
+/// A unix socket listener that removes its socket file once it's dropped
+#[cfg(unix)]
+#[derive(Debug)]
+struct UnixSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::warn!(
+                "failed to remove the unix socket {}: {:?}",
+                self.path.display(),
+                err,
+            );
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RoutedStream {
+    encoding: Option<StreamEncoding>,
+    /// The number of workers given by the stream's handshakes
+    peers: Option<usize>,
+    /// The stream's connections along with the worker they came from,
+    /// plain connections are numbered in the order they arrived in
+    connections: Vec<(usize, Connection)>,
+    /// Set once the stream's connections have been handed out
+    claimed: bool,
+}
+
+/// The connections of a single log stream, ordered by worker
+#[derive(Debug)]
+pub(crate) struct RoutedConnections {
+    pub(crate) encoding: StreamEncoding,
+    pub(crate) connections: Vec<(usize, Connection)>,
+}
+
+impl ConnectionRouter {
+    /// Routes connections made to the given listeners and unix socket, each listener's
+    /// log stream is enabled and is the stream that plain connections to its address
+    /// are assumed to carry
+    pub(crate) fn new(
+        listeners: Vec<(StreamKind, TcpListener)>,
+        unix_socket: Option<&Path>,
+        plain_encoding: StreamEncoding,
+    ) -> Result<Self> {
+        let listeners = listeners
+            .into_iter()
+            .map(|(stream, listener)| {
+                let address = listener
+                    .local_addr()
+                    .context("failed to get the listener's address")?;
+                listener
+                    .set_nonblocking(true)
+                    .context("failed to set listener to non-blocking mode")?;
+
+                Ok(Listener {
+                    stream,
+                    address,
+                    listener,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        #[cfg(unix)]
+        let unix_socket = unix_socket.map(bind_unix_socket).transpose()?;
+        #[cfg(not(unix))]
+        if let Some(path) = unix_socket {
+            anyhow::bail!(
+                "can't listen on {}, unix sockets are only supported on unix platforms",
+                path.display(),
+            );
+        }
+
+        let streams = listeners
+            .iter()
+            .map(|listener| (listener.stream, RoutedStream::default()))
+            .collect();
+
+        Ok(Self {
+            listeners,
+            #[cfg(unix)]
+            unix_socket,
+            pending: Vec::new(),
+            demuxes: Vec::new(),
+            streams,
+            plain_encoding,
+            target: None,
+        })
+    }
+
+    /// Takes the listener for the given log stream
+    pub(crate) fn take_listener(&mut self, stream: StreamKind) -> Option<TcpListener> {
+        let idx = self
+            .listeners
+            .iter()
+            .position(|listener| listener.stream == stream)?;
+
+        Some(self.listeners.remove(idx).listener)
+    }
+
+    /// The first handshake that was received, if any
+    pub(crate) const fn target(&self) -> Option<&Handshake> {
+        self.target.as_ref()
+    }
+
+    /// Waits for every worker's connection for the given log stream, expecting the number
+    /// of workers given by their handshakes or `connections` plain connections
+    pub(crate) fn wait_for(
+        &mut self,
+        stream: StreamKind,
+        connections: NonZeroUsize,
+        progress: &ProgressBar,
+    ) -> Result<RoutedConnections> {
+        let mut connected = None;
+
+        loop {
+            self.poll()?;
+
+            let routed = &self.streams[&stream];
+            let expected = routed.peers.unwrap_or_else(|| connections.get());
+            let plural = if expected == 1 { "" } else { "s" };
+
+            if connected != Some((routed.connections.len(), expected)) {
+                connected = Some((routed.connections.len(), expected));
+                tracing::info!(
+                    "{}/{} {} connection{} received",
+                    routed.connections.len(),
+                    expected,
+                    stream,
+                    plural,
+                );
+
+                progress.set_length(expected as u64);
+                progress.set_position(routed.connections.len() as u64);
+                progress.set_message(match routed.peers {
+                    Some(_) => format!(
+                        "{}/{} worker{} connected",
+                        routed.connections.len(),
+                        expected,
+                        plural,
+                    ),
+                    None => format!(
+                        "connected to {}/{} socket{}",
+                        routed.connections.len(),
+                        expected,
+                        plural,
+                    ),
+                });
+            }
+
+            if routed.connections.len() >= expected {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let routed = self
+            .streams
+            .get_mut(&stream)
+            .expect("waited for a stream that isn't enabled");
+        routed.claimed = true;
+
+        let mut connections = routed.connections.drain(..).collect::<Vec<_>>();
+        connections.sort_unstable_by_key(|&(worker, _)| worker);
+
+        Ok(RoutedConnections {
+            encoding: routed.encoding.unwrap_or(self.plain_encoding),
+            connections,
+        })
+    }
+
+    /// Accepts new connections and routes any that have been identified
+    ///
+    /// Connections that fail or are rejected are logged and closed without
+    /// affecting any others, only the listeners failing returns an error
+    fn poll(&mut self) -> Result<()> {
+        for listener in self.listeners.iter() {
+            loop {
+                match listener.listener.accept() {
+                    Ok((socket, peer)) => {
+                        tracing::debug!(
+                            peer = %peer,
+                            "accepted connection from {} on {}",
+                            peer,
+                            listener.address,
+                        );
+
+                        match PendingConnection::new(socket, peer) {
+                            Ok(pending) => self.pending.push((listener.stream, pending)),
+                            Err(err) => tracing::warn!("{:?}", err),
+                        }
+                    }
+
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) if is_aborted(&err) => {
+                        tracing::debug!(
+                            "failed to accept a connection on {}: {:?}",
+                            listener.address,
+                            err,
+                        );
+                    }
+                    Err(err) => return Err(err).context("failed to accept socket connection"),
+                }
+            }
+        }
+
+        // Everything sent over the unix socket is multiplexed
+        #[cfg(unix)]
+        if let Some(unix_socket) = self.unix_socket.as_ref() {
+            loop {
+                match unix_socket.listener.accept() {
+                    Ok((socket, _)) => {
+                        let peer = unix_socket.path.display().to_string();
+                        tracing::debug!("accepted connection on {}", peer);
+
+                        socket
+                            .set_nonblocking(true)
+                            .context("failed to set socket to non-blocking mode")?;
+                        self.demuxes.push(Demultiplexer::new(socket, peer));
+                    }
+
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err).context("failed to accept unix connection"),
+                }
+            }
+        }
+
+        let mut idx = 0;
+        while idx < self.pending.len() {
+            let identity = match self.pending[idx].1.poll() {
+                Ok(Some(identity)) => identity,
+                Ok(None) => {
+                    idx += 1;
+                    continue;
+                }
+
+                Err(err) => {
+                    tracing::warn!("{:?}", err);
+                    self.pending.swap_remove(idx);
+                    continue;
+                }
+            };
+
+            let (listener_stream, pending) = self.pending.swap_remove(idx);
+            let peer = pending.peer().to_string();
+
+            let routed = match identity {
+                Identity::Handshake(handshake) => self.route_handshake(
+                    handshake,
+                    Connection::Socket(pending.into_socket()),
+                    &peer,
+                ),
+
+                Identity::Multiplexed => {
+                    tracing::debug!("{} sent a multiplexed connection", peer);
+                    self.demuxes
+                        .push(Demultiplexer::new(pending.into_socket(), peer));
+
+                    Ok(())
+                }
+
+                Identity::Plain => self.route_plain(listener_stream, pending.into_socket(), &peer),
+            };
+
+            // Rejected connections are closed by dropping them
+            if let Err(err) = routed {
+                tracing::warn!("{:?}", err);
+            }
+        }
+
+        let mut idx = 0;
+        while idx < self.demuxes.len() {
+            let demux = self.demuxes[idx].clone();
+
+            let opened = match demux.poll() {
+                Ok(opened) => opened,
+                Err(err) => {
+                    tracing::warn!("{:?}", err);
+                    self.demuxes.swap_remove(idx);
+                    continue;
+                }
+            };
+
+            for (handshake, channel) in opened {
+                // Rejected channels are closed by dropping them, anything
+                // else that's sent over them is ignored
+                let channel = Connection::Channel(channel);
+                if let Err(err) = self.route_handshake(handshake, channel, demux.peer()) {
+                    tracing::warn!("{:?}", err);
+                }
+            }
+
+            idx += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Routes a connection that started with a handshake to the log stream it carries
+    fn route_handshake(
+        &mut self,
+        handshake: Handshake,
+        connection: Connection,
+        peer: &str,
+    ) -> Result<()> {
+        let stream = StreamKind::from(handshake.stream);
+        let describe = || {
+            format!(
+                "the {} logs of worker {} from {} ({})",
+                stream, handshake.worker, handshake.program, peer,
+            )
+        };
+
+        if let Some(target) = self.target.as_ref() {
+            if handshake.program != target.program {
+                anyhow::bail!(
+                    "rejected {} since ddshow is already receiving logs from {}",
+                    describe(),
+                    target.program,
+                );
+            }
+
+            if handshake.peers != target.peers {
+                anyhow::bail!(
+                    "rejected {}, it reported {} workers while earlier \
+                     connections reported {} workers",
+                    describe(),
+                    handshake.peers,
+                    target.peers,
+                );
+            }
+        }
+
+        if handshake.worker >= handshake.peers {
+            anyhow::bail!(
+                "rejected {}, the worker index is out of range for {} worker{}",
+                describe(),
+                handshake.peers,
+                if handshake.peers == 1 { "" } else { "s" },
+            );
+        }
+
+        let encoding = StreamEncoding::from(handshake.encoding);
+        if matches!(connection, Connection::Channel(_)) && encoding != StreamEncoding::Rkyv {
+            anyhow::bail!(
+                "rejected {} since they're {} encoded, multiplexed \
+                 streams must be rkyv encoded",
+                describe(),
+                encoding,
+            );
+        }
+
+        let routed = match self.streams.get(&stream) {
+            Some(routed) => routed,
+            None => anyhow::bail!(
+                "rejected {} since {} logs aren't enabled, enable them with `--{}`",
+                describe(),
+                stream,
+                stream,
+            ),
+        };
+
+        if routed
+            .connections
+            .iter()
+            .any(|&(worker, _)| worker == handshake.worker)
+        {
+            anyhow::bail!(
+                "rejected {} since that worker is already connected, use \
+                 `--reconnect` to accept workers that reconnect",
+                describe(),
+            );
+        }
+
+        if routed.peers.is_none() && !routed.connections.is_empty() {
+            anyhow::bail!(
+                "rejected {} since earlier {} connections didn't send a \
+                 handshake, plain connections and connections from \
+                 ddshow-sink can't be mixed",
+                describe(),
+                stream,
+            );
+        }
+
+        self.add_connection(
+            stream,
+            encoding,
+            handshake.worker,
+            Some(handshake.peers),
+            connection,
+            peer,
+        )?;
+
+        // The target is only known once one of its connections is accepted,
+        // a rejected connection mustn't decide what later ones are checked against
+        if self.target.is_none() {
+            tracing::info!(
+                program = %handshake.program,
+                peers = handshake.peers,
+                started_at = ?handshake.started_at,
+                "receiving logs from {} with {} worker{}",
+                handshake.program,
+                handshake.peers,
+                if handshake.peers == 1 { "" } else { "s" },
+            );
+
+            self.target = Some(handshake);
+        }
+
+        Ok(())
+    }
+
+    /// Routes a connection without a handshake to the log stream of the address it connected to
+    fn route_plain(
+        &mut self,
+        listener_stream: StreamKind,
+        socket: TcpStream,
+        peer: &str,
+    ) -> Result<()> {
+        let routed = &self.streams[&listener_stream];
+        if routed.peers.is_some() {
+            anyhow::bail!(
+                "rejected a {} connection from {} that didn't send a handshake \
+                 since earlier {} connections did, plain connections and \
+                 connections from ddshow-sink can't be mixed",
+                listener_stream,
+                peer,
+                listener_stream,
+            );
+        }
+
+        let worker = routed.connections.len();
+        self.add_connection(
+            listener_stream,
+            self.plain_encoding,
+            worker,
+            None,
+            Connection::Socket(socket),
+            peer,
+        )
+    }
+
+    fn add_connection(
+        &mut self,
+        stream: StreamKind,
+        encoding: StreamEncoding,
+        worker: usize,
+        peers: Option<usize>,
+        connection: Connection,
+        peer: &str,
+    ) -> Result<()> {
+        let routed = self
+            .streams
+            .get_mut(&stream)
+            .expect("connections are only routed to enabled streams");
+
+        if routed.claimed {
+            tracing::warn!(
+                peer = %peer,
+                "ignoring a {} connection from {} that arrived after all {} connections were received",
+                stream,
+                peer,
+                stream,
+            );
+
+            return Ok(());
+        }
+
+        match routed.encoding {
+            Some(expected) if expected != encoding => anyhow::bail!(
+                "rejected {} encoded {} logs from worker {} ({}) since the stream's \
+                 other connections are {} encoded",
+                encoding,
+                stream,
+                worker,
+                peer,
+                expected,
+            ),
+            _ => routed.encoding = Some(encoding),
+        }
+
+        tracing::info!(
+            peer = %peer,
+            worker = worker,
+            "routed {} logs from worker {} ({})",
+            stream,
+            worker,
+            peer,
+        );
+
+        routed.peers = peers;
+        routed.connections.push((worker, connection));
+
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionRouter {
+    fn drop(&mut self) {
+        // Multiplexed connections outlive the router, but
+        // nothing's left to route the channels they open
+        for demux in self.demuxes.iter() {
+            demux.close_to_new_channels();
+        }
+    }
+}
+
+/// Binds to a unix socket, replacing any stale socket that was left behind
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path) -> Result<UnixSocket> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove the old unix socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to bind to unix socket {}", path.display()))?;
+    listener
+        .set_nonblocking(true)
+        .context("failed to set listener to non-blocking mode")?;
+
+    Ok(UnixSocket {
+        path: path.to_owned(),
+        listener,
+    })
+}
@@ -0,0 +1,236 @@
+//! A small embedded http server for viewing the graph and dashboard while the
+//! target computation is still running
+//!
+//! Alongside the usual graph files the server exposes `/data.json`, which
+//! the graph polls so that operator timings update live, and `/metrics`
+//! which serves the dataflow's stats for Prometheus to scrape
+
+use crate::{
+    args::Args,
+    dataflow::DataflowData,
+    metrics::{self, OPENMETRICS_CONTENT_TYPE},
+    ui::{self, GraphData},
+    vega,
+};
+use anyhow::{Context, Result};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+/// How often the served data is refreshed from the running dataflow
+pub(crate) const SERVER_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait on a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest request (including headers) that will be read from a client
+const MAX_REQUEST_LEN: u64 = 16 * 1024;
+
+/// A handle to the http server started by `--serve`
+///
+/// The server runs on a background thread for the rest of the process' lifetime,
+/// serving whatever data was most recently given to [`Server::publish()`]
+#[derive(Debug, Clone)]
+pub struct Server {
+    addr: SocketAddr,
+    data: Arc<Mutex<Arc<DataflowData>>>,
+}
+
+impl Server {
+    /// Binds the server to the given address and starts serving requests
+    pub fn start(args: Arc<Args>, addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("failed to bind the http server to {}", addr))?;
+        let addr = listener
+            .local_addr()
+            .context("failed to get the http server's address")?;
+
+        let data = Arc::new(Mutex::new(Arc::new(DataflowData::default())));
+
+        let server_data = data.clone();
+        thread::Builder::new()
+            .name("ddshow-http-server".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            tracing::warn!("failed to accept http connection: {:?}", err);
+                            continue;
+                        }
+                    };
+
+                    let (args, data) = (args.clone(), server_data.clone());
+                    thread::spawn(move || {
+                        if let Err(err) = handle_connection(stream, &args, &data) {
+                            tracing::debug!("failed to respond to http request: {:?}", err);
+                        }
+                    });
+                }
+            })
+            .context("failed to spawn the http server thread")?;
+
+        tracing::info!("serving the dataflow graph at http://{}", addr);
+
+        Ok(Self { addr, data })
+    }
+
+    /// The address the server is listening on
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Replaces the served data
+    pub fn publish(&self, data: DataflowData) {
+        *self.data.lock().unwrap_or_else(PoisonError::into_inner) = Arc::new(data);
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    args: &Args,
+    data: &Mutex<Arc<DataflowData>>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_LEN));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip over the request's headers, we don't use any of them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? != 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut request = request_line.split_whitespace();
+    let (method, target) = (request.next().unwrap_or(""), request.next().unwrap_or("/"));
+    let path = target.split('?').next().unwrap_or(target);
+    tracing::debug!(method = method, path = path, "got http request");
+
+    let response = match method {
+        "GET" | "HEAD" => {
+            let data = data.lock().unwrap_or_else(PoisonError::into_inner).clone();
+
+            route(path, args, &data).unwrap_or_else(|err| {
+                tracing::error!("failed to render {}: {:?}", path, err);
+                Response::error(500, "Internal Server Error")
+            })
+        }
+
+        _ => Response::error(405, "Method Not Allowed"),
+    };
+
+    response.write_to(&mut &stream, method == "HEAD")
+}
+
+fn route(path: &str, args: &Args, data: &DataflowData) -> Result<Response> {
+    let file = match path.trim_start_matches('/') {
+        "" => "graph.html",
+        file => file,
+    };
+
+    let response = match file {
+        "graph.js" => {
+            let graph_data = GraphData::new(args, data);
+            let graph_js = ui::render_graph_js(&graph_data, Some(SERVER_UPDATE_INTERVAL))?;
+
+            Response::ok(file, graph_js.into_bytes())
+        }
+
+        "dashboard.html" => {
+            let mut dashboard = Vec::new();
+            vega::render_dashboard(data, &mut dashboard)?;
+
+            Response::ok(file, dashboard)
+        }
+
+        "data.json" => {
+            let graph_data = GraphData::new(args, data);
+            let json = serde_json::to_vec(&graph_data).context("failed to serialize graph data")?;
+
+            Response::ok(file, json)
+        }
+
+        "metrics" => {
+            let metrics = metrics::render_metrics(data);
+
+            Response::new(OPENMETRICS_CONTENT_TYPE, metrics.into_bytes())
+        }
+
+        file => match ui::STATIC_FILES.iter().find(|&&(name, _)| name == file) {
+            Some(&(name, contents)) => Response::ok(name, contents.as_bytes().to_owned()),
+            None => Response::error(404, "Not Found"),
+        },
+    };
+
+    Ok(response)
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(file: &str, body: Vec<u8>) -> Self {
+        let content_type = match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html; charset=utf-8",
+            Some("css") => "text/css; charset=utf-8",
+            Some("js") => "application/javascript; charset=utf-8",
+            Some("json") => "application/json",
+            _ => "application/octet-stream",
+        };
+
+        Self::new(content_type, body)
+    }
+
+    const fn new(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body: reason.as_bytes().to_owned(),
+        }
+    }
+
+    fn write_to<W>(&self, writer: &mut W, head_only: bool) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-cache\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len(),
+        )?;
+
+        if !head_only {
+            writer.write_all(&self.body)?;
+        }
+
+        writer.flush()
+    }
+}
@@ -0,0 +1,292 @@
+//! Measures how unevenly each operator's work is spread across workers
+//!
+//! Operators are matched across workers by the worker consistency check, and
+//! for each one the max/median ratio and coefficient of variation of its total
+//! runtime, activations and arrangement size are calculated. Data skew on
+//! exchanges shows up as a handful of operators where one worker does most of
+//! the work while the others sit idle
+
+use crate::dataflow::{DataflowData, OperatorStats};
+use abomonation_derive::Abomonation;
+use bytecheck::CheckBytes;
+use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+/// The runtime coefficient of variation that an operator has to
+/// reach before it's highlighted as skewed
+const SKEW_THRESHOLD: f64 = 0.5;
+
+/// The most operators that will be highlighted as skewed
+const MAX_HIGHLIGHTED: usize = 5;
+
+/// The skew of a single operator across all of the workers it ran on
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct OperatorSkew {
+    /// The operator's id on the reference worker
+    pub id: OperatorId,
+    pub addr: OperatorAddr,
+    pub name: String,
+    /// The number of workers the operator ran on
+    pub workers: usize,
+    pub runtime: Skew,
+    pub activations: Skew,
+    /// `None` if the operator doesn't maintain an arrangement
+    pub arrangement_size: Option<Skew>,
+    /// Whether the operator is one of the most skewed operators in the program
+    pub highlighted: bool,
+}
+
+/// The spread of a single metric across workers
+#[derive(
+    Debug, Clone, Copy, Deserialize, Serialize, Abomonation, Archive, RkyvSerialize, RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct Skew {
+    /// The worker with the largest value
+    pub max_worker: WorkerId,
+    /// The largest value divided by the median value, `None` if the
+    /// median is zero while the largest value isn't
+    pub max_median_ratio: Option<f64>,
+    /// The standard deviation divided by the mean, zero when every value is zero
+    pub coefficient_of_variation: f64,
+}
+
+impl Skew {
+    /// Measures the skew of the per-worker values, there must be at least one value
+    fn new(values: &[(WorkerId, f64)]) -> Self {
+        let max_worker = values
+            .iter()
+            .copied()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(worker, _)| worker)
+            .expect("there must be at least one value to measure skew");
+
+        let mut sorted: Vec<_> = values.iter().map(|&(_, value)| value).collect();
+        sorted.sort_by(f64::total_cmp);
+
+        let len = sorted.len();
+        let max = sorted[len - 1];
+        let median = if len % 2 == 0 {
+            (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0
+        } else {
+            sorted[len / 2]
+        };
+
+        let max_median_ratio = if median != 0.0 {
+            Some(max / median)
+        } else if max == 0.0 {
+            Some(1.0)
+        } else {
+            None
+        };
+
+        let mean = sorted.iter().sum::<f64>() / len as f64;
+        let coefficient_of_variation = if mean != 0.0 {
+            let variance = sorted
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f64>()
+                / len as f64;
+
+            variance.sqrt() / mean
+        } else {
+            0.0
+        };
+
+        Self {
+            max_worker,
+            max_median_ratio,
+            coefficient_of_variation,
+        }
+    }
+
+    /// Skews are never negative or NaN, so comparing the floats' bits
+    /// gives the same order as comparing the floats themselves
+    fn key(&self) -> (WorkerId, Option<u64>, u64) {
+        (
+            self.max_worker,
+            self.max_median_ratio.map(f64::to_bits),
+            self.coefficient_of_variation.to_bits(),
+        )
+    }
+}
+
+impl PartialEq for Skew {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Skew {}
+
+impl PartialOrd for Skew {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Skew {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for Skew {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Measures the skew of every operator that ran on more than one worker,
+/// the operators with the most skewed runtimes come first
+pub fn operator_skew(data: &DataflowData) -> Vec<OperatorSkew> {
+    let leaves: HashSet<_> = data
+        .nodes
+        .iter()
+        .map(|((worker, _), event)| (*worker, event.id))
+        .collect();
+    let matching: HashMap<_, _> = data.operator_matching.iter().copied().collect();
+
+    // Group each worker's stats under the reference worker's operator
+    let mut operators: BTreeMap<OperatorId, Vec<(WorkerId, &OperatorStats)>> = BTreeMap::new();
+    for ((worker, operator), stats) in data.operator_stats.iter() {
+        if !leaves.contains(&(*worker, *operator)) {
+            continue;
+        }
+
+        if let Some(&reference) = matching.get(&(*worker, *operator)) {
+            operators
+                .entry(reference)
+                .or_default()
+                .push((*worker, stats));
+        }
+    }
+
+    let names: HashMap<_, _> = data.name_lookup.iter().cloned().collect();
+    let addrs: HashMap<_, _> = data.addr_lookup.iter().cloned().collect();
+
+    let mut skews: Vec<_> = operators
+        .into_iter()
+        .filter(|(_, workers)| workers.len() > 1)
+        .map(|(id, mut workers)| {
+            workers.sort_unstable_by_key(|&(worker, _)| worker);
+
+            // Matched operators have the same address and name on every worker
+            let (first_worker, first_operator) = (workers[0].0, workers[0].1.id);
+            let addr = addrs
+                .get(&(first_worker, first_operator))
+                .cloned()
+                .unwrap_or_else(|| OperatorAddr::from_elem(id));
+            let name = names
+                .get(&(first_worker, first_operator))
+                .cloned()
+                .unwrap_or_default();
+
+            let measure = |metric: fn(&OperatorStats) -> f64| {
+                let values: Vec<_> = workers
+                    .iter()
+                    .map(|&(worker, stats)| (worker, metric(stats)))
+                    .collect();
+
+                Skew::new(&values)
+            };
+
+            let arrangement_sizes: Vec<_> = workers
+                .iter()
+                .filter_map(|&(worker, stats)| {
+                    let arrangement = stats.arrangement_size.as_ref()?;
+                    Some((worker, arrangement.max_size as f64))
+                })
+                .collect();
+
+            OperatorSkew {
+                id,
+                addr,
+                name,
+                workers: workers.len(),
+                runtime: measure(|stats| stats.total.as_nanos() as f64),
+                activations: measure(|stats| stats.activations as f64),
+                arrangement_size: if arrangement_sizes.is_empty() {
+                    None
+                } else {
+                    Some(Skew::new(&arrangement_sizes))
+                },
+                highlighted: false,
+            }
+        })
+        .collect();
+
+    skews.sort_by(|a, b| {
+        b.runtime
+            .coefficient_of_variation
+            .total_cmp(&a.runtime.coefficient_of_variation)
+            .then_with(|| a.addr.cmp(&b.addr))
+    });
+
+    for skew in skews
+        .iter_mut()
+        .take(MAX_HIGHLIGHTED)
+        .filter(|skew| skew.runtime.coefficient_of_variation >= SKEW_THRESHOLD)
+    {
+        skew.highlighted = true;
+    }
+
+    skews
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Skew;
+    use ddshow_types::WorkerId;
+
+    #[test]
+    fn skew_of_uneven_workers() {
+        let skew = Skew::new(&[
+            (WorkerId::new(0), 1.0),
+            (WorkerId::new(1), 4.0),
+            (WorkerId::new(2), 1.0),
+        ]);
+
+        assert_eq!(skew.max_worker, WorkerId::new(1));
+        assert_eq!(skew.max_median_ratio, Some(4.0));
+        assert!((skew.coefficient_of_variation - 2f64.sqrt() / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skew_of_idle_workers() {
+        let idle = Skew::new(&[(WorkerId::new(0), 0.0), (WorkerId::new(1), 0.0)]);
+        assert_eq!(idle.max_median_ratio, Some(1.0));
+        assert_eq!(idle.coefficient_of_variation, 0.0);
+
+        // A single worker doing all of the work has an unbounded max/median ratio
+        let lopsided = Skew::new(&[
+            (WorkerId::new(0), 0.0),
+            (WorkerId::new(1), 0.0),
+            (WorkerId::new(2), 9.0),
+        ]);
+        assert_eq!(lopsided.max_worker, WorkerId::new(2));
+        assert_eq!(lopsided.max_median_ratio, None);
+        assert!((lopsided.coefficient_of_variation - 2f64.sqrt()).abs() < 1e-9);
+    }
+}
@@ -9,14 +9,34 @@
  *     min_activation_time: string;
  *     average_activation_time: string;
  *     total_activation_time: string;
+ *     std_dev_activation_time: string;
+ *     p50_activation_time: string;
+ *     p90_activation_time: string;
+ *     p99_activation_time: string;
+ *     p999_activation_time: string;
  *     invocations: number;
  *     fill_color: string;
  *     text_color: string;
  *     activation_durations: ActivationDuration[];
  *     max_arrangement_size: number | null;
  *     min_arrangement_size: number | null;
+ *     skew: OperatorSkew | null;
  * }} RawNode
  *
+ * @typedef {{
+ *     workers: number;
+ *     runtime: Skew;
+ *     activations: Skew;
+ *     arrangement_size: Skew | null;
+ *     highlighted: boolean;
+ * }} OperatorSkew
+ *
+ * @typedef {{
+ *     max_worker: number;
+ *     max_median_ratio: number | null;
+ *     coefficient_of_variation: number;
+ * }} Skew
+ *
  * @typedef {{ activation_time: number, activated_at: number }} ActivationDuration
  * 
  * @typedef {{
@@ -77,29 +97,46 @@
  *     input_messages: [number, [number, number]][];
  *     output_messages: [number, [number, number]][];
  * }} OperatorProgress
+ *
+ * @typedef {{
+ *     nodes: RawNode[];
+ *     subgraphs: Subgraph[];
+ *     edges: Edge[];
+ *     palette_colors: string[];
+ *     timeline_events: TimelineEvent[];
+ *     operator_shapes: OperatorShape[];
+ *     operator_progress: OperatorProgress[];
+ * }} GraphData
  * #}
  */
 
 /** @type {RawNode[]} */
-const raw_nodes = {{ nodes | json_encode() }};
+let raw_nodes = {{ nodes | json_encode() }};
 
 /** @type {Subgraph[]} */
-const raw_subgraphs = {{ subgraphs | json_encode() }};
+let raw_subgraphs = {{ subgraphs | json_encode() }};
 
 /** @type {Edge[]} */
-const raw_edges = {{ edges | json_encode() }};
+let raw_edges = {{ edges | json_encode() }};
 
 /** @type {string[]} */
 const palette_colors = {{ palette_colors | json_encode() }};
 
 /** @type {TimelineEvent[]} */
-const timeline_events = {{ timeline_events | json_encode() }};
+let timeline_events = {{ timeline_events | json_encode() }};
 
 /** @type {OperatorShape[]} */
-const operator_shapes = {{ operator_shapes | json_encode() }};
+let operator_shapes = {{ operator_shapes | json_encode() }};
 
 /** @type {OperatorProgress[]} */
-const operator_progress = {{ operator_progress | json_encode() }};
+let operator_progress = {{ operator_progress | json_encode() }};
+
+/**
+ * The interval (in milliseconds) to poll `./data.json` at, set when
+ * the graph is being served live by `ddshow --serve`
+ * @type {number | null}
+ */
+const live_update_interval = {{ live_update_interval | json_encode() }};
 
 const dataflow_svg = d3.select("#dataflow-graph");
 const svg = dataflow_svg.append("g");
@@ -148,96 +185,109 @@ const create_error_node = target_addr => {
 
 const slash_regexp = new RegExp("\\\\", "g");
 
-for (const subgraph of raw_subgraphs) {
-    worker_ids.add(subgraph.worker);
-    operator_addrs.add(subgraph.addr);
-
-    const subgraph_name = subgraph.name;
-    operator_names.set(subgraph.id, subgraph_name);
+/**
+ * Adds all subgraphs, nodes and edges to the graph, updating any that already exist
+ */
+function build_graph() {
+    for (const subgraph of raw_subgraphs) {
+        worker_ids.add(subgraph.worker);
+        operator_addrs.add(subgraph.addr);
+
+        const subgraph_name = subgraph.name;
+        operator_names.set(subgraph.id, subgraph_name);
+
+        const subgraph_id = format_addr(subgraph.addr);
+        graph.setNode(
+            subgraph_id,
+            {
+                label: `${subgraph_name.replace(slash_regexp, "\\\\")} @ ${subgraph.id}, ${subgraph_id}`,
+                style: "fill: #EEEEEE; stroke-dasharray: 5, 2;",
+                clusterLabelPos: "top",
+                data: { kind: "Subgraph", ...subgraph },
+            },
+        );
 
-    const subgraph_id = format_addr(subgraph.addr);
-    graph.setNode(
-        subgraph_id,
-        {
-            label: `${subgraph_name.replace(slash_regexp, "\\\\")} @ ${subgraph.id}, ${subgraph_id}`,
-            style: "fill: #EEEEEE; stroke-dasharray: 5, 2;",
-            clusterLabelPos: "top",
-            data: { kind: "Subgraph", ...subgraph },
-        },
-    );
+        if (subgraph.addr.length > 1) {
+            const parent_addr = format_addr(subgraph.addr.slice(0, subgraph.addr.length - 1));
+            if (!node_id_exists(parent_addr)) {
+                create_error_node(parent_addr);
+            }
 
-    if (subgraph.addr.length > 1) {
-        const parent_addr = format_addr(subgraph.addr.slice(0, subgraph.addr.length - 1));
-        if (!node_id_exists(parent_addr)) {
-            create_error_node(parent_addr);
+            graph.setParent(subgraph_id, parent_addr);
         }
-
-        graph.setParent(subgraph_id, parent_addr);
     }
-}
 
-for (const node of raw_nodes) {
-    worker_ids.add(node.worker);
-    operator_addrs.add(node.addr);
+    for (const node of raw_nodes) {
+        worker_ids.add(node.worker);
+        operator_addrs.add(node.addr);
 
-    const node_name = node.name;
-    operator_names.set(node.id, node_name);
+        const node_name = node.name;
+        operator_names.set(node.id, node_name);
 
-    const node_id = format_addr(node.addr);
-    graph.setNode(
-        node_id,
-        {
-            label: `${node_name.replace(slash_regexp, "\\\\")} @ ${node.id}, ${node_id}`,
-            style: `fill: ${node.fill_color}`,
-            labelStyle: `fill: ${node.text_color}`,
-            data: { kind: "Node", ...node },
-        },
-    );
+        // Outline the operators with the most skewed runtimes across workers
+        let style = `fill: ${node.fill_color}`;
+        if (node.skew !== null && node.skew.highlighted) {
+            style += "; stroke: #e03131; stroke-width: 4px";
+        }
+
+        const node_id = format_addr(node.addr);
+        graph.setNode(
+            node_id,
+            {
+                label: `${node_name.replace(slash_regexp, "\\\\")} @ ${node.id}, ${node_id}`,
+                style: style,
+                labelStyle: `fill: ${node.text_color}`,
+                data: { kind: "Node", ...node },
+            },
+        );
 
-    const parent_addr = format_addr(node.addr.slice(0, node.addr.length - 1));
-    if (!node_id_exists(parent_addr)) {
-        create_error_node(parent_addr);
+        const parent_addr = format_addr(node.addr.slice(0, node.addr.length - 1));
+        if (!node_id_exists(parent_addr)) {
+            create_error_node(parent_addr);
+        }
+
+        graph.setParent(node_id, parent_addr);
     }
 
-    graph.setParent(node_id, parent_addr);
-}
+    for (const edge of raw_edges) {
+        let style = "";
+        switch (edge.edge_kind) {
+            case "Crossing":
+                // Blue
+                style = "stroke: #5d5de6; stroke-dasharray: 5, 2; fill: none;"
+                break;
 
-for (const edge of raw_edges) {
-    let style = "";
-    switch (edge.edge_kind) {
-        case "Crossing":
-            // Blue
-            style = "stroke: #5d5de6; stroke-dasharray: 5, 2; fill: none;"
-            break;
+            case "Normal":
+                break;
 
-        case "Normal":
-            break;
+            default:
+                console.error(`invalid edge kind received: ${edge.edge_kind}"`);
+                break;
+        }
 
-        default:
-            console.error(`invalid edge kind received: ${edge.edge_kind}"`);
-            break;
-    }
+        const src_id = format_addr(edge.src);
+        const dest_id = format_addr(edge.dest);
 
-    const src_id = format_addr(edge.src);
-    const dest_id = format_addr(edge.dest);
+        if (!node_id_exists(src_id)) {
+            create_error_node(src_id);
+        }
+        if (!node_id_exists(dest_id)) {
+            create_error_node(dest_id);
+        }
 
-    if (!node_id_exists(src_id)) {
-        create_error_node(src_id);
-    }
-    if (!node_id_exists(dest_id)) {
-        create_error_node(dest_id);
+        graph.setEdge(
+            src_id,
+            dest_id,
+            {
+                style: style,
+                data: { kind: "Edge", ...edge },
+            },
+        );
     }
-
-    graph.setEdge(
-        src_id,
-        dest_id,
-        {
-            style: style,
-            data: { kind: "Edge", ...edge },
-        },
-    );
 }
 
+build_graph();
+
 // Render the graph
 render(svg, graph);
 
@@ -246,131 +296,156 @@ const tooltip = d3.select("#dataflow-graph-div")
     .append("div")
     .attr("id", "tooltip-template");
 
-// Node tooltips
-svg.selectAll("g.node")
-    // Reveal the tooltip on hover
-    .on("mouseover", () => tooltip.style("visibility", "visible"))
-    .on("mousemove", node_id => {
-        const unsafe_node = graph.node(node_id);
-        if (!unsafe_node || !unsafe_node.data || !unsafe_node.data.name || unsafe_node.data.kind === "Error") {
-            tooltip.style("visibility", "hidden");
-            return;
-        }
+/**
+ * Attaches tooltips to all of the graph's nodes and edges
+ */
+function attach_tooltips() {
+    // Node tooltips
+    svg.selectAll("g.node")
+        // Reveal the tooltip on hover
+        .on("mouseover", () => tooltip.style("visibility", "visible"))
+        .on("mousemove", node_id => {
+            const unsafe_node = graph.node(node_id);
+            if (!unsafe_node || !unsafe_node.data || !unsafe_node.data.name || unsafe_node.data.kind === "Error") {
+                tooltip.style("visibility", "hidden");
+                return;
+            }
 
-        const node = unsafe_node.data;
-        let html = `ran for ${node.total_activation_time} over ${node.invocations} invocations<br>\
-            average runtime of ${node.average_activation_time} \
-            (max: ${node.max_activation_time}, min: ${node.min_activation_time})`;
-
-        if (node.kind === "Node"
-            && node.max_arrangement_size !== null
-            && node.min_arrangement_size !== null
-        ) {
-            html += `<br>max arrangement size: ${node.max_arrangement_size}, \
-                min arrangement size: ${node.min_arrangement_size}`;
-        }
+            const node = unsafe_node.data;
+            let html = `ran for ${node.total_activation_time} over ${node.invocations} invocations<br>\
+                average runtime of ${node.average_activation_time} \
+                (max: ${node.max_activation_time}, min: ${node.min_activation_time})`;
+
+            if (node.kind === "Node") {
+                html += `<br>p50: ${node.p50_activation_time}, p90: ${node.p90_activation_time}, \
+                    p99: ${node.p99_activation_time}, p99.9: ${node.p999_activation_time} \
+                    (std dev: ${node.std_dev_activation_time})`;
+            }
+
+            if (node.kind === "Node"
+                && node.max_arrangement_size !== null
+                && node.min_arrangement_size !== null
+            ) {
+                html += `<br>max arrangement size: ${node.max_arrangement_size}, \
+                    min arrangement size: ${node.min_arrangement_size}`;
+            }
+
+            if (node.kind === "Node" && node.skew !== null) {
+                const runtime = node.skew.runtime;
+                const ratio = runtime.max_median_ratio === null
+                    ? "∞"
+                    : `${runtime.max_median_ratio.toFixed(2)}x`;
+
+                html += `<br>${node.skew.highlighted ? "<b>skewed</b> " : ""}runtime across \
+                    ${node.skew.workers} workers: max/median ${ratio}, \
+                    cv ${runtime.coefficient_of_variation.toFixed(2)}, \
+                    slowest on worker ${runtime.max_worker}`;
+            }
 
-        let operator_inputs = [];
-        let operator_outputs = [];
-        for (const progress of operator_progress) {
-            if (progress.operator === node.id) {
-                for (const [input_port, [messages, _channel]] of Object.entries(progress.input_messages)) {
-                    if (messages !== 0) {
-                        operator_inputs.push([input_port, messages]);
+            let operator_inputs = [];
+            let operator_outputs = [];
+            for (const progress of operator_progress) {
+                if (progress.operator === node.id) {
+                    for (const [input_port, [messages, _channel]] of Object.entries(progress.input_messages)) {
+                        if (messages !== 0) {
+                            operator_inputs.push([input_port, messages]);
+                        }
                     }
-                }
 
-                for (const [output_port, [messages, _channel]] of Object.entries(progress.output_messages)) {
-                    if (messages !== 0) {
-                        operator_outputs.push([output_port, messages]);
+                    for (const [output_port, [messages, _channel]] of Object.entries(progress.output_messages)) {
+                        if (messages !== 0) {
+                            operator_outputs.push([output_port, messages]);
+                        }
                     }
                 }
             }
-        }
 
-        operator_inputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
-        operator_outputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
+            operator_inputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
+            operator_outputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
 
-        for (const [port, messages] of operator_inputs) {
-            html += `<br>Consumed ${messages} messages at port ${port}`;
-        }
-        for (const [port, messages] of operator_outputs) {
-            html += `<br>Produced ${messages} messages at port ${port}`;
-        }
+            for (const [port, messages] of operator_inputs) {
+                html += `<br>Consumed ${messages} messages at port ${port}`;
+            }
+            for (const [port, messages] of operator_outputs) {
+                html += `<br>Produced ${messages} messages at port ${port}`;
+            }
 
-        tooltip
-            .html(html)
-            .style("top", (d3.event.pageY - 40) + "px")
-            .style("left", (d3.event.pageX + 40) + "px");
-    })
-    // Hide the tooltip on mouseout
-    .on("mouseout", () => tooltip.style("visibility", "hidden"));
-
-// Edge tooltips
-svg.selectAll("g.edgePath")
-    // Reveal the tooltip on hover
-    .on("mouseover", () => tooltip.style("visibility", "visible"))
-    .on("mousemove", edge_id => {
-        const unsafe_edge = graph.edge(edge_id);
-        if (!unsafe_edge || !unsafe_edge.data || !unsafe_edge.data.kind || unsafe_edge.data.kind === "Error") {
-            tooltip.style("visibility", "hidden");
-            return;
-        }
+            tooltip
+                .html(html)
+                .style("top", (d3.event.pageY - 40) + "px")
+                .style("left", (d3.event.pageX + 40) + "px");
+        })
+        // Hide the tooltip on mouseout
+        .on("mouseout", () => tooltip.style("visibility", "hidden"));
+
+    // Edge tooltips
+    svg.selectAll("g.edgePath")
+        // Reveal the tooltip on hover
+        .on("mouseover", () => tooltip.style("visibility", "visible"))
+        .on("mousemove", edge_id => {
+            const unsafe_edge = graph.edge(edge_id);
+            if (!unsafe_edge || !unsafe_edge.data || !unsafe_edge.data.kind || unsafe_edge.data.kind === "Error") {
+                tooltip.style("visibility", "hidden");
+                return;
+            }
 
-        /** @type Edge */
-        const edge = unsafe_edge.data;
+            /** @type Edge */
+            const edge = unsafe_edge.data;
 
-        const get_node_name = node_addr => {
-            const node = graph.node(format_addr(node_addr));
+            const get_node_name = node_addr => {
+                const node = graph.node(format_addr(node_addr));
 
-            let node_name = "";
-            if (!node || !node.data || !node.data.name || !node.data.kind || node.data.kind === "Error") {
-                node_name = "Error";
-            } else {
-                node_name = node.data.name;
-            }
+                let node_name = "";
+                if (!node || !node.data || !node.data.name || !node.data.kind || node.data.kind === "Error") {
+                    node_name = "Error";
+                } else {
+                    node_name = node.data.name;
+                }
 
-            return node_name;
-        };
+                return node_name;
+            };
 
-        const src_name = get_node_name(edge.src);
-        const dest_name = get_node_name(edge.dest);
+            const src_name = get_node_name(edge.src);
+            const dest_name = get_node_name(edge.dest);
 
-        let html = `channel from ${src_name} to ${dest_name}`;
+            let html = `channel from ${src_name} to ${dest_name}`;
 
-        let channel_inputs = [];
-        let channel_outputs = [];
-        for (const progress of operator_progress) {
-            for (const [input_port, [messages, channel]] of Object.entries(progress.input_messages)) {
-                if (channel === edge.channel_id) {
-                    channel_inputs.push([input_port, messages]);
+            let channel_inputs = [];
+            let channel_outputs = [];
+            for (const progress of operator_progress) {
+                for (const [input_port, [messages, channel]] of Object.entries(progress.input_messages)) {
+                    if (channel === edge.channel_id) {
+                        channel_inputs.push([input_port, messages]);
+                    }
                 }
-            }
 
-            for (const [output_port, [messages, channel]] of Object.entries(progress.output_messages)) {
-                if (channel === edge.channel_id) {
-                    channel_outputs.push([output_port, messages]);
+                for (const [output_port, [messages, channel]] of Object.entries(progress.output_messages)) {
+                    if (channel === edge.channel_id) {
+                        channel_outputs.push([output_port, messages]);
+                    }
                 }
             }
-        }
 
-        channel_inputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
-        channel_outputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
+            channel_inputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
+            channel_outputs.sort(([port1, _msg1], [port2, _msg2]) => port1 - port2);
 
-        for (const [port, messages] of channel_inputs) {
-            html += `<br>Consumed ${messages} messages from port ${port}`;
-        }
-        for (const [port, messages] of channel_outputs) {
-            html += `<br>Produced ${messages} messages from port ${port}`;
-        }
+            for (const [port, messages] of channel_inputs) {
+                html += `<br>Consumed ${messages} messages from port ${port}`;
+            }
+            for (const [port, messages] of channel_outputs) {
+                html += `<br>Produced ${messages} messages from port ${port}`;
+            }
+
+            tooltip
+                .html(html)
+                .style("top", (d3.event.pageY - 40) + "px")
+                .style("left", (d3.event.pageX + 40) + "px");
+        })
+        // Hide the tooltip on mouseout
+        .on("mouseout", () => tooltip.style("visibility", "hidden"));
+}
 
-        tooltip
-            .html(html)
-            .style("top", (d3.event.pageY - 40) + "px")
-            .style("left", (d3.event.pageX + 40) + "px");
-    })
-    // Hide the tooltip on mouseout
-    .on("mouseout", () => tooltip.style("visibility", "hidden"));
+attach_tooltips();
 
 // Add the palette legend
 const palette_legend = d3.select("body")
@@ -632,7 +707,7 @@ function format_duration(input_nanos) {
 function time_sink_radar(events) {
     /** @type {HTMLCanvasElement} */
     const operator_graph_div = document.getElementById("operator-graph");
-    const chart = echarts.init(operator_graph_div);
+    const chart = echarts.getInstanceByDom(operator_graph_div) || echarts.init(operator_graph_div);
 
     let workers = [];
     for (const event of events) {
@@ -736,3 +811,41 @@ function time_sink_radar(events) {
 }
 
 time_sink_radar(timeline_events);
+
+/**
+ * Replaces the graph's data with freshly polled data and re-renders everything
+ * @param {GraphData} data The new graph data
+ */
+function update_graph(data) {
+    raw_nodes = data.nodes;
+    raw_subgraphs = data.subgraphs;
+    raw_edges = data.edges;
+    timeline_events = data.timeline_events;
+    operator_shapes = data.operator_shapes;
+    operator_progress = data.operator_progress;
+
+    build_graph();
+    render(svg, graph);
+    attach_tooltips();
+    dataflow_svg.attr("height", graph.graph().height * initial_scale + 40);
+
+    worker_timeline(chart, timeline_events, Number(worker_selector.value), operator_names);
+    time_sink_radar(timeline_events);
+}
+
+// Poll for new data while the target computation is still running
+if (live_update_interval !== null) {
+    setInterval(async () => {
+        try {
+            const response = await fetch("./data.json", { cache: "no-store" });
+            if (!response.ok) {
+                console.error(`failed to fetch live graph data: ${response.status}`);
+                return;
+            }
+
+            update_graph(await response.json());
+        } catch (err) {
+            console.error("failed to fetch live graph data", err);
+        }
+    }, live_update_interval);
+}
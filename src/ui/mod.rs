@@ -1,16 +1,32 @@
 #![allow(clippy::unused_unit)]
 
 use crate::{
+    archive::StreamKind,
     args::Args,
-    dataflow::{OperatorProgress, OperatorShape, TimelineEvent as RawTimelineEvent},
+    colormap::{select_color, Color},
+    critical_path::{self, DataflowCriticalPath},
+    dataflow::{
+        self, constants::DDSHOW_VERSION, ActivationHistogram, Channel, DataflowData, EventKind,
+        OperatorProgress, OperatorShape, OperatorStats, Percentiles,
+        TimelineEvent as RawTimelineEvent,
+    },
+    lint::{self, Lint},
+    skew::{self, OperatorSkew},
 };
 use abomonation_derive::Abomonation;
 use anyhow::{Context as _, Result};
 use bytecheck::CheckBytes;
-use ddshow_types::{ChannelId, OperatorAddr, OperatorId, PortId, WorkerId};
+use ddshow_types::{
+    timely_logging::OperatesEvent, ChannelId, OperatorAddr, OperatorId, PortId, WorkerId,
+};
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
-use std::{fs, time::Duration};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs,
+    time::Duration,
+};
 use tera::{Context, Tera};
 
 const GRAPH_HTML: &str = include_str!("graph.html");
@@ -20,57 +36,57 @@ const D3_JS: &str = include_str!("d3.v5.js");
 const DAGRE_JS: &str = include_str!("dagre-d3.js");
 const ECHARTS_JS: &str = include_str!("echarts.min.js");
 
-#[allow(clippy::too_many_arguments)]
-pub fn render(
-    args: &Args,
-    nodes: Vec<Node>,
-    subgraphs: Vec<Subgraph>,
-    edges: Vec<Edge>,
-    palette_colors: Vec<String>,
-    timeline_events: Vec<RawTimelineEvent>,
-    operator_shapes: Vec<OperatorShape>,
-    operator_progress: Vec<OperatorProgress>,
-) -> Result<()> {
+/// The version of the [`DDShowStats`] json format written by `--dump-json`
+///
+/// This is bumped whenever a field is removed, renamed or changes meaning,
+/// new fields may be added without bumping it
+pub const STATS_SCHEMA_VERSION: u32 = 1;
+
+/// The [JSON Schema](https://json-schema.org) describing the output of `--dump-json`
+pub const STATS_JSON_SCHEMA: &str = include_str!("ddshow-stats.schema.json");
+
+/// The static files that make up the graph, everything except `graph.js`
+/// which is rendered from the graph's data
+pub(crate) const STATIC_FILES: &[(&str, &str)] = &[
+    ("graph.html", GRAPH_HTML),
+    ("graph.css", GRAPH_CSS),
+    ("d3.v5.js", D3_JS),
+    ("dagre-d3.js", DAGRE_JS),
+    ("echarts.min.js", ECHARTS_JS),
+];
+
+pub fn render(args: &Args, graph_data: &GraphData) -> Result<()> {
     let output_dir = &args.output_dir;
     tracing::info!(output_dir = ?output_dir, "writing graph files to disk");
 
     fs::create_dir_all(output_dir).context("failed to create output directory")?;
 
-    fs::write(output_dir.join("graph.html"), GRAPH_HTML)
-        .context("failed to write output graph to file")?;
-
-    fs::write(output_dir.join("graph.css"), GRAPH_CSS)
-        .context("failed to write output graph to file")?;
-
-    fs::write(output_dir.join("d3.v5.js"), D3_JS)
-        .context("failed to write output graph to file")?;
+    for &(file, contents) in STATIC_FILES {
+        fs::write(output_dir.join(file), contents)
+            .context("failed to write output graph to file")?;
+    }
 
-    fs::write(output_dir.join("dagre-d3.js"), DAGRE_JS)
+    let rendered_js = render_graph_js(graph_data, None)?;
+    fs::write(output_dir.join("graph.js"), rendered_js)
         .context("failed to write output graph to file")?;
 
-    fs::write(output_dir.join("echarts.min.js"), ECHARTS_JS)
-        .context("failed to write output graph to file")?;
+    Ok(())
+}
 
-    let graph_data = GraphData {
-        nodes,
-        subgraphs,
-        edges,
-        palette_colors,
-        timeline_events,
-        operator_shapes,
-        operator_progress,
-    };
-
-    let context =
+/// Renders `graph.js` for the given data, if `live_update_interval` is set
+/// the graph will poll `./data.json` for updates at that interval
+pub(crate) fn render_graph_js(
+    graph_data: &GraphData,
+    live_update_interval: Option<Duration>,
+) -> Result<String> {
+    let mut context =
         Context::from_serialize(graph_data).context("failed to render graph context as json")?;
+    context.insert(
+        "live_update_interval",
+        &live_update_interval.map(|interval| interval.as_millis() as u64),
+    );
 
-    let rendered_js =
-        Tera::one_off(GRAPH_JS, &context, false).context("failed to render output graph")?;
-
-    fs::write(output_dir.join("graph.js"), rendered_js)
-        .context("failed to write output graph to file")?;
-
-    Ok(())
+    Tera::one_off(GRAPH_JS, &context, false).context("failed to render output graph")
 }
 
 // TODO: Move this to another crate, make serde & abomonation feature-gated,
@@ -96,14 +112,28 @@ pub fn render(
 #[allow(clippy::upper_case_acronyms)]
 #[archive(strict, derive(CheckBytes))]
 pub struct DDShowStats {
+    /// The version of the stats' format, see [`STATS_SCHEMA_VERSION`]
+    pub schema_version: u32,
     pub program: ProgramStats,
     // TODO: Should/would these be better as trees?
     pub workers: Vec<WorkerStats>,
     pub dataflows: Vec<DataflowStats>,
+    /// Every operator, subgraph and dataflow on every worker
     pub nodes: Vec<NodeStats>,
     pub channels: Vec<ChannelStats>,
+    /// Only filled when differential logging is enabled
     pub arrangements: Vec<ArrangementStats>,
+    /// Empty when the timeline is disabled
     pub events: Vec<TimelineEvent>,
+    /// The lints found within the dataflow, most severe first
+    pub lints: Vec<Lint>,
+    /// The skew of every operator that ran on more than one worker, most skewed first
+    pub skew: Vec<OperatorSkew>,
+    /// The critical path of every dataflow, empty when progress logging is disabled
+    pub critical_paths: Vec<DataflowCriticalPath>,
+    /// The missing, dropped and truncated events of every replayed
+    /// log stream, empty for abomonation sources
+    pub data_quality: Vec<StreamQuality>,
     pub differential_enabled: bool,
     pub progress_enabled: bool,
     pub ddshow_version: String,
@@ -112,6 +142,223 @@ pub struct DDShowStats {
     // TODO: Progress logging
 }
 
+impl DDShowStats {
+    /// Collects the stats of the program from the dataflow's data
+    ///
+    /// Operators that were still alive when the trace ended don't have a known
+    /// lifespan, so they're given one that covers the program's whole runtime
+    pub fn new(args: &Args, data: &DataflowData) -> Self {
+        let program = data.program_stats.first().cloned().unwrap_or_default();
+        let workers = data
+            .worker_stats
+            .first()
+            .map(|workers| workers.iter().map(|(_, stats)| stats.clone()).collect())
+            .unwrap_or_default();
+
+        let mut dataflows = data.dataflow_stats.clone();
+        dataflows.sort_unstable_by(|a, b| (a.worker, &a.addr).cmp(&(b.worker, &b.addr)));
+
+        let whole_program = Lifespan::new(Duration::from_secs(0), program.runtime);
+        let lifespans: HashMap<_, _> = data.operator_lifespans.iter().cloned().collect();
+        let dataflow_lifespans: HashMap<_, _> = dataflows
+            .iter()
+            .map(|dataflow| ((dataflow.worker, dataflow.addr.clone()), dataflow.lifespan))
+            .collect();
+
+        let operator_stats: HashMap<_, _> = data
+            .operator_stats
+            .iter()
+            .map(|(operator, stats)| (*operator, stats))
+            .collect();
+        let shapes: HashMap<_, _> = data
+            .operator_shapes
+            .iter()
+            .map(|shape| ((shape.worker, shape.id), shape))
+            .collect();
+        let subgraphs: HashSet<_> = data
+            .subgraphs
+            .iter()
+            .map(|&((worker, _), ref event)| (worker, event.id))
+            .collect();
+
+        let mut nodes: Vec<_> = data
+            .nodes
+            .iter()
+            .chain(data.subgraphs.iter())
+            .map(|((worker, addr), OperatesEvent { id, name, .. })| {
+                let (worker, id) = (*worker, *id);
+
+                let kind = if !subgraphs.contains(&(worker, id)) {
+                    NodeKind::Operator
+                } else if addr.len() == 1 {
+                    NodeKind::Dataflow
+                } else {
+                    NodeKind::Subgraph
+                };
+
+                let (inputs, outputs) = shapes
+                    .get(&(worker, id))
+                    .map(|shape| (shape.inputs.clone(), shape.outputs.clone()))
+                    .unwrap_or_default();
+
+                let activations = operator_stats
+                    .get(&(worker, id))
+                    .map(|stats| AggregatedStats {
+                        total: stats.activations,
+                        max: stats.max,
+                        min: stats.min,
+                        average: stats.average,
+                        std_dev: stats.std_dev,
+                        percentiles: stats.percentiles,
+                        data_points: stats
+                            .activation_durations
+                            .iter()
+                            .map(|&(_start, duration)| duration)
+                            .collect(),
+                    })
+                    .unwrap_or_default();
+                let activation_histogram = operator_stats
+                    .get(&(worker, id))
+                    .map(|stats| stats.activation_histogram.clone())
+                    .unwrap_or_default();
+
+                NodeStats {
+                    id,
+                    addr: addr.clone(),
+                    worker,
+                    name: name.clone(),
+                    inputs,
+                    outputs,
+                    lifespan: lifespans
+                        .get(&(worker, id))
+                        .copied()
+                        .unwrap_or(whole_program),
+                    kind,
+                    activations,
+                    activation_histogram,
+                }
+            })
+            .collect();
+        nodes.sort_unstable_by(|a, b| (a.worker, &a.addr).cmp(&(b.worker, &b.addr)));
+
+        // Channels live for as long as the dataflow they're within
+        let mut channels: Vec<_> = data
+            .edges
+            .iter()
+            .map(|(worker, source, channel, target)| {
+                let (source_addr, target_addr) = (channel.source_addr(), channel.target_addr());
+
+                let kind = match channel {
+                    Channel::Normal { .. } => ChannelKind::Normal,
+                    Channel::ScopeCrossing { .. } => {
+                        match target_addr.len().cmp(&source_addr.len()) {
+                            Ordering::Greater => ChannelKind::Ingress,
+                            Ordering::Less => ChannelKind::Egress,
+                            Ordering::Equal => ChannelKind::Normal,
+                        }
+                    }
+                };
+
+                let dataflow = OperatorAddr::from(&source_addr[..source_addr.len().min(1)]);
+                let lifespan = dataflow_lifespans
+                    .get(&(*worker, dataflow))
+                    .copied()
+                    .unwrap_or(whole_program);
+
+                ChannelStats {
+                    id: channel.channel_id(),
+                    addr: OperatorAddr::from(&source_addr[..source_addr.len().saturating_sub(1)]),
+                    worker: *worker,
+                    source_node: source.id,
+                    dest_node: target.id,
+                    kind,
+                    lifespan,
+                }
+            })
+            .collect();
+        channels.sort_unstable_by_key(|channel| (channel.worker, channel.id));
+
+        let addrs: HashMap<_, _> = data.addr_lookup.iter().cloned().collect();
+        let mut merges: HashMap<_, Vec<_>> = HashMap::new();
+        for event in data.timeline_events.iter() {
+            if let EventKind::Merge { operator_id } = event.event {
+                merges
+                    .entry((event.worker, operator_id))
+                    .or_default()
+                    .push(Duration::from_nanos(event.duration));
+            }
+        }
+
+        let mut arrangements: Vec<_> = data
+            .operator_stats
+            .iter()
+            .filter_map(|&((worker, id), ref stats)| {
+                let arrangement = stats.arrangement_size.as_ref()?;
+                let sizes: Vec<_> = arrangement
+                    .spline_levels
+                    .iter()
+                    .map(|&(_time, _scale, size)| size)
+                    .collect();
+
+                Some(ArrangementStats {
+                    id,
+                    worker,
+                    operator_addr: addrs.get(&(worker, id)).cloned().unwrap_or_default(),
+                    size_stats: AggregatedStats {
+                        max: arrangement.max_size,
+                        min: arrangement.min_size,
+                        ..AggregatedStats::from_sizes(sizes)
+                    },
+                    merge_stats: AggregatedStats::from_durations(
+                        merges.remove(&(worker, id)).unwrap_or_default(),
+                    ),
+                    batch_stats: AggregatedStats::from_sizes(arrangement.batch_sizes.clone()),
+                    trace_shares: arrangement.trace_shares,
+                    lifespan: lifespans
+                        .get(&(worker, id))
+                        .copied()
+                        .unwrap_or(whole_program),
+                })
+            })
+            .collect();
+        arrangements.sort_unstable_by(|a, b| {
+            (a.worker, &a.operator_addr).cmp(&(b.worker, &b.operator_addr))
+        });
+
+        let mut events: Vec<_> = data
+            .timeline_events
+            .iter()
+            .map(|event| TimelineEvent {
+                worker: event.worker,
+                event: event.event,
+                lifespan: Lifespan::new(
+                    Duration::from_nanos(event.start_time),
+                    Duration::from_nanos(event.start_time + event.duration),
+                ),
+            })
+            .collect();
+        events.sort_unstable_by_key(|event| (event.worker, event.lifespan));
+
+        Self {
+            schema_version: STATS_SCHEMA_VERSION,
+            program,
+            workers,
+            dataflows,
+            nodes,
+            channels,
+            arrangements,
+            events,
+            lints: lint::lint(args, data),
+            skew: skew::operator_skew(data),
+            critical_paths: critical_path::critical_paths(data),
+            data_quality: data.data_quality.clone(),
+            differential_enabled: args.differential_enabled,
+            progress_enabled: args.progress_enabled,
+            ddshow_version: DDSHOW_VERSION.to_string(),
+        }
+    }
+}
+
 // - Program stats
 //  - # workers
 //  - # dataflows
@@ -153,6 +400,98 @@ pub struct ProgramStats {
     // TODO: Missing nodes & edges
 }
 
+/// The events of a single worker's log stream that never made it to ddshow
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct StreamQuality {
+    pub stream: StreamKind,
+    /// The worker the stream was logged by, for tcp sources
+    /// this is the order the connection was accepted in
+    pub worker: usize,
+    /// The ranges of events that were skipped over by the stream's sequence numbers
+    pub missing: Vec<MissingEvents>,
+    /// The number of events that `ddshow-sink` reported dropping
+    pub dropped: u64,
+    /// Whether the stream ended partway through an event
+    /// or before the target computation closed it
+    pub truncated: bool,
+    /// Whether the stream carried sequence numbers, missing
+    /// events can't be detected within streams that don't
+    pub sequenced: bool,
+    /// The stream only holds one in every this many of each operator's
+    /// activations, so its activation counts and durations are undercounted
+    pub schedule_sample_rate: u64,
+}
+
+impl StreamQuality {
+    pub const fn new(stream: StreamKind, worker: usize) -> Self {
+        Self {
+            stream,
+            worker,
+            missing: Vec::new(),
+            dropped: 0,
+            truncated: false,
+            sequenced: false,
+            schedule_sample_rate: 1,
+        }
+    }
+
+    /// The total number of missing events
+    pub fn missing_events(&self) -> u64 {
+        self.missing.iter().map(MissingEvents::events).sum()
+    }
+
+    /// Returns `true` if any of the stream's events are known to be lost
+    pub fn has_issues(&self) -> bool {
+        !self.missing.is_empty() || self.dropped != 0 || self.truncated
+    }
+}
+
+/// A range of events that are missing from a log stream, the
+/// range includes its start and excludes its end
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    Abomonation,
+    Archive,
+    RkyvSerialize,
+    RkyvDeserialize,
+)]
+#[archive(strict, derive(CheckBytes))]
+pub struct MissingEvents {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl MissingEvents {
+    /// The number of missing events
+    pub const fn events(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
 // - Worker stats
 //   - total worker runtime
 //  - # dataflows
@@ -271,7 +610,10 @@ pub struct NodeStats {
     pub outputs: Vec<PortId>,
     pub lifespan: Lifespan,
     pub kind: NodeKind,
+    /// The operator's activation times, `data_points` is only
+    /// filled in when `--activation-durations` is given
     pub activations: AggregatedStats<Duration>,
+    pub activation_histogram: ActivationHistogram,
 }
 
 #[derive(
@@ -437,6 +779,8 @@ impl Default for ChannelKind {
 )]
 #[archive(strict, derive(CheckBytes))]
 pub struct ArrangementStats {
+    pub id: OperatorId,
+    pub worker: WorkerId,
     pub operator_addr: OperatorAddr,
     pub size_stats: AggregatedStats<usize>,
     pub merge_stats: AggregatedStats<Duration>,
@@ -467,8 +811,62 @@ pub struct AggregatedStats<T> {
     pub max: T,
     pub min: T,
     pub average: T,
+    pub std_dev: T,
+    pub percentiles: Percentiles<T>,
     pub data_points: Vec<T>,
-    // TODO: Standard deviation, standard error
+    // TODO: Standard error
+}
+
+impl AggregatedStats<usize> {
+    fn from_sizes(data_points: Vec<usize>) -> Self {
+        let total = data_points.len();
+        let mut sorted = data_points.clone();
+        sorted.sort_unstable();
+
+        Self {
+            total,
+            max: data_points.iter().max().copied().unwrap_or_default(),
+            min: data_points.iter().min().copied().unwrap_or_default(),
+            average: data_points
+                .iter()
+                .sum::<usize>()
+                .checked_div(total)
+                .unwrap_or_default(),
+            std_dev: dataflow::std_dev(data_points.iter().map(|&size| size as f64)).round()
+                as usize,
+            percentiles: Percentiles::from_sorted(&sorted),
+            data_points,
+        }
+    }
+}
+
+impl AggregatedStats<Duration> {
+    fn from_durations(data_points: Vec<Duration>) -> Self {
+        let total = data_points.len();
+        let mut sorted = data_points.clone();
+        sorted.sort_unstable();
+
+        Self {
+            total,
+            max: data_points.iter().max().copied().unwrap_or_default(),
+            min: data_points.iter().min().copied().unwrap_or_default(),
+            average: data_points
+                .iter()
+                .sum::<Duration>()
+                .checked_div(total as u32)
+                .unwrap_or_default(),
+            std_dev: Duration::from_nanos(
+                dataflow::std_dev(
+                    data_points
+                        .iter()
+                        .map(|duration| duration.as_nanos() as f64),
+                )
+                .round() as u64,
+            ),
+            percentiles: Percentiles::from_sorted(&sorted),
+            data_points,
+        }
+    }
 }
 
 // - Timeline events
@@ -486,7 +884,6 @@ pub struct AggregatedStats<T> {
     PartialOrd,
     Ord,
     Hash,
-    Default,
     Deserialize,
     Serialize,
     Abomonation,
@@ -497,8 +894,7 @@ pub struct AggregatedStats<T> {
 #[archive(strict, derive(CheckBytes))]
 pub struct TimelineEvent {
     pub worker: WorkerId,
-    // TODO: Events
-    pub event: (),
+    pub event: EventKind,
     pub lifespan: Lifespan,
 }
 
@@ -513,6 +909,178 @@ pub struct GraphData {
     pub operator_progress: Vec<OperatorProgress>,
 }
 
+impl GraphData {
+    /// Builds the graph's nodes, subgraphs and edges from the dataflow's data
+    pub fn new(args: &Args, data: &DataflowData) -> Self {
+        let mut subgraph_ids = Vec::new();
+
+        let mut node_events = data.nodes.clone();
+        node_events.sort_unstable_by_key(|(addr, _)| addr.clone());
+        tracing::debug!("finished extracting {} node events", node_events.len());
+
+        let mut subgraph_events = data.subgraphs.clone();
+        subgraph_events.sort_unstable_by_key(|(addr, _)| addr.clone());
+        tracing::debug!(
+            "finished extracting {} subgraph events",
+            subgraph_events.len(),
+        );
+
+        for &((worker, ref _addr), ref event) in subgraph_events.iter() {
+            subgraph_ids.push((worker, event.id));
+        }
+
+        let (mut operator_stats, mut raw_timings) = (HashMap::new(), Vec::new());
+        let stats_events = &data.operator_stats;
+        tracing::debug!("finished extracting {} stats events", stats_events.len());
+
+        for &(operator, ref stats) in stats_events {
+            if !subgraph_ids.contains(&operator) {
+                raw_timings.push(stats.total);
+            }
+
+            operator_stats.insert(operator, stats);
+        }
+
+        let mut edge_events = data.edges.clone();
+        edge_events.sort_unstable_by_key(|(worker, _, channel, _)| (*worker, channel.channel_id()));
+        tracing::debug!("finished extracting {} edge events", edge_events.len());
+
+        let (max_time, min_time) = (
+            raw_timings.iter().max().copied().unwrap_or_default(),
+            raw_timings.iter().min().copied().unwrap_or_default(),
+        );
+
+        // Every worker's copy of an operator shares the skew of the reference worker's
+        let skews: HashMap<_, _> = skew::operator_skew(data)
+            .into_iter()
+            .map(|skew| (skew.id, skew))
+            .collect();
+        let operator_skews: HashMap<_, _> = data
+            .operator_matching
+            .iter()
+            .filter_map(|(operator, reference)| Some((*operator, skews.get(reference)?)))
+            .collect();
+
+        let timeline_events = data.timeline_events.clone();
+        tracing::debug!(
+            "finished extracting {} timeline events",
+            timeline_events.len(),
+        );
+
+        let nodes = node_events
+            .into_iter()
+            .filter_map(|((worker, addr), OperatesEvent { id, name, .. })| {
+                let &OperatorStats {
+                    max,
+                    min,
+                    average,
+                    total,
+                    std_dev,
+                    percentiles,
+                    activations: invocations,
+                    ref activation_durations,
+                    ref arrangement_size,
+                    ..
+                } = *operator_stats.get(&(worker, id))?;
+
+                let fill_color = select_color(&args.palette, total, (max_time, min_time));
+                let text_color = fill_color.text_color();
+
+                Some(Node {
+                    id,
+                    worker,
+                    addr,
+                    name,
+                    max_activation_time: format!("{:#?}", max),
+                    min_activation_time: format!("{:#?}", min),
+                    average_activation_time: format!("{:#?}", average),
+                    total_activation_time: format!("{:#?}", total),
+                    std_dev_activation_time: format!("{:#?}", std_dev),
+                    p50_activation_time: format!("{:#?}", percentiles.p50),
+                    p90_activation_time: format!("{:#?}", percentiles.p90),
+                    p99_activation_time: format!("{:#?}", percentiles.p99),
+                    p999_activation_time: format!("{:#?}", percentiles.p999),
+                    invocations,
+                    fill_color: format!("{}", fill_color),
+                    text_color: format!("{}", text_color),
+                    activation_durations: activation_durations
+                        .iter()
+                        .map(|(duration, time)| ActivationDuration {
+                            activation_time: duration.as_nanos() as u64,
+                            activated_at: time.as_nanos() as u64,
+                        })
+                        .collect(),
+                    max_arrangement_size: arrangement_size.as_ref().map(|arr| arr.max_size),
+                    min_arrangement_size: arrangement_size.as_ref().map(|arr| arr.min_size),
+                    skew: operator_skews.get(&(worker, id)).map(|&skew| skew.clone()),
+                })
+            })
+            .collect();
+
+        let subgraphs = subgraph_events
+            .into_iter()
+            .filter_map(|((worker, addr), OperatesEvent { id, name, .. })| {
+                let &OperatorStats {
+                    max,
+                    min,
+                    average,
+                    total,
+                    activations: invocations,
+                    ..
+                } = *operator_stats.get(&(worker, id))?;
+
+                let fill_color = select_color(&args.palette, total, (max, min));
+                let text_color = fill_color.text_color();
+
+                Some(Subgraph {
+                    id,
+                    worker,
+                    addr,
+                    name,
+                    max_activation_time: format!("{:#?}", max),
+                    min_activation_time: format!("{:#?}", min),
+                    average_activation_time: format!("{:#?}", average),
+                    total_activation_time: format!("{:#?}", total),
+                    invocations,
+                    fill_color: format!("{}", fill_color),
+                    text_color: format!("{}", text_color),
+                })
+            })
+            .collect();
+
+        let edges = edge_events
+            .into_iter()
+            .map(|(worker, _, channel, _)| Edge {
+                src: channel.source_addr(),
+                dest: channel.target_addr(),
+                worker,
+                channel_id: channel.channel_id(),
+                edge_kind: match channel {
+                    Channel::Normal { .. } => EdgeKind::Normal,
+                    Channel::ScopeCrossing { .. } => EdgeKind::Crossing,
+                },
+            })
+            .collect();
+
+        let mut palette_colors = Vec::with_capacity(10);
+        let mut pos = 0.0;
+        for _ in 0..10 {
+            palette_colors.push(format!("{}", Color::new(args.palette.eval_continuous(pos))));
+            pos += 0.1;
+        }
+
+        Self {
+            nodes,
+            subgraphs,
+            edges,
+            palette_colors,
+            timeline_events,
+            operator_shapes: data.operator_shapes.clone(),
+            operator_progress: data.operator_progress.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct Node {
     pub id: OperatorId,
@@ -523,12 +1091,19 @@ pub struct Node {
     pub min_activation_time: String,
     pub average_activation_time: String,
     pub total_activation_time: String,
+    pub std_dev_activation_time: String,
+    pub p50_activation_time: String,
+    pub p90_activation_time: String,
+    pub p99_activation_time: String,
+    pub p999_activation_time: String,
     pub invocations: usize,
     pub fill_color: String,
     pub text_color: String,
     pub activation_durations: Vec<ActivationDuration>,
     pub max_arrangement_size: Option<usize>,
     pub min_arrangement_size: Option<usize>,
+    /// The operator's skew across workers, `None` if it only ran on one worker
+    pub skew: Option<OperatorSkew>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
@@ -1,10 +1,13 @@
-use crate::{args::Args, dataflow::DataflowData};
+use crate::{
+    args::Args,
+    dataflow::{ActivationHistogram, DataflowData},
+};
 use anyhow::{Context as _, Result};
 use ddshow_types::{OperatorAddr, OperatorId, WorkerId};
 use serde::Serialize;
 use std::{
     fs::{self, File},
-    io::BufWriter,
+    io::{BufWriter, Write},
 };
 use tera::{Context, Tera};
 
@@ -21,6 +24,13 @@ struct VegaNode<'a> {
     average_activation_time: u64,
     max_activation_time: u64,
     min_activation_time: u64,
+    std_dev_activation_time: u64,
+    p50_activation_time: u64,
+    p90_activation_time: u64,
+    p99_activation_time: u64,
+    p999_activation_time: u64,
+    /// `(lower, upper, count)` buckets of the activation histogram
+    activation_histogram: Vec<(u64, u64, usize)>,
     activation_durations: Vec<(u64, u64)>,
     max_arrangement_size: Option<usize>,
     min_arrangement_size: Option<usize>,
@@ -36,6 +46,12 @@ struct VegaWorkerNode {
     average_activation_time: u64,
     max_activation_time: u64,
     min_activation_time: u64,
+    std_dev_activation_time: u64,
+    p50_activation_time: u64,
+    p90_activation_time: u64,
+    p99_activation_time: u64,
+    p999_activation_time: u64,
+    activation_histogram: Vec<(u64, u64, usize)>,
     activation_durations: Vec<(u64, u64)>,
     max_arrangement_size: Option<usize>,
     min_arrangement_size: Option<usize>,
@@ -50,6 +66,36 @@ enum NodeKind {
 }
 
 pub fn make_data(args: &Args, data: &DataflowData) -> Result<()> {
+    if let Err(err) = fs::create_dir_all(&args.output_dir) {
+        tracing::warn!(
+            output_dir = ?args.output_dir,
+            "failed to create directory for dashboard file: {:?}",
+            err,
+        );
+    }
+
+    let file = BufWriter::new(
+        File::create(args.output_dir.join("dashboard.html")).with_context(|| {
+            anyhow::format_err!(
+                "failed to create dashboard file at '{}'",
+                args.output_dir.join("dashboard.html").display(),
+            )
+        })?,
+    );
+
+    render_dashboard(data, file).with_context(|| {
+        anyhow::format_err!(
+            "failed to render dashboard to {}",
+            args.output_dir.join("dashboard.html").display(),
+        )
+    })
+}
+
+/// Renders the dashboard for the given data into `output`
+pub(crate) fn render_dashboard<W>(data: &DataflowData, output: W) -> Result<()>
+where
+    W: Write,
+{
     let nodes: Vec<_> = data
         .aggregated_operator_stats
         .iter()
@@ -97,6 +143,12 @@ pub fn make_data(args: &Args, data: &DataflowData) -> Result<()> {
                         average_activation_time: stats.average.as_nanos() as u64,
                         max_activation_time: stats.max.as_nanos() as u64,
                         min_activation_time: stats.min.as_nanos() as u64,
+                        std_dev_activation_time: stats.std_dev.as_nanos() as u64,
+                        p50_activation_time: stats.percentiles.p50.as_nanos() as u64,
+                        p90_activation_time: stats.percentiles.p90.as_nanos() as u64,
+                        p99_activation_time: stats.percentiles.p99.as_nanos() as u64,
+                        p999_activation_time: stats.percentiles.p999.as_nanos() as u64,
+                        activation_histogram: histogram_buckets(&stats.activation_histogram),
                         activation_durations,
                         max_arrangement_size: stats
                             .arrangement_size
@@ -130,6 +182,12 @@ pub fn make_data(args: &Args, data: &DataflowData) -> Result<()> {
                 average_activation_time: stats.average.as_nanos() as u64,
                 max_activation_time: stats.max.as_nanos() as u64,
                 min_activation_time: stats.min.as_nanos() as u64,
+                std_dev_activation_time: stats.std_dev.as_nanos() as u64,
+                p50_activation_time: stats.percentiles.p50.as_nanos() as u64,
+                p90_activation_time: stats.percentiles.p90.as_nanos() as u64,
+                p99_activation_time: stats.percentiles.p99.as_nanos() as u64,
+                p999_activation_time: stats.percentiles.p999.as_nanos() as u64,
+                activation_histogram: histogram_buckets(&stats.activation_histogram),
                 activation_durations,
                 max_arrangement_size: stats.arrangement_size.as_ref().map(|arr| arr.max_size),
                 min_arrangement_size: stats.arrangement_size.as_ref().map(|arr| arr.min_size),
@@ -140,23 +198,6 @@ pub fn make_data(args: &Args, data: &DataflowData) -> Result<()> {
         })
         .collect();
 
-    if let Err(err) = fs::create_dir_all(&args.output_dir) {
-        tracing::warn!(
-            output_dir = ?args.output_dir,
-            "failed to create directory for dashboard file: {:?}",
-            err,
-        );
-    }
-
-    let file = BufWriter::new(
-        File::create(args.output_dir.join("dashboard.html")).with_context(|| {
-            anyhow::format_err!(
-                "failed to create dashboard file at '{}'",
-                args.output_dir.join("dashboard.html").display(),
-            )
-        })?,
-    );
-
     let mut context = Context::new();
     context.insert("aggregated_stats", &nodes);
 
@@ -164,13 +205,21 @@ pub fn make_data(args: &Args, data: &DataflowData) -> Result<()> {
     tera.add_raw_template("dashboard", DASHBOARD)
         .context("internal error: failed to add dashboard template to tera")?;
 
-    tera.render_to("dashboard", &context, file)
-        .with_context(|| {
-            anyhow::format_err!(
-                "failed to render dashboard to {}",
-                args.output_dir.join("dashboard.html").display(),
-            )
-        })?;
+    tera.render_to("dashboard", &context, output)?;
 
     Ok(())
 }
+
+fn histogram_buckets(histogram: &ActivationHistogram) -> Vec<(u64, u64, usize)> {
+    histogram
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.lower.as_nanos() as u64,
+                bucket.upper.as_nanos() as u64,
+                bucket.count,
+            )
+        })
+        .collect()
+}
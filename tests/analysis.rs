@@ -0,0 +1,1743 @@
+use ddshow::{
+    archive::{self, ArchiveHeader, StreamKind},
+    args::{Args, StreamEncoding},
+    critical_path,
+    diff::TraceDiff,
+    lint::{self, LintRule, Severity},
+    skew, ActivationHistogram, Analysis, DataflowData, DivergenceKind, NodeKind, ReplaySources,
+    StopHandle, WorkerDivergence,
+};
+use ddshow_sink::{
+    BatchLogger, Compression, EventWriter, FlightRecorder, Handshake, Multiplexer, SinkConfig,
+};
+use ddshow_types::{timely_logging::TimelyEvent, OperatorAddr, OperatorId, PortId, WorkerId};
+use differential_dataflow::{
+    input::Input,
+    operators::{arrange::ArrangeBySelf, Iterate, Threshold},
+    AsCollection,
+};
+use serde_json::Value;
+use std::{
+    env, fs,
+    io::{self, Cursor, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    num::NonZeroUsize,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Barrier, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use timely::{
+    communication::{allocator::Generic, Allocate},
+    dataflow::{
+        operators::{Concat, ConnectLoop, Enter, Filter, Inspect, LoopVariable, Map, ToStream},
+        Scope,
+    },
+    logging::{ChannelsEvent, OperatesEvent, ScheduleEvent, TimelyEvent as RawTimelyEvent},
+    worker::Worker,
+};
+
+/// A writer that shares its buffer so the logs can be read back after the
+/// target computation has finished
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn reader(&self) -> Cursor<Vec<u8>> {
+        Cursor::new(self.0.lock().unwrap().clone())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The logs of a target's workers, with a buffer for each log stream of each worker
+#[derive(Debug, Clone)]
+struct TargetLogs {
+    timely: Vec<SharedBuffer>,
+    differential: Vec<SharedBuffer>,
+    progress: Vec<SharedBuffer>,
+}
+
+impl TargetLogs {
+    fn new(workers: usize) -> Self {
+        let buffers = || (0..workers).map(|_| SharedBuffer::default()).collect();
+
+        Self {
+            timely: buffers(),
+            differential: buffers(),
+            progress: buffers(),
+        }
+    }
+
+    /// Runs the dataflow on every worker, logging each stream as the config allows
+    fn profile<F>(self, config: &SinkConfig, dataflow: F) -> Self
+    where
+        F: Fn(&mut Worker<Generic>) + Send + Sync + 'static,
+    {
+        let (logs, config) = (self.clone(), config.clone());
+        timely::execute(timely::Config::process(self.timely.len()), move |worker| {
+            let index = worker.index();
+            ddshow_sink::enable_timely_logging_with_config(
+                worker,
+                logs.timely[index].clone(),
+                &config,
+            );
+            ddshow_sink::enable_differential_logging_with_config(
+                worker,
+                logs.differential[index].clone(),
+                &config,
+            );
+            ddshow_sink::enable_timely_progress_logging_with_config(
+                worker,
+                logs.progress[index].clone(),
+                &config,
+            );
+
+            dataflow(worker);
+        })
+        .unwrap();
+
+        self
+    }
+
+    /// Writes the timely logs of a worker that built the given operators and channels,
+    /// activating each operator once unless given an `(operator, activations)` count.
+    /// Every event takes a millisecond, so each activation runs for a millisecond
+    fn synthetic_worker(
+        self,
+        worker: usize,
+        operators: &[(usize, &[usize], &str)],
+        channels: &[SyntheticChannel],
+        activations: &[(usize, usize)],
+    ) -> Self {
+        let mut logger: BatchLogger<TimelyEvent, WorkerId, _> =
+            BatchLogger::new(EventWriter::new(self.timely[worker].clone()));
+
+        let mut events = Vec::new();
+        for &(id, addr, name) in operators {
+            events.push(RawTimelyEvent::Operates(OperatesEvent {
+                id,
+                addr: addr.to_vec(),
+                name: name.to_owned(),
+            }));
+        }
+        for (id, &(source, target)) in channels.iter().enumerate() {
+            events.push(RawTimelyEvent::Channels(ChannelsEvent {
+                id: operators.len() + id,
+                scope_addr: vec![0],
+                source,
+                target,
+            }));
+        }
+        for &(id, _, _) in operators {
+            let count = activations
+                .iter()
+                .find(|&&(operator, _)| operator == id)
+                .map_or(1, |&(_, count)| count);
+
+            for _ in 0..count {
+                events.push(RawTimelyEvent::Schedule(ScheduleEvent::start(id)));
+                events.push(RawTimelyEvent::Schedule(ScheduleEvent::stop(id)));
+            }
+        }
+
+        let mut batch: Vec<_> = events
+            .into_iter()
+            .enumerate()
+            .map(|(idx, event)| (Duration::from_millis(idx as u64 + 1), worker, event))
+            .collect();
+        let end = batch.last().map(|&(time, _, _)| time).unwrap() + Duration::from_millis(1);
+        logger.publish_batch(&end, &mut batch);
+        drop(logger);
+
+        self
+    }
+
+    /// Replays the logs of the streams that the args enable
+    fn sources(&self, args: &Args) -> ReplaySources {
+        self.sources_with(args, SharedBuffer::reader)
+    }
+
+    /// Replays the logs of the streams that the args enable, reading each buffer with `reader`
+    fn sources_with<R, F>(&self, args: &Args, reader: F) -> ReplaySources
+    where
+        R: Read + Send + 'static,
+        F: Fn(&SharedBuffer) -> R,
+    {
+        let readers = |logs: &[SharedBuffer]| logs.iter().map(&reader).collect();
+
+        ReplaySources::from_readers(
+            args,
+            readers(&self.timely),
+            Some(readers(&self.differential)),
+            Some(readers(&self.progress)),
+        )
+        .unwrap()
+    }
+}
+
+/// A channel's source and target `(operator index, port)`s within the `[0]` dataflow
+type SyntheticChannel = ((usize, usize), (usize, usize));
+
+/// Profiles [`target_dataflow`] on the given number of workers, logging every event
+fn profile_target(workers: usize) -> TargetLogs {
+    TargetLogs::new(workers).profile(&SinkConfig::new(), target_dataflow)
+}
+
+fn target_dataflow<A: Allocate>(worker: &mut Worker<A>) {
+    let (mut input, _trace) = worker.dataflow_named::<usize, _, _>("Target Dataflow", |scope| {
+        (0..1000).to_stream(scope).map(|x| x * 2).inspect(|_| {});
+
+        // Keep the arrangement's trace alive so that it actually maintains its batches
+        let (input, collection) = scope.new_collection::<usize, isize>();
+        let trace = collection.arrange_by_self().trace;
+
+        (input, trace)
+    });
+
+    // Feed the arrangement a few batches so that it'll merge them
+    for round in 0..20 {
+        for x in 0..100 {
+            input.insert(round * 100 + x);
+        }
+
+        input.advance_to(round + 1);
+        input.flush();
+        worker.step();
+    }
+}
+
+#[test]
+fn in_process_operator_stats() {
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        workers: NonZeroUsize::new(1).unwrap(),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let sources = profile_target(1).sources(&args);
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+
+    let names: Vec<&str> = data
+        .name_lookup
+        .iter()
+        .map(|(_, name)| name.as_str())
+        .collect();
+    assert!(names.contains(&"Target Dataflow"), "{:?}", names);
+    assert!(names.contains(&"Map"), "{:?}", names);
+
+    // Every leaf operator has stats and at least one activation
+    assert!(!data.operator_stats.is_empty());
+    assert!(data
+        .aggregated_operator_stats
+        .iter()
+        .any(|(_, stats)| stats.activations > 0));
+
+    // Activation percentiles are ordered and lie between the fastest and slowest activations
+    for (_, stats) in data.operator_stats.iter() {
+        let percentiles = stats.percentiles;
+        let ordered = [
+            stats.min,
+            percentiles.p50,
+            percentiles.p90,
+            percentiles.p99,
+            percentiles.p999,
+            stats.max,
+        ];
+        assert!(
+            ordered.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{:#?}",
+            stats
+        );
+        assert!(stats.std_dev <= stats.max - stats.min, "{:#?}", stats);
+    }
+
+    // The arrangement was picked up from the differential logs
+    let stats = ddshow::ddshow_stats(&args, &data);
+    assert_eq!(stats.program.workers, 1);
+    assert_eq!(stats.program.arrangements, 1);
+    assert!(stats.differential_enabled);
+}
+
+#[test]
+fn dump_json_matches_schema() {
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let sources = profile_target(1).sources(&args);
+
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    let stats = ddshow::ddshow_stats(&args, &data);
+
+    assert_eq!(stats.schema_version, ddshow::STATS_SCHEMA_VERSION);
+    assert!(stats
+        .nodes
+        .iter()
+        .any(|node| node.kind == NodeKind::Dataflow && node.name == "Target Dataflow"));
+    assert!(stats
+        .nodes
+        .iter()
+        .any(|node| node.kind == NodeKind::Operator && node.activations.total > 0));
+    assert!(!stats.channels.is_empty());
+    assert!(!stats.events.is_empty());
+
+    assert_eq!(stats.arrangements.len(), 1, "{:?}", stats.arrangements);
+    let arrangement = &stats.arrangements[0];
+    assert!(arrangement.size_stats.max > 0);
+    assert!(arrangement.batch_stats.total > 0);
+    assert!(arrangement.trace_shares > 0);
+
+    let schema: Value = serde_json::from_str(ddshow::STATS_JSON_SCHEMA).unwrap();
+    let json = serde_json::to_value(&stats).unwrap();
+    if let Err(err) = check_schema(&schema, &schema, &json, "stats") {
+        panic!("the stats don't match their schema: {}", err);
+    }
+}
+
+#[test]
+fn export_chrome_trace() {
+    let output_dir = env::temp_dir().join(format!("ddshow-chrome-trace-{}", process::id()));
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        no_report_file: true,
+        output_dir: output_dir.clone(),
+        chrome_trace: Some(output_dir.join("trace.json")),
+        program_name: Some("target".to_owned()),
+        ..Default::default()
+    });
+
+    let sources = profile_target(1).sources(&args);
+
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    let outputs = ddshow::write_outputs(&args, data).unwrap();
+    assert_eq!(outputs.chrome_trace_file, args.chrome_trace);
+
+    let trace: Value =
+        serde_json::from_slice(&fs::read(output_dir.join("trace.json")).unwrap()).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+
+    assert!(events
+        .iter()
+        .any(|event| event["name"] == "process_name" && event["args"]["name"] == "target"));
+    assert!(events
+        .iter()
+        .any(|event| event["name"] == "thread_name" && event["args"]["name"] == "Worker 0"));
+
+    // Activations are complete events named after their operator
+    let activations: Vec<_> = events
+        .iter()
+        .filter(|event| event["cat"] == "activation")
+        .collect();
+    assert!(!activations.is_empty());
+    assert!(activations.iter().all(|event| event["ph"] == "X"
+        && event["tid"] == 0
+        && event["ts"].is_number()
+        && event["dur"].is_number()));
+    assert!(activations
+        .iter()
+        .any(|event| event["name"].as_str().unwrap().starts_with("Map [")));
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn export_dot_graph() {
+    let output_dir = env::temp_dir().join(format!("ddshow-dot-{}", process::id()));
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        progress_enabled: true,
+        no_report_file: true,
+        output_dir: output_dir.clone(),
+        dot: Some(output_dir.join("graph.dot")),
+        ..Default::default()
+    });
+
+    // Progress only records message counts when they're exchanged between workers
+    let sources = profile_target(2).sources(&args);
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    let outputs = ddshow::write_outputs(&args, data).unwrap();
+    assert_eq!(outputs.dot_file, args.dot);
+
+    let dot = fs::read_to_string(output_dir.join("graph.dot")).unwrap();
+    assert!(dot.starts_with("digraph dataflow {"), "{}", dot);
+    assert!(dot.trim_end().ends_with('}'), "{}", dot);
+
+    // The dataflow is a cluster containing its operators
+    assert!(dot.contains("subgraph \"cluster_[0]\" {"), "{}", dot);
+    assert!(dot.contains("label=\"Target Dataflow [0]\";"), "{}", dot);
+    assert!(
+        dot.contains("        \"[0, 1]\" [label=\"ToStream\\n[0, 1]\", fillcolor=\"#"),
+        "{}",
+        dot
+    );
+
+    // Channels are labeled with the number of messages sent over them
+    assert!(
+        dot.contains("\"[0, 5]\" -> \"[0, 6]\" [label=\""),
+        "{}",
+        dot
+    );
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn critical_paths() {
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        progress_enabled: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    // Progress only records message counts when they're exchanged between workers
+    let sources = profile_target(2).sources(&args);
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+
+    let paths = critical_path::critical_paths(&data);
+    let path = paths
+        .iter()
+        .find(|path| path.name == "Target Dataflow")
+        .unwrap_or_else(|| panic!("the target dataflow has no critical path: {:#?}", paths));
+    assert_eq!(path.dataflow, OperatorAddr::from(vec![0]));
+    assert!(path.timestamps > 0);
+    assert!(path.average_latency <= path.max_latency);
+
+    // Every timestamp's latency is split between the operators on its path
+    let slowest = path.slowest.as_ref().unwrap();
+    assert_eq!(slowest.latency, path.max_latency);
+    assert!(!slowest.path.is_empty());
+    assert!(slowest
+        .path
+        .iter()
+        .all(|addr| addr.len() == 2 && addr[0] == OperatorId::new(0)));
+
+    let total_latency: Duration = path
+        .contributors
+        .iter()
+        .map(|contributor| contributor.latency)
+        .sum();
+    assert!(total_latency >= path.max_latency);
+    assert!(path
+        .contributors
+        .windows(2)
+        .all(|pair| pair[0].latency >= pair[1].latency));
+    assert!(path
+        .contributors
+        .iter()
+        .all(|contributor| contributor.timestamps <= path.timestamps));
+
+    let stats = ddshow::ddshow_stats(&args, &data);
+    assert_eq!(stats.critical_paths, paths);
+}
+
+#[test]
+fn lint_unarranged_loops() {
+    let output_dir = env::temp_dir().join(format!("ddshow-lints-{}", process::id()));
+    let mut args = Args {
+        differential_enabled: true,
+        output_dir: output_dir.clone(),
+        report_file: output_dir.join("report.txt"),
+        ..Default::default()
+    };
+
+    let logs = TargetLogs::new(1).profile(&SinkConfig::new(), |worker| {
+        worker.dataflow_named::<usize, _, _>("Loop Dataflow", |scope| {
+            // A timely loop that never consolidates the data flowing around it
+            let numbers = (0..10usize).to_stream(scope);
+            scope.iterative::<usize, _, _>(|inner| {
+                let (handle, cycle) = inner.loop_variable(1);
+                numbers
+                    .enter(inner)
+                    .concat(&cycle)
+                    .filter(|&x| x < 100)
+                    .map(|x| x + 1)
+                    .connect_loop(handle);
+            });
+
+            // A differential loop, which is consolidated by its `distinct()`
+            (0..10usize)
+                .to_stream(scope)
+                .map(|x| (x, 0, 1isize))
+                .as_collection()
+                .iterate(|inner| inner.map(|x| (x + 1) % 10).concat(inner).distinct());
+        });
+    });
+
+    let analyze = |args: &Args| {
+        let args = Arc::new(args.clone());
+        let sources = logs.sources(&args);
+
+        let data = ddshow::analyze(args.clone(), sources).unwrap();
+        (lint::lint(&args, &data), data)
+    };
+
+    let (lints, data) = analyze(&args);
+    let feedback: Vec<_> = lints
+        .iter()
+        .filter(|lint| lint.rule == LintRule::ArrangeBeforeFeedback)
+        .collect();
+    assert_eq!(feedback.len(), 1, "{:#?}", lints);
+    assert_eq!(feedback[0].severity, Severity::Warning);
+    assert!(
+        feedback[0].explanation.contains("Feedback"),
+        "{}",
+        feedback[0].explanation,
+    );
+    assert!(!lints
+        .iter()
+        .any(|lint| lint.rule == LintRule::InconsistentWorkers));
+
+    // The lints make it into the report
+    fs::create_dir_all(&output_dir).unwrap();
+    ddshow::write_outputs(&args, data).unwrap();
+    let report = fs::read_to_string(output_dir.join("report.txt")).unwrap();
+    assert!(report.contains("arrange-before-feedback"), "{}", report);
+
+    // Lints can be disabled
+    args.disabled_lints = vec![LintRule::ArrangeBeforeFeedback];
+    let (lints, _) = analyze(&args);
+    assert!(!lints
+        .iter()
+        .any(|lint| lint.rule == LintRule::ArrangeBeforeFeedback));
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn workers_with_different_dataflows() {
+    let args = Arc::new(Args {
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    // Worker 1 renamed the map and added an operator to the end of the dataflow
+    let logs = TargetLogs::new(2)
+        .synthetic_worker(
+            0,
+            &[
+                (0, &[0], "Dataflow"),
+                (1, &[0, 1], "Input"),
+                (2, &[0, 2], "Map"),
+                (3, &[0, 3], "Inspect"),
+            ],
+            &[((1, 0), (2, 0)), ((2, 0), (3, 0))],
+            &[],
+        )
+        .synthetic_worker(
+            1,
+            &[
+                (0, &[0], "Dataflow"),
+                (1, &[0, 1], "Input"),
+                (2, &[0, 2], "Filter"),
+                (3, &[0, 3], "Inspect"),
+                (4, &[0, 4], "Probe"),
+            ],
+            &[((1, 0), (2, 0)), ((2, 0), (3, 0)), ((3, 0), (4, 0))],
+            &[],
+        );
+
+    let sources = logs.sources(&args);
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+
+    let mut divergences = data.worker_divergences.clone();
+    divergences.sort();
+    let (worker, reference) = (WorkerId::new(1), WorkerId::new(0));
+    assert_eq!(
+        divergences,
+        vec![
+            WorkerDivergence {
+                worker,
+                reference,
+                addr: OperatorAddr::from_slice(&[OperatorId::new(0), OperatorId::new(2)]),
+                kind: DivergenceKind::RenamedOperator {
+                    name: "Filter".to_owned(),
+                    expected: "Map".to_owned(),
+                },
+            },
+            WorkerDivergence {
+                worker,
+                reference,
+                addr: OperatorAddr::from_slice(&[OperatorId::new(0), OperatorId::new(3)]),
+                kind: DivergenceKind::ExtraChannel {
+                    source_port: PortId::new(0),
+                    target: OperatorAddr::from_slice(&[OperatorId::new(0), OperatorId::new(4)]),
+                    target_port: PortId::new(0),
+                },
+            },
+            WorkerDivergence {
+                worker,
+                reference,
+                addr: OperatorAddr::from_slice(&[OperatorId::new(0), OperatorId::new(4)]),
+                kind: DivergenceKind::ExtraOperator {
+                    name: "Probe".to_owned(),
+                },
+            },
+        ],
+    );
+
+    // Only the operators that match the reference worker's are aggregated, so worker 1's
+    // filter and probe are left out
+    let mut aggregated: Vec<_> = data
+        .aggregated_operator_stats
+        .iter()
+        .map(|(operator, stats)| (operator.into_inner(), stats.activations))
+        .collect();
+    aggregated.sort_unstable();
+    assert_eq!(aggregated, vec![(0, 2), (1, 2), (2, 1), (3, 2)]);
+
+    // Every divergence is reported as a lint on the diverging worker
+    let lints = lint::lint(&args, &data);
+    let inconsistent: Vec<_> = lints
+        .iter()
+        .filter(|lint| lint.rule == LintRule::InconsistentWorkers)
+        .collect();
+    assert_eq!(inconsistent.len(), 3, "{:#?}", lints);
+    assert!(inconsistent
+        .iter()
+        .all(|lint| lint.severity == Severity::Critical && lint.workers == vec![worker]));
+}
+
+#[test]
+fn skewed_workers() {
+    let output_dir = env::temp_dir().join(format!("ddshow-skew-{}", process::id()));
+    let args = Arc::new(Args {
+        output_dir: output_dir.clone(),
+        report_file: output_dir.join("report.txt"),
+        ..Default::default()
+    });
+
+    // The first worker's map does nine times as much work as the second's
+    let operators: &[(usize, &[usize], &str)] = &[
+        (0, &[0], "Dataflow"),
+        (1, &[0, 1], "Input"),
+        (2, &[0, 2], "Map"),
+    ];
+    let channels = &[((1, 0), (2, 0))];
+    let sources = TargetLogs::new(2)
+        .synthetic_worker(0, operators, channels, &[(2, 9)])
+        .synthetic_worker(1, operators, channels, &[])
+        .sources(&args);
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+
+    let skews = skew::operator_skew(&data);
+    assert_eq!(skews.len(), 2, "{:#?}", skews);
+
+    // 9ms and 1ms have a median of 5ms and a standard deviation of 4ms
+    let map = &skews[0];
+    assert_eq!((map.name.as_str(), map.workers), ("Map", 2));
+    assert_eq!(map.runtime.max_worker, WorkerId::new(0));
+    assert_eq!(map.runtime.max_median_ratio, Some(1.8));
+    assert!(
+        (map.runtime.coefficient_of_variation - 0.8).abs() < 1e-9,
+        "{:#?}",
+        map
+    );
+    assert_eq!(map.activations.max_median_ratio, Some(1.8));
+    assert!(map.highlighted, "{:#?}", map);
+
+    // The input is evenly spread
+    let input = &skews[1];
+    assert_eq!(input.name, "Input");
+    assert_eq!(input.runtime.max_median_ratio, Some(1.0));
+    assert_eq!(input.runtime.coefficient_of_variation, 0.0);
+    assert!(!input.highlighted);
+
+    // The skew is in the report and highlighted on the graph
+    fs::create_dir_all(&output_dir).unwrap();
+    ddshow::write_outputs(&args, data).unwrap();
+    let report = fs::read_to_string(output_dir.join("report.txt")).unwrap();
+    assert!(
+        report.contains("Operators Ranked by Worker Skew"),
+        "{}",
+        report
+    );
+    assert!(report.contains("Map (skewed)"), "{}", report);
+
+    let graph = fs::read_to_string(output_dir.join("graph.js")).unwrap();
+    assert!(graph.contains("\"highlighted\":true"), "{}", graph);
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn activation_histograms() {
+    let operators: &[(usize, &[usize], &str)] = &[
+        (0, &[0], "Dataflow"),
+        (1, &[0, 1], "Input"),
+        (2, &[0, 2], "Map"),
+    ];
+    let channels = &[((1, 0), (2, 0))];
+
+    let analyze = |args: &Arc<Args>| {
+        let sources = TargetLogs::new(2)
+            .synthetic_worker(0, operators, channels, &[(2, 3)])
+            .synthetic_worker(1, operators, channels, &[(2, 2)])
+            .sources(args);
+
+        ddshow::analyze(args.clone(), sources).unwrap()
+    };
+
+    let output_dir = env::temp_dir().join(format!("ddshow-histograms-{}", process::id()));
+    let args = Arc::new(Args {
+        output_dir: output_dir.clone(),
+        no_report_file: true,
+        ..Default::default()
+    });
+    let data = analyze(&args);
+
+    // Every activation takes 1ms, so they all land in the bucket holding 1ms
+    let map_ids: Vec<_> = (0..2)
+        .map(|worker| {
+            data.name_lookup
+                .iter()
+                .find(|&&((op_worker, _), ref name)| {
+                    op_worker == WorkerId::new(worker) && name == "Map"
+                })
+                .map(|&((_, id), _)| id)
+                .unwrap()
+        })
+        .collect();
+    let bucket_of = |histogram: &ActivationHistogram| {
+        assert_eq!(histogram.buckets.len(), 1, "{:#?}", histogram);
+        let bucket = histogram.buckets[0];
+        assert!(
+            bucket.lower <= Duration::from_millis(1) && Duration::from_millis(1) < bucket.upper
+        );
+
+        bucket.count
+    };
+
+    for &(worker, activations) in &[(0, 3), (1, 2)] {
+        let (_, stats) = data
+            .operator_stats
+            .iter()
+            .find(|&&((op_worker, id), _)| {
+                op_worker == WorkerId::new(worker) && id == map_ids[worker]
+            })
+            .unwrap();
+        assert_eq!(bucket_of(&stats.activation_histogram), activations);
+        assert_eq!(stats.percentiles.p99, Duration::from_millis(1));
+        assert_eq!(stats.std_dev, Duration::from_secs(0));
+
+        // The raw durations aren't recorded by default
+        assert!(stats.activation_durations.is_empty());
+    }
+
+    let (_, aggregated) = data
+        .aggregated_operator_stats
+        .iter()
+        .find(|&&(id, _)| id == map_ids[0])
+        .unwrap();
+    assert_eq!(bucket_of(&aggregated.activation_histogram), 5);
+    assert!(aggregated.activation_durations.is_empty());
+
+    // The histograms are in the json output and the dashboard
+    let stats = ddshow::ddshow_stats(&args, &data);
+    assert!(stats
+        .nodes
+        .iter()
+        .filter(|node| node.name == "Map")
+        .all(|node| node.activation_histogram.buckets.len() == 1
+            && node.activations.data_points.is_empty()));
+
+    fs::create_dir_all(&output_dir).unwrap();
+    ddshow::write_outputs(&args, data).unwrap();
+    let dashboard = fs::read_to_string(output_dir.join("dashboard.html")).unwrap();
+    assert!(
+        dashboard.contains("\"activation_histogram\":[[999424,1015808,5]]"),
+        "{}",
+        dashboard
+    );
+    fs::remove_dir_all(&output_dir).unwrap();
+
+    // The raw durations can still be opted into
+    let args = Arc::new(Args {
+        activation_durations: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+    let data = analyze(&args);
+    let (_, aggregated) = data
+        .aggregated_operator_stats
+        .iter()
+        .find(|&&(id, _)| id == map_ids[0])
+        .unwrap();
+    assert_eq!(aggregated.activation_durations.len(), 5);
+    assert!(aggregated
+        .activation_durations
+        .iter()
+        .all(|&(_, duration)| duration == Duration::from_millis(1)));
+}
+
+/// Checks a value against the subset of JSON Schema that the stats schema uses,
+/// along with making sure that the schema describes every field of the value
+fn check_schema(schema: &Value, root: &Value, value: &Value, path: &str) -> Result<(), String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/definitions/");
+        return check_schema(&root["definitions"][name], root, value, path);
+    }
+
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let matches = variants
+            .iter()
+            .filter(|variant| check_schema(variant, root, value, path).is_ok())
+            .count();
+
+        return if matches == 1 {
+            Ok(())
+        } else {
+            Err(format!("{} matched {} variants: {}", path, matches, value))
+        };
+    }
+
+    if let Some(variants) = schema["enum"].as_array() {
+        if !variants.contains(value) {
+            return Err(format!("{} isn't a valid variant: {}", path, value));
+        }
+    }
+
+    if let Some(constant) = schema.get("const") {
+        if value != constant {
+            return Err(format!("{} should be {}: {}", path, constant, value));
+        }
+    }
+
+    let matches_type = match schema["type"].as_str() {
+        Some("object") => value.is_object(),
+        Some("array") => value.is_array(),
+        Some("integer") => value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("string") => value.is_string(),
+        Some("boolean") => value.is_boolean(),
+        Some("null") => value.is_null(),
+        _ => true,
+    };
+    if !matches_type {
+        return Err(format!(
+            "{} should be a {}: {}",
+            path, schema["type"], value
+        ));
+    }
+
+    if let (Some(object), Some(properties)) = (value.as_object(), schema["properties"].as_object())
+    {
+        for required in schema["required"].as_array().into_iter().flatten() {
+            let required = required.as_str().unwrap();
+            if !object.contains_key(required) {
+                return Err(format!("{} is missing `{}`", path, required));
+            }
+        }
+
+        for (key, field) in object {
+            let field_schema = properties
+                .get(key)
+                .ok_or_else(|| format!("{}.{} isn't in the schema", path, key))?;
+
+            check_schema(field_schema, root, field, &format!("{}.{}", path, key))?;
+        }
+    }
+
+    if let Some(items) = value.as_array() {
+        for (idx, item) in items.iter().enumerate() {
+            check_schema(&schema["items"], root, item, &format!("{}[{}]", path, idx))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn replay_compressed_logs() {
+    for &compression in &[Compression::Zstd, Compression::Gzip] {
+        let log_dir =
+            env::temp_dir().join(format!("ddshow-{}-logs-{}", compression, process::id()));
+        let _ = fs::remove_dir_all(&log_dir);
+
+        let save_dir = log_dir.clone();
+        timely::execute_directly(move |worker| {
+            let config = SinkConfig::new()
+                .save_to_disk(&save_dir)
+                .compression(compression);
+            ddshow_sink::install_timely_logging(worker, &config).unwrap();
+            target_dataflow(worker);
+        });
+
+        let args = Arc::new(Args {
+            replay_logs: Some(log_dir.clone()),
+            no_report_file: true,
+            ..Default::default()
+        });
+
+        let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+        assert_eq!(sources.total_sources(), 1);
+
+        let data = ddshow::analyze(args, sources).unwrap();
+        assert!(data
+            .name_lookup
+            .iter()
+            .any(|(_, name)| name == "Target Dataflow"));
+
+        fs::remove_dir_all(&log_dir).unwrap();
+    }
+}
+
+#[test]
+fn sampled_streams_are_flagged() {
+    let output_dir = env::temp_dir().join(format!("ddshow-sampled-{}", process::id()));
+    let args = Arc::new(Args {
+        output_dir: output_dir.clone(),
+        report_file: output_dir.join("report.txt"),
+        ..Default::default()
+    });
+
+    let config = SinkConfig::new().sample_schedules(NonZeroUsize::new(4).unwrap());
+    let sources = TargetLogs::new(1)
+        .profile(&config, target_dataflow)
+        .sources(&args);
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+
+    // Sampled streams are flagged as undercounting activations
+    assert_eq!(data.data_quality.len(), 1);
+    assert_eq!(data.data_quality[0].schedule_sample_rate, 4);
+    assert!(!data.data_quality[0].has_issues());
+
+    fs::create_dir_all(&output_dir).unwrap();
+    ddshow::write_outputs(&args, data).unwrap();
+    let report = fs::read_to_string(&args.report_file).unwrap();
+    assert!(
+        report.contains("only hold one in every 4 operator activations"),
+        "{}",
+        report
+    );
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn truncated_logs_are_reported() {
+    let output_dir = env::temp_dir().join(format!("ddshow-data-quality-{}", process::id()));
+    fs::create_dir_all(&output_dir).unwrap();
+    let args = Arc::new(Args {
+        output_dir: output_dir.clone(),
+        report_file: output_dir.join("report.txt"),
+        ..Default::default()
+    });
+
+    // Cut the log off partway through its last event
+    let sources = profile_target(1).sources_with(&args, |logs| {
+        let mut logs = logs.reader().into_inner();
+        logs.truncate(logs.len() - 4);
+        Cursor::new(logs)
+    });
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    assert!(!data.nodes.is_empty());
+
+    let quality = &data.data_quality[0];
+    assert_eq!((quality.stream, quality.worker), (StreamKind::Timely, 0));
+    assert!(quality.sequenced);
+    assert!(quality.truncated);
+    assert!(quality.missing.is_empty());
+
+    // The lost events make it into the report
+    ddshow::write_outputs(&args, data).unwrap();
+    let report = fs::read_to_string(&args.report_file).unwrap();
+    assert!(report.contains("Data Quality"), "{}", report);
+
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn flight_recorder_dumps() {
+    let dump_dir = env::temp_dir().join(format!("ddshow-flight-recorder-{}", process::id()));
+    let _ = fs::remove_dir_all(&dump_dir);
+
+    // Only the newest batch of events fits, but the dataflow graph is always kept
+    let recorder = FlightRecorder::new(&dump_dir).max_bytes(0);
+    let worker_recorder = recorder.clone();
+    timely::execute_directly(move |worker| {
+        ddshow_sink::record_timely_logs(worker, &worker_recorder).unwrap();
+        ddshow_sink::record_differential_logs(worker, &worker_recorder).unwrap();
+        target_dataflow(worker);
+    });
+    recorder.dump().unwrap();
+
+    let args = Arc::new(Args {
+        replay_logs: Some(dump_dir.clone()),
+        differential_enabled: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let window = ddshow::analyze(args.clone(), sources).unwrap();
+    fs::remove_dir_all(&dump_dir).unwrap();
+
+    let sources = profile_target(1).sources(&args);
+    let full = ddshow::analyze(args, sources).unwrap();
+    assert_eq!(window.nodes.len(), full.nodes.len());
+    assert_eq!(window.edges.len(), full.edges.len());
+    assert!(window
+        .name_lookup
+        .iter()
+        .any(|(_, name)| name == "Target Dataflow"));
+}
+
+#[test]
+fn replay_bundled_archive() {
+    let log_dir = env::temp_dir().join(format!("ddshow-bundled-logs-{}", process::id()));
+    let archive_file = log_dir.with_extension("ddarchive");
+    let _ = fs::remove_dir_all(&log_dir);
+
+    let save_dir = log_dir.clone();
+    timely::execute_directly(move |worker| {
+        let config = SinkConfig::new()
+            .save_to_disk(&save_dir)
+            .compression(Compression::Zstd);
+        ddshow_sink::install_timely_logging(worker, &config).unwrap();
+        ddshow_sink::save_differential_logs_to_disk(worker, &save_dir).unwrap();
+        target_dataflow(worker);
+    });
+
+    // Workers are taken from the files' handshakes rather than their names
+    fs::rename(
+        log_dir.join("timely.worker-0.ddshow.zst"),
+        log_dir.join("timely.worker-7.ddshow.zst"),
+    )
+    .unwrap();
+
+    let header = archive::bundle(&log_dir, &archive_file, "target").unwrap();
+    assert_eq!(header, ArchiveHeader::open(&archive_file).unwrap());
+    assert_eq!(header.workers, 1);
+    assert!(header.sections.iter().all(|section| section.worker == 0));
+    assert_eq!(
+        header.start_time,
+        Handshake::new(ddshow_sink::StreamKind::Timely, 0, 1).started_at,
+    );
+    assert_eq!(header.program_name, "target");
+    assert_eq!(
+        header.streams(),
+        vec![StreamKind::Timely, StreamKind::Differential],
+    );
+    fs::remove_dir_all(&log_dir).unwrap();
+
+    let args = Arc::new(Args {
+        replay_logs: Some(archive_file.clone()),
+        differential_enabled: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    assert!(data
+        .name_lookup
+        .iter()
+        .any(|(_, name)| name == "Target Dataflow"));
+    assert_eq!(ddshow::ddshow_stats(&args, &data).program.arrangements, 1);
+
+    // Truncated archives are rejected before anything is loaded
+    let archive = fs::read(&archive_file).unwrap();
+    fs::write(&archive_file, &archive[..archive.len() - 1]).unwrap();
+
+    let err = ddshow::connect_to_sources(&args).err().unwrap();
+    assert!(format!("{:?}", err).contains("truncated"), "{:?}", err);
+
+    fs::remove_file(&archive_file).unwrap();
+}
+
+fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serve_live_graph() {
+    let args = Arc::new(Args {
+        differential_enabled: true,
+        no_report_file: true,
+        serve: Some("127.0.0.1:0".parse().unwrap()),
+        ..Default::default()
+    });
+
+    let sources = profile_target(1).sources(&args);
+
+    let analysis = Analysis::start(args, sources).unwrap();
+    let addr = analysis.server_addr().unwrap();
+    analysis.wait_for_completion().unwrap();
+
+    // The server keeps serving the final data after the analysis finishes
+    let data = http_get(addr, "/data.json");
+    assert!(data.starts_with("HTTP/1.1 200 OK"), "{}", data);
+    assert!(data.contains("application/json"), "{}", data);
+    assert!(data.contains("\"Target Dataflow\""), "{}", data);
+
+    let graph = http_get(addr, "/");
+    assert!(graph.starts_with("HTTP/1.1 200 OK"), "{}", graph);
+    assert!(graph.contains("<title>Dataflow Graph</title>"));
+
+    let graph_js = http_get(addr, "/graph.js?refresh=1");
+    assert!(graph_js.contains("const live_update_interval = 1000;"));
+
+    let dashboard = http_get(addr, "/dashboard.html");
+    assert!(dashboard.starts_with("HTTP/1.1 200 OK"), "{}", dashboard);
+
+    let metrics = http_get(addr, "/metrics");
+    assert!(metrics.starts_with("HTTP/1.1 200 OK"), "{}", metrics);
+    assert!(
+        metrics.contains("Content-Type: application/openmetrics-text; version=1.0.0"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("ddshow_program_workers 1\n"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("# TYPE ddshow_operator_activations counter\n"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.lines().any(|line| line
+            .starts_with("ddshow_operator_activations_total{worker=\"0\",operator=")
+            && line.contains(",name=\"ArrangeBySelf\",addr=\"[0, 6]\"} ")),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("ddshow_arrangement_max_size{worker=\"0\""),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("ddshow_worker_events_total{worker=\"0\"} "),
+        "{}",
+        metrics
+    );
+    assert!(metrics.ends_with("# EOF\n"), "{}", metrics);
+
+    let missing = http_get(addr, "/missing.html");
+    assert!(missing.starts_with("HTTP/1.1 404 Not Found"), "{}", missing);
+}
+
+/// Reads the logs of a target that's still running, returning
+/// `WouldBlock` while the target isn't sending anything
+struct LiveSource {
+    logs: SharedBuffer,
+    position: usize,
+}
+
+impl LiveSource {
+    fn new(logs: SharedBuffer) -> Self {
+        Self { logs, position: 0 }
+    }
+}
+
+impl Read for LiveSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let logs = self.logs.0.lock().unwrap();
+        let len = buf.len().min(logs.len() - self.position);
+
+        if len == 0 {
+            drop(logs);
+            thread::sleep(Duration::from_millis(10));
+
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        buf[..len].copy_from_slice(&logs[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Runs an analysis over a target that stays alive until the analysis is
+/// stopped, returning how long the analysis ran for
+fn run_until_stopped<F>(mut args: Args, stop: F) -> Duration
+where
+    F: FnOnce(&StopHandle),
+{
+    args.differential_enabled = true;
+    args.no_report_file = true;
+    args.output_dir = env::temp_dir().join(format!(
+        "ddshow-stopped-{}-{:?}",
+        process::id(),
+        thread::current().id(),
+    ));
+    let args = Arc::new(args);
+
+    let (logs, finished) = (TargetLogs::new(1), Arc::new(AtomicBool::new(false)));
+
+    let (target_logs, target_finished) = (logs.clone(), finished.clone());
+    let target = thread::spawn(move || {
+        target_logs.profile(&SinkConfig::new(), move |worker| {
+            target_dataflow(worker);
+
+            // Idle without disconnecting until the analysis is done
+            worker.step();
+            while !target_finished.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+    });
+
+    let sources = logs.sources_with(&args, |logs| LiveSource::new(logs.clone()));
+
+    let started = Instant::now();
+    let analysis = Analysis::start(args.clone(), sources).unwrap();
+    stop(&analysis.stop_handle());
+
+    let data = analysis.wait_for_completion().unwrap();
+    let elapsed = started.elapsed();
+
+    finished.store(true, Ordering::Release);
+    target.join().unwrap();
+
+    assert!(data
+        .name_lookup
+        .iter()
+        .any(|(_, name)| name == "Target Dataflow"));
+
+    // Stopping early still writes out all of the output files
+    let outputs = ddshow::write_outputs(&args, data).unwrap();
+    for file in &["graph.html", "graph.js", "dashboard.html"] {
+        assert!(args.output_dir.join(file).is_file(), "{}", file);
+    }
+    assert!(outputs.graph_file.is_file());
+
+    fs::remove_dir_all(&args.output_dir).unwrap();
+    elapsed
+}
+
+#[test]
+fn stop_after_duration() {
+    let args = Args {
+        stop_after: Some(2),
+        ..Default::default()
+    };
+    assert!(!args.is_interactive());
+
+    let elapsed = run_until_stopped(args, |_| {});
+    assert!(elapsed >= Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[test]
+fn stop_when_idle() {
+    let args = Args {
+        stop_when_idle: Some(1),
+        ..Default::default()
+    };
+
+    let elapsed = run_until_stopped(args, |_| {});
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(60), "{:?}", elapsed);
+}
+
+#[test]
+fn stop_handle_stops_analysis() {
+    let args = Args {
+        stop_on_disconnect: true,
+        ..Default::default()
+    };
+
+    let elapsed = run_until_stopped(args, |stop| {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(1));
+            stop.stop();
+        });
+    });
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test]
+fn diff_saved_traces() {
+    let trace_dir = env::temp_dir().join(format!("ddshow-diff-traces-{}", process::id()));
+    let (trace_a, trace_b) = (trace_dir.join("a"), trace_dir.join("b"));
+    let _ = fs::remove_dir_all(&trace_dir);
+
+    for &(save_dir, extra_dataflow) in [(&trace_a, false), (&trace_b, true)].iter() {
+        let save_dir = save_dir.clone();
+        timely::execute_directly(move |worker| {
+            ddshow_sink::save_timely_logs_to_disk(worker, &save_dir).unwrap();
+            ddshow_sink::save_differential_logs_to_disk(worker, &save_dir).unwrap();
+            target_dataflow(worker);
+
+            if extra_dataflow {
+                worker.dataflow_named::<usize, _, _>("Extra Dataflow", |scope| {
+                    (0..1000).to_stream(scope).inspect(|_| {});
+                });
+            }
+        });
+    }
+
+    let diff = TraceDiff::new(&Args::default(), &trace_a, &trace_b).unwrap();
+
+    // Every operator of the first trace is matched with its twin in the second
+    assert!(diff.only_in_a.is_empty(), "{:?}", diff.only_in_a);
+    assert!(diff
+        .operators
+        .iter()
+        .any(|operator| operator.name == "Target Dataflow"));
+    assert!(diff
+        .operators
+        .iter()
+        .any(|operator| operator.arrangement_size_change.is_some()));
+    assert!(diff
+        .only_in_b
+        .iter()
+        .any(|operator| operator.name == "Extra Dataflow"));
+
+    let mut table = Vec::new();
+    diff.write_table(&mut table).unwrap();
+    let table = String::from_utf8(table).unwrap();
+    assert!(
+        table.contains("Operators Ranked by Runtime Change"),
+        "{}",
+        table
+    );
+    assert!(table.contains("Extra Dataflow"), "{}", table);
+
+    let json_file = trace_dir.join("diff.json");
+    diff.write_json(&json_file).unwrap();
+    let json: TraceDiff = serde_json::from_slice(&fs::read(&json_file).unwrap()).unwrap();
+    assert_eq!(json.operators.len(), diff.operators.len());
+    assert_eq!(json.only_in_b.len(), diff.only_in_b.len());
+
+    fs::remove_dir_all(&trace_dir).unwrap();
+}
+
+/// Gets an unused local address for ddshow to listen on
+fn unused_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Connects to ddshow once it's listening and sends the given handshake
+fn connect_when_listening(address: SocketAddr, handshake: &[u8]) -> TcpStream {
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => break stream,
+            Err(err) if started.elapsed() < Duration::from_secs(30) => {
+                let _ = err;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("ddshow never started listening on {}: {:?}", address, err),
+        }
+    };
+
+    stream.write_all(handshake).unwrap();
+    stream
+}
+
+#[test]
+fn restarted_workers_are_stitched() {
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        stream_encoding: StreamEncoding::Rkyv,
+        reconnect: true,
+        stop_when_idle: Some(1),
+        activation_durations: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    // Run the target twice, like a service that restarts
+    let address = args.timely_address;
+    let target = thread::spawn(move || {
+        for session in 1..=2 {
+            timely::execute_directly(move |worker| {
+                let handshake = Handshake {
+                    session,
+                    ..Handshake::new(ddshow_sink::StreamKind::Timely, 0, 1)
+                };
+                ddshow_sink::enable_timely_logging_with_config(
+                    worker,
+                    connect_when_listening(address, &handshake.to_bytes()),
+                    &SinkConfig::new().skip_handshake(),
+                );
+                target_dataflow(worker);
+            });
+        }
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    // Both runs make it into the same replay
+    let args = Arc::new(Args {
+        no_report_file: true,
+        ..Default::default()
+    });
+    let sources = profile_target(1).sources(&args);
+    let single_run = ddshow::analyze(args, sources).unwrap();
+
+    let activations = |data: &DataflowData| -> usize {
+        data.operator_stats
+            .iter()
+            .map(|(_, stats)| stats.activations)
+            .sum()
+    };
+    assert!(activations(&data) > activations(&single_run));
+
+    // The restarted run comes after the first one instead of overlapping it
+    for (_, stats) in data.operator_stats.iter() {
+        assert!(
+            stats
+                .activation_durations
+                .windows(2)
+                .all(|pair| pair[0].0 + pair[0].1 <= pair[1].0),
+            "{:#?}",
+            stats,
+        );
+    }
+
+    assert_eq!(data.data_quality.len(), 1);
+    assert!(data.data_quality[0].sequenced);
+    assert!(!data.data_quality[0].has_issues());
+}
+
+#[test]
+fn reconnecting_writer_reports_lost_events() {
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        stream_encoding: StreamEncoding::Rkyv,
+        reconnect: true,
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let address = args.timely_address;
+    let (started, finished) = (Arc::new(Barrier::new(2)), Arc::new(AtomicBool::new(false)));
+    let (target_started, target_finished) = (started.clone(), finished.clone());
+    let target = thread::spawn(move || {
+        timely::execute_directly(move |worker| {
+            ddshow_sink::stream_timely_logs(worker, address).unwrap();
+
+            // ddshow isn't listening yet, so these events are lost
+            target_dataflow(worker);
+            target_started.wait();
+
+            // Keep logging until the writer reconnects and ddshow has seen it
+            while !target_finished.load(Ordering::Acquire) {
+                worker.dataflow::<(), _, _>(|scope| {
+                    (0..10).to_stream(scope).inspect(|_| {});
+                });
+                worker.step();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+    });
+
+    started.wait();
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    let analysis = Analysis::start(args, sources).unwrap();
+    thread::sleep(Duration::from_secs(2));
+
+    analysis.stop_handle().stop();
+    let data = analysis.wait_for_completion().unwrap();
+    finished.store(true, Ordering::Release);
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+
+    // Everything logged before ddshow started listening was dropped
+    let quality = &data.data_quality[0];
+    assert!(quality.sequenced);
+    assert!(quality.dropped > 0);
+    assert!(quality.missing.is_empty());
+    assert!(!quality.truncated);
+}
+
+#[test]
+fn handshakes_configure_connections() {
+    // Neither the encoding nor the number of workers are given to ddshow
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        stop_when_idle: Some(1),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let address = args.timely_address;
+    let target = thread::spawn(move || {
+        timely::execute(timely::Config::process(2), move |worker| {
+            // The default loggers send the worker's handshake themselves
+            ddshow_sink::enable_timely_logging(worker, connect_when_listening(address, &[]));
+            target_dataflow(worker);
+        })
+        .unwrap();
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+    let workers: Vec<_> = data
+        .data_quality
+        .iter()
+        .map(|quality| quality.worker)
+        .collect();
+    assert_eq!(workers, vec![0, 1]);
+    assert!(data
+        .data_quality
+        .iter()
+        .all(|quality| !quality.has_issues()));
+}
+
+#[test]
+fn plain_connections_use_args() {
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        stream_encoding: StreamEncoding::Rkyv,
+        stop_when_idle: Some(1),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    // Connections without a handshake are read with the encoding given by the args
+    let address = args.timely_address;
+    let target = thread::spawn(move || {
+        timely::execute_directly(move |worker| {
+            ddshow_sink::enable_timely_logging_with_config(
+                worker,
+                connect_when_listening(address, &[]),
+                &SinkConfig::new().skip_handshake(),
+            );
+            target_dataflow(worker);
+        });
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+    assert_eq!(data.data_quality.len(), 1);
+}
+
+#[test]
+fn incompatible_handshakes_are_rejected() {
+    let mut newer_version = Handshake::new(ddshow_sink::StreamKind::Timely, 0, 1).to_bytes();
+    newer_version[8..12].copy_from_slice(&99u32.to_le_bytes());
+
+    let cases = vec![
+        newer_version.clone(),
+        // Differential logs aren't enabled, and since the connection was rejected
+        // its worker count doesn't decide which later connections are accepted
+        Handshake::new(ddshow_sink::StreamKind::Differential, 0, 2).to_bytes(),
+        // The worker index is out of range
+        Handshake::new(ddshow_sink::StreamKind::Timely, 2, 2).to_bytes(),
+        // The connection closes partway through its handshake
+        newer_version[..20].to_vec(),
+    ];
+
+    for handshake in cases {
+        let args = Args {
+            timely_address: unused_address(),
+            no_report_file: true,
+            ..Default::default()
+        };
+
+        let address = args.timely_address;
+        let target = thread::spawn(move || {
+            let mut rejected = connect_when_listening(address, &handshake);
+            rejected.shutdown(Shutdown::Write).unwrap();
+            rejected
+                .set_read_timeout(Some(Duration::from_secs(30)))
+                .unwrap();
+
+            // Wait for ddshow to close the rejected connection
+            let closed = match rejected.read(&mut [0; 1]) {
+                Ok(len) => len == 0,
+                Err(err) => err.kind() == io::ErrorKind::ConnectionReset,
+            };
+
+            // ddshow keeps accepting workers after rejecting a connection
+            let handshake = Handshake::new(ddshow_sink::StreamKind::Timely, 0, 1);
+            (
+                closed,
+                connect_when_listening(address, &handshake.to_bytes()),
+            )
+        });
+
+        let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+        let (closed, _accepted) = target.join().unwrap();
+
+        assert!(closed);
+        assert_eq!(sources.total_sources(), 1);
+    }
+}
+
+#[test]
+fn multiplexed_connection_is_demultiplexed() {
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        differential_address: unused_address(),
+        progress_address: unused_address(),
+        differential_enabled: true,
+        progress_enabled: true,
+        stop_when_idle: Some(1),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    // Every worker and log stream shares a single connection
+    let address = args.timely_address;
+    let target = thread::spawn(move || {
+        let multiplexer = Multiplexer::new(connect_when_listening(address, &[])).unwrap();
+
+        timely::execute(timely::Config::process(2), move |worker| {
+            ddshow_sink::multiplex_timely_logs(worker, &multiplexer).unwrap();
+            ddshow_sink::multiplex_differential_logs(worker, &multiplexer).unwrap();
+            ddshow_sink::multiplex_timely_progress(worker, &multiplexer).unwrap();
+
+            target_dataflow(worker);
+        })
+        .unwrap();
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 6);
+
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+
+    let streams: Vec<_> = data
+        .data_quality
+        .iter()
+        .map(|quality| (quality.stream, quality.worker))
+        .collect();
+    assert_eq!(
+        streams,
+        vec![
+            (StreamKind::Timely, 0),
+            (StreamKind::Timely, 1),
+            (StreamKind::Differential, 0),
+            (StreamKind::Differential, 1),
+            (StreamKind::Progress, 0),
+            (StreamKind::Progress, 1),
+        ],
+    );
+    assert!(data
+        .data_quality
+        .iter()
+        .all(|quality| !quality.has_issues()));
+}
+
+#[test]
+fn multiplexed_channels_of_disabled_streams_are_ignored() {
+    // Only timely logs are enabled
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        stop_when_idle: Some(1),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let address = args.timely_address;
+    let target = thread::spawn(move || {
+        let multiplexer = Multiplexer::new(connect_when_listening(address, &[])).unwrap();
+
+        timely::execute(timely::Config::process(2), move |worker| {
+            ddshow_sink::multiplex_differential_logs(worker, &multiplexer).unwrap();
+            ddshow_sink::multiplex_timely_logs(worker, &multiplexer).unwrap();
+
+            target_dataflow(worker);
+        })
+        .unwrap();
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+    assert_eq!(data.data_quality.len(), 2);
+    assert!(data
+        .data_quality
+        .iter()
+        .all(|quality| quality.stream == StreamKind::Timely && !quality.has_issues()));
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_is_demultiplexed() {
+    let socket = env::temp_dir().join(format!("ddshow-{}.sock", process::id()));
+    let args = Arc::new(Args {
+        timely_address: unused_address(),
+        unix_socket: Some(socket.clone()),
+        stop_when_idle: Some(1),
+        no_report_file: true,
+        ..Default::default()
+    });
+
+    let target_socket = socket.clone();
+    let target = thread::spawn(move || {
+        let started = Instant::now();
+        let multiplexer = loop {
+            match Multiplexer::connect_unix(&target_socket) {
+                Ok(multiplexer) => break multiplexer,
+                Err(err) if started.elapsed() < Duration::from_secs(30) => {
+                    let _ = err;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => panic!("ddshow never started listening: {:?}", err),
+            }
+        };
+
+        timely::execute(timely::Config::process(2), move |worker| {
+            ddshow_sink::multiplex_timely_logs(worker, &multiplexer).unwrap();
+            target_dataflow(worker);
+        })
+        .unwrap();
+    });
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args, sources).unwrap();
+    target.join().unwrap();
+
+    assert!(!data.nodes.is_empty());
+    assert_eq!(data.data_quality.len(), 2);
+    assert!(!socket.exists());
+}
@@ -0,0 +1,164 @@
+//! Makes sure that traces saved by previous releases can still be replayed
+//!
+//! Each `tests/fixtures/v{N}` directory holds a trace saved with version `N` of the
+//! event format, new fixtures can be generated by running
+//! `cargo test --test compat -- --ignored` after bumping `EVENT_FORMAT_VERSION`
+
+use ddshow::args::Args;
+use ddshow_sink::{EVENT_FORMAT_VERSION, EVENT_MAGIC};
+use differential_dataflow::{
+    input::Input,
+    operators::{arrange::ArrangeBySelf, Count},
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+use timely::dataflow::operators::{Inspect, Map, ToStream};
+
+fn fixture_dir(version: u32) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(format!("v{}", version))
+}
+
+fn replay_args(replay_logs: PathBuf) -> Arc<Args> {
+    Arc::new(Args {
+        replay_logs: Some(replay_logs),
+        differential_enabled: true,
+        no_report_file: true,
+        ..Default::default()
+    })
+}
+
+fn replay_fixture(version: u32) {
+    let args = replay_args(fixture_dir(version));
+
+    let sources = ddshow::connect_to_sources(&args).unwrap().unwrap();
+    assert_eq!(sources.total_sources(), 2);
+
+    let data = ddshow::analyze(args.clone(), sources).unwrap();
+    assert!(data
+        .name_lookup
+        .iter()
+        .any(|(_, name)| name == "Fixture Dataflow"));
+    assert!(data
+        .aggregated_operator_stats
+        .iter()
+        .any(|(_, stats)| stats.activations > 0));
+
+    // Fixtures are saved in full, but only newer ones have sequence numbers
+    assert_eq!(data.data_quality.len(), 2);
+    for quality in data.data_quality.iter() {
+        assert!(!quality.has_issues(), "{:?}", quality);
+        assert_eq!(quality.sequenced, version >= 3, "{:?}", quality);
+        assert_eq!(quality.schedule_sample_rate, 1, "{:?}", quality);
+    }
+
+    let stats = ddshow::ddshow_stats(&args, &data);
+    assert_eq!(stats.program.workers, 1);
+    assert!(stats.program.arrangements >= 1);
+}
+
+/// Traces from before event files had headers
+#[test]
+fn replay_v0_fixture() {
+    replay_fixture(0);
+}
+
+#[test]
+fn replay_v1_fixture() {
+    replay_fixture(1);
+}
+
+/// Traces that can contain the events dropped by the sink's background writers
+#[test]
+fn replay_v2_fixture() {
+    replay_fixture(2);
+}
+
+/// Traces with sequence numbers that reveal missing events
+#[test]
+fn replay_v3_fixture() {
+    replay_fixture(3);
+}
+
+/// Traces that start with a handshake and can record their schedule sample rate
+#[test]
+fn replay_v4_fixture() {
+    replay_fixture(4);
+}
+
+#[test]
+fn replay_current_fixture() {
+    replay_fixture(EVENT_FORMAT_VERSION);
+}
+
+#[test]
+fn reject_newer_versions() {
+    let log_dir = env::temp_dir().join(format!("ddshow-newer-format-{}", process::id()));
+    let _ = fs::remove_dir_all(&log_dir);
+    fs::create_dir_all(&log_dir).unwrap();
+
+    for file in &["timely.worker-0.ddshow", "differential.worker-0.ddshow"] {
+        let mut contents = fs::read(fixture_dir(EVENT_FORMAT_VERSION).join(file)).unwrap();
+
+        // The event header comes after the file's handshake
+        let header = contents
+            .windows(EVENT_MAGIC.len())
+            .position(|window| window == EVENT_MAGIC)
+            .unwrap();
+        let version = header + EVENT_MAGIC.len();
+        contents[version..version + 4].copy_from_slice(&(EVENT_FORMAT_VERSION + 1).to_le_bytes());
+
+        fs::write(log_dir.join(file), contents).unwrap();
+    }
+
+    let err = ddshow::connect_to_sources(&replay_args(log_dir.clone()))
+        .err()
+        .unwrap();
+    assert!(
+        format!("{:?}", err).contains(&format!(
+            "unsupported event format version {}",
+            EVENT_FORMAT_VERSION + 1,
+        )),
+        "{:?}",
+        err,
+    );
+
+    fs::remove_dir_all(&log_dir).unwrap();
+}
+
+/// Writes the fixture trace for the current event format version
+#[test]
+#[ignore]
+fn generate_fixture() {
+    let dir = fixture_dir(EVENT_FORMAT_VERSION);
+
+    timely::execute_directly(move |worker| {
+        ddshow_sink::save_timely_logs_to_disk(worker, &dir).unwrap();
+        ddshow_sink::save_differential_logs_to_disk(worker, &dir).unwrap();
+
+        let mut input = worker.dataflow_named::<usize, _, _>("Fixture Dataflow", |scope| {
+            (0..10).to_stream(scope).map(|x| x * 2).inspect(|_| {});
+
+            let (input, collection) = scope.new_collection::<usize, isize>();
+            collection.map(|x| x % 7).count().arrange_by_self();
+
+            input
+        });
+
+        for round in 0..5 {
+            for x in 0..10 {
+                input.insert(round * 10 + x);
+            }
+
+            input.advance_to(round + 1);
+            input.flush();
+            worker.step();
+        }
+    });
+}
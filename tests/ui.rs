@@ -3,7 +3,14 @@ mod pass;
 
 use anyhow::Result;
 use libtest_mimic::{run_tests, Arguments, Outcome, Test};
-use std::{iter, net::TcpStream, ops::Deref, process::Command, thread};
+use std::{
+    iter,
+    net::{SocketAddr, TcpListener, TcpStream},
+    ops::Deref,
+    process::Command,
+    thread,
+    time::Duration,
+};
 use timely::{
     communication::allocator::Generic, worker::Worker, CommunicationConfig, WorkerConfig,
 };
@@ -38,27 +45,35 @@ fn test_runtime(test: &Test<TestData>) -> Outcome {
         return Outcome::Ignored;
     }
 
+    // Use unused ports so that tests running at the same time don't fight over the default ones
+    let (timely_address, differential_address) = (unused_address(), unused_address());
+
+    let mut ddshow = Command::new(concat!(env!("CARGO_MANIFEST_DIR"), "/target/debug/ddshow"));
+    ddshow
+        .args(&test.data.ddshow_args)
+        .arg("--differential")
+        .arg("--address")
+        .arg(timely_address.to_string())
+        .arg("--differential-address")
+        .arg(differential_address.to_string());
+    let ddshow = ddshow.spawn().unwrap();
+
     let target = test.data.target;
     let handle = thread::spawn(move || {
         let (builders, others) = CommunicationConfig::Thread.try_build().unwrap();
         let worker_config = WorkerConfig::default();
 
         timely::execute::execute_from(builders, others, worker_config, move |worker| {
-            let stream = TcpStream::connect("127.0.0.1:51317").unwrap();
-            ddshow_sink::enable_timely_logging(worker, stream);
-
-            let stream = TcpStream::connect("127.0.0.1:51318").unwrap();
-            ddshow_sink::enable_differential_logging(worker, stream);
+            ddshow_sink::enable_timely_logging(worker, connect_when_listening(timely_address));
+            ddshow_sink::enable_differential_logging(
+                worker,
+                connect_when_listening(differential_address),
+            );
 
             target(worker)
         })
     });
 
-    // TODO: Run ddshow
-    let mut ddshow = Command::new(concat!(env!("CARGO_MANIFEST_DIR"), "/target/debug/ddshow"));
-    ddshow.args(&test.data.ddshow_args).arg("--differential");
-    let ddshow = ddshow.spawn().unwrap();
-
     handle.join().unwrap().unwrap();
     let output = ddshow.wait_with_output().unwrap();
 
@@ -79,6 +94,24 @@ fn test_runtime(test: &Test<TestData>) -> Outcome {
     }
 }
 
+/// Gets an unused local address for ddshow to listen on
+fn unused_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Connects to ddshow once it starts listening
+fn connect_when_listening(address: SocketAddr) -> TcpStream {
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => break stream,
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
 struct TestData {
     target: fn(&mut Worker<Generic>),
     ddshow_args: Vec<String>,